@@ -0,0 +1,113 @@
+use cgmath::{Vector3, Quaternion, Rotation, Rotation3, Deg, InnerSpace};
+
+use crate::animation::Interpolation;
+use crate::animation::track::Track;
+use crate::render::cam::CamStruct;
+
+/// Keyframed eye, lookat and up vectors of a camera.
+/// Empty tracks leave the corresponding vector of the camera untouched.
+#[derive(Clone, Debug)]
+pub struct CameraAnimation
+{
+    pub m_eye: Track<Vector3<f32>>,
+    pub m_lookat: Track<Vector3<f32>>,
+    pub m_up: Track<Vector3<f32>>,
+}
+
+impl CameraAnimation
+{
+    pub fn new(interpolation: Interpolation) -> CameraAnimation
+    {
+        CameraAnimation
+        {
+            m_eye: Track::new(interpolation),
+            m_lookat: Track::new(interpolation),
+            // Rotating the up vector keeps its length
+            m_up: Track::new(Interpolation::Slerp),
+        }
+    }
+
+    pub fn add_key(&mut self, time: f32, eye: Vector3<f32>, lookat: Vector3<f32>, up: Vector3<f32>)
+    {
+        self.m_eye.add_key(time, eye);
+        self.m_lookat.add_key(time, lookat);
+        self.m_up.add_key(time, up);
+    }
+
+    /// Set up a turntable: the eye circles around "lookat" on the plane perpendicular to "up",
+    /// doing one revolution in "duration".
+    pub fn turntable(eye: Vector3<f32>, lookat: Vector3<f32>, up: Vector3<f32>, duration: f32) -> CameraAnimation
+    {
+        let mut animation = CameraAnimation::new(Interpolation::CatmullRom);
+        let axis = up.normalize();
+        let n_keys = 16;
+        for i in 0..=n_keys
+        {
+            let angle = 360.0 * i as f32 / n_keys as f32;
+            let rotation = Quaternion::from_axis_angle(axis, Deg(angle));
+            let time = duration * i as f32 / n_keys as f32;
+            animation.add_key(time, lookat + rotation.rotate_vector(eye - lookat), lookat, up);
+        }
+        animation
+    }
+
+    /// Evaluate the tracks at "time"; the values in "core" are kept for empty tracks.
+    pub fn evaluate(&self, core: &CamStruct, time: f32) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>)
+    {
+        (self.m_eye.evaluate(time).unwrap_or(core.m_eye),
+         self.m_lookat.evaluate(time).unwrap_or(core.m_lookat),
+         self.m_up.evaluate(time).unwrap_or(core.m_up))
+    }
+
+    /// Move the camera to where it is at "time" and recompute its local coordinate system
+    pub fn apply(&self, core: &mut CamStruct, time: f32)
+    {
+        let (eye, lookat, up) = self.evaluate(core, time);
+        core.m_eye = eye;
+        core.m_lookat = lookat;
+        core.m_up = up;
+        core.compute_uvw();
+    }
+}
+
+#[cfg(test)]
+mod CameraAnimationTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use cgmath::Zero;
+
+    #[test]
+    fn apply_moves_camera()
+    {
+        let mut animation = CameraAnimation::new(Interpolation::Linear);
+        animation.m_eye.add_key(0.0, Vector3::new(0.0, 0.0, -10.0));
+        animation.m_eye.add_key(1.0, Vector3::new(0.0, 0.0, -20.0));
+
+        let mut core = CamStruct::new(Vector3::zero(), Vector3::new(0.0, 0.0, 10.0), Vector3::unit_y());
+        animation.apply(&mut core, 0.5);
+
+        assert_relative_eq!(core.m_eye, Vector3::new(0.0, 0.0, -15.0));
+        assert_relative_eq!(core.m_lookat, Vector3::new(0.0, 0.0, 10.0));
+        assert_relative_eq!(core.m_w, Vector3::new(0.0, 0.0, -1.0));
+    }
+
+    #[test]
+    fn turntable_keeps_distance()
+    {
+        let lookat = Vector3::new(1.0, 2.0, 3.0);
+        let eye = lookat + Vector3::new(0.0, 5.0, -40.0);
+        let animation = CameraAnimation::turntable(eye, lookat, Vector3::unit_y(), 4.0);
+        let core = CamStruct::new(eye, lookat, Vector3::unit_y());
+
+        for i in 0..10
+        {
+            let (animated_eye, _, _) = animation.evaluate(&core, 0.4 * i as f32);
+            let offset = animated_eye - lookat;
+            assert_relative_eq!(offset.y, 5.0, epsilon = 1e-3);
+            assert_relative_eq!(Vector3::new(offset.x, 0.0, offset.z).magnitude(), 40.0, epsilon = 0.1);
+        }
+        let (half_turn, _, _) = animation.evaluate(&core, 2.0);
+        assert_relative_eq!(half_turn - lookat, Vector3::new(0.0, 5.0, 40.0), epsilon = 1e-3);
+    }
+}
@@ -0,0 +1,81 @@
+use crate::animation::Interpolation;
+use crate::animation::track::Track;
+use crate::geometry::instance::{Instance, MotionTransform};
+
+/// Keyframed transform of an Instance, applied on top of its static transform
+#[derive(Clone, Debug)]
+pub struct InstanceAnimation
+{
+    pub m_transform: Track<MotionTransform>,
+}
+
+impl InstanceAnimation
+{
+    pub fn new(interpolation: Interpolation) -> InstanceAnimation
+    {
+        InstanceAnimation
+        {
+            m_transform: Track::new(interpolation),
+        }
+    }
+
+    pub fn add_key(&mut self, time: f32, transform: MotionTransform)
+    {
+        self.m_transform.add_key(time, transform);
+    }
+
+    /// Place the instance where it is at "frame_time". The instance keeps moving while
+    /// the shutter is open, so that fast objects get motion blur; the motion keys are
+    /// relative to the opening of the shutter, like the time of the rays.
+    pub fn apply(&self, instance: &mut Instance, frame_time: f32, exposure_time: f32)
+    {
+        let start = self.m_transform.evaluate(frame_time);
+        let end = self.m_transform.evaluate(frame_time + exposure_time);
+
+        match (start, end)
+        {
+            (Some(start), Some(end)) if exposure_time > 0.0 => instance.set_motion(start, end, 0.0, exposure_time),
+            (Some(start), _) =>
+                {
+                    instance.clear_motion();
+                    instance.add_motion_key(0.0, start);
+                }
+            _ => instance.clear_motion(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod InstanceAnimationTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use cgmath::{Vector3, Decomposed, Transform};
+    use std::sync::Arc;
+    use crate::geometry::sphere::Sphere;
+    use crate::geometry::{Boundable, Shadable};
+    use crate::material::matte::Matte;
+    use crate::brdf::lambertian::Lambertian;
+    use crate::utils::colorconstant::COLOR_RED;
+
+    #[test]
+    fn apply_covers_shutter_interval()
+    {
+        let mut animation = InstanceAnimation::new(Interpolation::Linear);
+        animation.add_key(0.0, MotionTransform::one());
+        animation.add_key(1.0, Decomposed { disp: Vector3::new(10.0, 0.0, 0.0), ..MotionTransform::one() });
+
+        let mut sphere = Sphere::new(1.0, Vector3::new(0.0, 0.0, 0.0));
+        sphere.set_material(Arc::new(Matte::new(Arc::new(Lambertian::new(0.5, COLOR_RED)),
+                                                Arc::new(Lambertian::new(0.5, COLOR_RED)))));
+        let mut instance = Instance::new(Arc::new(sphere));
+        let static_bbox = instance.get_bbox();
+        animation.apply(&mut instance, 0.5, 0.1);
+
+        assert!(instance.is_moving());
+        assert_relative_eq!(instance.get_motion_transform(0.0).disp, Vector3::new(5.0, 0.0, 0.0), epsilon = 1e-5);
+        assert_relative_eq!(instance.get_motion_transform(0.1).disp, Vector3::new(6.0, 0.0, 0.0), epsilon = 1e-5);
+        assert_relative_eq!(instance.get_bbox().m_vertex_0.x, static_bbox.m_vertex_0.x + 5.0, epsilon = 1e-4);
+        assert_relative_eq!(instance.get_bbox().m_vertex_1.x, static_bbox.m_vertex_1.x + 6.0, epsilon = 1e-4);
+    }
+}
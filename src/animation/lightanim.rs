@@ -0,0 +1,99 @@
+use cgmath::Vector3;
+
+use crate::animation::Interpolation;
+use crate::animation::track::Track;
+use crate::light::ambient::Ambient;
+use crate::light::directional::Directional;
+use crate::light::pointlight::PointLight;
+use crate::utils::color::Colorf;
+
+/// Lights whose parameters can be driven by a LightAnimation
+pub trait AnimatedLight
+{
+    fn set_radiance_scaling_factor(&mut self, ls: f32);
+    fn set_color(&mut self, color: Colorf);
+    fn set_location(&mut self, _location: Vector3<f32>) {}
+    fn set_direction(&mut self, _direction: Vector3<f32>) {}
+}
+
+impl AnimatedLight for PointLight
+{
+    fn set_radiance_scaling_factor(&mut self, ls: f32) { PointLight::set_radiance_scaling_factor(self, ls) }
+    fn set_color(&mut self, color: Colorf) { PointLight::set_color(self, color) }
+    fn set_location(&mut self, location: Vector3<f32>) { PointLight::set_location(self, location) }
+}
+
+impl AnimatedLight for Directional
+{
+    fn set_radiance_scaling_factor(&mut self, ls: f32) { Directional::set_radiance_scaling_factor(self, ls) }
+    fn set_color(&mut self, color: Colorf) { Directional::set_color(self, color) }
+    fn set_direction(&mut self, direction: Vector3<f32>) { Directional::set_direction(self, direction) }
+}
+
+impl AnimatedLight for Ambient
+{
+    fn set_radiance_scaling_factor(&mut self, ls: f32) { Ambient::set_radiance_scaling_factor(self, ls) }
+    fn set_color(&mut self, color: Colorf) { Ambient::set_color(self, color) }
+}
+
+/// Keyframed light parameters. Empty tracks leave the parameter untouched;
+/// the location and direction tracks only affect lights that have them.
+#[derive(Clone, Debug)]
+pub struct LightAnimation
+{
+    pub m_ls: Track<f32>,
+    pub m_color: Track<Colorf>,
+    pub m_location: Track<Vector3<f32>>,
+    pub m_direction: Track<Vector3<f32>>,
+}
+
+impl LightAnimation
+{
+    pub fn new(interpolation: Interpolation) -> LightAnimation
+    {
+        LightAnimation
+        {
+            m_ls: Track::new(interpolation),
+            m_color: Track::new(interpolation),
+            m_location: Track::new(interpolation),
+            m_direction: Track::new(Interpolation::Slerp),
+        }
+    }
+
+    pub fn apply(&self, light: &mut dyn AnimatedLight, time: f32)
+    {
+        if let Some(ls) = self.m_ls.evaluate(time) { light.set_radiance_scaling_factor(ls); }
+        if let Some(color) = self.m_color.evaluate(time) { light.set_color(color); }
+        if let Some(location) = self.m_location.evaluate(time) { light.set_location(location); }
+        if let Some(direction) = self.m_direction.evaluate(time) { light.set_direction(direction); }
+    }
+}
+
+#[cfg(test)]
+mod LightAnimationTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use crate::light::Light;
+    use crate::world::shaderec::ShadeRec;
+    use crate::utils::colorconstant::{COLOR_WHITE, COLOR_BLACK, COLOR_RED};
+
+    #[test]
+    fn apply_to_point_light()
+    {
+        let mut animation = LightAnimation::new(Interpolation::Linear);
+        animation.m_ls.add_key(0.0, 1.0);
+        animation.m_ls.add_key(2.0, 3.0);
+        animation.m_color.add_key(0.0, COLOR_BLACK);
+        animation.m_color.add_key(2.0, COLOR_WHITE);
+        animation.m_location.add_key(0.0, Vector3::new(0.0, 10.0, 0.0));
+
+        let mut light = PointLight::new(0.5, COLOR_RED, Vector3::new(0.0, -10.0, 0.0));
+        animation.apply(&mut light, 1.0);
+
+        // L = ls * color
+        let sr = ShadeRec::get_dummy();
+        assert_relative_eq!(light.L(&sr).m_g, 2.0 * 0.5);
+        assert_relative_eq!(light.get_direction(&sr), Vector3::unit_y());
+    }
+}
@@ -0,0 +1,208 @@
+pub mod track;
+pub mod cameraanim;
+pub mod instanceanim;
+pub mod lightanim;
+pub mod sequence;
+
+use cgmath::{Vector3, Quaternion, InnerSpace, VectorSpace, Decomposed};
+use std::ops::{Add, Sub, Mul};
+
+use crate::utils::color::Colorf;
+
+/// How the values between two keyframes are computed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation
+{
+    Linear,
+    /// Cubic Hermite spline whose tangents come from the neighbouring keyframes
+    CatmullRom,
+    /// Spherical interpolation; for vectors, direction and length are interpolated separately
+    Slerp,
+}
+
+/// Values that can be stored in an animation track
+pub trait Interpolate: Copy
+{
+    fn lerp(&self, other: &Self, alpha: f32) -> Self;
+
+    fn slerp(&self, other: &Self, alpha: f32) -> Self
+    {
+        self.lerp(other, alpha)
+    }
+
+    /// Interpolate between "p1" and "p2"; "p0" and "p3" are the keyframes before and after them.
+    /// "times" are the times of the four keyframes.
+    fn catmull_rom(_p0: &Self, p1: &Self, p2: &Self, _p3: &Self, _times: [f32; 4], alpha: f32) -> Self
+    {
+        p1.lerp(p2, alpha)
+    }
+}
+
+/// Catmull-Rom spline for non-uniformly spaced keyframes, written as a cubic Hermite spline
+fn hermite_catmull_rom<T>(p0: T, p1: T, p2: T, p3: T, times: [f32; 4], alpha: f32) -> T
+    where T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>
+{
+    let duration = times[2] - times[1];
+    let tangent_1 = if times[2] > times[0] { (p2 - p0) * (duration / (times[2] - times[0])) } else { p2 - p1 };
+    let tangent_2 = if times[3] > times[1] { (p3 - p1) * (duration / (times[3] - times[1])) } else { p2 - p1 };
+
+    let alpha2 = alpha * alpha;
+    let alpha3 = alpha2 * alpha;
+    p1 * (2.0 * alpha3 - 3.0 * alpha2 + 1.0)
+        + tangent_1 * (alpha3 - 2.0 * alpha2 + alpha)
+        + p2 * (-2.0 * alpha3 + 3.0 * alpha2)
+        + tangent_2 * (alpha3 - alpha2)
+}
+
+impl Interpolate for f32
+{
+    fn lerp(&self, other: &f32, alpha: f32) -> f32
+    {
+        self + (other - self) * alpha
+    }
+
+    fn catmull_rom(p0: &f32, p1: &f32, p2: &f32, p3: &f32, times: [f32; 4], alpha: f32) -> f32
+    {
+        hermite_catmull_rom(*p0, *p1, *p2, *p3, times, alpha)
+    }
+}
+
+impl Interpolate for Vector3<f32>
+{
+    fn lerp(&self, other: &Vector3<f32>, alpha: f32) -> Vector3<f32>
+    {
+        VectorSpace::lerp(*self, *other, alpha)
+    }
+
+    fn slerp(&self, other: &Vector3<f32>, alpha: f32) -> Vector3<f32>
+    {
+        let (length_0, length_1) = (self.magnitude(), other.magnitude());
+        if length_0 == 0.0 || length_1 == 0.0
+        {
+            return Interpolate::lerp(self, other, alpha);
+        }
+
+        let cos_theta = (self.dot(*other) / (length_0 * length_1)).clamp(-1.0, 1.0);
+        let theta = cos_theta.acos();
+        let length = length_0.lerp(&length_1, alpha);
+        if theta.sin().abs() < 1e-5
+        {
+            return Interpolate::lerp(self, other, alpha).normalize() * length;
+        }
+
+        let direction = (self / length_0) * (((1.0 - alpha) * theta).sin() / theta.sin())
+            + (other / length_1) * ((alpha * theta).sin() / theta.sin());
+        direction.normalize() * length
+    }
+
+    fn catmull_rom(p0: &Vector3<f32>, p1: &Vector3<f32>, p2: &Vector3<f32>, p3: &Vector3<f32>,
+                   times: [f32; 4], alpha: f32) -> Vector3<f32>
+    {
+        hermite_catmull_rom(*p0, *p1, *p2, *p3, times, alpha)
+    }
+}
+
+impl Interpolate for Colorf
+{
+    fn lerp(&self, other: &Colorf, alpha: f32) -> Colorf
+    {
+        *self + (*other - *self) * alpha
+    }
+
+    fn catmull_rom(p0: &Colorf, p1: &Colorf, p2: &Colorf, p3: &Colorf, times: [f32; 4], alpha: f32) -> Colorf
+    {
+        hermite_catmull_rom(*p0, *p1, *p2, *p3, times, alpha)
+    }
+}
+
+/// Rotations always take the shortest path
+impl Interpolate for Quaternion<f32>
+{
+    fn lerp(&self, other: &Quaternion<f32>, alpha: f32) -> Quaternion<f32>
+    {
+        let other = if self.dot(*other) < 0.0 { -*other } else { *other };
+        self.nlerp(other, alpha)
+    }
+
+    fn slerp(&self, other: &Quaternion<f32>, alpha: f32) -> Quaternion<f32>
+    {
+        let other = if self.dot(*other) < 0.0 { -*other } else { *other };
+        Quaternion::slerp(*self, other, alpha)
+    }
+
+    fn catmull_rom(_p0: &Quaternion<f32>, p1: &Quaternion<f32>, p2: &Quaternion<f32>, _p3: &Quaternion<f32>,
+                   _times: [f32; 4], alpha: f32) -> Quaternion<f32>
+    {
+        Interpolate::slerp(p1, p2, alpha)
+    }
+}
+
+/// Translation and scaling follow the interpolation scheme, while rotations are always spherical
+impl Interpolate for Decomposed<Vector3<f32>, Quaternion<f32>>
+{
+    fn lerp(&self, other: &Self, alpha: f32) -> Self
+    {
+        Decomposed
+        {
+            scale: self.scale.lerp(&other.scale, alpha),
+            rot: Interpolate::slerp(&self.rot, &other.rot, alpha),
+            disp: Interpolate::lerp(&self.disp, &other.disp, alpha),
+        }
+    }
+
+    fn catmull_rom(p0: &Self, p1: &Self, p2: &Self, p3: &Self, times: [f32; 4], alpha: f32) -> Self
+    {
+        Decomposed
+        {
+            scale: f32::catmull_rom(&p0.scale, &p1.scale, &p2.scale, &p3.scale, times, alpha),
+            rot: Interpolate::slerp(&p1.rot, &p2.rot, alpha),
+            disp: Vector3::catmull_rom(&p0.disp, &p1.disp, &p2.disp, &p3.disp, times, alpha),
+        }
+    }
+}
+
+#[cfg(test)]
+mod InterpolateTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use cgmath::{Rotation3, Deg};
+
+    #[test]
+    fn catmull_rom_passes_through_keys()
+    {
+        let times = [0.0, 1.0, 3.0, 4.0];
+        let (p0, p1, p2, p3) = (0.0_f32, 2.0, -1.0, 5.0);
+        assert_relative_eq!(f32::catmull_rom(&p0, &p1, &p2, &p3, times, 0.0), 2.0);
+        assert_relative_eq!(f32::catmull_rom(&p0, &p1, &p2, &p3, times, 1.0), -1.0);
+    }
+
+    #[test]
+    fn catmull_rom_reproduces_lines()
+    {
+        // Evenly moving keyframes stay on a straight line
+        let times = [0.0, 1.0, 2.0, 3.0];
+        let keys: Vec<Vector3<f32>> = (0..4).map(|i| Vector3::new(i as f32, 2.0 * i as f32, 0.0)).collect();
+        let mid = Vector3::catmull_rom(&keys[0], &keys[1], &keys[2], &keys[3], times, 0.5);
+        assert_relative_eq!(mid, Vector3::new(1.5, 3.0, 0.0), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn slerp_keeps_length()
+    {
+        let from = Vector3::new(2.0, 0.0, 0.0);
+        let to = Vector3::new(0.0, 0.0, 2.0);
+        let mid = Interpolate::slerp(&from, &to, 0.5);
+        assert_relative_eq!(mid.magnitude(), 2.0, epsilon = 1e-6);
+        assert_relative_eq!(mid.x, mid.z, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn quaternion_takes_shortest_path()
+    {
+        let from = Quaternion::from_angle_y(Deg(10.0));
+        let to = -Quaternion::from_angle_y(Deg(30.0));
+        let mid = Interpolate::slerp(&from, &to, 0.5);
+        assert_relative_eq!(mid, Quaternion::from_angle_y(Deg(20.0)), epsilon = 1e-6);
+    }
+}
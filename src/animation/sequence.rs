@@ -0,0 +1,203 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::output::OutputManager;
+use crate::output::imagewriter::ImageWriter;
+use crate::render::cam::Camera;
+use crate::render::control::RenderStatus;
+use crate::render::renderbuffer::RenderBuffer;
+use crate::utils::multithread::MultiThread;
+use crate::world::world::World;
+
+/// Renders a range of frames into a numbered image sequence
+#[derive(Clone, Debug)]
+pub struct FrameSequence
+{
+    /// Image path of every frame; a run of '#' is replaced by the zero-padded frame number
+    m_path_pattern: String,
+    pub m_start_frame: u32,
+    pub m_end_frame: u32, // Inclusive
+    pub m_frame_rate: f32,
+}
+
+impl FrameSequence
+{
+    pub fn new(path_pattern: &str, start_frame: u32, end_frame: u32, frame_rate: f32) -> FrameSequence
+    {
+        if end_frame < start_frame
+        {
+            panic!("The last frame comes before the first frame");
+        }
+        if frame_rate <= 0.0
+        {
+            panic!("Frame rate must be positive");
+        }
+
+        FrameSequence
+        {
+            m_path_pattern: String::from(path_pattern),
+            m_start_frame: start_frame,
+            m_end_frame: end_frame,
+            m_frame_rate: frame_rate,
+        }
+    }
+
+    pub fn get_frame_count(&self) -> u32
+    {
+        self.m_end_frame - self.m_start_frame + 1
+    }
+
+    /// The animation time of a frame, in seconds
+    pub fn get_frame_time(&self, frame: u32) -> f32
+    {
+        frame as f32 / self.m_frame_rate
+    }
+
+    /// Without any '#' in the pattern, the frame number is appended to the file stem
+    pub fn get_frame_path(&self, frame: u32) -> String
+    {
+        match self.m_path_pattern.find('#')
+        {
+            Some(start) =>
+                {
+                    let width = self.m_path_pattern[start..].chars().take_while(|c| *c == '#').count();
+                    format!("{}{:0width$}{}",
+                            &self.m_path_pattern[..start],
+                            frame,
+                            &self.m_path_pattern[start + width..],
+                            width = width)
+                }
+            None =>
+                {
+                    let path = Path::new(&self.m_path_pattern);
+                    let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("");
+                    let file_name = match path.extension().and_then(|ext| ext.to_str())
+                    {
+                        Some(ext) => format!("{}_{:04}.{}", stem, frame, ext),
+                        None => format!("{}_{:04}", stem, frame),
+                    };
+                    path.with_file_name(file_name).to_string_lossy().into_owned()
+                }
+        }
+    }
+
+    /// Render every frame of the sequence. "setup_frame" receives the frame number and its time,
+    /// and returns the scene and the camera of that frame, with the animations applied.
+    /// Return the paths of the images written, and how the last render ended: a frame stopped by
+    /// the render control of "multithread", e.g. cancelled or out of time, isn't written and ends
+    /// the sequence.
+    pub fn render<F>(&self, multithread: &mut MultiThread, mut setup_frame: F) -> (Vec<String>, RenderStatus)
+        where F: FnMut(u32, f32) -> (Arc<World>, Box<dyn Camera>)
+    {
+        let mut frame_paths = Vec::with_capacity(self.get_frame_count() as usize);
+
+        for frame in self.m_start_frame..=self.m_end_frame
+        {
+            let (world, camera) = setup_frame(frame, self.get_frame_time(frame));
+            let img_dim = camera.get_image_dim(world.m_viewplaneptr.as_ref());
+            let buffer = RenderBuffer::new(img_dim, multithread.get_tile_dim(img_dim));
+            let status = multithread.render_to_buffer(world, camera.as_ref(), &buffer);
+            if status != RenderStatus::Completed
+            {
+                return (frame_paths, status);
+            }
+
+            let frame_path = self.get_frame_path(frame);
+            let mut imgwriter = ImageWriter::new(&frame_path, img_dim.0, img_dim.1);
+            buffer.write(&mut imgwriter);
+            imgwriter.output();
+            frame_paths.push(frame_path);
+        }
+        (frame_paths, RenderStatus::Completed)
+    }
+}
+
+#[cfg(test)]
+mod FrameSequenceTest
+{
+    use super::*;
+    use cgmath::{Vector3, Zero};
+    use crate::animation::Interpolation;
+    use crate::animation::cameraanim::CameraAnimation;
+    use crate::render::cam::pinhole::Pinhole;
+    use crate::render::control::RenderControl;
+    use crate::sampler::Sampler;
+    use crate::sampler::mutijittered::MultiJittered;
+    use crate::world::viewplane::ViewPlane;
+
+    fn setup_world() -> Arc<World>
+    {
+        let mut sampler = MultiJittered::new(1, 1);
+        sampler.generate_sample_pattern();
+        let mut vp = ViewPlane::new(Arc::new(sampler));
+        vp.m_hres = 16;
+        vp.m_vres = 16;
+        Arc::new(World::new(Box::new(vp), "whitted"))
+    }
+
+    #[test]
+    fn frame_paths()
+    {
+        let sequence = FrameSequence::new("out/turntable_####.png", 1, 3, 24.0);
+        assert_eq!(sequence.get_frame_path(7), "out/turntable_0007.png");
+        assert_eq!(sequence.get_frame_path(12345), "out/turntable_12345.png");
+
+        let sequence = FrameSequence::new("out/flythrough.jpg", 1, 3, 24.0);
+        assert_eq!(sequence.get_frame_path(42), "out/flythrough_0042.jpg");
+        assert_eq!(sequence.get_frame_count(), 3);
+        assert_eq!(sequence.get_frame_time(48), 2.0);
+    }
+
+    #[test]
+    fn render_numbered_images()
+    {
+        let dir = std::env::temp_dir().join("raytracer_sequence_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let pattern = dir.join("frame_##.png");
+        let sequence = FrameSequence::new(pattern.to_str().unwrap(), 3, 4, 2.0);
+
+        let mut animation = CameraAnimation::new(Interpolation::Linear);
+        animation.m_eye.add_key(0.0, Vector3::new(0.0, 0.0, -10.0));
+        animation.m_eye.add_key(2.0, Vector3::new(5.0, 0.0, -10.0));
+
+        let mut multithread = MultiThread::new(2);
+        let mut frame_times = Vec::new();
+        let (paths, status) = sequence.render(&mut multithread, |_frame, time| {
+            frame_times.push(time);
+            let mut pinhole = Pinhole::new(Vector3::zero(), Vector3::new(0.0, 0.0, 10.0), Vector3::unit_y());
+            animation.apply(&mut pinhole.m_core, time);
+            (setup_world(), Box::new(pinhole) as Box<dyn Camera>)
+        });
+
+        assert_eq!(status, RenderStatus::Completed);
+        assert_eq!(frame_times, vec![1.5, 2.0]);
+        assert_eq!(paths.len(), 2);
+        for path in paths.iter()
+        {
+            assert!(path.ends_with(".png"));
+            assert!(Path::new(path).exists());
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn cancelled_sequence_stops()
+    {
+        let pattern = std::env::temp_dir().join(format!("raytracer_cancelled_{}_##.png", std::process::id()));
+        let sequence = FrameSequence::new(pattern.to_str().unwrap(), 1, 3, 24.0);
+        let control = Arc::new(RenderControl::new());
+        control.cancel();
+        let mut multithread = MultiThread::new(2);
+        multithread.set_render_control(control);
+
+        let mut frames = Vec::new();
+        let (paths, status) = sequence.render(&mut multithread, |frame, _time| {
+            frames.push(frame);
+            (setup_world(), Box::new(Pinhole::new(Vector3::zero(), Vector3::unit_z(), Vector3::unit_y())) as Box<dyn Camera>)
+        });
+        assert_eq!(status, RenderStatus::Cancelled);
+        assert!(paths.is_empty());
+        assert_eq!(frames, vec![1]);
+        assert!(!Path::new(&sequence.get_frame_path(1)).exists());
+    }
+}
@@ -0,0 +1,146 @@
+use crate::animation::{Interpolate, Interpolation};
+
+/// A sequence of keyframes of type T, sorted by time
+#[derive(Clone, Debug)]
+pub struct Track<T: Interpolate>
+{
+    m_keys: Vec<(f32, T)>,
+    pub m_interpolation: Interpolation,
+}
+
+impl<T: Interpolate> Track<T>
+{
+    pub fn new(interpolation: Interpolation) -> Track<T>
+    {
+        Track
+        {
+            m_keys: Vec::new(),
+            m_interpolation: interpolation,
+        }
+    }
+
+    /// A track that holds the same value all the time
+    pub fn constant(value: T) -> Track<T>
+    {
+        let mut track = Track::new(Interpolation::Linear);
+        track.add_key(0.0, value);
+        track
+    }
+
+    /// Insert a keyframe, replacing the existing one at the same time
+    pub fn add_key(&mut self, time: f32, value: T)
+    {
+        match self.m_keys.iter().position(|(key_time, _)| *key_time >= time)
+        {
+            Some(index) if self.m_keys[index].0 == time => self.m_keys[index].1 = value,
+            Some(index) => self.m_keys.insert(index, (time, value)),
+            None => self.m_keys.push((time, value)),
+        }
+    }
+
+    pub fn get_keys(&self) -> &Vec<(f32, T)>
+    {
+        &self.m_keys
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.m_keys.is_empty()
+    }
+
+    /// Time of the first and the last keyframe
+    pub fn get_time_range(&self) -> Option<(f32, f32)>
+    {
+        match self.m_keys.len()
+        {
+            0 => None,
+            n => Some((self.m_keys[0].0, self.m_keys[n - 1].0)),
+        }
+    }
+
+    /// The value of the track at "time"; the first and last keyframes are held
+    /// before and after the animated range.
+    pub fn evaluate(&self, time: f32) -> Option<T>
+    {
+        let n = self.m_keys.len();
+        if n == 0
+        {
+            return None;
+        }
+        if time <= self.m_keys[0].0
+        {
+            return Some(self.m_keys[0].1);
+        }
+        if time >= self.m_keys[n - 1].0
+        {
+            return Some(self.m_keys[n - 1].1);
+        }
+
+        let next = self.m_keys.iter().position(|(key_time, _)| *key_time > time).unwrap();
+        let (t1, p1) = &self.m_keys[next - 1];
+        let (t2, p2) = &self.m_keys[next];
+        let alpha = (time - t1) / (t2 - t1);
+
+        Some(match self.m_interpolation
+        {
+            Interpolation::Linear => p1.lerp(p2, alpha),
+            Interpolation::Slerp => p1.slerp(p2, alpha),
+            Interpolation::CatmullRom =>
+                {
+                    // Duplicate the end keyframes when there are no neighbours
+                    let (t0, p0) = &self.m_keys[if next >= 2 { next - 2 } else { next - 1 }];
+                    let (t3, p3) = &self.m_keys[if next + 1 < n { next + 1 } else { next }];
+                    T::catmull_rom(p0, p1, p2, p3, [*t0, *t1, *t2, *t3], alpha)
+                }
+        })
+    }
+}
+
+#[cfg(test)]
+mod TrackTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn keys_stay_sorted()
+    {
+        let mut track = Track::new(Interpolation::Linear);
+        track.add_key(2.0, 20.0_f32);
+        track.add_key(0.0, 0.0);
+        track.add_key(1.0, 5.0);
+        track.add_key(1.0, 10.0);
+
+        let times: Vec<f32> = track.get_keys().iter().map(|(time, _)| *time).collect();
+        assert_eq!(times, vec![0.0, 1.0, 2.0]);
+        assert_eq!(track.get_time_range(), Some((0.0, 2.0)));
+        assert_relative_eq!(track.evaluate(1.0).unwrap(), 10.0);
+    }
+
+    #[test]
+    fn evaluate_linear()
+    {
+        let mut track = Track::new(Interpolation::Linear);
+        assert!(track.evaluate(0.0).is_none());
+        track.add_key(0.0, 0.0_f32);
+        track.add_key(2.0, 4.0);
+
+        assert_relative_eq!(track.evaluate(0.5).unwrap(), 1.0);
+        assert_relative_eq!(track.evaluate(-3.0).unwrap(), 0.0);
+        assert_relative_eq!(track.evaluate(7.0).unwrap(), 4.0);
+    }
+
+    #[test]
+    fn evaluate_catmull_rom_is_smooth()
+    {
+        let mut track = Track::new(Interpolation::CatmullRom);
+        track.add_key(0.0, 0.0_f32);
+        track.add_key(1.0, 1.0);
+        track.add_key(2.0, 0.0);
+
+        // The spline overshoots the linear interpolation around the peak
+        assert!(track.evaluate(0.9).unwrap() > 0.9);
+        assert_relative_eq!(track.evaluate(1.0).unwrap(), 1.0);
+        assert_relative_eq!(track.evaluate(0.5).unwrap(), track.evaluate(1.5).unwrap(), epsilon = 1e-6);
+    }
+}
@@ -4,6 +4,7 @@ use cgmath::{Vector3, InnerSpace, ElementWise};
 use crate::utils::color::Colorf;
 use cgmath::num_traits::{Inv, Pow};
 use crate::utils::colorconstant::{COLOR_BLACK, COLOR_WHITE};
+use crate::utils::scenehash::SceneHasher;
 
 pub struct FresnelReflector
 {
@@ -43,6 +44,15 @@ impl BRDF for FresnelReflector
 
     /// Reflectance of the material
     fn rho(&self, sr: &ShadeRec, w_o: Vector3<f32>) -> Colorf { unimplemented!() }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("fresnel_reflector");
+        hasher.write_f32(self.m_index_of_reflection_in);
+        hasher.write_f32(self.m_index_of_reflection_out);
+        hasher.write_color(self.m_cr);
+        hasher.write_f32(self.m_kr);
+    }
 }
 
 impl Transmitter for FresnelReflector
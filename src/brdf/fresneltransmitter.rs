@@ -5,6 +5,7 @@ use crate::utils::color::Colorf;
 use cgmath::num_traits::{Inv, Pow};
 use std::ops::Neg;
 use crate::utils::colorconstant::COLOR_WHITE;
+use crate::utils::scenehash::SceneHasher;
 
 pub struct FresnelTransmitter
 {
@@ -37,6 +38,13 @@ impl BRDF for FresnelTransmitter
 
     /// Reflectance of the material
     fn rho(&self, sr: &ShadeRec, w_o: Vector3<f32>) -> Colorf { unimplemented!() }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("fresnel_transmitter");
+        hasher.write_f32(self.m_index_of_reflection_in);
+        hasher.write_f32(self.m_index_of_reflection_out);
+    }
 }
 
 impl Transmitter for FresnelTransmitter
@@ -3,15 +3,15 @@ use cgmath::{Vector3,
              ElementWise};
 use std::f32::consts::PI;
 use std::ops::Mul;
-use std::sync::Arc;
 
 use crate::brdf::BRDF;
 use crate::world::shaderec::ShadeRec;
-use crate::sampler::{mutijittered::MultiJittered,
-                     Sampler};
+use crate::sampler::map_square_to_hemisphere;
+use crate::sampler::pixelsampler::get_2d;
 use crate::utils::color::Colorf;
 use crate::utils::colorconstant::{COLOR_BLACK,
                                   COLOR_RED};
+use crate::utils::scenehash::SceneHasher;
 
 const INV_PI: f32 = 1.0 / PI;
 
@@ -23,12 +23,11 @@ pub struct GlossySpecular
     pub m_ks: f32,
     pub m_exp: f32,
     m_kd: f32,
-    m_samplerptr: Arc<dyn Sampler>
 }
 
 impl GlossySpecular
 {
-    pub fn new(kd: f32, colord: Colorf, sampler: Arc<dyn Sampler>) -> GlossySpecular
+    pub fn new(kd: f32, colord: Colorf) -> GlossySpecular
     {
         GlossySpecular
         {
@@ -37,15 +36,9 @@ impl GlossySpecular
             m_ks: 0.0,
             m_colors: COLOR_RED,
             m_exp: 1.0,
-            m_samplerptr: sampler,
         }
     }
 
-    pub fn set_sampler(&mut self, sampler: &Arc<dyn Sampler>)
-    {
-        self.m_samplerptr = sampler.clone();
-    }
-
     pub fn set_kd(&mut self, kd: f32)
     {
         self.m_kd = kd;
@@ -86,7 +79,8 @@ impl BRDF for GlossySpecular
         let u = Vector3::new(0.00045, 1.0, 0.00045).cross(w).normalize();
         let v = u.cross(w);
 
-        let sample_point = self.m_samplerptr.as_ref().get_hemisphere_sample();
+        // Distributed around the mirror direction following the specular lobe
+        let sample_point = map_square_to_hemisphere(get_2d(), self.m_exp);
         *w_i = u.mul_element_wise(sample_point.x )
             + v.mul_element_wise(sample_point.y)
             + w.mul_element_wise(sample_point.z);
@@ -107,4 +101,14 @@ impl BRDF for GlossySpecular
     {
         self.m_colord * self.m_kd
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("glossy_specular");
+        hasher.write_color(self.m_colord);
+        hasher.write_color(self.m_colors);
+        hasher.write_f32(self.m_ks);
+        hasher.write_f32(self.m_exp);
+        hasher.write_f32(self.m_kd);
+    }
 }
@@ -8,6 +8,7 @@ use crate::sampler::{mutijittered::MultiJittered,
                      Sampler};
 use crate::utils::color::Colorf;
 use std::sync::Arc;
+use crate::utils::scenehash::SceneHasher;
 
 const INV_PI: f32 = 1.0 / PI;
 
@@ -68,4 +69,11 @@ impl BRDF for Lambertian
     {
         self.m_colord * self.m_kd
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("lambertian");
+        hasher.write_f32(self.m_kd);
+        hasher.write_color(self.m_colord);
+    }
 }
@@ -9,6 +9,7 @@ use cgmath::{Vector3};
 use crate::utils::color::Colorf;
 use crate::world::shaderec::ShadeRec;
 use crate::utils::colorconstant::COLOR_BLACK;
+use crate::utils::scenehash::SceneHasher;
 
 pub trait BRDF
 {
@@ -21,6 +22,9 @@ pub trait BRDF
 
     /// Reflectance of the material
     fn rho(&self, sr: &ShadeRec, w_o: Vector3<f32>) -> Colorf { COLOR_BLACK }
+
+    /// Kind and coefficients, for the hash of the scenes using it
+    fn hash_scene(&self, hasher: &mut SceneHasher);
 }
 
 pub trait Transmitter
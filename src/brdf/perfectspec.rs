@@ -6,6 +6,7 @@ use crate::utils::colorconstant::COLOR_BLACK;
 use crate::sampler::mutijittered::MultiJittered;
 use crate::sampler::Sampler;
 use std::sync::Arc;
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Clone, Debug)]
 pub struct PerfectSpecular
@@ -44,4 +45,11 @@ impl BRDF for PerfectSpecular
     {
         COLOR_BLACK
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("perfect_specular");
+        hasher.write_f32(self.m_kr);
+        hasher.write_color(self.m_cr);
+    }
 }
\ No newline at end of file
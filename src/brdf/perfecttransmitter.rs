@@ -7,6 +7,7 @@ use cgmath::num_traits::{Inv, Pow};
 use crate::utils::color::Colorf;
 use crate::utils::colorconstant::COLOR_WHITE;
 use std::ops::Neg;
+use crate::utils::scenehash::SceneHasher;
 
 pub struct PerfectTransmitter
 {
@@ -68,4 +69,11 @@ impl BRDF for PerfectTransmitter
 
     /// Reflectance of the material
     fn rho(&self, sr: &ShadeRec, w_o: Vector3<f32>) -> Colorf { unimplemented!() }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("perfect_transmitter");
+        hasher.write_f32(self.m_kt);
+        hasher.write_f32(self.m_index_of_reflection);
+    }
 }
\ No newline at end of file
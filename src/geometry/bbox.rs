@@ -6,6 +6,7 @@ use crate::ray::Ray;
 use std::fmt;
 use crate::math::float_cmp;
 use crate::math::float_cmp::{min, max};
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Clone)]
 pub struct BBox
@@ -130,6 +131,13 @@ impl Geometry for BBox
         let result = BBox::calculate_hit_time(self, incomeray, &mut TMIN, &mut TMAX);
         Ok(result)
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("bbox");
+        hasher.write_vector3(self.m_vertex_0);
+        hasher.write_vector3(self.m_vertex_1);
+    }
 }
 
 #[cfg(test)]
@@ -2,7 +2,7 @@ use cgmath::{Vector3, Zero, ElementWise};
 use std::sync::Arc;
 
 use crate::world::shaderec::ShadeRec;
-use crate::material::Material;
+use crate::material::{Material, hash_material};
 use crate::ray::Ray;
 use std::fmt;
 use crate::math::float_cmp;
@@ -12,6 +12,7 @@ use crate::math::float_cmp::{max, min};
 use crate::geometry::{Geometry, KEPSILON, Shadable, GeomError, Boundable};
 use crate::utils::color::Colorf;
 use crate::utils::colorconstant::COLOR_WHITE;
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Clone)]
 pub struct Cuboid
@@ -173,6 +174,14 @@ impl Geometry for Cuboid
         }
         Ok(false)
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("cuboid");
+        hasher.write_vector3(self.m_vec0);
+        hasher.write_vector3(self.m_vec1);
+        hash_material(&self.m_material, hasher);
+    }
 }
 
 impl Boundable for Cuboid
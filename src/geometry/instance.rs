@@ -1,6 +1,7 @@
 use crate::geometry::{Geometry, GeomError, Boundable, BoundedConcrete};
 use std::sync::Arc;
-use cgmath::{Matrix3, SquareMatrix, Vector3, Matrix4, InnerSpace, ElementWise, Rad, Deg, Zero, Vector4};
+use cgmath::{Matrix3, SquareMatrix, Vector3, Matrix4, InnerSpace, ElementWise, Rad, Deg, Zero, Vector4,
+             Decomposed, Quaternion, Matrix, VectorSpace, Transform};
 use crate::world::shaderec::ShadeRec;
 use crate::ray::Ray;
 use std::fmt;
@@ -9,6 +10,13 @@ use crate::math::constants;
 use crate::material::Material;
 use crate::geometry::bbox::BBox;
 use std::f32::INFINITY;
+use crate::utils::scenehash::SceneHasher;
+
+/// Rigid transform with uniform scaling, which can be interpolated between keyframes
+pub type MotionTransform = Decomposed<Vector3<f32>, Quaternion<f32>>;
+
+/// Number of steps per keyframe interval used to bound the motion of the instance
+const MOTION_BBOX_STEPS: usize = 8;
 
 #[derive(Clone)]
 pub struct Instance
@@ -19,14 +27,14 @@ pub struct Instance
     m_forward_matrix: Matrix4<f32>,
     m_do_transform_texture: bool,
     m_bbox: BBox,
+    m_motion_keys: Vec<(f32, MotionTransform)>, // Sorted by time, applied on top of the static transform
 }
 
 impl Instance
 {
     pub fn new(geomptr: Arc<dyn BoundedConcrete>) -> Instance
     {
-        let mut mat_buffer = Matrix4::identity();
-        mat_buffer[3][3] = 0.0;
+        let mat_buffer = Matrix4::identity();
 
         Instance
         {
@@ -36,6 +44,92 @@ impl Instance
             m_forward_matrix: mat_buffer,
             m_do_transform_texture: true,
             m_bbox: geomptr.as_ref().get_bbox(),
+            m_motion_keys: Vec::new(),
+        }
+    }
+
+    /// Add a keyframe of the instance's motion; "time" is within the camera's shutter interval.
+    /// Between keyframes, the transforms are interpolated per ray.
+    pub fn add_motion_key(&mut self, time: f32, transform: MotionTransform)
+    {
+        let index = self.m_motion_keys.iter()
+            .position(|(key_time, _)| *key_time > time)
+            .unwrap_or(self.m_motion_keys.len());
+        self.m_motion_keys.insert(index, (time, transform));
+        self.compute_bbox();
+    }
+
+    /// Move the instance from "start" to "end" while the shutter is open
+    pub fn set_motion(&mut self, start: MotionTransform, end: MotionTransform, shutter_open: f32, shutter_close: f32)
+    {
+        self.m_motion_keys.clear();
+        self.add_motion_key(shutter_open, start);
+        self.add_motion_key(shutter_close, end);
+    }
+
+    pub fn clear_motion(&mut self)
+    {
+        self.m_motion_keys.clear();
+        self.compute_bbox();
+    }
+
+    pub fn is_moving(&self) -> bool
+    {
+        !self.m_motion_keys.is_empty()
+    }
+
+    /// Interpolate the motion keyframes at "time"; the transform is held constant
+    /// before the first and after the last keyframe.
+    pub fn get_motion_transform(&self, time: f32) -> MotionTransform
+    {
+        let keys = &self.m_motion_keys;
+        if keys.is_empty()
+        {
+            return MotionTransform::one();
+        }
+        if time <= keys[0].0
+        {
+            return keys[0].1;
+        }
+        if time >= keys[keys.len() - 1].0
+        {
+            return keys[keys.len() - 1].1;
+        }
+
+        let next = keys.iter().position(|(key_time, _)| *key_time > time).unwrap();
+        let (t0, from) = keys[next - 1];
+        let (t1, to) = keys[next];
+        Instance::interpolate_transform(&from, &to, (time - t0) / (t1 - t0))
+    }
+
+    fn interpolate_transform(from: &MotionTransform, to: &MotionTransform, alpha: f32) -> MotionTransform
+    {
+        let rot = if from.rot.dot(to.rot) < 0.0 { -to.rot } else { to.rot };
+        Decomposed
+        {
+            scale: from.scale + (to.scale - from.scale) * alpha,
+            rot: from.rot.slerp(rot, alpha),
+            disp: from.disp.lerp(to.disp, alpha),
+        }
+    }
+
+    /// The object-to-world matrix at "time"
+    fn get_forward_matrix(&self, time: f32) -> Matrix4<f32>
+    {
+        match self.is_moving()
+        {
+            true => Matrix4::from(self.get_motion_transform(time)) * self.m_forward_matrix,
+            false => self.m_forward_matrix,
+        }
+    }
+
+    /// The world-to-object matrix at "time"
+    fn get_inv_matrix(&self, time: f32) -> Matrix4<f32>
+    {
+        match self.is_moving()
+        {
+            true => self.get_forward_matrix(time).invert().unwrap_or(self.m_inv_matrix),
+            false => self.m_inv_matrix,
         }
     }
 
@@ -67,21 +161,21 @@ impl Instance
     pub fn rotate_x(&mut self, theta: f32)
     {
         self.m_forward_matrix = Matrix4::from_angle_x(Deg(theta)) * self.m_forward_matrix;
-        self.m_inv_matrix = Matrix4::from_angle_x(-Deg(theta)) * self.m_forward_matrix;
+        self.m_inv_matrix = self.m_inv_matrix * Matrix4::from_angle_x(-Deg(theta));
         self.compute_bbox();
     }
 
     pub fn rotate_y(&mut self, theta: f32)
     {
         self.m_forward_matrix = Matrix4::from_angle_y(Deg(theta)) * self.m_forward_matrix;
-        self.m_inv_matrix = Matrix4::from_angle_y(-Deg(theta)) * self.m_forward_matrix;
+        self.m_inv_matrix = self.m_inv_matrix * Matrix4::from_angle_y(-Deg(theta));
         self.compute_bbox();
     }
 
     pub fn rotate_z(&mut self, theta: f32)
     {
         self.m_forward_matrix = Matrix4::from_angle_z(Deg(theta)) * self.m_forward_matrix;
-        self.m_inv_matrix = Matrix4::from_angle_z(-Deg(theta)) * self.m_forward_matrix;
+        self.m_inv_matrix = self.m_inv_matrix * Matrix4::from_angle_z(-Deg(theta));
         self.compute_bbox();
     }
 
@@ -91,6 +185,13 @@ impl Instance
         let transformed_vector4 = (*mat4) * vector4;
         Vector3::new(transformed_vector4.x, transformed_vector4.y, transformed_vector4.z)
     }
+
+    /// Unlike points, directions are not affected by translation
+    fn transform_direction(mat4: &Matrix4<f32>, direction: &Vector3<f32>) -> Vector3<f32>
+    {
+        let transformed_vector4 = (*mat4) * direction.extend(0.0);
+        transformed_vector4.truncate()
+    }
 }
 
 impl fmt::Debug for Instance
@@ -106,14 +207,20 @@ impl fmt::Debug for Instance
 impl Geometry for Instance
 {
      fn hit(&self, incomeray: &Ray, time: &mut f32, shaderecord: &mut ShadeRec) -> Result<bool, GeomError> {
-        let mut inverted_ray = Ray::new(Instance::transform_vector3(&self.m_inv_matrix, &(incomeray.m_origin)),
-                                        Instance::transform_vector3(&self.m_inv_matrix, &incomeray.m_direction));
+        let inv_matrix = self.get_inv_matrix(incomeray.m_time);
+        // The direction is not normalised, so that the hit time stays the same in both spaces
+        let inverted_ray = Ray
+        {
+            m_origin: Instance::transform_vector3(&inv_matrix, &incomeray.m_origin),
+            m_direction: Instance::transform_direction(&inv_matrix, &incomeray.m_direction),
+            m_time: incomeray.m_time,
+        };
 
         if self.m_geomptr.hit(&inverted_ray, time, shaderecord)
             .unwrap_or(false)
         {
-            shaderecord.m_normal = Instance::transform_vector3(&self.m_inv_matrix, &shaderecord.m_normal);
-            shaderecord.m_normal.normalize();
+            shaderecord.m_normal = Instance::transform_direction(&inv_matrix.transpose(), &shaderecord.m_normal)
+                .normalize();
 
             if !self.m_do_transform_texture
             {
@@ -123,36 +230,85 @@ impl Geometry for Instance
         }
         Ok(false)
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("instance");
+        self.m_geomptr.hash_scene(hasher);
+        self.m_material_ptr.hash_scene(hasher);
+        let forward: &[f32; 16] = self.m_forward_matrix.as_ref();
+        forward.iter().for_each(|value| hasher.write_f32(*value));
+        hasher.write_bool(self.m_do_transform_texture);
+        hasher.write_u64(self.m_motion_keys.len() as u64);
+        for (time, transform) in self.m_motion_keys.iter()
+        {
+            hasher.write_f32(*time);
+            hasher.write_f32(transform.scale);
+            hasher.write_f32(transform.rot.s);
+            hasher.write_vector3(transform.rot.v);
+            hasher.write_vector3(transform.disp);
+        }
+    }
 }
 
 impl Boundable for Instance
 {
     fn compute_bbox(&mut self)
     {
-        self.m_bbox = self.m_geomptr.get_bbox();
-        let mut trans_vertices = vec![self.m_bbox.m_vertex_0,
-            Vector3::new(self.m_bbox.m_vertex_1.x,
-                         self.m_bbox.m_vertex_0.y,
-                         self.m_bbox.m_vertex_0.z),
-            Vector3::new(self.m_bbox.m_vertex_0.x,
-                         self.m_bbox.m_vertex_1.y,
-                         self.m_bbox.m_vertex_0.z),
-            Vector3::new(self.m_bbox.m_vertex_0.x,
-                         self.m_bbox.m_vertex_0.y,
-                         self.m_bbox.m_vertex_1.z),
-            Vector3::new(self.m_bbox.m_vertex_1.x,
-                         self.m_bbox.m_vertex_1.y,
-                         self.m_bbox.m_vertex_0.z),
-            Vector3::new(self.m_bbox.m_vertex_1.x,
-                         self.m_bbox.m_vertex_0.y,
-                         self.m_bbox.m_vertex_1.z),
-            Vector3::new(self.m_bbox.m_vertex_0.x,
-                         self.m_bbox.m_vertex_1.y,
-                         self.m_bbox.m_vertex_1.z),
-            self.m_bbox.m_vertex_1];
+        if !self.is_moving()
+        {
+            self.m_bbox = self.get_transformed_bbox(&self.m_forward_matrix);
+            return;
+        }
+
+        // Sample the motion between every pair of keyframes, so that rotations are covered as well
+        let mut bbox = self.get_transformed_bbox(&self.get_forward_matrix(self.m_motion_keys[0].0));
+        for pair in self.m_motion_keys.windows(2)
+        {
+            for step in 1..=MOTION_BBOX_STEPS
+            {
+                let time = pair[0].0 + (pair[1].0 - pair[0].0) * step as f32 / MOTION_BBOX_STEPS as f32;
+                bbox = bbox.union(&self.get_transformed_bbox(&self.get_forward_matrix(time)));
+            }
+        }
+        self.m_bbox = bbox;
+    }
+
+    fn get_bbox(&self) -> BBox
+    {
+        self.m_bbox.clone()
+    }
+}
+
+impl Instance
+{
+    /// Bounding box of the underlying geometry after transforming it with "forward_matrix"
+    fn get_transformed_bbox(&self, forward_matrix: &Matrix4<f32>) -> BBox
+    {
+        let geom_bbox = self.m_geomptr.get_bbox();
+        let mut trans_vertices = vec![geom_bbox.m_vertex_0,
+            Vector3::new(geom_bbox.m_vertex_1.x,
+                         geom_bbox.m_vertex_0.y,
+                         geom_bbox.m_vertex_0.z),
+            Vector3::new(geom_bbox.m_vertex_0.x,
+                         geom_bbox.m_vertex_1.y,
+                         geom_bbox.m_vertex_0.z),
+            Vector3::new(geom_bbox.m_vertex_0.x,
+                         geom_bbox.m_vertex_0.y,
+                         geom_bbox.m_vertex_1.z),
+            Vector3::new(geom_bbox.m_vertex_1.x,
+                         geom_bbox.m_vertex_1.y,
+                         geom_bbox.m_vertex_0.z),
+            Vector3::new(geom_bbox.m_vertex_1.x,
+                         geom_bbox.m_vertex_0.y,
+                         geom_bbox.m_vertex_1.z),
+            Vector3::new(geom_bbox.m_vertex_0.x,
+                         geom_bbox.m_vertex_1.y,
+                         geom_bbox.m_vertex_1.z),
+            geom_bbox.m_vertex_1];
 
         trans_vertices = trans_vertices.iter()
-            .map(|vertex| Instance::transform_vector3(&self.m_forward_matrix, vertex))
+            .map(|vertex| Instance::transform_vector3(forward_matrix, vertex))
             .collect();
 
         let mut min_x = INFINITY;
@@ -169,12 +325,7 @@ impl Boundable for Instance
             if v.y < min_y { min_y = v.y } else if v.y > max_y { max_y = v.y };
             if v.z < min_z { min_z = v.z } else if v.z > max_z { max_z = v.z };
         }
-        self.m_bbox = BBox::new(Vector3::new(min_x, min_y, min_z), Vector3::new(max_x, max_y, max_z))
-    }
-
-    fn get_bbox(&self) -> BBox
-    {
-        self.m_bbox.clone()
+        BBox::new(Vector3::new(min_x, min_y, min_z), Vector3::new(max_x, max_y, max_z))
     }
 }
 
@@ -191,9 +342,9 @@ mod InstanceTest
     use crate::utils::color::Colorf;
     use crate::geometry::Shadable;
     use crate::geometry::triangle::Triangle;
+    use cgmath::Rotation3;
 
     const INV_PI: f32 = 1.0 / PI ;
-    const INV_GAMMA: f32 = 1.0 / 1.8;
 
     #[test]
     pub fn TestTranslate()
@@ -227,6 +378,61 @@ mod InstanceTest
         let triangle = setup_triangle();
     }
 
+    #[test]
+    fn TestMotionBlurHit()
+    {
+        let mut instance = Instance::new(Arc::new(setup_sphere()));
+        instance.set_motion(MotionTransform::one(),
+                            Decomposed { disp: Vector3::new(100.0, 0.0, 0.0), ..MotionTransform::one() },
+                            0.0, 1.0);
+        let mut sr = ShadeRec::get_dummy();
+
+        let hits = |origin_x: f32, ray_time: f32, sr: &mut ShadeRec| {
+            let ray = Ray::new_with_time(Vector3::new(origin_x, 10.0, -100.0), Vector3::unit_z(), ray_time);
+            let mut t = INFINITY;
+            instance.hit(&ray, &mut t, sr).unwrap()
+        };
+        assert!(hits(20.0, 0.0, &mut sr));
+        assert!(!hits(20.0, 1.0, &mut sr));
+        assert!(hits(70.0, 0.5, &mut sr));
+        assert!(hits(120.0, 1.0, &mut sr));
+        assert!(!hits(120.0, 0.0, &mut sr));
+    }
+
+    #[test]
+    fn TestMotionBBox()
+    {
+        let mut instance = Instance::new(Arc::new(setup_sphere()));
+        let static_bbox = instance.get_bbox();
+        instance.set_motion(MotionTransform::one(),
+                            Decomposed { disp: Vector3::new(0.0, -50.0, 0.0), ..MotionTransform::one() },
+                            0.0, 0.02);
+        let motion_bbox = instance.get_bbox();
+
+        assert_relative_eq!(motion_bbox.m_vertex_0.y, static_bbox.m_vertex_0.y - 50.0, epsilon = 1e-4);
+        assert_relative_eq!(motion_bbox.m_vertex_1.y, static_bbox.m_vertex_1.y, epsilon = 1e-4);
+
+        instance.clear_motion();
+        assert_relative_eq!(instance.get_bbox().m_vertex_0.y, static_bbox.m_vertex_0.y, epsilon = 1e-4);
+    }
+
+    #[test]
+    fn TestMotionInterpolation()
+    {
+        let mut instance = Instance::new(Arc::new(setup_sphere()));
+        instance.add_motion_key(2.0, Decomposed { rot: Quaternion::from_angle_z(Deg(90.0)),
+                                                  disp: Vector3::new(0.0, 0.0, 8.0),
+                                                  scale: 3.0 });
+        instance.add_motion_key(0.0, MotionTransform::one());
+
+        let halfway = instance.get_motion_transform(1.0);
+        assert_relative_eq!(halfway.disp, Vector3::new(0.0, 0.0, 4.0));
+        assert_relative_eq!(halfway.scale, 2.0);
+        assert_relative_eq!(halfway.rot, Quaternion::from_angle_z(Deg(45.0)), epsilon = 1e-6);
+        // Hold the last keyframe after the shutter closes
+        assert_relative_eq!(instance.get_motion_transform(5.0).scale, 3.0);
+    }
+
     fn setup_sphere() -> Sphere
     {
         let mut sphere = Sphere::new(10.0, Vector3::new(20.0, 10.0, 30.0));
@@ -13,11 +13,12 @@ use obj::Obj;
 use crate::world::shaderec::ShadeRec;
 use crate::ray::Ray;
 use crate::geometry::trimesh::TriMesh;
-use crate::material::Material;
+use crate::material::{Material, hash_material};
 use crate::utils::color::Colorf;
 use crate::geometry::{Boundable, Geometry, GeomError, BoundedConcrete, Shadable};
 use crate::geometry::bbox::BBox;
 use std::ptr::null;
+use crate::utils::scenehash::SceneHasher;
 
 // TODO: Fix fatal bugs in this implementation
 /// KDTree is implemented for accelerating ray tracing. The implementation takes reference from
@@ -618,6 +619,14 @@ impl<T> Geometry for KDTree<T> where T: BoundedConcrete + Clone
         }
 
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("kdtree");
+        hasher.write_u64(self.m_primitives.len() as u64);
+        self.m_primitives.iter().for_each(|primitive| primitive.hash_scene(hasher));
+        hash_material(&self.m_material, hasher);
+    }
 }
 
 impl<T> Shadable for KDTree<T> where T: BoundedConcrete + Clone
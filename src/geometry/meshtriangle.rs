@@ -14,6 +14,7 @@ use crate::geometry::triangle::Triangle;
 use crate::geometry::bbox::BBox;
 use std::f32::INFINITY;
 use crate::geometry::trimesh::TriMesh;
+use crate::utils::scenehash::SceneHasher;
 
 
 // Stores the indices of the face only
@@ -104,8 +105,20 @@ impl Geometry for MeshTriangle
         shaderecord.m_normal = self.interpolate_normal(solution.x, solution.y, normal0, normal1, normal2);
         *time = solution.z;
         shaderecord.m_hitpoint = incomeray.m_origin + solution.z * incomeray.m_direction;
+        shaderecord.m_uv = Vector2::new(solution.x, solution.y);
         Ok(true)
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        // The material is the one of the mesh
+        hasher.write_str("mesh_triangle");
+        for axis in 0..3
+        {
+            hasher.write_vector3(self.m_mesh_ptr.m_vertex_position[self.m_vertex_index[axis]]);
+            hasher.write_vector3(self.m_mesh_ptr.m_normals[self.m_vertex_index[axis]]);
+        }
+    }
 }
 
 impl Shadable for MeshTriangle
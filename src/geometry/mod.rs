@@ -18,6 +18,7 @@ use crate::material::Material;
 use crate::utils::colorconstant::COLOR_BLACK;
 use crate::geometry::bbox::BBox;
 use crate::world::shaderec::ShadeRec;
+use crate::utils::scenehash::SceneHasher;
 
 pub const KEPSILON: f32 = 0.0001;
 /// This trait
@@ -46,6 +47,8 @@ impl fmt::Display for GeomError
 pub trait Geometry: fmt::Debug + Send + Sync
 {
     fn hit(&self, incomeray: &Ray, time: &mut f32, shaderecord: &mut ShadeRec) -> Result<bool, GeomError>;
+    /// Write the shape, placement and material of the object to the hash identifying the scene
+    fn hash_scene(&self, hasher: &mut SceneHasher);
 }
 
 pub trait Boundable
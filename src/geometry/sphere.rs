@@ -1,6 +1,6 @@
 use std::{f32};
 use cgmath::prelude::*;
-use cgmath::{Vector3, dot};
+use cgmath::{Vector2, Vector3, dot};
 
 use crate::geometry::{Geometry, KEPSILON, Shadable, GeomError, Boundable};
 use crate::ray::Ray;
@@ -8,10 +8,11 @@ use crate::world::shaderec::ShadeRec;
 use crate::math::polynomial::*;
 use std::fmt;
 use crate::utils::color::Colorf;
-use crate::material::Material;
+use crate::material::{Material, hash_material};
 use std::sync::Arc;
 use crate::geometry::bbox::BBox;
-use std::f32::consts::SQRT_2;
+use std::f32::consts::{PI, SQRT_2};
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Clone)]
 pub struct Sphere
@@ -74,6 +75,7 @@ impl Geometry for Sphere
                 {
                     //c_updateShadeRecNormal(time);
                     shaderecord.m_normal = (temp + *time * incomeray.m_direction).normalize();
+                    shaderecord.m_uv = get_sphere_uv(shaderecord.m_normal);
                     *tmin = *time;
                     res = true;
                     break;
@@ -82,6 +84,22 @@ impl Geometry for Sphere
         }
         Ok(res)
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("sphere");
+        hasher.write_f32(self.m_radius);
+        hasher.write_vector3(self.m_center);
+        hash_material(&self.m_material, hasher);
+    }
+}
+
+/// Longitude and latitude of a point of the unit sphere, within [0, 1]; v is 1 at the north pole
+fn get_sphere_uv(normal: Vector3<f32>) -> Vector2<f32>
+{
+    let phi = normal.x.atan2(normal.z);
+    let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+    Vector2::new(phi / (2.0 * PI), 1.0 - normal.y.clamp(-1.0, 1.0).acos() / PI)
 }
 
 impl Boundable for Sphere
@@ -120,7 +138,6 @@ impl Shadable for Sphere
                 if *root > KEPSILON
                 {
                     *tmin = *root;
-                    println!("shadow hit!");
                     return true;
                 }
             }
@@ -138,7 +155,6 @@ mod TestSphere
     use crate::utils::colorconstant::COLOR_RED;
 
     const INV_PI: f32 = 1.0 / PI ;
-    const INV_GAMMA: f32 = 1.0 / 1.8;
 
     #[test]
     pub fn TestSphereBBox()
@@ -1,14 +1,15 @@
-use cgmath::{Vector3, Zero, ElementWise, Matrix3, Transform, InnerSpace, SquareMatrix};
+use cgmath::{Vector2, Vector3, Zero, ElementWise, Matrix3, Transform, InnerSpace, SquareMatrix};
 use crate::geometry::{Geometry, KEPSILON, Shadable, GeomError, Boundable};
 use crate::utils::color::Colorf;
 use std::sync::Arc;
 use crate::world::shaderec::ShadeRec;
-use crate::material::Material;
+use crate::material::{Material, hash_material};
 use crate::ray::Ray;
 use std::fmt;
 use crate::utils::colorconstant::COLOR_BLACK;
 use crate::geometry::bbox::BBox;
 use crate::math::float_cmp::{max, min};
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Clone)]
 pub struct Triangle
@@ -123,10 +124,23 @@ impl Geometry for Triangle
         {
             *time = solution.z;
             shaderecord.m_hitpoint = incomeray.m_origin + solution.z * incomeray.m_direction;
+            shaderecord.m_uv = Vector2::new(solution.x, solution.y);
             return Ok(true);
         }
         Ok(false)
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("triangle");
+        for vertex in [self.m_vertex_0, self.m_vertex_1, self.m_vertex_2].iter()
+        {
+            hasher.write_vector3(*vertex);
+        }
+        hasher.write_u64(self.m_normals.len() as u64);
+        self.m_normals.iter().for_each(|normal| hasher.write_vector3(*normal));
+        hash_material(&self.m_material, hasher);
+    }
 }
 
 impl Boundable for Triangle
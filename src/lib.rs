@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 
+pub mod animation;
 pub mod brdf;
 pub mod render;
 //pub mod gallery;
@@ -4,6 +4,7 @@ use crate::light::Light;
 use crate::utils::color::Colorf;
 use crate::world::shaderec::ShadeRec;
 use crate::ray::Ray;
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Debug)]
 pub struct Ambient
@@ -50,4 +51,11 @@ impl Light for Ambient
     }
 
     fn get_type(&self) -> String { String::from("Ambient")}
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("ambient");
+        hasher.write_f32(self.m_ls);
+        hasher.write_color(self.m_color);
+    }
 }
\ No newline at end of file
@@ -1,5 +1,4 @@
 use cgmath::{Vector3, InnerSpace, ElementWise};
-use std::sync::{Arc, RwLock};
 use std::f32::INFINITY;
 use std::cell::RefCell;
 
@@ -7,43 +6,42 @@ use crate::light::Light;
 use crate::utils::color::Colorf;
 use crate::world::shaderec::ShadeRec;
 use crate::ray::Ray;
-use crate::sampler::Sampler;
+use crate::sampler::map_square_to_hemisphere;
+use crate::sampler::pixelsampler::get_2d;
 use crate::utils::colorconstant::COLOR_BLACK;
+use crate::utils::scenehash::SceneHasher;
 
 pub struct AmbientOccluder
 {
-    pub m_u: RwLock<Vector3<f32>>,
-    pub m_v: RwLock<Vector3<f32>>,
-    pub m_w: RwLock<Vector3<f32>>,
     m_color: Colorf,
     m_ls: f32,
     pub m_min_color: Colorf,
-    m_samplerptr: Arc<dyn Sampler>,
 }
 
 impl AmbientOccluder
 {
-    pub fn new(min_color: Colorf, ls: f32, samplerptr: Arc<dyn Sampler>) -> AmbientOccluder
+    pub fn new(min_color: Colorf, ls: f32) -> AmbientOccluder
     {
         AmbientOccluder
         {
-            m_u: RwLock::new(Vector3::unit_x()),
-            m_v: RwLock::new(Vector3::unit_y()),
-            m_w: RwLock::new(Vector3::unit_z()),
             m_color: COLOR_BLACK,
             m_ls: ls,
             m_min_color: min_color,
-            m_samplerptr: samplerptr,
         }
     }
-    pub fn set_sampler(&mut self, sampler: Arc<dyn Sampler>)
+    pub fn set_color(&mut self, color: Colorf)
     {
-        self.m_samplerptr = sampler;
+        self.m_color = color;
     }
 
-    pub fn set_color(&mut self, color: Colorf)
+    /// Orthonormal basis around the normal. It is computed for every shading point rather than
+    /// stored in the light, which the render threads share.
+    fn get_basis(normal: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>, Vector3<f32>)
     {
-        self.m_color = color;
+        let jittered_up = Vector3::new(0.00031, 1.0, 0.00021).normalize();
+        let v = normal.cross(jittered_up).normalize();
+        let u = v.cross(normal).normalize();
+        (u, v, normal)
     }
 }
 
@@ -51,22 +49,15 @@ impl Light for AmbientOccluder
 {
     fn get_direction(&self, sr: &ShadeRec) -> Vector3<f32>
     {
-        let sample = self.m_samplerptr.as_ref().get_hemisphere_sample();
-        let result = (self.m_u.read().unwrap().mul_element_wise(sample.x)
-            + self.m_v.read().unwrap().mul_element_wise(sample.y )
-            + self.m_w.read().unwrap().mul_element_wise(sample.z )).normalize();
-        //println!("{}, {}, {}", result.x, result.y, result.z);
-        result
+        // Cosine distributed directions around the normal, drawn from the thread sampler
+        let (u, v, w) = AmbientOccluder::get_basis(sr.m_normal);
+        let sample = map_square_to_hemisphere(get_2d(), 1.0);
+        (u.mul_element_wise(sample.x) + v.mul_element_wise(sample.y) + w.mul_element_wise(sample.z)).normalize()
     }
 
     fn L(&self, sr: &ShadeRec) -> Colorf
     {
-        *self.m_w.write().unwrap() = sr.m_normal;
-        let jittered_up = Vector3::new(0.00031, 1.0, 0.00021).normalize();
-        *self.m_v.write().unwrap() = self.m_w.read().unwrap().cross(jittered_up).normalize();
-        *self.m_u.write().unwrap() = self.m_v.read().unwrap().cross(*self.m_w.read().unwrap()).normalize();
-
-        let shadow_ray = Ray::new(sr.m_hitpoint, self.get_direction(sr));
+        let shadow_ray = Ray::new_with_time(sr.m_hitpoint, self.get_direction(sr), sr.m_ray.m_time);
         if self.is_in_shadow(sr, &shadow_ray)
         {
             return self.m_color * self.m_min_color * self.m_ls;
@@ -95,4 +86,12 @@ impl Light for AmbientOccluder
     {
         String::from("AmbientOccluder")
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("ambient_occluder");
+        hasher.write_f32(self.m_ls);
+        hasher.write_color(self.m_color);
+        hasher.write_color(self.m_min_color);
+    }
 }
\ No newline at end of file
@@ -8,6 +8,8 @@ use crate::ray::Ray;
 use std::f32::INFINITY;
 use crate::material::emissive::Emissive;
 use crate::utils::colorconstant::COLOR_BLACK;
+use crate::material::Material;
+use crate::utils::scenehash::SceneHasher;
 
 pub struct AreaLight
 {
@@ -74,4 +76,11 @@ impl Light for AreaLight
     }
 
     fn get_type(&self) -> String { String::from("AreaLight") }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("area");
+        self.m_materialptr.hash_scene(hasher);
+        self.m_geomobject.hash_scene(hasher);
+    }
 }
\ No newline at end of file
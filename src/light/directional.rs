@@ -3,6 +3,7 @@ use crate::light::Light;
 use crate::utils::color::Colorf;
 use crate::world::shaderec::ShadeRec;
 use crate::ray::Ray;
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Debug)]
 pub struct Directional
@@ -29,6 +30,11 @@ impl Directional
     {
         self.m_color = newcolor;
     }
+
+    pub fn set_direction(&mut self, newdirection: Vector3<f32>)
+    {
+        self.m_direction = newdirection.normalize();
+    }
 }
 
 impl Light for Directional
@@ -49,4 +55,12 @@ impl Light for Directional
     {
         true
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("directional");
+        hasher.write_f32(self.m_ls);
+        hasher.write_color(self.m_color);
+        hasher.write_vector3(self.m_direction);
+    }
 }
\ No newline at end of file
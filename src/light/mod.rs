@@ -7,6 +7,7 @@ use crate::world::shaderec::ShadeRec;
 use crate::utils::color::Colorf;
 use std::fmt::{Debug, Formatter, Error};
 use crate::ray::Ray;
+use crate::utils::scenehash::SceneHasher;
 
 pub mod directional;
 pub mod pointlight;
@@ -19,6 +20,8 @@ pub trait Light: Send + Sync
     fn does_cast_shadow(&self) -> bool;
     fn is_in_shadow(&self, sr: &ShadeRec, ray: &Ray) -> bool;
     fn get_type(&self) -> String {  String::from("") }
+    /// Write what the light emits, and from where, to the hash identifying the scene
+    fn hash_scene(&self, hasher: &mut SceneHasher);
 }
 
 impl Debug for dyn Light
@@ -6,6 +6,7 @@ use crate::utils::color::Colorf;
 use crate::world::shaderec::ShadeRec;
 use crate::cgmath::InnerSpace;
 use crate::ray::Ray;
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Debug)]
 pub struct PointLight
@@ -31,6 +32,11 @@ impl PointLight
     {
         self.m_color = newcolor;
     }
+
+    pub fn set_location(&mut self, newlocation: Vector3<f32>)
+    {
+        self.m_location = newlocation;
+    }
 }
 
 impl Light for PointLight
@@ -62,4 +68,12 @@ impl Light for PointLight
         }
         false
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("point");
+        hasher.write_f32(self.m_ls);
+        hasher.write_color(self.m_color);
+        hasher.write_vector3(self.m_location);
+    }
 }
\ No newline at end of file
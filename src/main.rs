@@ -5,6 +5,7 @@ use std::rc::Rc;
 use std::f32::INFINITY;
 use std::fs::File;
 use std::io::BufReader;
+use std::net::TcpListener;
 use obj::{Obj};
 
 use raytracer::utils::color::Colorf;
@@ -16,6 +17,10 @@ use raytracer::geometry::sphere::Sphere;
 use raytracer::ray::Ray;
 use raytracer::world::shaderec::ShadeRec;
 use raytracer::output::imagewriter::ImageWriter;
+use raytracer::output::exrwriter::{ExrWriter, ExrPixelType};
+use raytracer::output::postprocess::{PostProcessing, BloomEffect, ChromaticAberrationEffect, VignetteEffect, FilmGrainEffect};
+use raytracer::output::terminalpreview::TerminalPreview;
+use raytracer::output::tonemap::{ToneMapping, FilmicToneMapper};
 use raytracer::output::OutputManager;
 use raytracer::material::phong::Phong;
 use raytracer::material::glossyreflector::GlossyReflector;
@@ -37,12 +42,28 @@ use rand::rngs::ThreadRng;
 use rand::{thread_rng, Rng};
 use raytracer::render::cam::pinhole::Pinhole;
 use raytracer::render::cam::Camera;
+use raytracer::render::aov::Aov;
+use raytracer::render::denoise::Denoiser;
 use raytracer::render::renderbuffer::RenderBuffer;
+use raytracer::render::server::RenderServer;
+use raytracer::render::filter::mitchell::MitchellFilter;
+use raytracer::render::tileorder::TileOrder;
 use raytracer::utils::multithread::MultiThread;
 use std::time::Instant;
 
 fn main()
 {
+    // raytracer serve [address]: render the scene files submitted over HTTP, see RenderServer
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(|arg| arg.as_str()) == Some("serve")
+    {
+        let address = args.get(2).map(|arg| arg.as_str()).unwrap_or("127.0.0.1:7878");
+        let listener = TcpListener::bind(address).expect("Can't listen on the address");
+        println!("Render server listening on {}", address);
+        RenderServer::new(3).serve(&listener).unwrap();
+        return;
+    }
+
     let sampler = MultiJittered::new(32, 3);
     let sampler_ptr = Arc::new(sampler);
     let mut boxed_vp = Box::new(ViewPlane::new(sampler_ptr.clone()));
@@ -51,9 +72,9 @@ fn main()
     boxed_vp.m_hres = vp_hres;
     boxed_vp.m_vres = vp_vres;
     boxed_vp.m_pixsize = 0.5;
-    boxed_vp.set_gamma(1.8);
 
     let mut imgwriter = ImageWriter::new("8_multhreading.jpg", vp_hres as usize, vp_vres as usize);
+    imgwriter.set_tone_mapping(ToneMapping::new(Arc::new(FilmicToneMapper::default())));
     let mut world = World::new(boxed_vp, "whitted");
 
     let mut sphere = Arc::new(Mutex::new(Sphere::new(10.0,
@@ -94,24 +115,21 @@ fn main()
         .map(|x| setUpMaterial(rng.gen_range(0.0, 1.0) * (*x) as f32,
                                rng.gen_range(0.0, 1.0) * (*x) as f32,
                                rng.gen_range(0.0, 1.0) * (*x) as f32,
-                               "matte",
-                               sampler_ptr.clone()))
+                               "matte"))
         .collect::<Vec<Arc<dyn Material>>>();
 
     let phong_materials: Vec<Arc<dyn Material>> = (0..4).collect::<Vec<_>>().iter()
         .map(|x| setUpMaterial(rng.gen_range(0.0, 1.0)* (*x) as f32,
                                rng.gen_range(0.0, 1.0) * (*x) as f32,
                                rng.gen_range(0.0, 1.0)* (*x) as f32,
-                               "phong",
-                        sampler_ptr.clone()))
+                               "phong"))
         .collect::<Vec<Arc<dyn Material>>>();
 
     let glossy_materials: Vec<Arc<dyn Material>> = (0..4).collect::<Vec<_>>().iter()
         .map(|x| setUpMaterial(rng.gen_range(0.0, 1.0)* (*x) as f32,
                                rng.gen_range(0.0, 1.0) * (*x) as f32,
                                rng.gen_range(0.0, 1.0)* (*x) as f32,
-                               "glossy",
-                                sampler_ptr.clone()))
+                               "glossy"))
         .collect::<Vec<Arc<dyn Material>>>();
 
     let mut rand_uint = 0 as u8;
@@ -131,27 +149,95 @@ fn main()
     setUpLights(&mut world);
     let mut ph = setUpCamera();
     let worldptr = Arc::new(world);
-    let mut buffer = RenderBuffer::new((800, 600), (100, 100));
     let n_thread = 3;
     let mut multithread = MultiThread::new(n_thread);
+    // Optional features of the demo, all off by default:
+    // --mitchell: Mitchell-Netravali reconstruction filter instead of the default one
+    // --hilbert, --work-stealing: tile order and scheduling of the threads
+    // --post: bloom, chromatic aberration, vignette and film grain
+    // --denoise: also write a denoised image, from the depth, normal and albedo AOVs
+    // --exr: also write the image and its AOVs to an EXR file
+    let has_flag = |flag: &str| args.iter().any(|arg| arg == flag);
+    let tile_dim = multithread.get_tile_dim((800, 600));
+    let mut buffer = if has_flag("--mitchell")
+    {
+        RenderBuffer::new_with_filter((800, 600), tile_dim, Arc::new(MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)))
+    }
+    else
+    {
+        RenderBuffer::new((800, 600), tile_dim)
+    };
+    if has_flag("--hilbert")
+    {
+        buffer.set_tile_order(TileOrder::Hilbert);
+    }
+    buffer.set_work_stealing(has_flag("--work-stealing"));
+    let mut aovs = Vec::new();
+    if has_flag("--denoise") || has_flag("--exr")
+    {
+        aovs.extend_from_slice(&[Aov::Depth, Aov::Normal, Aov::Albedo]);
+    }
+    if has_flag("--exr")
+    {
+        aovs.push(Aov::ObjectId);
+    }
+    buffer.set_aovs(&aovs);
+    if has_flag("--post")
+    {
+        let mut post_processing = PostProcessing::new();
+        post_processing.add_effect(Arc::new(BloomEffect::new(1.0, 0.3)));
+        post_processing.add_effect(Arc::new(ChromaticAberrationEffect::new(0.003)));
+        post_processing.add_effect(Arc::new(VignetteEffect::new(0.6)));
+        post_processing.add_effect(Arc::new(FilmGrainEffect::new(0.03, 0)));
+        buffer.set_post_processing(post_processing);
+    }
 
     let start_time = Instant::now();
     // print!("{}", bufferiter.m_queue.len());
 
-    multithread.render_to_buffer(worldptr, &ph, &buffer);
+    // raytracer --preview: draw the render in the terminal while it progresses
+    if has_flag("--preview")
+    {
+        let control = multithread.get_render_control().clone();
+        std::thread::scope(|scope|
+            {
+                scope.spawn(|| TerminalPreview::from_environment().watch(&buffer, &control, &mut std::io::stdout()));
+                multithread.render_to_buffer(worldptr, &ph, &buffer);
+            });
+    }
+    else
+    {
+        multithread.render_to_buffer(worldptr, &ph, &buffer);
+    }
     println!("{} threads used", multithread.get_thread_count());
     let duration = Instant::now() - start_time;
     println!("{} nanoseconds using {} threads...", duration.as_nanos(), n_thread);
 
     buffer.write(&mut imgwriter);
     imgwriter.output();
+
+    if has_flag("--denoise")
+    {
+        let mut denoisedwriter = ImageWriter::new("8_multhreading_denoised.jpg", vp_hres as usize, vp_vres as usize);
+        denoisedwriter.set_tone_mapping(imgwriter.get_tone_mapping().clone());
+        Denoiser::default().write(&buffer, &mut denoisedwriter);
+        denoisedwriter.output();
+    }
+
+    if has_flag("--exr")
+    {
+        let mut exrwriter = ExrWriter::new("8_multhreading.exr", vp_hres as usize, vp_vres as usize, ExrPixelType::Half);
+        buffer.write(&mut exrwriter);
+        buffer.write_aovs_to_exr(&mut exrwriter);
+        exrwriter.output();
+    }
 }
 
-fn setUpMaterial(r: f32, g: f32, b: f32, material_type: &str, sampler: Arc<dyn Sampler>) -> Arc<dyn Material>
+fn setUpMaterial(r: f32, g: f32, b: f32, material_type: &str) -> Arc<dyn Material>
 {
     let color = Colorf::new(r, g, b);
     let random_lambertian = Arc::new(Lambertian::new(0.5*g, color.clone()));
-    let glossy = Arc::new(GlossySpecular::new(r, color.clone(), sampler));
+    let glossy = Arc::new(GlossySpecular::new(r, color.clone()));
     let phong = Arc::new(Phong::new(random_lambertian.clone(), random_lambertian.clone(),
                                     glossy.clone()));
 
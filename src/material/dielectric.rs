@@ -13,6 +13,7 @@ use crate::brdf::{BRDF,
                   fresnelreflector::FresnelReflector,
                   fresneltransmitter::FresnelTransmitter,
                   Transmitter};
+use crate::utils::scenehash::SceneHasher;
 
 /// Calls FresnelReflector::sample_func, FresnelTransmitter::total_internal_reflection
 /// and FresnelTransmitter::sample_func
@@ -53,7 +54,7 @@ impl Material for Dielectric
         let mut w_o = -sr.m_ray.m_direction;
         let mut dummy = 0.0_f32;
         let fr = BRDF::sample_func(self.m_fresnel_brdf.as_ref(), sr, &mut w_i, &mut w_o, &mut dummy);
-        let reflected_ray = Ray::new(sr.m_hitpoint, w_i);
+        let reflected_ray = Ray::new_with_time(sr.m_hitpoint, w_i, sr.m_ray.m_time);
 
         let mut t = INFINITY;
         let mut clr_reflected = COLOR_BLACK;
@@ -80,7 +81,7 @@ impl Material for Dielectric
             // no total internal reflection
             let mut w_t = Vector3::zero();
             let ft = Transmitter::sampleFunc(self.m_fresnel_btdf.as_ref(), sr, &mut w_o, &mut w_t);
-            let transmittted_ray = Ray::new(sr.m_hitpoint, w_t);
+            let transmittted_ray = Ray::new_with_time(sr.m_hitpoint, w_t, sr.m_ray.m_time);
             let n_dot_w_t = sr.m_normal.dot(w_t);
 
             if n_dot_w_t < 0.0
@@ -110,4 +111,19 @@ impl Material for Dielectric
     fn area_light_shade(&self, sr: &mut ShadeRec) -> Colorf {
         unimplemented!()
     }
+
+    fn get_albedo(&self, sr: &ShadeRec) -> Colorf
+    {
+        self.m_phong.get_albedo(sr)
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("dielectric");
+        self.m_phong.hash_scene(hasher);
+        hasher.write_color(self.m_color_filter_in);
+        hasher.write_color(self.m_color_filter_out);
+        self.m_fresnel_brdf.hash_scene(hasher);
+        self.m_fresnel_btdf.hash_scene(hasher);
+    }
 }
\ No newline at end of file
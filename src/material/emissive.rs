@@ -4,6 +4,7 @@ use crate::material::Material;
 use crate::world::shaderec::ShadeRec;
 use crate::utils::color::Colorf;
 use crate::utils::colorconstant::COLOR_BLACK;
+use crate::utils::scenehash::SceneHasher;
 
 pub struct Emissive
 {
@@ -54,4 +55,16 @@ impl Material for Emissive
         }
         else { return COLOR_BLACK }
     }
+
+    fn get_albedo(&self, _sr: &ShadeRec) -> Colorf
+    {
+        self.m_color
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("emissive");
+        hasher.write_f32(self.m_ls);
+        hasher.write_color(self.m_color);
+    }
 }
\ No newline at end of file
@@ -9,6 +9,7 @@ use crate::material::phong::Phong;
 use crate::tracer::Tracer;
 use crate::brdf::{BRDF,
                   glossyspec::GlossySpecular};
+use crate::utils::scenehash::SceneHasher;
 
 pub struct GlossyReflector
 {
@@ -37,7 +38,7 @@ impl Material for GlossyReflector
         let mut pdf = 0.0_f32;
 
         let fr = self.m_glossyspec_brdf.sample_func(sr, &mut w_i, &mut w_o, &mut pdf);
-        let reflected_ray = Ray::new(sr.m_hitpoint, w_i);
+        let reflected_ray = Ray::new_with_time(sr.m_hitpoint, w_i, sr.m_ray.m_time);
 
         clr += fr * sr.m_worldptr.m_tracer.trace_ray(sr.m_worldptr.clone(), &reflected_ray, sr.m_depth +1)
             * sr.m_normal.normalize().dot(w_i) / pdf;
@@ -52,10 +53,22 @@ impl Material for GlossyReflector
         let mut pdf = 0.0_f32;
 
         let fr = self.m_glossyspec_brdf.sample_func(sr, &mut w_i, &mut w_o, &mut pdf);
-        let reflected_ray = Ray::new(sr.m_hitpoint, w_i);
+        let reflected_ray = Ray::new_with_time(sr.m_hitpoint, w_i, sr.m_ray.m_time);
 
         clr += fr * sr.m_worldptr.clone().m_tracer.trace_ray(sr.m_worldptr.clone(), &reflected_ray, sr.m_depth +1)
             * sr.m_normal.dot(w_i) / pdf;
         clr
     }
+
+    fn get_albedo(&self, sr: &ShadeRec) -> Colorf
+    {
+        self.m_phong.get_albedo(sr)
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("glossy_reflector");
+        self.m_phong.hash_scene(hasher);
+        self.m_glossyspec_brdf.hash_scene(hasher);
+    }
 }
\ No newline at end of file
@@ -7,6 +7,7 @@ use crate::material::Material;
 use crate::utils::color::Colorf;
 use crate::brdf::BRDF;
 use crate::ray::Ray;
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Clone, Debug)]
 pub struct Matte
@@ -56,7 +57,7 @@ impl Material for Matte
                 let mut in_shadow = false;
                 if worldptr.m_lights[i].does_cast_shadow()
                 {
-                    let shadow_ray = Ray::new(sr.m_hitpoint, w_i);
+                    let shadow_ray = Ray::new_with_time(sr.m_hitpoint, w_i, sr.m_ray.m_time);
                     in_shadow = worldptr.m_lights[i].is_in_shadow(sr, &shadow_ray);
                 }
                 if !in_shadow
@@ -82,13 +83,12 @@ impl Material for Matte
 
             w_i = light.get_direction(sr);
             n_dot_w_i = sr.m_normal.normalize().dot(w_i);
-            println!("n_dot_w_i{}", n_dot_w_i);
             if n_dot_w_i < 0.0 { continue; }
 
             let mut in_shadow = true;
             if light.does_cast_shadow()
             {
-                let shadow_ray= Ray::new(sr.m_hitpoint, w_i);
+                let shadow_ray= Ray::new_with_time(sr.m_hitpoint, w_i, sr.m_ray.m_time);
                 in_shadow = light.is_in_shadow(sr, &shadow_ray);
             }
 
@@ -103,4 +103,16 @@ impl Material for Matte
         fn pathShade<'a>(&self, sr: &'a mut ShadeRec);
 
      */
+
+    fn get_albedo(&self, sr: &ShadeRec) -> Colorf
+    {
+        self.m_diffuse_brdf.rho(sr, -sr.m_ray.m_direction.normalize())
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("matte");
+        self.m_ambient_brdf.hash_scene(hasher);
+        self.m_diffuse_brdf.hash_scene(hasher);
+    }
 }
@@ -7,14 +7,30 @@ pub mod phong;
 
 use std::fmt;
 use std::error::Error;
+use std::sync::Arc;
 
 use crate::world::shaderec::ShadeRec;
 use crate::utils::color::Colorf;
+use crate::utils::scenehash::SceneHasher;
 
 pub trait Material: Send + Sync
 {
     fn shade(&self, sr: &mut ShadeRec) -> Colorf;
     fn area_light_shade(&self, sr: &mut ShadeRec) -> Colorf;
+    /// Diffuse reflectance at the hit, for the albedo render pass
+    fn get_albedo(&self, sr: &ShadeRec) -> Colorf;
+    /// Write the parameters of the material and of its BRDFs to the hash identifying the scene
+    fn hash_scene(&self, hasher: &mut SceneHasher);
+}
+
+/// Objects without a material yet hash as such
+pub fn hash_material(material: &Option<Arc<dyn Material>>, hasher: &mut SceneHasher)
+{
+    match material
+    {
+        Some(material) => material.hash_scene(hasher),
+        None => hasher.write_str("no material"),
+    }
 }
 
 impl fmt::Debug for dyn Material
@@ -8,6 +8,7 @@ use crate::material::Material;
 use crate::brdf::{BRDF,
                   lambertian::Lambertian,
                   glossyspec::GlossySpecular};
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Clone, Debug)]
 pub struct Phong
@@ -50,13 +51,12 @@ impl Material for Phong
 
             w_i = light.get_direction(sr);
             n_dot_w_i = sr.m_normal.normalize().dot(w_i);
-            println!("n_dot_w_i{}", n_dot_w_i);
             if n_dot_w_i < 0.0 { continue; }
 
             let mut in_shadow = true;
             if light.does_cast_shadow()
             {
-                let shadow_ray = Ray::new(sr.m_hitpoint, w_i);
+                let shadow_ray = Ray::new_with_time(sr.m_hitpoint, w_i, sr.m_ray.m_time);
                 in_shadow = light.is_in_shadow(sr, &shadow_ray);
             }
 
@@ -73,4 +73,17 @@ impl Material for Phong
     fn area_light_shade(&self, sr: &mut ShadeRec) -> Colorf {
         unimplemented!()
     }
+
+    fn get_albedo(&self, sr: &ShadeRec) -> Colorf
+    {
+        self.m_diffuse_brdf.rho(sr, -sr.m_ray.m_direction.normalize())
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("phong");
+        self.m_ambient_brdf.hash_scene(hasher);
+        self.m_diffuse_brdf.hash_scene(hasher);
+        self.m_spec_brdf.hash_scene(hasher);
+    }
 }
\ No newline at end of file
@@ -9,6 +9,7 @@ use crate::material::phong::Phong;
 use crate::tracer::Tracer;
 use crate::brdf::{BRDF,
                   perfectspec::PerfectSpecular};
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Clone, Debug)]
 pub struct Reflective
@@ -41,7 +42,7 @@ impl Material for Reflective
         let mut w_i = Vector3::zero();
         let mut dummy = 0.0;
         let f_reflect = self.m_reflective_brdf.sample_func(sr, &mut w_i, &mut w_o, &mut dummy);
-        let reflected_ray = Ray::new(sr.m_hitpoint, w_i);
+        let reflected_ray = Ray::new_with_time(sr.m_hitpoint, w_i, sr.m_ray.m_time);
 
         // TODO: Holy crap we are using the tracer of world here!!!
         clr += f_reflect * sr.m_worldptr.as_ref().m_tracer.trace_ray(sr.m_worldptr.clone(), &reflected_ray, sr.m_depth +1)
@@ -53,4 +54,16 @@ impl Material for Reflective
     {
         unimplemented!()
     }
+
+    fn get_albedo(&self, sr: &ShadeRec) -> Colorf
+    {
+        self.m_phong.get_albedo(sr)
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("reflective");
+        self.m_phong.hash_scene(hasher);
+        self.m_reflective_brdf.hash_scene(hasher);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+use crate::output::OutputManager;
+use crate::utils::color::Colorf;
+
+/// How the values of the channels are stored in an OpenEXR file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrPixelType
+{
+    /// 16-bit floats, up to 65504
+    Half,
+    Float,
+}
+
+impl ExrPixelType
+{
+    fn get_id(&self) -> i32
+    {
+        match self
+        {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        }
+    }
+
+    fn get_size(&self) -> usize
+    {
+        match self
+        {
+            ExrPixelType::Half => 2,
+            ExrPixelType::Float => 4,
+        }
+    }
+}
+
+/// Writes linear colors to an uncompressed scanline OpenEXR image (.exr). Besides R, G and B,
+/// any number of named channels can be added, e.g. "depth" or "normal.X".
+pub struct ExrWriter<'a>
+{
+    pub m_imgpath: &'a str,
+    m_imgresolution: (usize, usize),
+    m_pixel_type: ExrPixelType,
+    // EXR wants the channels sorted by name
+    m_channels: BTreeMap<String, Vec<f32>>,
+}
+
+impl ExrWriter<'_>
+{
+    pub fn new(imgpath: &str, width: usize, height: usize, pixel_type: ExrPixelType) -> ExrWriter<'_>
+    {
+        let mut writer = ExrWriter
+        {
+            m_imgpath: imgpath,
+            m_imgresolution: (width, height),
+            m_pixel_type: pixel_type,
+            m_channels: BTreeMap::new(),
+        };
+        for name in ["R", "G", "B"].iter()
+        {
+            writer.add_channel(name);
+        }
+        writer
+    }
+
+    pub fn get_pixel_type(&self) -> ExrPixelType
+    {
+        self.m_pixel_type
+    }
+
+    /// Add a channel, black everywhere. Adding an existing channel leaves it as is.
+    pub fn add_channel(&mut self, name: &str)
+    {
+        if name.is_empty() || name.len() > 255 || name.contains('\0')
+        {
+            panic!("Invalid EXR channel name {:?}", name);
+        }
+        let pixel_count = self.m_imgresolution.0 * self.m_imgresolution.1;
+        self.m_channels.entry(name.to_string()).or_insert_with(|| vec![0.0; pixel_count]);
+    }
+
+    pub fn get_channel_names(&self) -> Vec<&str>
+    {
+        self.m_channels.keys().map(|name| name.as_str()).collect()
+    }
+
+    pub fn write_channel(&mut self, name: &str, x: usize, y: usize, value: f32)
+    {
+        let width = self.m_imgresolution.0;
+        match self.m_channels.get_mut(name)
+        {
+            Some(values) => values[x + y * width] = value,
+            None => panic!("No EXR channel named {:?}", name),
+        }
+    }
+
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        let (width, height) = self.m_imgresolution;
+        let header = self.get_header();
+        let line_size = width * self.m_channels.len() * self.m_pixel_type.get_size();
+
+        out.write_all(&header)?;
+        // One scanline per block when uncompressed; the offsets are from the start of the file
+        let first_block = header.len() + 8 * height;
+        for y in 0..height
+        {
+            out.write_all(&((first_block + y * (8 + line_size)) as u64).to_le_bytes())?;
+        }
+
+        for y in 0..height
+        {
+            out.write_all(&(y as i32).to_le_bytes())?;
+            out.write_all(&(line_size as i32).to_le_bytes())?;
+            for values in self.m_channels.values()
+            {
+                for value in values[y * width..(y + 1) * width].iter()
+                {
+                    match self.m_pixel_type
+                    {
+                        ExrPixelType::Half => out.write_all(&to_half_bits(*value).to_le_bytes())?,
+                        ExrPixelType::Float => out.write_all(&value.to_le_bytes())?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_header(&self) -> Vec<u8>
+    {
+        let mut header = vec![0x76, 0x2f, 0x31, 0x01];
+        // Version 2, with the long names flag if any channel name doesn't fit in 31 bytes
+        let long_names = self.m_channels.keys().any(|name| name.len() > 31);
+        header.extend_from_slice(&(2u32 | if long_names { 0x400 } else { 0 }).to_le_bytes());
+
+        let mut channels = Vec::new();
+        for name in self.m_channels.keys()
+        {
+            channels.extend_from_slice(name.as_bytes());
+            channels.push(0);
+            channels.extend_from_slice(&self.m_pixel_type.get_id().to_le_bytes());
+            // pLinear and reserved bytes, then the x and y sampling
+            channels.extend_from_slice(&[0, 0, 0, 0]);
+            channels.extend_from_slice(&1i32.to_le_bytes());
+            channels.extend_from_slice(&1i32.to_le_bytes());
+        }
+        channels.push(0);
+
+        let mut window = Vec::new();
+        for coord in [0, 0, self.m_imgresolution.0 as i32 - 1, self.m_imgresolution.1 as i32 - 1].iter()
+        {
+            window.extend_from_slice(&coord.to_le_bytes());
+        }
+
+        add_attribute(&mut header, "channels", "chlist", &channels);
+        add_attribute(&mut header, "compression", "compression", &[0]);
+        add_attribute(&mut header, "dataWindow", "box2i", &window);
+        add_attribute(&mut header, "displayWindow", "box2i", &window);
+        add_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        add_attribute(&mut header, "pixelAspectRatio", "float", &1.0f32.to_le_bytes());
+        add_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        add_attribute(&mut header, "screenWindowWidth", "float", &1.0f32.to_le_bytes());
+        header.push(0);
+        header
+    }
+}
+
+fn add_attribute(header: &mut Vec<u8>, name: &str, attribute_type: &str, value: &[u8])
+{
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(attribute_type.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Bits of the 16-bit float nearest to "value", ties to even. Too large values become infinite.
+pub fn to_half_bits(value: f32) -> u16
+{
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff
+    {
+        // Infinity stays infinite, NaN stays NaN
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f
+    {
+        return sign | 0x7c00;
+    }
+
+    // Drop the low bits of the mantissa, rounding to the nearest even value. A carry out of
+    // the mantissa correctly moves to the next exponent, or to infinity.
+    let round = |value: u32, shift: u32| -> u32 {
+        let truncated = value >> shift;
+        let remainder = value & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        if remainder > halfway || (remainder == halfway && truncated & 1 == 1) { truncated + 1 } else { truncated }
+    };
+
+    if half_exponent <= 0
+    {
+        // Subnormal half, with the implicit leading bit of the float made explicit
+        if half_exponent < -10
+        {
+            return sign;
+        }
+        return sign | round(mantissa | 0x80_0000, (14 - half_exponent) as u32) as u16;
+    }
+    sign | round(((half_exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+impl fmt::Debug for ExrWriter<'_>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("ExrWriter")
+            .field("image file destination", &self.m_imgpath)
+            .field("image resolution", &self.m_imgresolution)
+            .field("pixel type", &self.m_pixel_type)
+            .field("channels", &self.get_channel_names())
+            .finish()
+    }
+}
+
+impl OutputManager for ExrWriter<'_>
+{
+    fn get_img_dim(&self) -> (usize, usize)
+    {
+        self.m_imgresolution
+    }
+
+    /// The color is stored linear, without gamma correction
+    fn write_pixel(&mut self, x: usize, y: usize, color: Colorf)
+    {
+        self.write_channel("R", x, y, color.m_r);
+        self.write_channel("G", x, y, color.m_g);
+        self.write_channel("B", x, y, color.m_b);
+    }
+
+    fn output(&mut self)
+    {
+        let mut file = BufWriter::new(File::create(self.m_imgpath).unwrap());
+        self.write_to(&mut file).unwrap();
+        file.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod ExrWriterTest
+{
+    use super::*;
+
+    fn read_i32(bytes: &[u8], at: usize) -> i32
+    {
+        i32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+    }
+
+    fn read_string(bytes: &[u8], at: &mut usize) -> String
+    {
+        let end = *at + bytes[*at..].iter().position(|byte| *byte == 0).unwrap();
+        let string = String::from_utf8(bytes[*at..end].to_vec()).unwrap();
+        *at = end + 1;
+        string
+    }
+
+    /// The attributes of the header, and where the offset table starts
+    fn read_header(bytes: &[u8]) -> (BTreeMap<String, (String, Vec<u8>)>, usize)
+    {
+        assert_eq!(&bytes[..4], &[0x76, 0x2f, 0x31, 0x01]);
+        assert_eq!(read_i32(bytes, 4) & 0xff, 2);
+        let mut attributes = BTreeMap::new();
+        let mut at = 8;
+        loop
+        {
+            let name = read_string(bytes, &mut at);
+            if name.is_empty() { break; }
+            let attribute_type = read_string(bytes, &mut at);
+            let size = read_i32(bytes, at) as usize;
+            attributes.insert(name, (attribute_type, bytes[at + 4..at + 4 + size].to_vec()));
+            at += 4 + size;
+        }
+        (attributes, at)
+    }
+
+    #[test]
+    fn half_conversion()
+    {
+        assert_eq!(to_half_bits(0.0), 0x0000);
+        assert_eq!(to_half_bits(-0.0), 0x8000);
+        assert_eq!(to_half_bits(1.0), 0x3c00);
+        assert_eq!(to_half_bits(0.5), 0x3800);
+        assert_eq!(to_half_bits(-2.0), 0xc000);
+        assert_eq!(to_half_bits(65504.0), 0x7bff);
+        assert_eq!(to_half_bits(65520.0), 0x7c00);
+        assert_eq!(to_half_bits(1e10), 0x7c00);
+        assert_eq!(to_half_bits(f32::INFINITY), 0x7c00);
+        assert_eq!(to_half_bits(f32::NAN) & 0x7e00, 0x7e00);
+        // Ties go to the even mantissa
+        assert_eq!(to_half_bits(1.0 + 2f32.powi(-11)), 0x3c00);
+        assert_eq!(to_half_bits(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+        // Subnormals
+        assert_eq!(to_half_bits(2f32.powi(-24)), 0x0001);
+        assert_eq!(to_half_bits(2f32.powi(-15)), 0x0200);
+        assert_eq!(to_half_bits(1e-9), 0x0000);
+        assert_eq!(to_half_bits(2f32.powi(-14) * (1.0 - 2f32.powi(-12))), 0x0400);
+    }
+
+    #[test]
+    fn header_and_scanlines()
+    {
+        let mut writer = ExrWriter::new("unused.exr", 3, 2, ExrPixelType::Float);
+        writer.add_channel("depth");
+        writer.write_pixel(2, 1, Colorf::new(40.0, 0.5, 2.0));
+        writer.write_channel("depth", 0, 1, 123.0);
+        assert_eq!(writer.get_channel_names(), vec!["B", "G", "R", "depth"]);
+
+        let mut bytes = Vec::new();
+        writer.write_to(&mut bytes).unwrap();
+        let (attributes, table) = read_header(&bytes);
+        let names: Vec<&str> = attributes.keys().map(|name| name.as_str()).collect();
+        assert_eq!(names, vec!["channels", "compression", "dataWindow", "displayWindow", "lineOrder",
+                               "pixelAspectRatio", "screenWindowCenter", "screenWindowWidth"]);
+        let (chlist_type, chlist) = &attributes["channels"];
+        assert_eq!(chlist_type, "chlist");
+        assert_eq!(chlist.len(), 4 * 16 + 3 * 2 + 6 + 1);
+        assert_eq!(read_i32(&attributes["dataWindow"].1, 8), 2);
+        assert_eq!(attributes["compression"].1, vec![0]);
+
+        // Second scanline: B, G, R and depth of its 3 pixels
+        let offset = u64::from_le_bytes([bytes[table + 8], bytes[table + 9], bytes[table + 10], bytes[table + 11],
+                                         bytes[table + 12], bytes[table + 13], bytes[table + 14], bytes[table + 15]]) as usize;
+        assert_eq!(read_i32(&bytes, offset), 1);
+        assert_eq!(read_i32(&bytes, offset + 4), 4 * 3 * 4);
+        let values: Vec<f32> = bytes[offset + 8..offset + 8 + 48].chunks(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        assert_eq!(values, vec![0.0, 0.0, 2.0, 0.0, 0.0, 0.5, 0.0, 0.0, 40.0, 123.0, 0.0, 0.0]);
+        assert_eq!(bytes.len(), offset + 8 + 48);
+    }
+
+    #[test]
+    fn half_scanlines()
+    {
+        let mut writer = ExrWriter::new("unused.exr", 1, 1, ExrPixelType::Half);
+        writer.write_pixel(0, 0, Colorf::new(2.0, 1.0, 0.5));
+        let mut bytes = Vec::new();
+        writer.write_to(&mut bytes).unwrap();
+
+        let (attributes, table) = read_header(&bytes);
+        assert_eq!(read_i32(&attributes["channels"].1, 2), 1);
+        assert_eq!(read_i32(&bytes, table + 8 + 4), 6);
+        assert_eq!(&bytes[table + 16..], &[0x00, 0x38, 0x00, 0x3c, 0x00, 0x40]);
+    }
+}
@@ -0,0 +1,92 @@
+use image::Rgb;
+use image::hdr::HDREncoder;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::output::OutputManager;
+use crate::utils::color::Colorf;
+
+/// Writes linear colors to a Radiance RGBE image (.hdr)
+pub struct HdrWriter<'a>
+{
+    pub m_imgpath: &'a str,
+    m_imgresolution: (usize, usize),
+    m_pixels: Vec<Rgb<f32>>,
+}
+
+impl HdrWriter<'_>
+{
+    pub fn new(imgpath: &str, width: usize, height: usize) -> HdrWriter<'_>
+    {
+        HdrWriter
+        {
+            m_imgpath: imgpath,
+            m_imgresolution: (width, height),
+            m_pixels: vec![Rgb([0.0, 0.0, 0.0]); width * height],
+        }
+    }
+}
+
+impl fmt::Debug for HdrWriter<'_>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("HdrWriter")
+            .field("image file destination", &self.m_imgpath)
+            .field("image resolution", &self.m_imgresolution)
+            .finish()
+    }
+}
+
+impl OutputManager for HdrWriter<'_>
+{
+    fn get_img_dim(&self) -> (usize, usize)
+    {
+        self.m_imgresolution
+    }
+
+    /// The color is stored linear, without gamma correction
+    fn write_pixel(&mut self, x: usize, y: usize, color: Colorf)
+    {
+        // RGBE has no sign
+        self.m_pixels[x + y * self.m_imgresolution.0] = Rgb([color.m_r.max(0.0), color.m_g.max(0.0), color.m_b.max(0.0)]);
+    }
+
+    fn output(&mut self)
+    {
+        let file = BufWriter::new(File::create(self.m_imgpath).unwrap());
+        HDREncoder::new(file).encode(&self.m_pixels, self.m_imgresolution.0, self.m_imgresolution.1).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod HdrWriterTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use image::hdr::HdrDecoder;
+    use std::io::BufReader;
+
+    #[test]
+    fn values_above_one_survive()
+    {
+        let path = std::env::temp_dir().join(format!("raytracer_hdrwriter_{}.hdr", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut writer = HdrWriter::new(path, 9, 2);
+        writer.write_pixel(0, 0, Colorf::new(12.0, 0.5, 0.0));
+        writer.write_pixel(8, 1, Colorf::new(0.0, 300.0, -1.0));
+        writer.output();
+
+        let decoder = HdrDecoder::new(BufReader::new(File::open(path).unwrap())).unwrap();
+        assert_eq!((decoder.metadata().width, decoder.metadata().height), (9, 2));
+        let pixels = decoder.read_image_hdr().unwrap();
+        // RGBE shares the exponent, so the smaller channels lose precision
+        assert_relative_eq!(pixels[0][0], 12.0);
+        assert_relative_eq!(pixels[0][1], 0.5, epsilon = 0.05);
+        assert_relative_eq!(pixels[17][1], 300.0);
+        assert_eq!(pixels[17][2], 0.0);
+        assert_eq!(pixels[1], Rgb([0.0, 0.0, 0.0]));
+        std::fs::remove_file(path).unwrap();
+    }
+}
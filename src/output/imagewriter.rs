@@ -1,7 +1,9 @@
 use image;
-use image::{Rgba, Pixel, RgbaImage, Rgb};
+use image::{Rgba, RgbaImage, ImageError};
+use image::error::{ParameterError, ParameterErrorKind};
 use crate::utils::color::{Colorf, Color8bit};
 use crate::output::OutputManager;
+use crate::output::tonemap::ToneMapping;
 use std::fmt::Formatter;
 use core::fmt;
 use cgmath::Vector2;
@@ -11,6 +13,7 @@ pub struct ImageWriter<'a>
     pub m_imgpath: &'a str,
     m_imgresolution: (usize, usize),
     m_imgbuffer: RgbaImage,
+    m_tonemapping: ToneMapping,
 }
 
 impl ImageWriter<'_>
@@ -21,13 +24,41 @@ impl ImageWriter<'_>
         {
             m_imgpath: imgpath,
             m_imgresolution: (width, height),
-            m_imgbuffer: RgbaImage::new(width as u32, height as u32)
+            m_imgbuffer: RgbaImage::new(width as u32, height as u32),
+            m_tonemapping: ToneMapping::default(),
         }
     }
 
-    fn gamma_correction()
+    /// How the radiance is turned into 8-bit display values; sRGB encoded clamping by default
+    pub fn set_tone_mapping(&mut self, tonemapping: ToneMapping)
     {
-        // TODO: make the gamma correction a function
+        self.m_tonemapping = tonemapping;
+    }
+
+    pub fn get_tone_mapping(&self) -> &ToneMapping
+    {
+        &self.m_tonemapping
+    }
+
+    /// The image encoded in "format", e.g. to send it rather than save it
+    pub fn encode(&self, format: image::ImageOutputFormat) -> image::ImageResult<Vec<u8>>
+    {
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageRgba8(self.m_imgbuffer.clone()).write_to(&mut bytes, format)?;
+        Ok(bytes)
+    }
+
+    /// Start from the pixels of an existing image of the same resolution, e.g. the full frame a
+    /// render region is composited into; the pixels that aren't written keep their value
+    pub fn load_background(&mut self, path: &str) -> image::ImageResult<()>
+    {
+        let background = image::open(path)?.to_rgba();
+        if (background.width() as usize, background.height() as usize) != self.m_imgresolution
+        {
+            return Err(ImageError::Parameter(ParameterError::from_kind(ParameterErrorKind::DimensionMismatch)));
+        }
+        self.m_imgbuffer = background;
+        Ok(())
     }
 }
 
@@ -38,6 +69,7 @@ impl fmt::Debug for ImageWriter<'_>
         f.debug_struct("ImageWriter")
             .field("image file destination", &self.m_imgpath)
             .field("image resolution", &self.m_imgresolution)
+            .field("tone mapping", &self.m_tonemapping)
             .finish()
     }
 }
@@ -49,12 +81,9 @@ impl OutputManager for ImageWriter<'_>
         self.m_imgresolution
     }
 
-    fn write_pixel(&mut self, x: usize, y: usize, color: Colorf, inv_gamma: f32)
+    fn write_pixel(&mut self, x: usize, y: usize, color: Colorf)
     {
-        let mut convcolor = Color8bit::from(color);
-        convcolor.m_r = ((convcolor.m_r as f32) * inv_gamma.exp()) as u8;
-        convcolor.m_g = ((convcolor.m_g as f32) * inv_gamma.exp()) as u8;
-        convcolor.m_b = ((convcolor.m_b as f32) * inv_gamma.exp()) as u8;
+        let convcolor = Color8bit::from(self.m_tonemapping.apply(color));
         self.m_imgbuffer.put_pixel(x as u32, y as u32, Rgba([convcolor.m_r, convcolor.m_g, convcolor.m_b, 255]));
     }
 
@@ -62,4 +91,36 @@ impl OutputManager for ImageWriter<'_>
     {
         self.m_imgbuffer.save(self.m_imgpath).unwrap();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod ImageWriterTest
+{
+    use super::*;
+
+    #[test]
+    fn composite_onto_background()
+    {
+        let path = std::env::temp_dir().join(format!("raytracer_imagewriter_{}.png", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut background = ImageWriter::new(path, 4, 3);
+        for x in 0..4
+        {
+            for y in 0..3
+            {
+                background.write_pixel(x, y, Colorf::new(1.0, 0.0, 0.0));
+            }
+        }
+        background.output();
+
+        let mut writer = ImageWriter::new(path, 4, 3);
+        writer.load_background(path).unwrap();
+        writer.write_pixel(2, 1, Colorf::new(0.0, 0.0, 1.0));
+        assert_eq!(writer.m_imgbuffer.get_pixel(2, 1), &Rgba([0, 0, 255, 255]));
+        assert_eq!(writer.m_imgbuffer.get_pixel(3, 2), &Rgba([255, 0, 0, 255]));
+
+        let mut smaller = ImageWriter::new(path, 2, 3);
+        assert!(smaller.load_background(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
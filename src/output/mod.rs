@@ -3,10 +3,17 @@ use std::fmt;
 use std::fmt::Formatter;
 
 pub mod imagewriter;
+pub mod exrwriter;
+pub mod hdrwriter;
+pub mod pfmwriter;
+pub mod postprocess;
+pub mod terminalpreview;
+pub mod tonemap;
 
 pub trait OutputManager: fmt::Debug
 {
     fn get_img_dim(&self) -> (usize, usize);
-    fn write_pixel(&mut self, x: usize, y: usize, colorf: Colorf, inv_gamma: f32);
+    /// Linear, unbounded radiance; display outputs tone map and encode it themselves
+    fn write_pixel(&mut self, x: usize, y: usize, colorf: Colorf);
     fn output(&mut self);
 }
\ No newline at end of file
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+
+use crate::output::OutputManager;
+use crate::utils::color::Colorf;
+
+/// Writes linear colors as 32-bit floats to a Portable Float Map (.pfm)
+pub struct PfmWriter<'a>
+{
+    pub m_imgpath: &'a str,
+    m_imgresolution: (usize, usize),
+    m_pixels: Vec<Colorf>,
+}
+
+impl PfmWriter<'_>
+{
+    pub fn new(imgpath: &str, width: usize, height: usize) -> PfmWriter<'_>
+    {
+        PfmWriter
+        {
+            m_imgpath: imgpath,
+            m_imgresolution: (width, height),
+            m_pixels: vec![Colorf::new(0.0, 0.0, 0.0); width * height],
+        }
+    }
+
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        let (width, height) = self.m_imgresolution;
+        // A negative scale means little endian
+        write!(out, "PF\n{} {}\n-1.0\n", width, height)?;
+
+        // The rows go from the bottom of the image to the top
+        for y in (0..height).rev()
+        {
+            for color in self.m_pixels[y * width..(y + 1) * width].iter()
+            {
+                for value in [color.m_r, color.m_g, color.m_b].iter()
+                {
+                    out.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PfmWriter<'_>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("PfmWriter")
+            .field("image file destination", &self.m_imgpath)
+            .field("image resolution", &self.m_imgresolution)
+            .finish()
+    }
+}
+
+impl OutputManager for PfmWriter<'_>
+{
+    fn get_img_dim(&self) -> (usize, usize)
+    {
+        self.m_imgresolution
+    }
+
+    /// The color is stored linear, without gamma correction
+    fn write_pixel(&mut self, x: usize, y: usize, color: Colorf)
+    {
+        self.m_pixels[x + y * self.m_imgresolution.0] = color;
+    }
+
+    fn output(&mut self)
+    {
+        let mut file = BufWriter::new(File::create(self.m_imgpath).unwrap());
+        self.write_to(&mut file).unwrap();
+        file.flush().unwrap();
+    }
+}
+
+#[cfg(test)]
+mod PfmWriterTest
+{
+    use super::*;
+
+    #[test]
+    fn bottom_row_first()
+    {
+        let mut writer = PfmWriter::new("unused.pfm", 2, 2);
+        writer.write_pixel(0, 0, Colorf::new(5.5, 0.25, 0.0));
+        writer.write_pixel(1, 1, Colorf::new(0.0, 0.0, 1000.0));
+
+        let mut bytes = Vec::new();
+        writer.write_to(&mut bytes).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 2 * 2 * 3 * 4);
+
+        let values: Vec<f32> = bytes[header.len()..].chunks(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        // Pixel (1, 1) is on the first row of the file, pixel (0, 0) on the second
+        assert_eq!(values[5], 1000.0);
+        assert_eq!(&values[6..9], &[5.5, 0.25, 0.0]);
+    }
+}
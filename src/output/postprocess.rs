@@ -0,0 +1,388 @@
+use std::fmt;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::render::adaptive::get_luminance;
+use crate::sampler::{hash_u32, hash_combine, to_unit_float};
+use crate::utils::color::Colorf;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PostEffectError
+{
+    /// The bloom needs at least one radius, and every radius must be positive and finite
+    InvalidRadii(Vec<f32>),
+}
+
+impl std::error::Error for PostEffectError {}
+
+impl fmt::Display for PostEffectError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            PostEffectError::InvalidRadii(radii) => write!(f, "The bloom needs positive radii, got {:?}", radii),
+        }
+    }
+}
+
+/// An image-space effect on the linear, unbounded radiance of a render, before tone mapping.
+/// The pixels are stored row by row.
+pub trait PostEffect: Debug + Send + Sync
+{
+    fn apply(&self, pixels: &mut [Colorf], dim: (usize, usize));
+}
+
+/// A chain of effects, applied in the order they were added
+#[derive(Clone, Debug, Default)]
+pub struct PostProcessing
+{
+    pub m_effects: Vec<Arc<dyn PostEffect>>,
+}
+
+impl PostProcessing
+{
+    pub fn new() -> PostProcessing
+    {
+        PostProcessing{ m_effects: Vec::new() }
+    }
+
+    pub fn add_effect(&mut self, effect: Arc<dyn PostEffect>)
+    {
+        self.m_effects.push(effect);
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.m_effects.is_empty()
+    }
+
+    pub fn apply(&self, pixels: &mut [Colorf], dim: (usize, usize))
+    {
+        assert_eq!(pixels.len(), dim.0 * dim.1);
+        self.m_effects.iter().for_each(|effect| effect.apply(pixels, dim));
+    }
+}
+
+/// Glow around the parts of the image brighter than m_threshold. What is above the threshold
+/// is blurred at every radius of m_radii, in pixels, and the average of the blurs is added
+/// to the image, scaled by m_intensity.
+#[derive(Clone, Debug)]
+pub struct BloomEffect
+{
+    pub m_threshold: f32,
+    pub m_intensity: f32,
+    pub m_radii: Vec<f32>,
+}
+
+impl BloomEffect
+{
+    pub fn new(threshold: f32, intensity: f32) -> BloomEffect
+    {
+        BloomEffect{ m_threshold: threshold, m_intensity: intensity, m_radii: vec![2.0, 8.0, 32.0] }
+    }
+
+    /// Standard deviations of the gaussian blurs, from the tight core of the glow to its halo
+    pub fn set_radii(&mut self, radii: &[f32]) -> Result<(), PostEffectError>
+    {
+        if radii.is_empty() || radii.iter().any(|radius| !radius.is_finite() || *radius <= 0.0)
+        {
+            return Err(PostEffectError::InvalidRadii(radii.to_vec()));
+        }
+        self.m_radii = radii.to_vec();
+        Ok(())
+    }
+}
+
+impl PostEffect for BloomEffect
+{
+    fn apply(&self, pixels: &mut [Colorf], dim: (usize, usize))
+    {
+        // The part of a color above the threshold, with the same hue
+        let bright: Vec<Colorf> = pixels.iter()
+            .map(|color|
+                {
+                    let luminance = get_luminance(*color);
+                    if luminance <= self.m_threshold { Colorf::new(0.0, 0.0, 0.0) }
+                    else { *color * ((luminance - self.m_threshold) / luminance) }
+                })
+            .collect();
+
+        let scale = self.m_intensity / self.m_radii.len() as f32;
+        for radius in self.m_radii.iter()
+        {
+            let blurred = gaussian_blur(&bright, dim, *radius);
+            pixels.iter_mut().zip(blurred.iter()).for_each(|(color, glow)| *color += *glow * scale);
+        }
+    }
+}
+
+/// Separable gaussian blur of standard deviation "sigma". Near the edges, the kernel is
+/// normalized over the pixels within the image.
+fn gaussian_blur(pixels: &[Colorf], dim: (usize, usize), sigma: f32) -> Vec<Colorf>
+{
+    let radius = (3.0 * sigma).ceil() as isize;
+    let kernel: Vec<f32> = (-radius..=radius)
+        .map(|offset| (-(offset * offset) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+
+    let blur_1d = |source: &[Colorf], index: &dyn Fn(usize, usize) -> usize, length: usize, count: usize| -> Vec<Colorf>
+    {
+        let mut result = vec![Colorf::new(0.0, 0.0, 0.0); source.len()];
+        for line in 0..count
+        {
+            for position in 0..length as isize
+            {
+                let mut sum = Colorf::new(0.0, 0.0, 0.0);
+                let mut weight_sum = 0.0;
+                let first = (position - radius).max(0);
+                let last = (position + radius).min(length as isize - 1);
+                for neighbour in first..=last
+                {
+                    let weight = kernel[(neighbour - position + radius) as usize];
+                    sum += source[index(neighbour as usize, line)] * weight;
+                    weight_sum += weight;
+                }
+                result[index(position as usize, line)] = sum / weight_sum;
+            }
+        }
+        result
+    };
+
+    let horizontal = blur_1d(pixels, &|x, y| x + y * dim.0, dim.0, dim.1);
+    blur_1d(&horizontal, &|y, x| x + y * dim.0, dim.1, dim.0)
+}
+
+/// Darkening towards the corners, like the cos^4 falloff of a lens. m_strength is the tangent
+/// of the angle between the optical axis and the corners; 0 leaves the image as it is.
+#[derive(Clone, Copy, Debug)]
+pub struct VignetteEffect
+{
+    pub m_strength: f32,
+}
+
+impl VignetteEffect
+{
+    pub fn new(strength: f32) -> VignetteEffect
+    {
+        VignetteEffect{ m_strength: strength }
+    }
+
+    /// Factor of a pixel at "radius" from the center, where the corners are at 1
+    pub fn get_falloff(&self, radius: f32) -> f32
+    {
+        let tan_theta = radius * self.m_strength;
+        let cos_theta_sqr = 1.0 / (1.0 + tan_theta * tan_theta);
+        cos_theta_sqr * cos_theta_sqr
+    }
+}
+
+impl PostEffect for VignetteEffect
+{
+    fn apply(&self, pixels: &mut [Colorf], dim: (usize, usize))
+    {
+        let center = (dim.0 as f32 * 0.5, dim.1 as f32 * 0.5);
+        let half_diagonal = (center.0 * center.0 + center.1 * center.1).sqrt().max(f32::EPSILON);
+        for (index, color) in pixels.iter_mut().enumerate()
+        {
+            let offset = ((index % dim.0) as f32 + 0.5 - center.0, (index / dim.0) as f32 + 0.5 - center.1);
+            let radius = (offset.0 * offset.0 + offset.1 * offset.1).sqrt() / half_diagonal;
+            *color *= self.get_falloff(radius);
+        }
+    }
+}
+
+/// Lateral chromatic aberration: the lens magnifies red a bit more than green, and blue a bit
+/// less, so the channels drift apart towards the edges. m_strength is the relative difference
+/// of magnification, e.g. 0.005.
+#[derive(Clone, Copy, Debug)]
+pub struct ChromaticAberrationEffect
+{
+    pub m_strength: f32,
+}
+
+impl ChromaticAberrationEffect
+{
+    pub fn new(strength: f32) -> ChromaticAberrationEffect
+    {
+        ChromaticAberrationEffect{ m_strength: strength }
+    }
+}
+
+impl PostEffect for ChromaticAberrationEffect
+{
+    fn apply(&self, pixels: &mut [Colorf], dim: (usize, usize))
+    {
+        let source = pixels.to_vec();
+        let center = (dim.0 as f32 * 0.5, dim.1 as f32 * 0.5);
+        for (index, color) in pixels.iter_mut().enumerate()
+        {
+            let offset = ((index % dim.0) as f32 + 0.5 - center.0, (index / dim.0) as f32 + 0.5 - center.1);
+            // A channel magnified by "scale" shows at this pixel what is at offset / scale
+            let sample = |scale: f32| sample_bilinear(&source, dim, center.0 + offset.0 / scale, center.1 + offset.1 / scale);
+            color.m_r = sample(1.0 + self.m_strength).m_r;
+            color.m_b = sample(1.0 - self.m_strength).m_b;
+        }
+    }
+}
+
+/// Color at (x, y) in pixels, between the centers of the pixels around it
+fn sample_bilinear(pixels: &[Colorf], dim: (usize, usize), x: f32, y: f32) -> Colorf
+{
+    let x = (x - 0.5).max(0.0).min((dim.0 - 1) as f32);
+    let y = (y - 0.5).max(0.0).min((dim.1 - 1) as f32);
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (x1, y1) = ((x0 + 1).min(dim.0 - 1), (y0 + 1).min(dim.1 - 1));
+    let (tx, ty) = (x - x0 as f32, y - y0 as f32);
+
+    let top = pixels[x0 + y0 * dim.0] * (1.0 - tx) + pixels[x1 + y0 * dim.0] * tx;
+    let bottom = pixels[x0 + y1 * dim.0] * (1.0 - tx) + pixels[x1 + y1 * dim.0] * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+/// Film grain: every pixel is scaled by 1 + m_intensity * n, where n is uniform within [-1, 1].
+/// The grain depends only on m_seed and the pixel, so it stays the same from one render to another.
+#[derive(Clone, Copy, Debug)]
+pub struct FilmGrainEffect
+{
+    pub m_intensity: f32,
+    pub m_seed: u32,
+}
+
+impl FilmGrainEffect
+{
+    pub fn new(intensity: f32, seed: u32) -> FilmGrainEffect
+    {
+        FilmGrainEffect{ m_intensity: intensity, m_seed: seed }
+    }
+}
+
+impl PostEffect for FilmGrainEffect
+{
+    fn apply(&self, pixels: &mut [Colorf], dim: (usize, usize))
+    {
+        let seed = hash_u32(self.m_seed);
+        for (index, color) in pixels.iter_mut().enumerate()
+        {
+            let hash = hash_u32(hash_combine(hash_combine(seed, (index % dim.0) as u32), (index / dim.0) as u32));
+            let noise = 2.0 * to_unit_float(hash) - 1.0;
+            *color *= (1.0 + self.m_intensity * noise).max(0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod PostProcessingTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn gray(value: f32) -> Colorf
+    {
+        Colorf::new(value, value, value)
+    }
+
+    fn get_sum(pixels: &[Colorf]) -> f32
+    {
+        pixels.iter().map(|color| color.m_g).sum()
+    }
+
+    #[test]
+    fn bloom_spreads_what_is_above_the_threshold()
+    {
+        let dim = (41, 41);
+        let mut pixels = vec![gray(0.0); dim.0 * dim.1];
+        pixels[20 + 20 * 41] = gray(10.0);
+        pixels[3 + 3 * 41] = gray(0.5);
+        let mut bloom = BloomEffect::new(1.0, 0.5);
+        bloom.set_radii(&[1.0, 3.0]).unwrap();
+        assert_eq!(bloom.set_radii(&[]), Err(PostEffectError::InvalidRadii(Vec::new())));
+        assert_eq!(bloom.set_radii(&[2.0, 0.0]), Err(PostEffectError::InvalidRadii(vec![2.0, 0.0])));
+        assert!(bloom.set_radii(&[f32::INFINITY]).is_err());
+        assert_eq!(bloom.m_radii, vec![1.0, 3.0]);
+        bloom.apply(&mut pixels, dim);
+
+        // Away from the edges, the blurs keep the energy above the threshold
+        assert_relative_eq!(get_sum(&pixels), 10.5 + 0.5 * 9.0, epsilon = 1e-3);
+        assert!(pixels[23 + 20 * 41].m_g > 0.0);
+        assert!(pixels[22 + 20 * 41].m_g > pixels[26 + 20 * 41].m_g);
+        assert_relative_eq!(pixels[20 + 21 * 41].m_g, pixels[21 + 20 * 41].m_g, epsilon = 1e-6);
+        // The dim pixel has no glow of its own
+        assert_eq!(pixels[3 + 3 * 41], gray(0.5));
+    }
+
+    #[test]
+    fn vignette_darkens_the_corners()
+    {
+        let dim = (20, 10);
+        let mut pixels = vec![gray(1.0); dim.0 * dim.1];
+        VignetteEffect::new(0.8).apply(&mut pixels, dim);
+
+        assert!(pixels[10 + 5 * 20].m_r > 0.99);
+        assert_relative_eq!(pixels[0].m_r, pixels[19 + 9 * 20].m_r, epsilon = 1e-6);
+        assert!(pixels[0].m_r < pixels[5 + 2 * 20].m_r);
+        assert_relative_eq!(VignetteEffect::new(1.0).get_falloff(1.0), 0.25);
+
+        let mut unchanged = vec![gray(1.0); dim.0 * dim.1];
+        VignetteEffect::new(0.0).apply(&mut unchanged, dim);
+        assert!(unchanged.iter().all(|color| *color == gray(1.0)));
+    }
+
+    #[test]
+    fn chromatic_aberration_moves_red_outwards()
+    {
+        let dim = (21, 21);
+        let mut uniform = vec![gray(0.3); dim.0 * dim.1];
+        ChromaticAberrationEffect::new(0.1).apply(&mut uniform, dim);
+        assert!(uniform.iter().all(|color| (color.m_r - 0.3).abs() < 1e-6 && (color.m_b - 0.3).abs() < 1e-6));
+
+        // A white line 10 pixels right of the center
+        let mut pixels = vec![gray(0.0); dim.0 * dim.1];
+        for y in 0..21
+        {
+            pixels[20 + y * 21] = gray(1.0);
+            pixels[10 + y * 21] = gray(1.0);
+        }
+        ChromaticAberrationEffect::new(0.1).apply(&mut pixels, dim);
+        // Red is magnified past the edge, blue shrinks towards the center; the center stays white
+        assert_eq!(pixels[10 + 10 * 21], gray(1.0));
+        assert!(pixels[20 + 10 * 21].m_r < 1.0);
+        assert!(pixels[19 + 10 * 21].m_b > 0.0);
+        assert_eq!(pixels[19 + 10 * 21].m_g, 0.0);
+        assert_eq!(pixels[19 + 10 * 21].m_r, 0.0);
+    }
+
+    #[test]
+    fn film_grain_is_deterministic_noise()
+    {
+        let dim = (64, 64);
+        let mut pixels = vec![gray(0.5); dim.0 * dim.1];
+        FilmGrainEffect::new(0.2, 7).apply(&mut pixels, dim);
+        let mut again = vec![gray(0.5); dim.0 * dim.1];
+        FilmGrainEffect::new(0.2, 7).apply(&mut again, dim);
+        assert_eq!(pixels, again);
+
+        assert!(pixels.iter().all(|color| color.m_g >= 0.4 && color.m_g <= 0.6 && color.m_r == color.m_g));
+        assert!(pixels.iter().any(|color| color.m_g != 0.5));
+        assert_relative_eq!(get_sum(&pixels) / (64.0 * 64.0), 0.5, epsilon = 0.005);
+
+        let mut black = vec![gray(0.0); 4];
+        FilmGrainEffect::new(0.2, 7).apply(&mut black, (2, 2));
+        assert!(black.iter().all(|color| *color == gray(0.0)));
+    }
+
+    #[test]
+    fn chain_applies_effects_in_order()
+    {
+        let dim = (4, 4);
+        let mut chain = PostProcessing::new();
+        assert!(chain.is_empty());
+        chain.add_effect(Arc::new(BloomEffect::new(1.0, 1.0)));
+        chain.add_effect(Arc::new(VignetteEffect::new(0.5)));
+
+        let mut pixels = vec![gray(2.0); dim.0 * dim.1];
+        chain.apply(&mut pixels, dim);
+        // The bloom adds 1 everywhere, then the vignette darkens the corners
+        let corner_falloff = VignetteEffect::new(0.5).get_falloff(1.5_f32.hypot(1.5) / 2.0_f32.hypot(2.0));
+        assert_relative_eq!(pixels[0].m_g, 3.0 * corner_falloff, epsilon = 1e-5);
+    }
+}
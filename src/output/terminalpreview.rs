@@ -0,0 +1,314 @@
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::io;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::output::tonemap::ToneMapping;
+use crate::render::control::{RenderControl, RenderProgress, RenderStatus};
+use crate::render::renderbuffer::RenderBuffer;
+use crate::utils::color::{Color8bit, Colorf};
+use crate::utils::colorconstant::COLOR_BLACK;
+
+// Every character shows two pixels: the top one in the foreground, the bottom one in the background
+const HALF_BLOCK: char = '▀';
+
+/// Preview of a render in a terminal, e.g. over SSH, in 24-bit colour with a status line for the
+/// progress, samples per pixel and time left
+pub struct TerminalPreview
+{
+    m_columns: usize,
+    m_rows: usize, // Including the status line
+    m_tonemapping: ToneMapping,
+    pub m_update_interval: Duration,
+    pub m_start_timeout: Duration, // How long watch waits for a render that hasn't started
+}
+
+impl TerminalPreview
+{
+    pub fn new(columns: usize, rows: usize) -> TerminalPreview
+    {
+        TerminalPreview
+        {
+            m_columns: columns.max(1),
+            m_rows: rows.max(2),
+            m_tonemapping: ToneMapping::default(),
+            m_update_interval: Duration::from_secs(1),
+            m_start_timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Preview that fills the terminal of the standard input, as "stty size" reports it. Without
+    /// one, it follows COLUMNS and LINES, which shells rarely export, and is 80 x 24 otherwise.
+    pub fn from_environment() -> TerminalPreview
+    {
+        let get_size = |name: &str, default: usize| env::var(name).ok()
+            .and_then(|value| value.trim().parse::<usize>().ok())
+            .unwrap_or(default);
+        let (columns, rows) = get_terminal_size()
+            .unwrap_or_else(|| (get_size("COLUMNS", 80), get_size("LINES", 24)));
+        TerminalPreview::new(columns, rows)
+    }
+
+    pub fn set_tone_mapping(&mut self, tonemapping: ToneMapping)
+    {
+        self.m_tonemapping = tonemapping;
+    }
+
+    pub fn get_tone_mapping(&self) -> &ToneMapping
+    {
+        &self.m_tonemapping
+    }
+
+    /// Pixels of the preview of an image: as large as the terminal allows, with the image's aspect
+    /// ratio, given that a character is about twice as high as wide
+    pub fn get_preview_dim(&self, img_dim: (usize, usize)) -> (usize, usize)
+    {
+        let max_dim = (self.m_columns, 2 * (self.m_rows - 1));
+        let scale = (max_dim.0 as f32 / img_dim.0.max(1) as f32).min(max_dim.1 as f32 / img_dim.1.max(1) as f32);
+        (((img_dim.0 as f32 * scale) as usize).clamp(1, max_dim.0),
+         ((img_dim.1 as f32 * scale) as usize).clamp(1, max_dim.1))
+    }
+
+    /// The samples of the buffer so far, averaged down to the preview's pixels, stored row by row.
+    /// The blocks being rendered are left out, and pixels without samples are black.
+    pub fn downsample(&self, buffer: &RenderBuffer) -> (Vec<Colorf>, (usize, usize))
+    {
+        let img_dim = buffer.get_dim();
+        let preview_dim = self.get_preview_dim(img_dim);
+        let film = buffer.resolve_available();
+        let mut pixels = Vec::with_capacity(preview_dim.0 * preview_dim.1);
+
+        let get_range = |index: usize, size: usize, preview_size: usize|
+            {
+                let start = index * size / preview_size;
+                start..((index + 1) * size / preview_size).max(start + 1).min(size)
+            };
+        for j in 0..preview_dim.1
+        {
+            for i in 0..preview_dim.0
+            {
+                let mut sum = COLOR_BLACK;
+                let mut count = 0;
+                for y in get_range(j, img_dim.1, preview_dim.1)
+                {
+                    for x in get_range(i, img_dim.0, preview_dim.0)
+                    {
+                        let pixel = &film[x + y * img_dim.0];
+                        if pixel.has_estimate()
+                        {
+                            sum += pixel.resolve();
+                            count += 1;
+                        }
+                    }
+                }
+                pixels.push(if count == 0 { COLOR_BLACK } else { sum / count as f32 });
+            }
+        }
+        (pixels, preview_dim)
+    }
+
+    /// A frame of the preview, drawn from the top left corner of the terminal
+    pub fn get_frame(&self, buffer: &RenderBuffer, progress: &RenderProgress) -> String
+    {
+        let (pixels, preview_dim) = self.downsample(buffer);
+        let to_8bit = |color: Colorf| Color8bit::from(self.m_tonemapping.apply(color));
+        let mut frame = String::from("\x1b[H");
+
+        for j in (0..preview_dim.1).step_by(2)
+        {
+            for i in 0..preview_dim.0
+            {
+                let top = to_8bit(pixels[i + j * preview_dim.0]);
+                let _ = write!(frame, "\x1b[38;2;{};{};{}m", top.m_r, top.m_g, top.m_b);
+                match pixels.get(i + (j + 1) * preview_dim.0).filter(|_| j + 1 < preview_dim.1)
+                {
+                    Some(bottom) =>
+                        {
+                            let bottom = to_8bit(*bottom);
+                            let _ = write!(frame, "\x1b[48;2;{};{};{}m", bottom.m_r, bottom.m_g, bottom.m_b);
+                        }
+                    // The last line of an odd number of rows
+                    None => frame.push_str("\x1b[49m"),
+                }
+                frame.push(HALF_BLOCK);
+            }
+            frame.push_str("\x1b[0m\x1b[K\r\n");
+        }
+        frame.push_str(&get_status(buffer, progress));
+        frame.push_str("\x1b[K");
+        frame
+    }
+
+    pub fn draw(&self, buffer: &RenderBuffer, progress: &RenderProgress, out: &mut dyn Write) -> io::Result<()>
+    {
+        out.write_all(self.get_frame(buffer, progress).as_bytes())?;
+        out.flush()
+    }
+
+    /// Draw the buffer every m_update_interval while "control" reports a render into it, e.g. from
+    /// another thread than the render's; return once the render is over, after drawing its result.
+    /// A render that hasn't started after m_start_timeout gives a TimedOut error.
+    pub fn watch(&self, buffer: &RenderBuffer, control: &RenderControl, out: &mut dyn Write) -> io::Result<()>
+    {
+        let start = Instant::now();
+        out.write_all(b"\x1b[2J\x1b[?25l")?;
+        loop
+        {
+            let status = control.get_status();
+            let finished = !matches!(status, RenderStatus::NotStarted | RenderStatus::Running);
+            self.draw(buffer, &control.get_progress(), out)?;
+            if finished { break; }
+            if matches!(status, RenderStatus::NotStarted) && start.elapsed() >= self.m_start_timeout
+            {
+                out.write_all(b"\x1b[?25h\r\n")?;
+                out.flush()?;
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "The render didn't start"));
+            }
+
+            let last_draw = Instant::now();
+            while last_draw.elapsed() < self.m_update_interval
+                && matches!(control.get_status(), RenderStatus::NotStarted | RenderStatus::Running)
+            {
+                thread::sleep(Duration::from_millis(20));
+            }
+        }
+        out.write_all(b"\x1b[?25h\r\n")?;
+        out.flush()
+    }
+}
+
+/// Columns and rows of the terminal of the standard input, if there is one
+fn get_terminal_size() -> Option<(usize, usize)>
+{
+    let output = Command::new("stty").arg("size")
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output().ok()?;
+    if !output.status.success() { return None; }
+    let text = String::from_utf8(output.stdout).ok()?;
+    let mut sizes = text.split_whitespace().map(|size| size.parse::<usize>().ok());
+    match (sizes.next()??, sizes.next()??)
+    {
+        // A terminal that doesn't know its size reports 0 0
+        (rows, columns) if rows > 0 && columns > 0 => Some((columns, rows)),
+        _ => None,
+    }
+}
+
+/// "42.0% | 12/30 tiles | 16.0 spp | 0:07 elapsed | ETA 0:10"
+fn get_status(buffer: &RenderBuffer, progress: &RenderProgress) -> String
+{
+    let samples_per_pixel = if progress.m_samples_per_pixel > 0
+    {
+        progress.m_samples_per_pixel as f32
+    }
+    else
+    {
+        progress.m_sample_count as f32 / buffer.get_region().get_area().max(1) as f32
+    };
+    let eta = progress.get_eta().map_or("--".to_string(), format_duration);
+    format!("{:5.1}% | {}/{} tiles | {:.1} spp | {} elapsed | ETA {}",
+            progress.get_fraction_done() * 100.0, progress.m_tiles_done, progress.m_tile_count,
+            samples_per_pixel, format_duration(progress.m_elapsed), eta)
+}
+
+fn format_duration(duration: Duration) -> String
+{
+    let seconds = duration.as_secs();
+    if seconds >= 3600
+    {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    }
+    else
+    {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod TerminalPreviewTest
+{
+    use super::*;
+    use cgmath::Vector2;
+    use crate::render::renderdata::{RenderMeta, RenderSample};
+
+    #[test]
+    fn preview_fits_the_terminal()
+    {
+        let preview = TerminalPreview::new(80, 25);
+        assert_eq!(preview.get_preview_dim((800, 600)), (64, 48));
+        assert_eq!(preview.get_preview_dim((1600, 400)), (80, 20));
+        assert_eq!(preview.get_preview_dim((20, 10)), (80, 40));
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+        assert_eq!(format_duration(Duration::from_secs(65)), "1:05");
+    }
+
+    #[test]
+    fn frame_of_half_blocks()
+    {
+        // Red on top of blue, the right half without samples
+        let buffer = RenderBuffer::new((4, 4), (2, 2));
+        for meta in buffer.iter().collect::<Vec<RenderMeta>>().iter().filter(|meta| meta.get_start_coords().0 == 0)
+        {
+            let mut tile = buffer.lock_tile(meta);
+            for x in meta.get_start_coords().0..meta.get_end_coords().0
+            {
+                for y in meta.get_start_coords().1..meta.get_end_coords().1
+                {
+                    let color = if y < 2 { Colorf::new(1.0, 0.0, 0.0) } else { Colorf::new(0.0, 0.0, 1.0) };
+                    tile.add_sample(&RenderSample::from_pixel(x, y, Vector2::new(0.5, 0.5), color));
+                }
+            }
+        }
+
+        let preview = TerminalPreview::new(2, 2);
+        let (pixels, preview_dim) = preview.downsample(&buffer);
+        assert_eq!(preview_dim, (2, 2));
+        assert_eq!(pixels, vec![Colorf::new(1.0, 0.0, 0.0), COLOR_BLACK, Colorf::new(0.0, 0.0, 1.0), COLOR_BLACK]);
+
+        let progress = RenderProgress
+        {
+            m_tiles_done: 2,
+            m_tile_count: 4,
+            m_sample_count: 8,
+            m_samples_per_pixel: 0,
+            m_noise_level: None,
+            m_elapsed: Duration::from_secs(10),
+        };
+        let frame = preview.get_frame(&buffer, &progress);
+        assert!(frame.starts_with("\x1b[H\x1b[38;2;255;0;0m\x1b[48;2;0;0;255m▀\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m▀\x1b[0m\x1b[K\r\n"));
+        assert!(frame.ends_with(" 50.0% | 2/4 tiles | 0.5 spp | 0:10 elapsed | ETA 0:10\x1b[K"));
+    }
+
+    #[test]
+    fn watch_a_finished_render()
+    {
+        let buffer = RenderBuffer::new((4, 4), (2, 2));
+        let control = RenderControl::new();
+        control.start(4);
+        control.finish();
+        let mut out = Vec::new();
+        TerminalPreview::new(4, 3).watch(&buffer, &control, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.matches("\x1b[H").count(), 1);
+        assert!(out.contains("0/4 tiles"));
+        assert!(out.ends_with("\x1b[?25h\r\n"));
+    }
+
+    #[test]
+    fn stop_watching_a_render_that_doesnt_start()
+    {
+        let buffer = RenderBuffer::new((4, 4), (2, 2));
+        let control = RenderControl::new();
+        let mut preview = TerminalPreview::new(4, 3);
+        preview.m_update_interval = Duration::from_millis(10);
+        preview.m_start_timeout = Duration::from_millis(50);
+        let mut out = Vec::new();
+        let error = preview.watch(&buffer, &control, &mut out).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(out.ends_with(b"\x1b[?25h\r\n"));
+    }
+}
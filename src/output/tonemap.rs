@@ -0,0 +1,310 @@
+use std::fmt;
+use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::render::adaptive::get_luminance;
+use crate::utils::color::Colorf;
+
+/// Maps the unbounded, linear radiance of the render to displayable linear values within [0, 1]
+pub trait ToneMapper: Debug + Send + Sync
+{
+    fn map(&self, color: Colorf) -> Colorf;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapperError
+{
+    /// The luminance mapped to 1 must be positive
+    InvalidWhitePoint(f32),
+}
+
+impl std::error::Error for ToneMapperError {}
+
+impl fmt::Display for ToneMapperError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            ToneMapperError::InvalidWhitePoint(white) => write!(f, "The white point must be positive, got {}", white),
+        }
+    }
+}
+
+fn check_white_point(white: f32) -> Result<f32, ToneMapperError>
+{
+    if white.is_nan() || white <= 0.0
+    {
+        return Err(ToneMapperError::InvalidWhitePoint(white));
+    }
+    Ok(white)
+}
+
+/// Scale a color to the given luminance, which keeps its hue
+fn scale_luminance(color: Colorf, mapped_luminance: impl Fn(f32) -> f32) -> Colorf
+{
+    let luminance = get_luminance(color);
+    if luminance <= 0.0 { return Colorf::new(0.0, 0.0, 0.0); }
+    color * (mapped_luminance(luminance) / luminance)
+}
+
+fn map_channels(color: Colorf, mapping: impl Fn(f32) -> f32) -> Colorf
+{
+    Colorf::new(mapping(color.m_r), mapping(color.m_g), mapping(color.m_b))
+}
+
+/// Cuts everything above 1
+#[derive(Clone, Copy, Debug)]
+pub struct ClampToneMapper;
+
+impl ToneMapper for ClampToneMapper
+{
+    fn map(&self, color: Colorf) -> Colorf
+    {
+        color.clamp()
+    }
+}
+
+/// Reinhard's global operator, L / (1 + L), on the luminance
+#[derive(Clone, Copy, Debug)]
+pub struct ReinhardToneMapper;
+
+impl ToneMapper for ReinhardToneMapper
+{
+    fn map(&self, color: Colorf) -> Colorf
+    {
+        scale_luminance(color, |luminance| luminance / (1.0 + luminance)).clamp()
+    }
+}
+
+/// Reinhard's extended operator, which maps the luminance m_white to 1 instead of infinity
+#[derive(Clone, Copy, Debug)]
+pub struct ReinhardExtendedToneMapper
+{
+    pub m_white: f32,
+}
+
+impl ReinhardExtendedToneMapper
+{
+    pub fn new(white: f32) -> Result<ReinhardExtendedToneMapper, ToneMapperError>
+    {
+        Ok(ReinhardExtendedToneMapper{ m_white: check_white_point(white)? })
+    }
+}
+
+impl ToneMapper for ReinhardExtendedToneMapper
+{
+    fn map(&self, color: Colorf) -> Colorf
+    {
+        let inv_white_sqr = 1.0 / (self.m_white * self.m_white);
+        scale_luminance(color, |luminance| luminance * (1.0 + luminance * inv_white_sqr) / (1.0 + luminance)).clamp()
+    }
+}
+
+/// Hable's filmic curve from Uncharted 2, per channel, normalized so that m_white maps to 1
+#[derive(Clone, Copy, Debug)]
+pub struct FilmicToneMapper
+{
+    pub m_white: f32,
+}
+
+impl FilmicToneMapper
+{
+    pub fn new(white: f32) -> Result<FilmicToneMapper, ToneMapperError>
+    {
+        Ok(FilmicToneMapper{ m_white: check_white_point(white)? })
+    }
+
+    fn curve(x: f32) -> f32
+    {
+        // Shoulder strength, linear strength, linear angle, toe strength, toe numerator and denominator
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+    }
+}
+
+impl Default for FilmicToneMapper
+{
+    fn default() -> Self
+    {
+        FilmicToneMapper{ m_white: 11.2 }
+    }
+}
+
+impl ToneMapper for FilmicToneMapper
+{
+    fn map(&self, color: Colorf) -> Colorf
+    {
+        let inv_white = 1.0 / FilmicToneMapper::curve(self.m_white);
+        map_channels(color, |value| FilmicToneMapper::curve(value.max(0.0)) * inv_white).clamp()
+    }
+}
+
+/// Narkowicz's fit of the ACES reference rendering and output transforms, per channel
+#[derive(Clone, Copy, Debug)]
+pub struct AcesToneMapper;
+
+impl ToneMapper for AcesToneMapper
+{
+    fn map(&self, color: Colorf) -> Colorf
+    {
+        map_channels(color, |value| {
+            let value = value.max(0.0);
+            (value * (2.51 * value + 0.03)) / (value * (2.43 * value + 0.59) + 0.14)
+        }).clamp()
+    }
+}
+
+/// Exact sRGB encoding of a linear value within [0, 1]
+pub fn linear_to_srgb(value: f32) -> f32
+{
+    if value <= 0.003_130_8 { 12.92 * value } else { 1.055 * value.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Exact sRGB decoding, the inverse of linear_to_srgb
+pub fn srgb_to_linear(value: f32) -> f32
+{
+    if value <= 0.040_45 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+}
+
+/// How the tone mapped values are encoded for the display
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferFunction
+{
+    Linear,
+    Srgb,
+    /// A pure power law, value^(1 / gamma)
+    Gamma(f32),
+}
+
+impl TransferFunction
+{
+    pub fn encode(&self, value: f32) -> f32
+    {
+        match self
+        {
+            TransferFunction::Linear => value,
+            TransferFunction::Srgb => linear_to_srgb(value),
+            TransferFunction::Gamma(gamma) => value.powf(1.0 / gamma),
+        }
+    }
+}
+
+/// The display transform of an image: an exposure in stops, a tone mapping operator, then a transfer function
+#[derive(Clone, Debug)]
+pub struct ToneMapping
+{
+    pub m_exposure: f32,
+    pub m_tonemapper: Arc<dyn ToneMapper>,
+    pub m_transfer: TransferFunction,
+}
+
+impl ToneMapping
+{
+    pub fn new(tonemapper: Arc<dyn ToneMapper>) -> ToneMapping
+    {
+        ToneMapping{ m_exposure: 0.0, m_tonemapper: tonemapper, m_transfer: TransferFunction::Srgb }
+    }
+
+    /// Scale the radiance by 2^stops before tone mapping it
+    pub fn set_exposure(&mut self, stops: f32)
+    {
+        self.m_exposure = stops;
+    }
+
+    pub fn set_transfer(&mut self, transfer: TransferFunction)
+    {
+        self.m_transfer = transfer;
+    }
+
+    /// Encoded display color, within [0, 1], of a linear radiance
+    pub fn apply(&self, color: Colorf) -> Colorf
+    {
+        let mapped = self.m_tonemapper.map(color * 2f32.powf(self.m_exposure));
+        map_channels(mapped, |value| self.m_transfer.encode(value))
+    }
+}
+
+/// Clamp and sRGB encoding, without exposure
+impl Default for ToneMapping
+{
+    fn default() -> Self
+    {
+        ToneMapping::new(Arc::new(ClampToneMapper))
+    }
+}
+
+#[cfg(test)]
+mod ToneMappingTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn gray(value: f32) -> Colorf
+    {
+        Colorf::new(value, value, value)
+    }
+
+    #[test]
+    fn srgb_transfer()
+    {
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert_relative_eq!(linear_to_srgb(1.0), 1.0, epsilon = 1e-6);
+        assert_relative_eq!(linear_to_srgb(0.5), 0.735_356_9, epsilon = 1e-6);
+        assert_relative_eq!(linear_to_srgb(0.001), 0.012_92);
+        // Both pieces meet at the threshold
+        assert_relative_eq!(12.92 * 0.003_130_8, 1.055 * 0.003_130_8f32.powf(1.0 / 2.4) - 0.055, epsilon = 1e-6);
+        for value in [0.0, 0.002, 0.01, 0.2, 0.5, 0.9, 1.0].iter()
+        {
+            assert_relative_eq!(srgb_to_linear(linear_to_srgb(*value)), *value, epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    fn operators()
+    {
+        assert_eq!(ClampToneMapper.map(Colorf::new(2.0, 0.5, -1.0)), Colorf::new(1.0, 0.5, 0.0));
+
+        // Reinhard maps a luminance of 1 to 1 / 2, and keeps the ratios of the channels
+        let mapped = ReinhardToneMapper.map(Colorf::new(2.0, 1.0, 0.0) / get_luminance(Colorf::new(2.0, 1.0, 0.0)));
+        assert_relative_eq!(get_luminance(mapped), 0.5, epsilon = 1e-6);
+        assert_relative_eq!(mapped.m_r, 2.0 * mapped.m_g, epsilon = 1e-6);
+
+        let extended = ReinhardExtendedToneMapper::new(4.0).unwrap();
+        assert_relative_eq!(extended.map(gray(4.0)).m_g, 1.0, epsilon = 1e-6);
+        assert!(extended.map(gray(2.0)).m_g > ReinhardToneMapper.map(gray(2.0)).m_g);
+
+        let filmic = FilmicToneMapper::default();
+        assert_relative_eq!(filmic.map(gray(11.2)).m_b, 1.0, epsilon = 1e-6);
+        assert_relative_eq!(filmic.map(gray(0.0)).m_b, 0.0, epsilon = 1e-6);
+        assert!(filmic.map(gray(0.5)).m_b < filmic.map(gray(1.0)).m_b);
+
+        assert_eq!(AcesToneMapper.map(gray(0.0)).m_r, 0.0);
+        assert_relative_eq!(AcesToneMapper.map(gray(0.18)).m_r, 0.2670, epsilon = 1e-3);
+        assert_eq!(AcesToneMapper.map(gray(1000.0)).m_r, 1.0);
+    }
+
+    #[test]
+    fn white_point_must_be_positive()
+    {
+        assert_eq!(ReinhardExtendedToneMapper::new(0.0).unwrap_err(), ToneMapperError::InvalidWhitePoint(0.0));
+        assert_eq!(FilmicToneMapper::new(-1.0).unwrap_err(), ToneMapperError::InvalidWhitePoint(-1.0));
+        assert!(FilmicToneMapper::new(f32::NAN).is_err());
+        assert_eq!(FilmicToneMapper::new(4.0).unwrap().m_white, 4.0);
+    }
+
+    #[test]
+    fn exposure_and_transfer()
+    {
+        let mut tonemapping = ToneMapping::new(Arc::new(ClampToneMapper));
+        tonemapping.set_transfer(TransferFunction::Linear);
+        tonemapping.set_exposure(1.0);
+        assert_relative_eq!(tonemapping.apply(gray(0.25)).m_r, 0.5);
+        assert_relative_eq!(tonemapping.apply(gray(0.75)).m_r, 1.0);
+
+        tonemapping.set_exposure(0.0);
+        tonemapping.set_transfer(TransferFunction::Gamma(2.0));
+        assert_relative_eq!(tonemapping.apply(gray(0.25)).m_g, 0.5);
+
+        assert_relative_eq!(ToneMapping::default().apply(gray(0.5)).m_b, linear_to_srgb(0.5));
+    }
+}
@@ -7,12 +7,18 @@ pub struct Ray
 {
     pub m_origin: Vector3<f32>,
     pub m_direction: Vector3<f32>,
+    pub m_time: f32, // The moment within the shutter interval at which the ray is cast
 }
 
 impl Ray
 {
     pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Ray
     {
-        Ray{ m_origin: origin, m_direction: direction.normalize()}
+        Ray{ m_origin: origin, m_direction: direction.normalize(), m_time: 0.0 }
+    }
+
+    pub fn new_with_time(origin: Vector3<f32>, direction: Vector3<f32>, time: f32) -> Ray
+    {
+        Ray{ m_origin: origin, m_direction: direction.normalize(), m_time: time }
     }
 }
\ No newline at end of file
@@ -0,0 +1,303 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use crate::render::aov::{AovTile, render_sample_with_aovs};
+use crate::render::cam::Camera;
+use crate::render::checkpoint::{write_u32, write_f32, read_u32, read_f32};
+use crate::render::film::FilmTile;
+use crate::render::renderdata::RenderMeta;
+use crate::sampler::pixelsampler::set_thread_sampler;
+use crate::utils::color::Colorf;
+use crate::utils::colorconstant::COLOR_BLACK;
+use crate::world::world::World;
+
+/// Settings of adaptive sampling: every pixel gets at least m_min_samples, and keeps
+/// receiving batches of samples while its estimated error exceeds the threshold
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveSampling
+{
+    pub m_min_samples: u32,
+    pub m_max_samples: u32,
+    pub m_batch_size: u32,
+    /// Largest acceptable standard error of the pixel luminance, in the units of the samples
+    /// the camera returns: radiance scaled by its exposure time, before tone mapping
+    pub m_threshold: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AdaptiveSamplingError
+{
+    /// The error of a pixel can't be estimated from fewer than 2 samples
+    TooFewSamples(u32),
+    MaxBelowMin { min_samples: u32, max_samples: u32 },
+}
+
+impl std::error::Error for AdaptiveSamplingError {}
+
+impl fmt::Display for AdaptiveSamplingError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            AdaptiveSamplingError::TooFewSamples(min_samples) =>
+                write!(f, "Adaptive sampling needs at least 2 samples per pixel, got {}", min_samples),
+            AdaptiveSamplingError::MaxBelowMin { min_samples, max_samples } =>
+                write!(f, "The maximum sample count {} is below the minimum {}", max_samples, min_samples),
+        }
+    }
+}
+
+impl AdaptiveSampling
+{
+    pub fn new(min_samples: u32, max_samples: u32, threshold: f32) -> Result<AdaptiveSampling, AdaptiveSamplingError>
+    {
+        if min_samples < 2
+        {
+            return Err(AdaptiveSamplingError::TooFewSamples(min_samples));
+        }
+        if max_samples < min_samples
+        {
+            return Err(AdaptiveSamplingError::MaxBelowMin { min_samples, max_samples });
+        }
+
+        Ok(AdaptiveSampling
+        {
+            m_min_samples: min_samples,
+            m_max_samples: max_samples,
+            m_batch_size: min_samples,
+            m_threshold: threshold,
+        })
+    }
+
+    fn needs_samples(&self, statistics: &PixelStatistics) -> bool
+    {
+        statistics.get_count() < self.m_min_samples
+            || (statistics.get_count() < self.m_max_samples && statistics.get_standard_error() > self.m_threshold)
+    }
+}
+
+/// Running mean and variance of the samples of a pixel (Welford's algorithm).
+/// The variance is tracked on the luminance, which the error estimate is based on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PixelStatistics
+{
+    m_count: u32,
+    m_mean: Colorf,
+    m_luminance_mean: f32,
+    m_luminance_m2: f32,
+}
+
+impl PixelStatistics
+{
+    pub fn new() -> PixelStatistics
+    {
+        PixelStatistics
+        {
+            m_count: 0,
+            m_mean: COLOR_BLACK,
+            m_luminance_mean: 0.0,
+            m_luminance_m2: 0.0,
+        }
+    }
+
+    pub fn add_sample(&mut self, sample: Colorf)
+    {
+        self.m_count += 1;
+        let inv_count = 1.0 / self.m_count as f32;
+        self.m_mean += (sample - self.m_mean) * inv_count;
+
+        let luminance = get_luminance(sample);
+        let delta = luminance - self.m_luminance_mean;
+        self.m_luminance_mean += delta * inv_count;
+        self.m_luminance_m2 += delta * (luminance - self.m_luminance_mean);
+    }
+
+    pub fn get_count(&self) -> u32
+    {
+        self.m_count
+    }
+
+    pub fn get_mean(&self) -> Colorf
+    {
+        self.m_mean
+    }
+
+    /// Unbiased sample variance of the luminance
+    pub fn get_variance(&self) -> f32
+    {
+        if self.m_count < 2 { 0.0 } else { self.m_luminance_m2 / (self.m_count - 1) as f32 }
+    }
+
+    /// Estimated error of the mean luminance; unknown, thus infinite, below 2 samples
+    pub fn get_standard_error(&self) -> f32
+    {
+        if self.m_count < 2 { f32::INFINITY } else { (self.get_variance() / self.m_count as f32).sqrt() }
+    }
+
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        write_u32(out, self.m_count)?;
+        for value in [self.m_mean.m_r, self.m_mean.m_g, self.m_mean.m_b, self.m_luminance_mean, self.m_luminance_m2].iter()
+        {
+            write_f32(out, *value)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(input: &mut dyn Read) -> io::Result<PixelStatistics>
+    {
+        Ok(PixelStatistics
+        {
+            m_count: read_u32(input)?,
+            m_mean: Colorf::new(read_f32(input)?, read_f32(input)?, read_f32(input)?),
+            m_luminance_mean: read_f32(input)?,
+            m_luminance_m2: read_f32(input)?,
+        })
+    }
+}
+
+impl Default for PixelStatistics
+{
+    fn default() -> Self
+    {
+        PixelStatistics::new()
+    }
+}
+
+/// Rec. 709 luminance
+pub fn get_luminance(color: Colorf) -> f32
+{
+    0.2126 * color.m_r + 0.7152 * color.m_g + 0.0722 * color.m_b
+}
+
+/// Color of a sample count in the heatmap, from blue (few samples) to red (max_samples)
+pub fn get_heatmap_color(count: u32, max_samples: u32) -> Colorf
+{
+    let t = (count as f32 / max_samples.max(1) as f32).min(1.0);
+    let channel = |center: f32| (1.5 - (4.0 * t - center).abs()).clamp(0.0, 1.0);
+    Colorf::new(channel(3.0), channel(2.0), channel(1.0))
+}
+
+/// Sample the pixels of a block until they all converge or reach the maximum sample count,
+/// and splat the samples onto the film tile of the block and their AOVs onto "aovs".
+/// "statistics" holds the pixels of the block in the order the cameras render them, and may
+/// already contain samples; the new ones continue the sample sequence of each pixel.
+pub fn render_adaptive(camera: &dyn Camera,
+                       world_ptr: &Arc<World>,
+                       rendermeta: &RenderMeta,
+                       settings: &AdaptiveSampling,
+                       statistics: &mut [PixelStatistics],
+                       tile: &mut FilmTile,
+                       aovs: &mut AovTile)
+{
+    set_thread_sampler(world_ptr.m_viewplaneptr.get_pixel_sampler());
+    let start_coords = rendermeta.get_start_coords();
+    let end_coords = rendermeta.get_end_coords();
+
+    loop
+    {
+        let mut converged = true;
+        for x in start_coords.0..end_coords.0
+        {
+            for y in start_coords.1..end_coords.1
+            {
+                let local_index = rendermeta.get_local_index(x, y);
+                let pixel = &mut statistics[local_index];
+                if !settings.needs_samples(pixel) { continue; }
+
+                converged = false;
+                let count = pixel.get_count();
+                let target = (count + settings.m_batch_size.max(1)).max(settings.m_min_samples).min(settings.m_max_samples);
+                for sample_index in count..target
+                {
+                    let sample = render_sample_with_aovs(camera, world_ptr, x, y, sample_index, aovs, local_index);
+                    pixel.add_sample(sample.m_color);
+                    tile.add_sample(&sample);
+                }
+            }
+        }
+        if converged { break; }
+    }
+}
+
+#[cfg(test)]
+mod AdaptiveSamplingTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use crate::render::renderbuffer::RenderBuffer;
+    use crate::render::testscene::{build_world, setup_camera, get_two_spheres, get_white_matte};
+    use crate::sampler::DEFAULT_SEED;
+    use crate::utils::multithread::MultiThread;
+
+    #[test]
+    fn running_statistics()
+    {
+        let mut statistics = PixelStatistics::new();
+        for value in [0.2_f32, 0.4, 0.9, 0.5].iter()
+        {
+            statistics.add_sample(Colorf::new(*value, *value, *value));
+        }
+        // mean 0.5, squared deviations 0.09 + 0.01 + 0.16 + 0.0
+        assert_eq!(statistics.get_count(), 4);
+        assert_relative_eq!(statistics.get_mean().m_g, 0.5, epsilon = 1e-6);
+        assert_relative_eq!(statistics.get_variance(), 0.26 / 3.0, epsilon = 1e-6);
+        assert_relative_eq!(statistics.get_standard_error(), (0.26_f32 / 12.0).sqrt(), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn invalid_sample_counts()
+    {
+        assert_eq!(AdaptiveSampling::new(1, 16, 0.01).unwrap_err(), AdaptiveSamplingError::TooFewSamples(1));
+        assert_eq!(AdaptiveSampling::new(8, 4, 0.01).unwrap_err(),
+                   AdaptiveSamplingError::MaxBelowMin { min_samples: 8, max_samples: 4 });
+    }
+
+    #[test]
+    fn sample_until_converged()
+    {
+        let settings = AdaptiveSampling::new(4, 64, 0.01).unwrap();
+        let mut flat = PixelStatistics::new();
+        let mut noisy = PixelStatistics::new();
+        for i in 0..4
+        {
+            flat.add_sample(Colorf::new(0.3, 0.3, 0.3));
+            let value = (i % 2) as f32;
+            noisy.add_sample(Colorf::new(value, value, value));
+        }
+        assert!(!settings.needs_samples(&flat));
+        assert!(settings.needs_samples(&noisy));
+        assert!(settings.needs_samples(&PixelStatistics::new()));
+    }
+
+    #[test]
+    fn samples_go_to_noisy_pixels()
+    {
+        // The background is flat, the occlusion between the spheres is noisy
+        let mut world = build_world((16, 16), DEFAULT_SEED, get_two_spheres(get_white_matte(), get_white_matte()));
+        world.m_viewplaneptr.m_adaptive = Some(AdaptiveSampling::new(4, 32, 0.005).unwrap());
+        let camera = setup_camera();
+        let buffer = RenderBuffer::new((16, 16), (8, 8));
+        MultiThread::new(2).render_to_buffer(Arc::new(world), &camera, &buffer);
+
+        let blocks: Vec<RenderMeta> = (0..4).map(|b| RenderMeta::new((b % 2 * 8, b / 2 * 8), (b % 2 * 8 + 8, b / 2 * 8 + 8), (b % 2, b / 2)))
+            .collect();
+        for block in blocks.iter()
+        {
+            assert!(buffer.lock_statistics(block).iter().all(|pixel| pixel.get_count() >= 4 && pixel.get_count() <= 32));
+        }
+        // Background in the corner, partial occlusion in the gap between the spheres
+        assert_eq!(buffer.lock_statistics(&blocks[0])[blocks[0].get_local_index(0, 0)].get_count(), 4);
+        assert_eq!(buffer.lock_statistics(&blocks[2])[blocks[2].get_local_index(7, 8)].get_count(), 32);
+    }
+
+    #[test]
+    fn heatmap_colors()
+    {
+        assert_eq!(get_heatmap_color(0, 16), Colorf::new(0.0, 0.0, 0.5));
+        assert_eq!(get_heatmap_color(16, 16), Colorf::new(0.5, 0.0, 0.0));
+        assert_eq!(get_heatmap_color(32, 16), get_heatmap_color(16, 16));
+    }
+}
@@ -0,0 +1,599 @@
+use cgmath::InnerSpace;
+use std::cell::RefCell;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use crate::render::cam::Camera;
+use crate::render::checkpoint::{write_f32, read_f32, write_u32, read_u32};
+use crate::render::renderdata::RenderSample;
+use crate::sampler::pixelsampler::{get_thread_sampler, set_thread_sampler};
+use crate::utils::color::Colorf;
+use crate::world::shaderec::ShadeRec;
+use crate::world::world::World;
+
+/// Arbitrary output variables: what a render can store besides the image, one value per pixel.
+/// They all come from the first hit of the camera rays, and are 0 where the rays hit nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Aov
+{
+    /// Distance from the ray origin to the hit
+    Depth,
+    /// Shading normal, in world space
+    Normal,
+    /// Diffuse reflectance of the material
+    Albedo,
+    /// Index of the hit object in the world, plus 1
+    ObjectId,
+    /// Index of the first object in the world with the hit material, plus 1
+    MaterialId,
+    /// Surface parametrization of the hit
+    Uv,
+    /// Emitted radiance and light reaching the eye after one bounce, or the background
+    Direct,
+    /// What the image has on top of the direct light, i.e. reflections and refractions
+    Indirect,
+}
+
+/// Number of values in an AovSample, the channels of all the AOVs
+pub const AOV_SAMPLE_SIZE: usize = 17;
+
+impl Aov
+{
+    pub const ALL: [Aov; 8] = [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::ObjectId,
+                               Aov::MaterialId, Aov::Uv, Aov::Direct, Aov::Indirect];
+
+    pub fn get_name(&self) -> &'static str
+    {
+        match self
+        {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "objectId",
+            Aov::MaterialId => "materialId",
+            Aov::Uv => "uv",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+        }
+    }
+
+    pub fn get_channel_names(&self) -> &'static [&'static str]
+    {
+        match self
+        {
+            Aov::Depth => &["Z"],
+            Aov::Normal => &["X", "Y", "Z"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::Uv => &["U", "V"],
+            Aov::Albedo | Aov::Direct | Aov::Indirect => &["R", "G", "B"],
+        }
+    }
+
+    pub fn get_channel_count(&self) -> usize
+    {
+        self.get_channel_names().len()
+    }
+
+    /// Identifiers can't be averaged: a pixel keeps the value of its first sample
+    pub fn is_identifier(&self) -> bool
+    {
+        matches!(self, Aov::ObjectId | Aov::MaterialId)
+    }
+
+    /// Where the channels of the AOV start in an AovSample
+    fn get_offset(&self) -> usize
+    {
+        Aov::ALL.iter()
+            .take_while(|aov| *aov != self)
+            .map(|aov| aov.get_channel_count())
+            .sum()
+    }
+
+    fn get_bit(&self) -> u32
+    {
+        1 << Aov::ALL.iter().position(|aov| aov == self).unwrap()
+    }
+}
+
+/// Set of AOVs as a bit mask, e.g. to tell whether a checkpoint has the AOVs of a buffer
+pub fn get_aov_mask(aovs: &[Aov]) -> u32
+{
+    aovs.iter().fold(0, |mask, aov| mask | aov.get_bit())
+}
+
+/// The AOVs of a mask made by get_aov_mask
+pub fn get_aovs(aov_mask: u32) -> Vec<Aov>
+{
+    Aov::ALL.iter().copied().filter(|aov| aov_mask & aov.get_bit() != 0).collect()
+}
+
+/// The values of every AOV for a camera sample
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AovSample
+{
+    m_values: [f32; AOV_SAMPLE_SIZE],
+}
+
+impl AovSample
+{
+    pub fn new() -> AovSample
+    {
+        AovSample{ m_values: [0.0; AOV_SAMPLE_SIZE] }
+    }
+
+    pub fn get(&self, aov: Aov) -> &[f32]
+    {
+        let offset = aov.get_offset();
+        &self.m_values[offset..offset + aov.get_channel_count()]
+    }
+
+    pub fn set(&mut self, aov: Aov, values: &[f32])
+    {
+        let offset = aov.get_offset();
+        self.m_values[offset..offset + aov.get_channel_count()].copy_from_slice(values);
+    }
+
+    pub fn get_color(&self, aov: Aov) -> Colorf
+    {
+        let values = self.get(aov);
+        Colorf::new(values[0], values[1], values[2])
+    }
+
+    pub fn set_color(&mut self, aov: Aov, color: Colorf)
+    {
+        self.set(aov, &[color.m_r, color.m_g, color.m_b]);
+    }
+}
+
+impl Default for AovSample
+{
+    fn default() -> Self
+    {
+        AovSample::new()
+    }
+}
+
+/// The AOVs of the camera sample a thread is rendering
+struct AovRecording
+{
+    m_recording: bool,
+    m_aov_mask: u32,
+    m_sample: AovSample,
+}
+
+thread_local!
+{
+    static THREAD_AOVS: RefCell<AovRecording> = RefCell::new(AovRecording
+    {
+        m_recording: false,
+        m_aov_mask: 0,
+        m_sample: AovSample::new(),
+    });
+}
+
+/// Record the AOVs of "aov_mask" for the next camera sample of this thread; the others stay 0
+pub fn start_aov_sample(aov_mask: u32)
+{
+    THREAD_AOVS.with(|recording|
+        {
+            let mut recording = recording.borrow_mut();
+            recording.m_recording = true;
+            recording.m_aov_mask = aov_mask;
+            recording.m_sample = AovSample::new();
+        });
+}
+
+/// Stop recording, and return the AOVs of the camera sample
+pub fn finish_aov_sample() -> AovSample
+{
+    THREAD_AOVS.with(|recording|
+        {
+            let mut recording = recording.borrow_mut();
+            recording.m_recording = false;
+            recording.m_sample
+        })
+}
+
+/// The mask of the AOVs to record if the ray at recursion "depth" is a recorded camera ray
+fn get_recording(depth: u16) -> Option<u32>
+{
+    if depth > 0 { return None; }
+    THREAD_AOVS.with(|recording|
+        {
+            let recording = recording.borrow();
+            if recording.m_recording { Some(recording.m_aov_mask) } else { None }
+        })
+}
+
+/// Shade a hit with "shade", and record the requested AOVs if it is the first hit of a camera ray.
+/// The direct light is what "shade" gives at the maximum recursion depth, where the reflected
+/// and transmitted rays are black. It is shaded with the same samples as the image, and the
+/// sample sequence goes on as if it had not been shaded. That shades the hit twice, so it is
+/// only done when the direct or indirect AOV is requested.
+pub fn shade_and_record<F>(sr: &mut ShadeRec, depth: u16, shade: F) -> Colorf
+    where F: Fn(&mut ShadeRec) -> Colorf
+{
+    let aov_mask = match get_recording(depth)
+    {
+        Some(aov_mask) => aov_mask,
+        None => return shade(sr),
+    };
+    let requested = |aov: Aov| aov_mask & aov.get_bit() != 0;
+
+    let mut sample = AovSample::new();
+    let material = sr.m_material.clone().unwrap();
+    if requested(Aov::Depth)
+    {
+        sample.set(Aov::Depth, &[sr.m_time * sr.m_ray.m_direction.magnitude()]);
+    }
+    if requested(Aov::Normal)
+    {
+        sample.set(Aov::Normal, &[sr.m_normal.x, sr.m_normal.y, sr.m_normal.z]);
+    }
+    if requested(Aov::Albedo)
+    {
+        sample.set_color(Aov::Albedo, material.get_albedo(sr));
+    }
+    if requested(Aov::ObjectId)
+    {
+        sample.set(Aov::ObjectId, &[sr.m_object_index.map_or(0.0, |index| (index + 1) as f32)]);
+    }
+    if requested(Aov::MaterialId)
+    {
+        sample.set(Aov::MaterialId, &[sr.m_worldptr.get_material_index(&material).map_or(0.0, |index| (index + 1) as f32)]);
+    }
+    if requested(Aov::Uv)
+    {
+        sample.set(Aov::Uv, &[sr.m_uv.x, sr.m_uv.y]);
+    }
+
+    let split_lighting = requested(Aov::Direct) || requested(Aov::Indirect);
+    let mut direct_sr = if split_lighting { Some(sr.clone()) } else { None };
+    let sampler = get_thread_sampler();
+    let color = shade(sr);
+    if let Some(direct_sr) = direct_sr.as_mut()
+    {
+        let next_sampler = get_thread_sampler();
+        set_thread_sampler(sampler);
+        direct_sr.m_depth = direct_sr.m_worldptr.m_viewplaneptr.m_maxdepth;
+        sample.set_color(Aov::Direct, shade(direct_sr));
+        set_thread_sampler(next_sampler);
+    }
+
+    THREAD_AOVS.with(|recording| recording.borrow_mut().m_sample = sample);
+    color
+}
+
+/// Record a camera ray that hit nothing: the background is all of its direct light
+pub fn record_miss(depth: u16, background: Colorf)
+{
+    if get_recording(depth).is_none() { return; }
+    let mut sample = AovSample::new();
+    sample.set_color(Aov::Direct, background);
+    THREAD_AOVS.with(|recording| recording.borrow_mut().m_sample = sample);
+}
+
+/// The AOVs of the pixels of a block of the RenderBuffer, in the order the cameras render them.
+/// Each pixel averages the AOVs of its own samples; the reconstruction filter of the image
+/// doesn't apply to them.
+#[derive(Clone, Debug)]
+pub struct AovTile
+{
+    m_aovs: Vec<Aov>,
+    m_sums: Vec<AovSample>,
+    m_counts: Vec<u32>,
+}
+
+impl AovTile
+{
+    /// Tile of "area" pixels storing "aovs"; without AOVs it stores nothing
+    pub fn new(aovs: &[Aov], area: usize) -> AovTile
+    {
+        let area = if aovs.is_empty() { 0 } else { area };
+        AovTile
+        {
+            m_aovs: aovs.to_vec(),
+            m_sums: vec![AovSample::new(); area],
+            m_counts: vec![0; area],
+        }
+    }
+
+    pub fn get_aovs(&self) -> &[Aov]
+    {
+        &self.m_aovs
+    }
+
+    pub fn is_empty(&self) -> bool
+    {
+        self.m_aovs.is_empty()
+    }
+
+    pub fn get_aov_mask(&self) -> u32
+    {
+        get_aov_mask(&self.m_aovs)
+    }
+
+    pub fn add_sample(&mut self, local_index: usize, sample: &AovSample)
+    {
+        let count = self.m_counts[local_index];
+        let sum = &mut self.m_sums[local_index];
+        for aov in self.m_aovs.iter()
+        {
+            let offset = aov.get_offset();
+            for channel in offset..offset + aov.get_channel_count()
+            {
+                if !aov.is_identifier() { sum.m_values[channel] += sample.m_values[channel]; }
+                else if count == 0 { sum.m_values[channel] = sample.m_values[channel]; }
+            }
+        }
+        self.m_counts[local_index] = count + 1;
+    }
+
+    /// Value of "aov" in a pixel, 0 if it has no sample yet
+    pub fn get_pixel(&self, local_index: usize, aov: Aov) -> Vec<f32>
+    {
+        let count = self.m_counts[local_index];
+        let values = self.m_sums[local_index].get(aov);
+        if aov.is_identifier() || count == 0 { values.to_vec() }
+        else { values.iter().map(|value| value / count as f32).collect() }
+    }
+
+    /// Replace a pixel with the same pixel of "other", a tile of the same AOVs and area
+    pub fn copy_pixel(&mut self, other: &AovTile, local_index: usize)
+    {
+        if self.is_empty() { return; }
+        self.m_sums[local_index] = other.m_sums[local_index];
+        self.m_counts[local_index] = other.m_counts[local_index];
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.m_sums.iter_mut().for_each(|sum| *sum = AovSample::new());
+        self.m_counts.iter_mut().for_each(|count| *count = 0);
+    }
+
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        for (sum, count) in self.m_sums.iter().zip(self.m_counts.iter())
+        {
+            write_u32(out, *count)?;
+            for aov in self.m_aovs.iter()
+            {
+                sum.get(*aov).iter().try_for_each(|value| write_f32(out, *value))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the pixels of the tile with those saved by write_to, for the same AOVs
+    pub fn read_from(&mut self, input: &mut dyn Read) -> io::Result<()>
+    {
+        for (sum, count) in self.m_sums.iter_mut().zip(self.m_counts.iter_mut())
+        {
+            *count = read_u32(input)?;
+            *sum = AovSample::new();
+            for aov in self.m_aovs.iter()
+            {
+                let offset = aov.get_offset();
+                for channel in offset..offset + aov.get_channel_count()
+                {
+                    sum.m_values[channel] = read_f32(input)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Render a sample of pixel (x, y) like Camera::render_sample, and add its AOVs to pixel
+/// "local_index" of "aovs". The direct and indirect light are scaled by the exposure time, like
+/// the image, and add up to it.
+pub fn render_sample_with_aovs<C>(camera: &C,
+                                  world_ptr: &Arc<World>,
+                                  x: usize,
+                                  y: usize,
+                                  sample_index: u32,
+                                  aovs: &mut AovTile,
+                                  local_index: usize) -> RenderSample
+    where C: Camera + ?Sized
+{
+    if aovs.is_empty()
+    {
+        return camera.render_sample(world_ptr, x, y, sample_index);
+    }
+
+    start_aov_sample(aovs.get_aov_mask());
+    let sample = camera.render_sample(world_ptr, x, y, sample_index);
+    let mut aov_sample = finish_aov_sample();
+    let direct = aov_sample.get_color(Aov::Direct) * camera.get_exposure_time();
+    aov_sample.set_color(Aov::Direct, direct);
+    aov_sample.set_color(Aov::Indirect, sample.m_color - direct);
+    aovs.add_sample(local_index, &aov_sample);
+    sample
+}
+
+#[cfg(test)]
+mod AovTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use cgmath::Vector3;
+    use crate::brdf::glossyspec::GlossySpecular;
+    use crate::brdf::lambertian::Lambertian;
+    use crate::light::pointlight::PointLight;
+    use crate::material::phong::Phong;
+    use crate::material::reflector::Reflective;
+    use crate::output::OutputManager;
+    use crate::output::exrwriter::{ExrWriter, ExrPixelType};
+    use crate::render::renderbuffer::RenderBuffer;
+    use crate::render::renderdata::RenderMeta;
+    use crate::render::testscene::{build_world, setup_camera, get_two_spheres, get_white_matte};
+    use crate::sampler::DEFAULT_SEED;
+    use crate::utils::colorconstant::{COLOR_BLACK, COLOR_WHITE};
+    use crate::utils::multithread::MultiThread;
+
+    /// A reflective sphere on the left, a matte one on the right, lit by a point light and an ambient occluder
+    fn setup_scene() -> Arc<World>
+    {
+        let red = Arc::new(Lambertian::new(0.5, Colorf::new(1.0, 0.0, 0.0)));
+        let phong = Phong::new(red.clone(), red, Arc::new(GlossySpecular::new(0.2, COLOR_WHITE)));
+        let mirror = Arc::new(Reflective::new(Arc::new(phong), 0.75, COLOR_WHITE));
+        let mut world = build_world((12, 10), DEFAULT_SEED, get_two_spheres(mirror, get_white_matte()));
+        world.m_backgroundcolor = Colorf::new(0.2, 0.3, 0.4);
+        world.add_light(Arc::new(PointLight::new(2.0, COLOR_WHITE, Vector3::new(0.0, 20.0, -50.0))));
+        Arc::new(world)
+    }
+
+    fn render(world: &Arc<World>, aovs: &[Aov]) -> RenderBuffer
+    {
+        let camera = setup_camera();
+        let mut buffer = RenderBuffer::new((12, 10), (4, 4));
+        buffer.set_aovs(aovs);
+        MultiThread::new(2).render_to_buffer(world.clone(), &camera, &buffer);
+        buffer
+    }
+
+    /// Pixel (x, y) of an AOV of the buffer
+    fn get_aov(buffer: &RenderBuffer, aov: Aov, x: usize, y: usize) -> Vec<f32>
+    {
+        let meta = RenderMeta::new((x / 4 * 4, y / 4 * 4), ((x / 4 * 4 + 4).min(12), (y / 4 * 4 + 4).min(10)), (x / 4, y / 4));
+        buffer.lock_aovs(&meta).get_pixel(meta.get_local_index(x, y), aov)
+    }
+
+    #[test]
+    fn channel_layout()
+    {
+        assert_eq!(Aov::ALL.iter().map(|aov| aov.get_channel_count()).sum::<usize>(), AOV_SAMPLE_SIZE);
+        assert_eq!(Aov::Indirect.get_offset() + 3, AOV_SAMPLE_SIZE);
+
+        let mut sample = AovSample::new();
+        sample.set(Aov::Uv, &[0.25, 0.75]);
+        sample.set_color(Aov::Albedo, Colorf::new(0.1, 0.2, 0.3));
+        assert_eq!(sample.get(Aov::Uv), &[0.25, 0.75]);
+        assert_eq!(sample.get_color(Aov::Albedo), Colorf::new(0.1, 0.2, 0.3));
+        assert_eq!(sample.get(Aov::ObjectId), &[0.0]);
+        assert_eq!(get_aov_mask(&[Aov::Depth, Aov::Indirect]), 0b1000_0001);
+        assert_eq!(get_aovs(0b1000_0001), vec![Aov::Depth, Aov::Indirect]);
+    }
+
+    #[test]
+    fn tile_averages_all_but_identifiers()
+    {
+        let mut tile = AovTile::new(&[Aov::Depth, Aov::ObjectId], 2);
+        assert_eq!(tile.get_aov_mask(), 0b1001);
+        for (depth, id) in [(2.0, 3.0), (4.0, 5.0)].iter()
+        {
+            let mut sample = AovSample::new();
+            sample.set(Aov::Depth, &[*depth]);
+            sample.set(Aov::ObjectId, &[*id]);
+            tile.add_sample(1, &sample);
+        }
+        assert_relative_eq!(tile.get_pixel(1, Aov::Depth)[0], 3.0);
+        assert_eq!(tile.get_pixel(1, Aov::ObjectId), vec![3.0]);
+        assert_eq!(tile.get_pixel(0, Aov::Depth), vec![0.0]);
+
+        let mut saved = Vec::new();
+        tile.write_to(&mut saved).unwrap();
+        let mut loaded = AovTile::new(&[Aov::Depth, Aov::ObjectId], 2);
+        loaded.read_from(&mut saved.as_slice()).unwrap();
+        assert_eq!(loaded.get_pixel(1, Aov::Depth), tile.get_pixel(1, Aov::Depth));
+        assert_eq!(loaded.get_pixel(1, Aov::ObjectId), vec![3.0]);
+
+        tile.clear();
+        assert_eq!(tile.get_pixel(1, Aov::ObjectId), vec![0.0]);
+        assert!(AovTile::new(&[], 64).is_empty());
+    }
+
+    #[test]
+    fn passes_of_a_scene()
+    {
+        let world = setup_scene();
+        let buffer = render(&world, &Aov::ALL);
+        let film = buffer.resolve();
+        let mut hits = [0, 0];
+
+        for x in 0..12
+        {
+            for y in 0..10
+            {
+                let id = get_aov(&buffer, Aov::ObjectId, x, y)[0];
+                assert_eq!(get_aov(&buffer, Aov::MaterialId, x, y)[0], id);
+                let direct = get_aov(&buffer, Aov::Direct, x, y);
+                let indirect = get_aov(&buffer, Aov::Indirect, x, y);
+                let beauty = film[x + y * 12].resolve();
+                for (value, expected) in direct.iter().zip(indirect.iter()).map(|(d, i)| d + i).zip([beauty.m_r, beauty.m_g, beauty.m_b].iter())
+                {
+                    assert_relative_eq!(value, *expected, epsilon = 1e-4);
+                }
+                if id == 0.0 { continue; }
+
+                // The pixels on the silhouettes average the hits of the spheres with misses, or of both spheres
+                let depth = get_aov(&buffer, Aov::Depth, x, y)[0];
+                assert!(depth > 0.0 && depth < 100.0, "depth {}", depth);
+                let uv = get_aov(&buffer, Aov::Uv, x, y);
+                assert!(uv.iter().all(|value| (0.0..=1.0).contains(value)));
+                // The pixels entirely on a sphere have its albedo
+                let albedo = get_aov(&buffer, Aov::Albedo, x, y);
+                if id == 1.0 && albedo == vec![0.5, 0.0, 0.0]
+                {
+                    assert!(indirect.iter().any(|value| *value > 0.0), "Nothing reflected in ({}, {})", x, y);
+                    hits[0] += 1;
+                }
+                if id == 2.0 && albedo == vec![0.8; 3]
+                {
+                    hits[1] += 1;
+                }
+                if albedo[0] == 0.5 || albedo[0] == 0.8
+                {
+                    // The camera looks down the z axis
+                    let normal = get_aov(&buffer, Aov::Normal, x, y);
+                    assert!(normal[2] < 0.0);
+                    assert_relative_eq!(Vector3::new(normal[0], normal[1], normal[2]).magnitude(), 1.0, epsilon = 0.1);
+                }
+            }
+        }
+        assert!(hits[0] > 0 && hits[1] > 0);
+        assert_eq!(get_aov(&buffer, Aov::ObjectId, 0, 0), vec![0.0]);
+        assert_eq!(get_aov(&buffer, Aov::Depth, 0, 0), vec![0.0]);
+        assert_relative_eq!(get_aov(&buffer, Aov::Direct, 0, 0)[2], 0.4);
+    }
+
+    #[test]
+    fn recording_leaves_the_image_unchanged()
+    {
+        let world = setup_scene();
+        assert_eq!(render(&world, &Aov::ALL).resolve(), render(&world, &[]).resolve());
+    }
+
+    #[test]
+    fn only_requested_aovs_are_recorded()
+    {
+        let world = setup_scene();
+        let camera = setup_camera();
+        let mut tile = AovTile::new(&[Aov::Depth, Aov::MaterialId], 1);
+        render_sample_with_aovs(&camera, &world, 2, 5, 0, &mut tile, 0);
+        assert!(tile.get_pixel(0, Aov::Depth)[0] > 0.0);
+        assert_eq!(tile.get_pixel(0, Aov::MaterialId), vec![2.0]);
+        assert_eq!(tile.m_sums[0].get(Aov::ObjectId), &[0.0]);
+        assert_eq!(tile.m_sums[0].get_color(Aov::Albedo), COLOR_BLACK);
+
+        // The material indices are assigned again when the objects change
+        let mut world = Arc::try_unwrap(world).unwrap();
+        let material = world.m_objects[1].lock().unwrap().get_material();
+        assert_eq!(world.get_material_index(&material), Some(1));
+        world.remove_object(0);
+        assert_eq!(world.get_material_index(&material), Some(0));
+    }
+
+    #[test]
+    fn exr_layers()
+    {
+        let world = setup_scene();
+        let buffer = render(&world, &[Aov::Normal, Aov::ObjectId]);
+        let mut writer = ExrWriter::new("unused.exr", 12, 10, ExrPixelType::Float);
+        buffer.write_aovs_to_exr(&mut writer);
+        assert_eq!(writer.get_channel_names(), vec!["B", "G", "R", "normal.X", "normal.Y", "normal.Z", "objectId.id"]);
+        assert_eq!(writer.get_img_dim(), (12, 10));
+    }
+}
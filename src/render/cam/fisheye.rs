@@ -0,0 +1,181 @@
+use cgmath::{Vector3, Vector2, Zero, ElementWise, InnerSpace};
+use cgmath::num_traits::Inv;
+use std::sync::Arc;
+
+use crate::{render::cam::{CamStruct, Camera},
+            ray::Ray,
+            world::world::World};
+use crate::utils::colorconstant::COLOR_BLACK;
+use crate::output::OutputManager;
+use crate::render::renderdata::{RenderMeta, RenderSample};
+use crate::utils::color::Colorf;
+use crate::math::constants::PI_OVER_180;
+use crate::sampler::pixelsampler::{set_thread_sampler, start_pixel_sample, get_2d};
+use crate::utils::scenehash::SceneHasher;
+
+/// Fisheye camera with an equidistant projection, as used for dome projection.
+/// The image circle is inscribed in the smaller dimension of the view plane;
+/// pixels outside of it are left black.
+pub struct FishEye
+{
+    m_zoom: f32,
+    m_inv_zoom: f32,
+    pub m_core: CamStruct,
+    /// Half of the field of view, in degrees
+    pub m_psi_max: f32,
+}
+
+impl FishEye
+{
+    pub fn new(eye: Vector3<f32>, lookat: Vector3<f32>, up: Vector3<f32>, field_of_view: f32) -> FishEye
+    {
+        let mut core = CamStruct::new(eye, lookat, up);
+        core.compute_uvw();
+        FishEye{ m_core: core, m_zoom: 1.0, m_inv_zoom: 1.0, m_psi_max: 0.5 * field_of_view }
+    }
+
+    /// "ndc" are the normalised device coordinates of the sample, see ViewPlane::get_normalized_coordinate.
+    /// Return None if the sample falls outside of the image circle.
+    pub fn get_fisheye_direction(&self, ndc: Vector2<f32>) -> Option<Vector3<f32>>
+    {
+        let ndc = ndc.mul_element_wise(self.m_inv_zoom);
+        let r_squared = ndc.magnitude2();
+        if r_squared > 1.0
+        {
+            return None;
+        }
+
+        let r = r_squared.sqrt();
+        let psi = r * self.m_psi_max * PI_OVER_180;
+        let (sin_alpha, cos_alpha) = if r > 0.0 { (ndc.y / r, ndc.x / r) } else { (0.0, 0.0) };
+        Some((self.m_core.m_u.mul_element_wise(psi.sin() * cos_alpha)
+            + self.m_core.m_v.mul_element_wise(psi.sin() * sin_alpha)
+            - self.m_core.m_w.mul_element_wise(psi.cos()))
+            .normalize())
+    }
+}
+
+impl Camera for FishEye
+{
+    /// "vp_coords" are expected to be normalised device coordinates
+    fn get_ray_direction(&self, vp_coords: Vector2<f32>) -> Vector3<f32>
+    {
+        self.get_fisheye_direction(vp_coords).unwrap_or(Vector3::zero())
+    }
+
+    fn render_sample(&self, world_ptr: &Arc<World>, x: usize, y: usize, sample_index: u32) -> RenderSample
+    {
+        let vp = world_ptr.m_viewplaneptr.as_ref();
+        start_pixel_sample(x as u32, y as u32, sample_index);
+        let sample = get_2d();
+        let ndc = vp.get_normalized_coordinate(x as u16, y as u16, sample, true);
+        let color = match self.get_fisheye_direction(ndc)
+        {
+            Some(direction) =>
+                {
+                    let ray = Ray::new_with_time(self.m_core.m_eye, direction, self.m_core.get_time_sample());
+                    world_ptr.as_ref().m_tracer.trace_ray(world_ptr.clone(), &ray, 0) * self.m_core.m_exposure_time
+                }
+            None => COLOR_BLACK, // Outside of the image circle
+        };
+        RenderSample::from_pixel(x, y, sample, color)
+    }
+
+    fn render_scene(&mut self, worldptr: Arc<World>, outmgr: &mut dyn OutputManager)
+    {
+        let vp = worldptr.m_viewplaneptr.as_ref();
+        set_thread_sampler(vp.get_pixel_sampler());
+
+        for x in 0..vp.m_hres
+        {
+            for y in 0..vp.m_vres
+            {
+                let clr = self.render_pixel(&worldptr, x.into(), y.into());
+                outmgr.write_pixel(x.into(), y.into(), clr);
+            }
+        }
+    }
+
+    fn render(&self, world_ptr: Arc<World>, rendermeta: &RenderMeta) -> Vec<Colorf>
+    {
+        set_thread_sampler(world_ptr.m_viewplaneptr.get_pixel_sampler());
+        let start_coords = rendermeta.get_start_coords();
+        let end_coords = rendermeta.get_end_coords();
+        let mut samples = Vec::with_capacity(rendermeta.m_area);
+
+        for x in start_coords.0..end_coords.0
+        {
+            for y in start_coords.1..end_coords.1
+            {
+                samples.push(self.render_pixel(&world_ptr, x, y));
+            }
+        }
+        samples
+    }
+
+    fn set_zoom(&mut self, zoom: f32)
+    {
+        if zoom == 0.0
+        {
+            panic!("Zoom factor can't be 0")
+        }
+        else
+        {
+            self.m_zoom = zoom;
+            self.m_inv_zoom = zoom.inv();
+        }
+    }
+
+    fn get_zoom(&mut self) -> f32
+    {
+        self.m_zoom
+    }
+
+    fn get_exposure_time(&self) -> f32
+    {
+        self.m_core.m_exposure_time
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("fisheye");
+        self.m_core.hash_scene(hasher);
+        hasher.write_f32(self.m_zoom);
+        hasher.write_f32(self.m_psi_max);
+    }
+}
+
+#[cfg(test)]
+mod FishEyeTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn setup_fisheye() -> FishEye
+    {
+        FishEye::new(Vector3::zero(), Vector3::new(0.0, 0.0, 10.0), Vector3::unit_y(), 180.0)
+    }
+
+    #[test]
+    fn center_looks_forward()
+    {
+        let fisheye = setup_fisheye();
+        let direction = fisheye.get_fisheye_direction(Vector2::zero()).unwrap();
+        assert_relative_eq!(direction, -fisheye.m_core.m_w);
+    }
+
+    #[test]
+    fn rim_reaches_half_field_of_view()
+    {
+        let fisheye = setup_fisheye();
+        let direction = fisheye.get_fisheye_direction(Vector2::new(0.0, 1.0)).unwrap();
+        assert_relative_eq!(direction, fisheye.m_core.m_v, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn outside_image_circle()
+    {
+        let fisheye = setup_fisheye();
+        assert!(fisheye.get_fisheye_direction(Vector2::new(0.8, 0.8)).is_none());
+    }
+}
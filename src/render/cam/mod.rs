@@ -1,4 +1,9 @@
 pub mod pinhole;
+pub mod thinlens;
+pub mod orthographic;
+pub mod fisheye;
+pub mod spherical;
+pub mod stereo;
 
 use cgmath::{Vector3,
              InnerSpace,
@@ -10,9 +15,15 @@ use std::{f32,
           sync::Arc};
 use crate::{world::world::World,
             output::OutputManager};
+use crate::render::aov::{AovTile, render_sample_with_aovs};
 use crate::render::renderbuffer::RenderBuffer;
-use crate::render::renderdata::RenderMeta;
+use crate::render::renderdata::{RenderMeta, RenderSample};
+use crate::render::film::FilmTile;
 use crate::utils::color::Colorf;
+use crate::utils::colorconstant::COLOR_BLACK;
+use crate::world::viewplane::ViewPlane;
+use crate::sampler::pixelsampler::{get_1d, set_thread_sampler};
+use crate::utils::scenehash::SceneHasher;
 
 type Point3<T> = Vector3<T>;
 
@@ -47,6 +58,15 @@ impl CamStruct
         }
     }
 
+    /// Placement and exposure, shared by every kind of camera
+    pub fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_vector3(self.m_eye);
+        hasher.write_vector3(self.m_lookat);
+        hasher.write_vector3(self.m_up);
+        hasher.write_f32(self.m_exposure_time);
+    }
+
     /// Compute the world denotation of axis of render's local coordinate system
     pub fn compute_uvw(&mut self)
     {
@@ -61,6 +81,17 @@ impl CamStruct
         self.m_u = self.m_up.cross(self.m_w).normalize();
         self.m_v = self.m_w.cross(self.m_u).normalize();
     }
+
+    /// Pick a moment within the shutter interval, which starts at 0 and lasts for m_exposure_time.
+    /// It consumes a dimension of the thread sampler.
+    pub fn get_time_sample(&self) -> f32
+    {
+        if self.m_exposure_time > 0.0
+        {
+            get_1d() * self.m_exposure_time
+        }
+        else { 0.0 }
+    }
 }
 
 pub trait Camera: Send + Sync
@@ -69,8 +100,51 @@ pub trait Camera: Send + Sync
     // TODO: remove render_scene, as it directly writes to OutputManager
     fn render_scene<'a>(&mut self, worldptr: Arc<World>, outmgr: &'a mut dyn OutputManager);
     fn render(&self, world_ptr: Arc<World>, rendermeta: &RenderMeta) -> Vec<Colorf>;
+    /// The "sample_index"-th sample of pixel (x, y), scaled by the exposure time, and where it lies in the pixel.
+    /// It starts the pixel sample on the thread sampler.
+    fn render_sample(&self, world_ptr: &Arc<World>, x: usize, y: usize, sample_index: u32) -> RenderSample;
     fn set_zoom(&mut self, zoom: f32);
     fn get_zoom(&mut self) -> f32;
+    /// Time the shutter stays open, by which the cameras scale the radiance of their samples
+    fn get_exposure_time(&self) -> f32;
+    /// Write the kind and settings of the camera to the hash identifying the render
+    fn hash_scene(&self, hasher: &mut SceneHasher);
+
+    /// Average of the samples of pixel (x, y), as many as the view plane's sampler has per pattern
+    fn render_pixel(&self, world_ptr: &Arc<World>, x: usize, y: usize) -> Colorf
+    {
+        let sample_count = world_ptr.m_viewplaneptr.m_sampler.get_sample_per_pattern();
+        let sum = (0..sample_count)
+            .fold(COLOR_BLACK, |sum, sample_index| sum + self.render_sample(world_ptr, x, y, sample_index as u32).m_color);
+        (sum / sample_count as f32).clamp()
+    }
+
+    /// Splat the samples of the pixels of a block onto its film tile, and add their AOVs to "aovs"
+    fn render_to_film(&self, world_ptr: &Arc<World>, rendermeta: &RenderMeta, tile: &mut FilmTile, aovs: &mut AovTile)
+    {
+        let sample_count = world_ptr.m_viewplaneptr.m_sampler.get_sample_per_pattern();
+        let start_coords = rendermeta.get_start_coords();
+        let end_coords = rendermeta.get_end_coords();
+        set_thread_sampler(world_ptr.m_viewplaneptr.get_pixel_sampler());
+
+        for x in start_coords.0..end_coords.0
+        {
+            for y in start_coords.1..end_coords.1
+            {
+                let local_index = rendermeta.get_local_index(x, y);
+                for sample_index in 0..sample_count
+                {
+                    tile.add_sample(&render_sample_with_aovs(self, world_ptr, x, y, sample_index as u32, aovs, local_index));
+                }
+            }
+        }
+    }
+
+    /// Dimensions of the image rendered for a view plane
+    fn get_image_dim(&self, vp: &ViewPlane) -> (usize, usize)
+    {
+        (vp.m_hres as usize, vp.m_vres as usize)
+    }
 }
 
 #[cfg(test)]
@@ -90,4 +164,18 @@ mod CamStructTest
         cs.compute_uvw();
         assert_relative_eq!(cs.m_w, Vector3::new(-0.22941573387056174, 0.6882472016116852, 0.6882472016116852));
     }
+
+    #[test]
+    fn time_sample_within_shutter()
+    {
+        let mut cs = CamStruct::new(Vector3::zero(), Vector3::unit_z(), Vector3::unit_y());
+        cs.m_exposure_time = 0.5;
+        for _ in 0..100
+        {
+            let time = cs.get_time_sample();
+            assert!(time >= 0.0 && time < 0.5);
+        }
+        cs.m_exposure_time = 0.0;
+        assert_eq!(cs.get_time_sample(), 0.0);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,152 @@
+use cgmath::{Vector3, Vector2, Zero, ElementWise};
+use cgmath::num_traits::Inv;
+use std::sync::Arc;
+
+use crate::{render::cam::{CamStruct, Camera},
+            ray::Ray,
+            world::world::World};
+use crate::output::OutputManager;
+use crate::render::renderdata::{RenderMeta, RenderSample};
+use crate::utils::color::Colorf;
+use crate::sampler::pixelsampler::{set_thread_sampler, start_pixel_sample, get_2d};
+use crate::utils::scenehash::SceneHasher;
+
+/// Camera with parallel projection; all primary rays share the viewing direction
+/// and start from the view plane itself.
+pub struct Orthographic
+{
+    m_zoom: f32,
+    m_inv_zoom: f32,
+    pub m_core: CamStruct,
+}
+
+impl Orthographic
+{
+    pub fn new(eye: Vector3<f32>, lookat: Vector3<f32>, up: Vector3<f32>) -> Orthographic
+    {
+        let mut core = CamStruct::new(eye, lookat, up);
+        core.compute_uvw();
+        Orthographic{ m_core: core, m_zoom: 1.0, m_inv_zoom: 1.0 }
+    }
+
+    /// Zooming shrinks the area of the view plane that we look at
+    pub fn get_ray(&self, vp_coords: Vector2<f32>) -> Ray
+    {
+        let origin = self.m_core.m_eye
+            + self.m_core.m_u.mul_element_wise(vp_coords.x * self.m_inv_zoom)
+            + self.m_core.m_v.mul_element_wise(vp_coords.y * self.m_inv_zoom);
+        Ray::new(origin, self.get_ray_direction(vp_coords))
+    }
+}
+
+impl Camera for Orthographic
+{
+    fn get_ray_direction(&self, _vp_coords: Vector2<f32>) -> Vector3<f32>
+    {
+        -self.m_core.m_w
+    }
+
+    fn render_sample(&self, world_ptr: &Arc<World>, x: usize, y: usize, sample_index: u32) -> RenderSample
+    {
+        let vp = world_ptr.m_viewplaneptr.as_ref();
+        let (x, y) = (x as u16, y as u16);
+        start_pixel_sample(x as u32, y as u32, sample_index);
+        let sample = get_2d();
+        let actual_sample_point = vp.get_coordinate_from_index(x, y)
+            .unwrap_or(Vector2::zero())
+            .add_element_wise(sample);
+        let mut ray = self.get_ray(actual_sample_point);
+        ray.m_time = self.m_core.get_time_sample();
+        RenderSample::from_pixel(x.into(), y.into(), sample, world_ptr.as_ref().m_tracer.trace_ray(world_ptr.clone(), &ray, 0) * self.m_core.m_exposure_time)
+    }
+
+    fn render_scene(&mut self, worldptr: Arc<World>, outmgr: &mut dyn OutputManager)
+    {
+        let vp = worldptr.m_viewplaneptr.as_ref();
+        set_thread_sampler(vp.get_pixel_sampler());
+
+        for x in 0..vp.m_hres
+        {
+            for y in 0..vp.m_vres
+            {
+                let clr = self.render_pixel(&worldptr, x.into(), y.into());
+                outmgr.write_pixel(x.into(), y.into(), clr);
+            }
+        }
+    }
+
+    fn render(&self, world_ptr: Arc<World>, rendermeta: &RenderMeta) -> Vec<Colorf>
+    {
+        set_thread_sampler(world_ptr.m_viewplaneptr.get_pixel_sampler());
+        let start_coords = rendermeta.get_start_coords();
+        let end_coords = rendermeta.get_end_coords();
+        let mut samples = Vec::with_capacity(rendermeta.m_area);
+
+        for x in start_coords.0..end_coords.0
+        {
+            for y in start_coords.1..end_coords.1
+            {
+                samples.push(self.render_pixel(&world_ptr, x, y));
+            }
+        }
+        samples
+    }
+
+    fn set_zoom(&mut self, zoom: f32)
+    {
+        if zoom == 0.0
+        {
+            panic!("Zoom factor can't be 0")
+        }
+        else
+        {
+            self.m_zoom = zoom;
+            self.m_inv_zoom = zoom.inv();
+        }
+    }
+
+    fn get_zoom(&mut self) -> f32
+    {
+        self.m_zoom
+    }
+
+    fn get_exposure_time(&self) -> f32
+    {
+        self.m_core.m_exposure_time
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("orthographic");
+        self.m_core.hash_scene(hasher);
+        hasher.write_f32(self.m_zoom);
+    }
+}
+
+#[cfg(test)]
+mod OrthographicTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn rays_are_parallel()
+    {
+        let ortho = Orthographic::new(Vector3::new(0.0, 0.0, -10.0), Vector3::zero(), Vector3::unit_y());
+        let ray_a = ortho.get_ray(Vector2::new(-3.0, 2.0));
+        let ray_b = ortho.get_ray(Vector2::new(5.0, 7.0));
+
+        assert_relative_eq!(ray_a.m_direction, Vector3::unit_z());
+        assert_relative_eq!(ray_a.m_direction, ray_b.m_direction);
+        assert_relative_eq!(ray_b.m_origin - ray_a.m_origin, Vector3::new(-8.0, 5.0, 0.0), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn zoom_shrinks_view_plane()
+    {
+        let mut ortho = Orthographic::new(Vector3::new(0.0, 0.0, -10.0), Vector3::zero(), Vector3::unit_y());
+        ortho.set_zoom(2.0);
+        let ray = ortho.get_ray(Vector2::new(0.0, 4.0));
+        assert_relative_eq!(ray.m_origin, Vector3::new(0.0, 2.0, -10.0), epsilon = 1e-6);
+    }
+}
@@ -4,12 +4,13 @@ use std::sync::Arc;
 use crate::{render::cam::{CamStruct, Camera},
             ray::Ray,
             world::world::World};
-use crate::utils::colorconstant::COLOR_BLACK;
 use crate::output::OutputManager;
 use cgmath::num_traits::Inv;
 use crate::render::renderbuffer::RenderBuffer;
-use crate::render::renderdata::RenderMeta;
+use crate::render::renderdata::{RenderMeta, RenderSample};
 use crate::utils::color::Colorf;
+use crate::sampler::pixelsampler::{set_thread_sampler, start_pixel_sample, get_2d};
+use crate::utils::scenehash::SceneHasher;
 
 
 pub struct Pinhole
@@ -39,74 +40,49 @@ impl Camera for Pinhole
              - self.m_core.m_w.mul_element_wise(self.m_distance_from_vp))
              .normalize()
     }
+
+    fn render_sample(&self, world_ptr: &Arc<World>, x: usize, y: usize, sample_index: u32) -> RenderSample
+    {
+        let vp = world_ptr.m_viewplaneptr.as_ref();
+        start_pixel_sample(x as u32, y as u32, sample_index);
+        let sample = get_2d();
+        let pixel_coords = vp.get_coordinate_from_index(x as u16, y as u16).unwrap_or(Vector2::zero());
+        let actual_sample_point = pixel_coords.add_element_wise(sample);
+        let ray = Ray::new_with_time(self.m_core.m_eye,
+                                     self.get_ray_direction(actual_sample_point),
+                                     self.m_core.get_time_sample());
+        RenderSample::from_pixel(x, y, sample, world_ptr.as_ref().m_tracer.trace_ray(world_ptr.clone(), &ray, 0) * self.m_core.m_exposure_time)
+    }
+
     // Should render to buffer...
     fn render_scene<'a>(&mut self, worldptr: Arc<World>, outmgr: &'a mut dyn OutputManager)
     {
-        let mut clr = COLOR_BLACK;
         let vp = worldptr.m_viewplaneptr.as_ref();
-        let mut ray = Ray::new(self.m_core.m_eye, Vector3::new(0.0, 0.0, 1.0));
-        let mut actual_sample_point = Vector2::zero();
+        set_thread_sampler(vp.get_pixel_sampler());
 
         for x in 0..vp.m_hres
         {
             for y in 0..vp.m_vres
             {
-                clr = COLOR_BLACK;
-
-                for sample in vp.m_sampler.get_unit_square_pattern()
-                {
-                    // TODO: Shall we make sampler a member of viewplane ?
-                    actual_sample_point = vp.get_coordinate_from_index(x, y)
-                                            .unwrap_or(Vector2::zero())
-                                            .add_element_wise(*sample);
-                    ray.m_direction = self.get_ray_direction(actual_sample_point);
-                    clr += worldptr.as_ref().m_tracer.trace_ray(worldptr.clone(), &ray, 0);
-                    // TODO: Why should Tracer be part of the World class
-                }
-                let r_before = clr.m_g;
-                // print!("r_before {}", r_before.to_string());
-                clr /= (vp.m_sampler.get_sample_per_pattern() as f32);
-                // print!("r_after {}", clr.m_g.to_string());
-                clr *= self.m_core.m_exposure_time;
-                clr.clamp();
-                outmgr.write_pixel(x.into(), y.into(), clr, vp.get_inv_gamma());
+                let clr = self.render_pixel(&worldptr, x.into(), y.into());
+                outmgr.write_pixel(x.into(), y.into(), clr);
             }
         }
     }
 
     fn render(&self, world_ptr: Arc<World>, rendermeta: &RenderMeta) -> Vec<Colorf>
     {
-        let mut clr = COLOR_BLACK;
         let vp = world_ptr.m_viewplaneptr.as_ref();
-        let mut ray = Ray::new(self.m_core.m_eye, Vector3::new(0.0, 0.0, 1.0));
-        let mut actual_sample_point = Vector2::zero();
         let start_coords = rendermeta.get_start_coords();
         let end_coords = rendermeta.get_end_coords();
         let mut samples = Vec::with_capacity(rendermeta.m_area);
+        set_thread_sampler(vp.get_pixel_sampler());
 
         for x in start_coords.0..end_coords.0
         {
             for y in start_coords.1..end_coords.1
             {
-                clr = COLOR_BLACK;
-
-                for sample in vp.m_sampler.get_unit_square_pattern()
-                {
-                    // TODO: Shall we make sampler a member of viewplane ?
-                    actual_sample_point = vp.get_coordinate_from_index(x as u16, y as u16)
-                        .unwrap_or(Vector2::zero())
-                        .add_element_wise(*sample);
-                    ray.m_direction = self.get_ray_direction(actual_sample_point);
-                    clr += world_ptr.as_ref().m_tracer.trace_ray(world_ptr.clone(), &ray, 0);
-                    // TODO: Why should Tracer be part of the World class
-                }
-                let r_before = clr.m_g;
-                // print!("r_before {}", r_before.to_string());
-                clr /= (vp.m_sampler.get_sample_per_pattern() as f32); // this seems quite fishy
-                // print!("r_after {}", clr.m_g.to_string());
-                clr *= self.m_core.m_exposure_time;
-                clr.clamp();
-                samples.push(clr);
+                samples.push(self.render_pixel(&world_ptr, x, y));
             }
         }
         samples
@@ -129,4 +105,65 @@ impl Camera for Pinhole
     {
         self.m_zoom
     }
-}
\ No newline at end of file
+
+    fn get_exposure_time(&self) -> f32
+    {
+        self.m_core.m_exposure_time
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("pinhole");
+        self.m_core.hash_scene(hasher);
+        hasher.write_f32(self.m_zoom);
+        hasher.write_f32(self.m_distance_from_vp);
+    }
+}
+#[cfg(test)]
+mod PinholeTest
+{
+    use super::*;
+    use crate::render::testscene::{build_world, get_two_spheres, get_white_matte};
+    use crate::sampler::pixelsampler::SampleSequence;
+
+    /// Ambient occlusion between the two spheres depends on the sampled directions
+    fn setup_scene(sequence: SampleSequence, seed: u32) -> Arc<World>
+    {
+        let mut world = build_world((8, 8), seed, get_two_spheres(get_white_matte(), get_white_matte()));
+        world.m_viewplaneptr.m_sample_sequence = sequence;
+        Arc::new(world)
+    }
+
+    fn render_tiles(world: &Arc<World>, camera: &Pinhole, tiles: &[RenderMeta]) -> Vec<Vec<Colorf>>
+    {
+        tiles.iter().map(|tile| camera.render(world.clone(), tile)).collect()
+    }
+
+    #[test]
+    fn render_is_deterministic()
+    {
+        let camera = Pinhole::new(Vector3::new(0.0, 0.0, -100.0), Vector3::zero(), Vector3::unit_y());
+        let tiles = [RenderMeta::new((0, 0), (4, 8), (0, 0)), RenderMeta::new((4, 0), (8, 8), (1, 0))];
+
+        for sequence in [SampleSequence::Random, SampleSequence::OwenSobol].iter()
+        {
+            let world = setup_scene(*sequence, 42);
+            let in_order = render_tiles(&world, &camera, &tiles);
+
+            // Other thread, other tile order, other thread history
+            let reversed = std::thread::scope(|scope| {
+                scope.spawn(|| {
+                    render_tiles(&world, &camera, &tiles[..1]);
+                    let mut tiles = tiles.to_vec();
+                    tiles.reverse();
+                    render_tiles(&world, &camera, &tiles)
+                }).join().unwrap()
+            });
+            assert_eq!(in_order[0], reversed[1]);
+            assert_eq!(in_order[1], reversed[0]);
+
+            let other_seed = render_tiles(&setup_scene(*sequence, 43), &camera, &tiles);
+            assert_ne!(in_order, other_seed);
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use cgmath::{Vector3, Vector2, ElementWise, InnerSpace};
+use cgmath::num_traits::Inv;
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use crate::{render::cam::{CamStruct, Camera},
+            ray::Ray,
+            world::world::World};
+use crate::output::OutputManager;
+use crate::render::renderdata::{RenderMeta, RenderSample};
+use crate::utils::color::Colorf;
+use crate::math::constants::PI_OVER_180;
+use crate::sampler::pixelsampler::{set_thread_sampler, start_pixel_sample, get_2d};
+use crate::utils::scenehash::SceneHasher;
+
+/// Camera with a spherical (latitude-longitude) projection.
+/// With the default field of view of 360 x 180 degrees, it renders equirectangular panoramas.
+pub struct Spherical
+{
+    m_zoom: f32,
+    m_inv_zoom: f32,
+    pub m_core: CamStruct,
+    /// Half of the horizontal field of view, in degrees
+    pub m_lambda_max: f32,
+    /// Half of the vertical field of view, in degrees
+    pub m_psi_max: f32,
+}
+
+impl Spherical
+{
+    pub fn new(eye: Vector3<f32>, lookat: Vector3<f32>, up: Vector3<f32>) -> Spherical
+    {
+        let mut core = CamStruct::new(eye, lookat, up);
+        core.compute_uvw();
+        Spherical{ m_core: core, m_zoom: 1.0, m_inv_zoom: 1.0, m_lambda_max: 180.0, m_psi_max: 90.0 }
+    }
+
+    pub fn set_field_of_view(&mut self, horizontal: f32, vertical: f32)
+    {
+        self.m_lambda_max = 0.5 * horizontal;
+        self.m_psi_max = 0.5 * vertical;
+    }
+
+    /// "ndc" are the normalised device coordinates of the sample, see ViewPlane::get_normalized_coordinate.
+    pub fn get_spherical_direction(&self, ndc: Vector2<f32>) -> Vector3<f32>
+    {
+        let ndc = ndc.mul_element_wise(self.m_inv_zoom);
+        let lambda = ndc.x * self.m_lambda_max * PI_OVER_180;
+        let psi = ndc.y * self.m_psi_max * PI_OVER_180;
+        let phi = PI - lambda;
+        let theta = 0.5 * PI - psi;
+
+        (self.m_core.m_u.mul_element_wise(theta.sin() * phi.sin())
+            + self.m_core.m_v.mul_element_wise(theta.cos())
+            + self.m_core.m_w.mul_element_wise(theta.sin() * phi.cos()))
+            .normalize()
+    }
+}
+
+impl Camera for Spherical
+{
+    /// "vp_coords" are expected to be normalised device coordinates
+    fn get_ray_direction(&self, vp_coords: Vector2<f32>) -> Vector3<f32>
+    {
+        self.get_spherical_direction(vp_coords)
+    }
+
+    fn render_sample(&self, world_ptr: &Arc<World>, x: usize, y: usize, sample_index: u32) -> RenderSample
+    {
+        let vp = world_ptr.m_viewplaneptr.as_ref();
+        start_pixel_sample(x as u32, y as u32, sample_index);
+        let sample = get_2d();
+        let ndc = vp.get_normalized_coordinate(x as u16, y as u16, sample, false);
+        let ray = Ray::new_with_time(self.m_core.m_eye,
+                                     self.get_spherical_direction(ndc),
+                                     self.m_core.get_time_sample());
+        RenderSample::from_pixel(x, y, sample, world_ptr.as_ref().m_tracer.trace_ray(world_ptr.clone(), &ray, 0) * self.m_core.m_exposure_time)
+    }
+
+    fn render_scene(&mut self, worldptr: Arc<World>, outmgr: &mut dyn OutputManager)
+    {
+        let vp = worldptr.m_viewplaneptr.as_ref();
+        set_thread_sampler(vp.get_pixel_sampler());
+
+        for x in 0..vp.m_hres
+        {
+            for y in 0..vp.m_vres
+            {
+                let clr = self.render_pixel(&worldptr, x.into(), y.into());
+                outmgr.write_pixel(x.into(), y.into(), clr);
+            }
+        }
+    }
+
+    fn render(&self, world_ptr: Arc<World>, rendermeta: &RenderMeta) -> Vec<Colorf>
+    {
+        set_thread_sampler(world_ptr.m_viewplaneptr.get_pixel_sampler());
+        let start_coords = rendermeta.get_start_coords();
+        let end_coords = rendermeta.get_end_coords();
+        let mut samples = Vec::with_capacity(rendermeta.m_area);
+
+        for x in start_coords.0..end_coords.0
+        {
+            for y in start_coords.1..end_coords.1
+            {
+                samples.push(self.render_pixel(&world_ptr, x, y));
+            }
+        }
+        samples
+    }
+
+    fn set_zoom(&mut self, zoom: f32)
+    {
+        if zoom == 0.0
+        {
+            panic!("Zoom factor can't be 0")
+        }
+        else
+        {
+            self.m_zoom = zoom;
+            self.m_inv_zoom = zoom.inv();
+        }
+    }
+
+    fn get_zoom(&mut self) -> f32
+    {
+        self.m_zoom
+    }
+
+    fn get_exposure_time(&self) -> f32
+    {
+        self.m_core.m_exposure_time
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("spherical");
+        self.m_core.hash_scene(hasher);
+        hasher.write_f32(self.m_zoom);
+        hasher.write_f32(self.m_lambda_max);
+        hasher.write_f32(self.m_psi_max);
+    }
+}
+
+#[cfg(test)]
+mod SphericalTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use cgmath::Zero;
+
+    fn setup_spherical() -> Spherical
+    {
+        Spherical::new(Vector3::zero(), Vector3::new(0.0, 0.0, 10.0), Vector3::unit_y())
+    }
+
+    #[test]
+    fn center_looks_forward()
+    {
+        let spherical = setup_spherical();
+        assert_relative_eq!(spherical.get_spherical_direction(Vector2::zero()), -spherical.m_core.m_w, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn panorama_wraps_around()
+    {
+        let spherical = setup_spherical();
+        let left = spherical.get_spherical_direction(Vector2::new(-1.0, 0.0));
+        let right = spherical.get_spherical_direction(Vector2::new(1.0, 0.0));
+        assert_relative_eq!(left, spherical.m_core.m_w, epsilon = 1e-6);
+        assert_relative_eq!(right, spherical.m_core.m_w, epsilon = 1e-6);
+        assert_relative_eq!(spherical.get_spherical_direction(Vector2::new(0.5, 0.0)), spherical.m_core.m_u, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn poles()
+    {
+        let spherical = setup_spherical();
+        assert_relative_eq!(spherical.get_spherical_direction(Vector2::new(0.3, 1.0)), spherical.m_core.m_v, epsilon = 1e-6);
+        assert_relative_eq!(spherical.get_spherical_direction(Vector2::new(-0.7, -1.0)), -spherical.m_core.m_v, epsilon = 1e-6);
+    }
+}
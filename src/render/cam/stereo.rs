@@ -0,0 +1,413 @@
+use cgmath::{Vector3, Vector2, Zero, ElementWise, InnerSpace};
+use cgmath::num_traits::Inv;
+use std::sync::Arc;
+
+use crate::{render::cam::{CamStruct, Camera},
+            ray::Ray,
+            world::world::World};
+use crate::output::OutputManager;
+use crate::render::cam::pinhole::Pinhole;
+use crate::render::cam::spherical::Spherical;
+use crate::render::renderdata::{RenderMeta, RenderSample};
+use crate::utils::color::Colorf;
+use crate::world::viewplane::ViewPlane;
+use crate::sampler::pixelsampler::{set_thread_sampler, start_pixel_sample, get_2d};
+use crate::utils::scenehash::SceneHasher;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Eye
+{
+    Left,
+    Right,
+}
+
+impl Eye
+{
+    /// -1 for the left eye and 1 for the right eye, along the rig's "u" axis
+    fn get_sign(&self) -> f32
+    {
+        match self
+        {
+            Eye::Left => -1.0,
+            Eye::Right => 1.0,
+        }
+    }
+}
+
+/// How the lines of sight of the two eyes meet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Convergence
+{
+    /// Both eyes look in the same direction. The view planes are shifted so that
+    /// objects at the lookat distance have zero parallax.
+    Parallel,
+    /// Both eyes are rotated to look at the lookat point
+    ToeIn,
+}
+
+/// Which projection each eye uses
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoProjection
+{
+    /// Two Pinhole cameras
+    Planar,
+    /// Omni-directional stereo; each eye renders an equirectangular panorama
+    /// with ray origins on a circle of diameter equal to the interocular distance.
+    OmniDirectional,
+}
+
+/// How the images of the two eyes are arranged in the output image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StereoLayout
+{
+    SideBySide,
+    TopBottom,
+}
+
+/// Stereo camera rig that renders both eyes into a single image.
+/// The view plane describes the image of one eye; Camera::get_image_dim
+/// gives the dimensions of the combined image.
+pub struct Stereo
+{
+    m_zoom: f32,
+    m_inv_zoom: f32,
+    pub m_core: CamStruct,
+    m_distance_from_vp: f32,
+    m_interocular_distance: f32,
+    m_convergence: Convergence,
+    m_projection: StereoProjection,
+    m_layout: StereoLayout,
+    m_left: Pinhole,
+    m_right: Pinhole,
+    m_panorama: Spherical,
+}
+
+impl Stereo
+{
+    pub fn new(eye: Vector3<f32>, lookat: Vector3<f32>, up: Vector3<f32>, interocular_distance: f32) -> Stereo
+    {
+        let mut core = CamStruct::new(eye, lookat, up);
+        core.compute_uvw();
+        let mut stereo = Stereo
+        {
+            m_zoom: 1.0,
+            m_inv_zoom: 1.0,
+            m_core: core,
+            m_distance_from_vp: 50.0,
+            m_interocular_distance: interocular_distance,
+            m_convergence: Convergence::Parallel,
+            m_projection: StereoProjection::Planar,
+            m_layout: StereoLayout::SideBySide,
+            m_left: Pinhole::new(eye, lookat, up),
+            m_right: Pinhole::new(eye, lookat, up),
+            m_panorama: Spherical::new(eye, lookat, up),
+        };
+        stereo.setup_cameras();
+        stereo
+    }
+
+    pub fn set_interocular_distance(&mut self, distance: f32)
+    {
+        self.m_interocular_distance = distance;
+        self.setup_cameras();
+    }
+
+    pub fn set_distance_from_vp(&mut self, distance: f32)
+    {
+        self.m_distance_from_vp = distance;
+        self.setup_cameras();
+    }
+
+    pub fn set_convergence(&mut self, convergence: Convergence)
+    {
+        self.m_convergence = convergence;
+        self.setup_cameras();
+    }
+
+    pub fn set_projection(&mut self, projection: StereoProjection)
+    {
+        self.m_projection = projection;
+    }
+
+    pub fn set_layout(&mut self, layout: StereoLayout)
+    {
+        self.m_layout = layout;
+    }
+
+    pub fn get_eye_camera(&self, eye: Eye) -> &Pinhole
+    {
+        match eye
+        {
+            Eye::Left => &self.m_left,
+            Eye::Right => &self.m_right,
+        }
+    }
+
+    /// Find out which eye, and which pixel of that eye, a pixel of the combined image belongs to
+    pub fn get_eye_pixel(&self, vp: &ViewPlane, x: usize, y: usize) -> (Eye, u16, u16)
+    {
+        let (hres, vres) = (vp.m_hres as usize, vp.m_vres as usize);
+        match self.m_layout
+        {
+            StereoLayout::SideBySide if x < hres => (Eye::Left, x as u16, y as u16),
+            StereoLayout::SideBySide => (Eye::Right, (x - hres) as u16, y as u16),
+            StereoLayout::TopBottom if y < vres => (Eye::Left, x as u16, y as u16),
+            StereoLayout::TopBottom => (Eye::Right, x as u16, (y - vres) as u16),
+        }
+    }
+
+    /// The horizontal shift of the view plane for parallel viewing
+    fn get_vp_shift(&self, eye: Eye) -> f32
+    {
+        match self.m_convergence
+        {
+            Convergence::Parallel =>
+                {
+                    let lookat_distance = (self.m_core.m_lookat - self.m_core.m_eye).magnitude();
+                    -eye.get_sign() * 0.5 * self.m_interocular_distance * self.m_distance_from_vp / lookat_distance
+                }
+            Convergence::ToeIn => 0.0,
+        }
+    }
+
+    /// Generate the ray of a planar eye through a point of its view plane
+    pub fn get_planar_ray(&self, eye: Eye, vp_coords: Vector2<f32>) -> Ray
+    {
+        let camera = self.get_eye_camera(eye);
+        let shifted_coords = vp_coords.add_element_wise(Vector2::new(self.get_vp_shift(eye), 0.0));
+        Ray::new(camera.m_core.m_eye, camera.get_ray_direction(shifted_coords.mul_element_wise(self.m_inv_zoom)))
+    }
+
+    /// Generate the omni-directional stereo ray for normalised device coordinates of a panorama
+    pub fn get_ods_ray(&self, eye: Eye, ndc: Vector2<f32>) -> Ray
+    {
+        let direction = self.m_panorama.get_spherical_direction(ndc.mul_element_wise(self.m_inv_zoom));
+        let up = self.m_core.m_v;
+        let horizontal = direction - up.mul_element_wise(direction.dot(up));
+        let offset = if horizontal.magnitude2() > 0.0
+        {
+            horizontal.cross(up).normalize()
+                .mul_element_wise(eye.get_sign() * 0.5 * self.m_interocular_distance)
+        }
+        else { Vector3::zero() };
+        Ray::new(self.m_core.m_eye + offset, direction)
+    }
+
+    fn setup_cameras(&mut self)
+    {
+        let half_offset = self.m_core.m_u.mul_element_wise(0.5 * self.m_interocular_distance);
+        let (left_lookat, right_lookat) = match self.m_convergence
+        {
+            Convergence::Parallel => (self.m_core.m_lookat - half_offset, self.m_core.m_lookat + half_offset),
+            Convergence::ToeIn => (self.m_core.m_lookat, self.m_core.m_lookat),
+        };
+
+        self.m_left = Pinhole::new(self.m_core.m_eye - half_offset, left_lookat, self.m_core.m_up);
+        self.m_right = Pinhole::new(self.m_core.m_eye + half_offset, right_lookat, self.m_core.m_up);
+        self.m_left.m_distance_from_vp = self.m_distance_from_vp;
+        self.m_right.m_distance_from_vp = self.m_distance_from_vp;
+        self.m_panorama = Spherical::new(self.m_core.m_eye, self.m_core.m_lookat, self.m_core.m_up);
+    }
+}
+
+impl Camera for Stereo
+{
+    /// Direction of the ray through the center of the rig
+    fn get_ray_direction(&self, vp_coords: Vector2<f32>) -> Vector3<f32>
+    {
+        (self.m_core.m_u.mul_element_wise(vp_coords.x)
+            + self.m_core.m_v.mul_element_wise(vp_coords.y)
+            - self.m_core.m_w.mul_element_wise(self.m_distance_from_vp))
+            .normalize()
+    }
+
+    fn render_sample(&self, world_ptr: &Arc<World>, x: usize, y: usize, sample_index: u32) -> RenderSample
+    {
+        let vp = world_ptr.m_viewplaneptr.as_ref();
+        let (eye, eye_x, eye_y) = self.get_eye_pixel(vp, x, y);
+        start_pixel_sample(x as u32, y as u32, sample_index);
+        let sample = get_2d();
+        let mut ray = match self.m_projection
+        {
+            StereoProjection::Planar =>
+                {
+                    let vp_coords = vp.get_coordinate_from_index(eye_x, eye_y)
+                        .unwrap_or(Vector2::zero())
+                        .add_element_wise(sample);
+                    self.get_planar_ray(eye, vp_coords)
+                }
+            StereoProjection::OmniDirectional =>
+                {
+                    self.get_ods_ray(eye, vp.get_normalized_coordinate(eye_x, eye_y, sample, false))
+                }
+        };
+        ray.m_time = self.m_core.get_time_sample();
+        RenderSample::from_pixel(x, y, sample, world_ptr.as_ref().m_tracer.trace_ray(world_ptr.clone(), &ray, 0) * self.m_core.m_exposure_time)
+    }
+
+    fn render_scene(&mut self, worldptr: Arc<World>, outmgr: &mut dyn OutputManager)
+    {
+        let vp = worldptr.m_viewplaneptr.as_ref();
+        set_thread_sampler(vp.get_pixel_sampler());
+        let img_dim = self.get_image_dim(vp);
+
+        for x in 0..img_dim.0
+        {
+            for y in 0..img_dim.1
+            {
+                let clr = self.render_pixel(&worldptr, x, y);
+                outmgr.write_pixel(x, y, clr);
+            }
+        }
+    }
+
+    /// "rendermeta" refers to a block of the combined image
+    fn render(&self, world_ptr: Arc<World>, rendermeta: &RenderMeta) -> Vec<Colorf>
+    {
+        set_thread_sampler(world_ptr.m_viewplaneptr.get_pixel_sampler());
+        let start_coords = rendermeta.get_start_coords();
+        let end_coords = rendermeta.get_end_coords();
+        let mut samples = Vec::with_capacity(rendermeta.m_area);
+
+        for x in start_coords.0..end_coords.0
+        {
+            for y in start_coords.1..end_coords.1
+            {
+                samples.push(self.render_pixel(&world_ptr, x, y));
+            }
+        }
+        samples
+    }
+
+    fn set_zoom(&mut self, zoom: f32)
+    {
+        if zoom == 0.0
+        {
+            panic!("Zoom factor can't be 0")
+        }
+        else
+        {
+            self.m_zoom = zoom;
+            self.m_inv_zoom = zoom.inv();
+        }
+    }
+
+    fn get_zoom(&mut self) -> f32
+    {
+        self.m_zoom
+    }
+
+    fn get_exposure_time(&self) -> f32
+    {
+        self.m_core.m_exposure_time
+    }
+
+    /// Dimensions of the combined image
+    fn get_image_dim(&self, vp: &ViewPlane) -> (usize, usize)
+    {
+        match self.m_layout
+        {
+            StereoLayout::SideBySide => (2 * vp.m_hres as usize, vp.m_vres as usize),
+            StereoLayout::TopBottom => (vp.m_hres as usize, 2 * vp.m_vres as usize),
+        }
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("stereo");
+        self.m_core.hash_scene(hasher);
+        hasher.write_f32(self.m_zoom);
+        hasher.write_f32(self.m_distance_from_vp);
+        hasher.write_f32(self.m_interocular_distance);
+        hasher.write_str(&format!("{:?} {:?} {:?}", self.m_convergence, self.m_projection, self.m_layout));
+        // The eyes are set up from the rig, but may have been adjusted since
+        self.m_left.hash_scene(hasher);
+        self.m_right.hash_scene(hasher);
+        self.m_panorama.hash_scene(hasher);
+    }
+}
+
+#[cfg(test)]
+mod StereoTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use crate::sampler::dummy::DummySampler;
+
+    fn setup_stereo(convergence: Convergence) -> Stereo
+    {
+        let mut stereo = Stereo::new(Vector3::zero(), Vector3::new(0.0, 0.0, 100.0), Vector3::unit_y(), 6.0);
+        stereo.set_distance_from_vp(40.0);
+        stereo.set_convergence(convergence);
+        stereo
+    }
+
+    fn distance_to_lookat(stereo: &Stereo, ray: &Ray) -> f32
+    {
+        let to_lookat = stereo.m_core.m_lookat - ray.m_origin;
+        (to_lookat - ray.m_direction * to_lookat.dot(ray.m_direction)).magnitude()
+    }
+
+    #[test]
+    fn parallel_eyes_converge_at_lookat()
+    {
+        let stereo = setup_stereo(Convergence::Parallel);
+        let left = stereo.get_eye_camera(Eye::Left);
+        let right = stereo.get_eye_camera(Eye::Right);
+        assert_relative_eq!(left.m_core.m_w, right.m_core.m_w);
+        assert_relative_eq!((right.m_core.m_eye - left.m_core.m_eye).magnitude(), 6.0);
+
+        for eye in [Eye::Left, Eye::Right].iter()
+        {
+            let ray = stereo.get_planar_ray(*eye, Vector2::zero());
+            assert_relative_eq!(distance_to_lookat(&stereo, &ray), 0.0, epsilon = 1e-3);
+        }
+    }
+
+    #[test]
+    fn toe_in_eyes_look_at_lookat()
+    {
+        let stereo = setup_stereo(Convergence::ToeIn);
+        for eye in [Eye::Left, Eye::Right].iter()
+        {
+            let ray = stereo.get_planar_ray(*eye, Vector2::zero());
+            assert_relative_eq!(distance_to_lookat(&stereo, &ray), 0.0, epsilon = 1e-3);
+        }
+    }
+
+    #[test]
+    fn ods_origins_lie_on_circle()
+    {
+        let stereo = setup_stereo(Convergence::Parallel);
+        for ndc in [Vector2::new(0.0, 0.0), Vector2::new(0.5, 0.2), Vector2::new(-0.9, -0.4)].iter()
+        {
+            let left = stereo.get_ods_ray(Eye::Left, *ndc);
+            let right = stereo.get_ods_ray(Eye::Right, *ndc);
+            assert_relative_eq!(left.m_direction, right.m_direction);
+            assert_relative_eq!((left.m_origin - stereo.m_core.m_eye).magnitude(), 3.0, epsilon = 1e-5);
+            assert_relative_eq!(left.m_origin + right.m_origin, 2.0 * stereo.m_core.m_eye, epsilon = 1e-5);
+            assert_relative_eq!((left.m_origin - stereo.m_core.m_eye).dot(left.m_direction), 0.0, epsilon = 1e-5);
+        }
+        // Looking forward, the left eye sits on the left
+        let left = stereo.get_ods_ray(Eye::Left, Vector2::zero());
+        assert_relative_eq!(left.m_origin, stereo.m_core.m_u * -3.0, epsilon = 1e-5);
+    }
+
+    #[test]
+    fn layout_maps_pixels_to_eyes()
+    {
+        let mut vp = ViewPlane::new(Arc::new(DummySampler::new(1, 1)));
+        vp.m_hres = 40;
+        vp.m_vres = 30;
+        let mut stereo = setup_stereo(Convergence::Parallel);
+
+        assert_eq!(stereo.get_image_dim(&vp), (80, 30));
+        assert_eq!(stereo.get_eye_pixel(&vp, 39, 5), (Eye::Left, 39, 5));
+        assert_eq!(stereo.get_eye_pixel(&vp, 45, 5), (Eye::Right, 5, 5));
+
+        stereo.set_layout(StereoLayout::TopBottom);
+        assert_eq!(stereo.get_image_dim(&vp), (40, 60));
+        assert_eq!(stereo.get_eye_pixel(&vp, 10, 29), (Eye::Left, 10, 29));
+        assert_eq!(stereo.get_eye_pixel(&vp, 10, 31), (Eye::Right, 10, 1));
+    }
+}
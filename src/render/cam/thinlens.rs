@@ -0,0 +1,356 @@
+use cgmath::{Vector3, Vector2, Zero, ElementWise, InnerSpace};
+use cgmath::num_traits::Inv;
+use std::f32::consts::PI;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::{render::cam::{CamStruct, Camera},
+            ray::Ray,
+            world::world::World};
+use crate::output::OutputManager;
+use crate::render::renderdata::{RenderMeta, RenderSample};
+use crate::sampler::Sampler;
+use crate::utils::color::Colorf;
+use crate::sampler::pixelsampler::{set_thread_sampler, start_pixel_sample, get_2d};
+use crate::utils::scenehash::SceneHasher;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApertureError
+{
+    /// A polygon needs at least 3 blades
+    TooFewBlades(u16),
+}
+
+impl std::error::Error for ApertureError {}
+
+impl fmt::Display for ApertureError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            ApertureError::TooFewBlades(blades) =>
+                write!(f, "A polygonal aperture needs at least 3 blades, got {}", blades),
+        }
+    }
+}
+
+/// Shape of the lens aperture, which decides the shape of the bokeh
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aperture
+{
+    Circular,
+    /// A regular polygon with "blades" sides, rotated by "rotation" radians
+    Polygonal { blades: u16, rotation: f32 },
+}
+
+impl Aperture
+{
+    /// Polygons need at least 3 blades
+    pub fn is_valid(&self) -> bool
+    {
+        match *self
+        {
+            Aperture::Circular => true,
+            Aperture::Polygonal { blades, .. } => blades >= 3,
+        }
+    }
+
+    /// Map a sample on the unit disk onto the aperture, which must be valid.
+    /// For polygonal apertures, the radius of the sample is scaled by the distance
+    /// from the center to the polygon's edge along the direction of the sample.
+    pub fn map_disk_sample(&self, disk_sample: Vector2<f32>) -> Vector2<f32>
+    {
+        match *self
+        {
+            Aperture::Circular => disk_sample,
+            Aperture::Polygonal { blades, rotation } =>
+                {
+                    let half_sector = PI / blades as f32;
+                    let phi = disk_sample.y.atan2(disk_sample.x) - rotation;
+                    let local_phi = phi.rem_euclid(2.0 * half_sector) - half_sector;
+                    disk_sample.mul_element_wise(half_sector.cos() / local_phi.cos())
+                }
+        }
+    }
+}
+
+/// Camera with a thin lens of finite aperture, which gives us depth of field.
+/// Every point on the focal plane is in focus; the rest gets blurred.
+pub struct ThinLens
+{
+    m_zoom: f32,
+    m_inv_zoom: f32,
+    pub m_core: CamStruct,
+    pub m_distance_from_vp: f32,
+    pub m_lens_radius: f32,
+    pub m_focal_distance: f32,
+    m_aperture: Aperture,
+    m_samplerptr: Arc<dyn Sampler>,
+}
+
+impl ThinLens
+{
+    /// The lens samples come from the disk samples of "sampler", see set_sampler
+    pub fn new(eye: Vector3<f32>,
+               lookat: Vector3<f32>,
+               up: Vector3<f32>,
+               lens_radius: f32,
+               focal_distance: f32,
+               mut sampler: Box<dyn Sampler>) -> ThinLens
+    {
+        let mut core = CamStruct::new(eye, lookat, up);
+        core.compute_uvw();
+        sampler.set_map_to_disk(true);
+        ThinLens
+        {
+            m_zoom: 1.0,
+            m_inv_zoom: 1.0,
+            m_core: core,
+            m_distance_from_vp: 50.0,
+            m_lens_radius: lens_radius,
+            m_focal_distance: focal_distance,
+            m_aperture: Aperture::Circular,
+            m_samplerptr: Arc::from(sampler),
+        }
+    }
+
+    /// Sampler of the lens, with its patterns generated, which gets them mapped to the unit disk.
+    /// Its patterns and samples are picked with the pixel sampler of the rendering thread.
+    pub fn set_sampler(&mut self, mut sampler: Box<dyn Sampler>)
+    {
+        sampler.set_map_to_disk(true);
+        self.m_samplerptr = Arc::from(sampler);
+    }
+
+    pub fn set_aperture(&mut self, aperture: Aperture) -> Result<(), ApertureError>
+    {
+        match aperture
+        {
+            Aperture::Polygonal { blades, .. } if !aperture.is_valid() => Err(ApertureError::TooFewBlades(blades)),
+            _ =>
+                {
+                    self.m_aperture = aperture;
+                    Ok(())
+                }
+        }
+    }
+
+    pub fn get_aperture(&self) -> Aperture
+    {
+        self.m_aperture
+    }
+
+    /// Compute the direction of the ray leaving "lens_point" that passes through
+    /// the point on the focal plane hit by the ray through the lens center.
+    pub fn get_lens_ray_direction(&self, vp_coords: Vector2<f32>, lens_point: Vector2<f32>) -> Vector3<f32>
+    {
+        let focal_point = vp_coords.mul_element_wise(self.m_focal_distance / self.m_distance_from_vp);
+        (self.m_core.m_u.mul_element_wise(focal_point.x - lens_point.x)
+            + self.m_core.m_v.mul_element_wise(focal_point.y - lens_point.y)
+            - self.m_core.m_w.mul_element_wise(self.m_focal_distance))
+            .normalize()
+    }
+
+    /// Generate the primary ray for a point on the view plane and a sample on the unit disk
+    pub fn get_ray(&self, vp_coords: Vector2<f32>, disk_sample: Vector2<f32>) -> Ray
+    {
+        let lens_point = self.m_aperture.map_disk_sample(disk_sample)
+            .mul_element_wise(self.m_lens_radius);
+        let origin = self.m_core.m_eye
+            + self.m_core.m_u.mul_element_wise(lens_point.x)
+            + self.m_core.m_v.mul_element_wise(lens_point.y);
+        Ray::new(origin, self.get_lens_ray_direction(vp_coords, lens_point))
+    }
+}
+
+impl Camera for ThinLens
+{
+    fn get_ray_direction(&self, vp_coords: Vector2<f32>) -> Vector3<f32>
+    {
+        self.get_lens_ray_direction(vp_coords, Vector2::zero())
+    }
+
+    fn render_sample(&self, world_ptr: &Arc<World>, x: usize, y: usize, sample_index: u32) -> RenderSample
+    {
+        let vp = world_ptr.m_viewplaneptr.as_ref();
+        let (x, y) = (x as u16, y as u16);
+        start_pixel_sample(x as u32, y as u32, sample_index);
+        let sample = get_2d();
+        let actual_sample_point = vp.get_coordinate_from_index(x, y)
+            .unwrap_or(Vector2::zero())
+            .add_element_wise(sample);
+        let mut ray = self.get_ray(actual_sample_point, self.m_samplerptr.get_disk_sample());
+        ray.m_time = self.m_core.get_time_sample();
+        RenderSample::from_pixel(x.into(), y.into(), sample, world_ptr.as_ref().m_tracer.trace_ray(world_ptr.clone(), &ray, 0) * self.m_core.m_exposure_time)
+    }
+
+    fn render_scene(&mut self, worldptr: Arc<World>, outmgr: &mut dyn OutputManager)
+    {
+        let vp = worldptr.m_viewplaneptr.as_ref();
+        set_thread_sampler(vp.get_pixel_sampler());
+
+        for x in 0..vp.m_hres
+        {
+            for y in 0..vp.m_vres
+            {
+                let clr = self.render_pixel(&worldptr, x.into(), y.into());
+                outmgr.write_pixel(x.into(), y.into(), clr);
+            }
+        }
+    }
+
+    fn render(&self, world_ptr: Arc<World>, rendermeta: &RenderMeta) -> Vec<Colorf>
+    {
+        set_thread_sampler(world_ptr.m_viewplaneptr.get_pixel_sampler());
+        let start_coords = rendermeta.get_start_coords();
+        let end_coords = rendermeta.get_end_coords();
+        let mut samples = Vec::with_capacity(rendermeta.m_area);
+
+        for x in start_coords.0..end_coords.0
+        {
+            for y in start_coords.1..end_coords.1
+            {
+                samples.push(self.render_pixel(&world_ptr, x, y));
+            }
+        }
+        samples
+    }
+
+    fn set_zoom(&mut self, zoom: f32)
+    {
+        if zoom == 0.0
+        {
+            panic!("Zoom factor can't be 0")
+        }
+        else
+        {
+            self.m_zoom = zoom;
+            self.m_inv_zoom = zoom.inv();
+        }
+    }
+
+    fn get_zoom(&mut self) -> f32
+    {
+        self.m_zoom
+    }
+
+    fn get_exposure_time(&self) -> f32
+    {
+        self.m_core.m_exposure_time
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("thinlens");
+        self.m_core.hash_scene(hasher);
+        hasher.write_f32(self.m_zoom);
+        hasher.write_f32(self.m_distance_from_vp);
+        hasher.write_f32(self.m_lens_radius);
+        hasher.write_f32(self.m_focal_distance);
+        hasher.write_str(&format!("{:?}", self.m_aperture));
+        hasher.write_u64(self.m_samplerptr.get_sample_per_pattern() as u64);
+    }
+}
+
+#[cfg(test)]
+mod ThinLensTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use crate::render::cam::pinhole::Pinhole;
+    use crate::sampler::mutijittered::MultiJittered;
+    use crate::sampler::pixelsampler::{PixelSampler, SampleSequence};
+
+    fn setup_thinlens(lens_radius: f32) -> ThinLens
+    {
+        let mut sampler = MultiJittered::new(16, 2);
+        sampler.generate_sample_pattern();
+        let mut thinlens = ThinLens::new(Vector3::new(0.0, 0.0, -100.0),
+                                         Vector3::zero(),
+                                         Vector3::unit_y(),
+                                         lens_radius,
+                                         80.0,
+                                         Box::new(sampler));
+        thinlens.m_distance_from_vp = 40.0;
+        thinlens
+    }
+
+    #[test]
+    fn pinhole_when_lens_is_closed()
+    {
+        let thinlens = setup_thinlens(0.0);
+        let mut pinhole = Pinhole::new(Vector3::new(0.0, 0.0, -100.0), Vector3::zero(), Vector3::unit_y());
+        pinhole.m_distance_from_vp = 40.0;
+
+        let vp_coords = Vector2::new(3.0, -7.5);
+        let ray = thinlens.get_ray(vp_coords, Vector2::new(0.3, 0.4));
+        assert_relative_eq!(ray.m_origin, thinlens.m_core.m_eye);
+        assert_relative_eq!(ray.m_direction, pinhole.get_ray_direction(vp_coords), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn rays_converge_on_focal_plane()
+    {
+        let thinlens = setup_thinlens(2.0);
+        let vp_coords = Vector2::new(-4.0, 6.0);
+        let center_ray = thinlens.get_ray(vp_coords, Vector2::zero());
+        let center_focal_point = center_ray.m_origin
+            + center_ray.m_direction * (thinlens.m_focal_distance / -center_ray.m_direction.dot(thinlens.m_core.m_w));
+
+        for disk_sample in [Vector2::new(1.0, 0.0), Vector2::new(-0.3, 0.7), Vector2::new(0.0, -0.5)].iter()
+        {
+            let ray = thinlens.get_ray(vp_coords, *disk_sample);
+            let t = thinlens.m_focal_distance / -ray.m_direction.dot(thinlens.m_core.m_w);
+            assert_relative_eq!(ray.m_origin + ray.m_direction * t, center_focal_point, epsilon = 1e-3);
+        }
+    }
+
+    #[test]
+    fn polygonal_aperture_bounds()
+    {
+        let aperture = Aperture::Polygonal { blades: 6, rotation: 0.0 };
+        // Vertices of the hexagon stay on the unit circle...
+        let vertex = aperture.map_disk_sample(Vector2::new(1.0, 0.0));
+        assert_relative_eq!(vertex.magnitude(), 1.0, epsilon = 1e-6);
+        // ...while midpoints of the edges are pulled in to the apothem.
+        let half_sector = PI / 6.0;
+        let edge_mid = aperture.map_disk_sample(Vector2::new(half_sector.cos(), half_sector.sin()));
+        assert_relative_eq!(edge_mid.magnitude(), half_sector.cos(), epsilon = 1e-6);
+    }
+
+    #[test]
+    fn aperture_with_two_blades_is_rejected()
+    {
+        let mut thinlens = setup_thinlens(1.0);
+        assert_eq!(thinlens.set_aperture(Aperture::Polygonal { blades: 2, rotation: 0.0 }),
+                   Err(ApertureError::TooFewBlades(2)));
+        assert_eq!(thinlens.get_aperture(), Aperture::Circular);
+        let hexagon = Aperture::Polygonal { blades: 6, rotation: 0.5 };
+        assert_eq!(thinlens.set_aperture(hexagon), Ok(()));
+        assert_eq!(thinlens.get_aperture(), hexagon);
+    }
+
+    #[test]
+    fn lens_samples_come_from_the_disk_samples()
+    {
+        let thinlens = setup_thinlens(1.0);
+        set_thread_sampler(PixelSampler::new(SampleSequence::Sobol, 7));
+        let mut samples = Vec::new();
+        for sample_index in 0..16
+        {
+            start_pixel_sample(3, 4, sample_index);
+            samples.push(thinlens.m_samplerptr.get_disk_sample());
+        }
+        assert!(samples.iter().all(|sample| sample.magnitude() <= 1.0));
+        // The same pixel sample picks the same lens sample
+        start_pixel_sample(3, 4, 5);
+        assert_eq!(thinlens.m_samplerptr.get_disk_sample(), samples[5]);
+    }
+
+    #[test]
+    fn circular_aperture_is_identity()
+    {
+        let sample = Vector2::new(0.25, -0.6);
+        assert_eq!(Aperture::Circular.map_disk_sample(sample), sample);
+    }
+}
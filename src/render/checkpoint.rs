@@ -0,0 +1,419 @@
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+
+use crate::render::cam::Camera;
+use crate::render::filter::Filter;
+use crate::sampler::pixelsampler::SampleSequence;
+use crate::utils::scenehash::SceneHasher;
+use crate::world::world::World;
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTCKPT03";
+
+#[derive(Debug)]
+pub enum CheckpointError
+{
+    IoError(io::Error),
+    InvalidFormat,
+    /// The checkpoint was saved from another scene, or with another seed or sequence
+    SceneMismatch,
+    /// The checkpoint was saved from a RenderBuffer of other dimensions, with another filter or other AOVs
+    BufferMismatch,
+}
+
+impl std::error::Error for CheckpointError {}
+
+impl fmt::Display for CheckpointError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            CheckpointError::IoError(error) => write!(f, "Can't access the checkpoint: {}", error),
+            CheckpointError::InvalidFormat => write!(f, "Not a render checkpoint"),
+            CheckpointError::SceneMismatch => write!(f, "The checkpoint belongs to another scene"),
+            CheckpointError::BufferMismatch => write!(f, "The checkpoint belongs to a buffer of other dimensions"),
+        }
+    }
+}
+
+impl From<io::Error> for CheckpointError
+{
+    fn from(error: io::Error) -> Self
+    {
+        match error.kind()
+        {
+            io::ErrorKind::UnexpectedEof => CheckpointError::InvalidFormat,
+            _ => CheckpointError::IoError(error),
+        }
+    }
+}
+
+/// Hash of what a render depends on: the objects and their materials, the lights, the view
+/// plane and the camera. It stays the same from one run to another.
+pub fn get_scene_hash(world: &World, camera: &dyn Camera) -> u64
+{
+    let mut hasher = SceneHasher::new();
+    world.hash_scene(&mut hasher);
+    camera.hash_scene(&mut hasher);
+    hasher.get_hash()
+}
+
+/// Hash of the kind and parameters of a filter
+pub fn get_filter_hash(filter: &dyn Filter) -> u64
+{
+    let mut hasher = SceneHasher::new();
+    filter.hash_scene(&mut hasher);
+    hasher.get_hash()
+}
+
+/// What identifies the render a checkpoint belongs to, and how far it went
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CheckpointHeader
+{
+    pub m_scene_hash: u64,
+    pub m_seed: u32,
+    pub m_sequence: SampleSequence,
+    pub m_img_dim: (usize, usize),
+    pub m_block_dim: (usize, usize),
+    pub m_filter_hash: u64,
+    /// The AOVs stored besides the image, see get_aov_mask
+    pub m_aov_mask: u32,
+    /// Samples per pixel rendered so far; the render resumes at this sample index
+    pub m_sample_count: u32,
+}
+
+impl CheckpointHeader
+{
+    pub fn new(world: &World,
+               camera: &dyn Camera,
+               img_dim: (usize, usize),
+               block_dim: (usize, usize),
+               filter: &dyn Filter,
+               aov_mask: u32,
+               sample_count: u32) -> CheckpointHeader
+    {
+        CheckpointHeader
+        {
+            m_scene_hash: get_scene_hash(world, camera),
+            m_seed: world.m_viewplaneptr.m_seed,
+            m_sequence: world.m_viewplaneptr.m_sample_sequence,
+            m_img_dim: img_dim,
+            m_block_dim: block_dim,
+            m_filter_hash: get_filter_hash(filter),
+            m_aov_mask: aov_mask,
+            m_sample_count: sample_count,
+        }
+    }
+
+    /// Whether the state saved with "self" can be resumed by the render described by "expected"
+    pub fn check(&self, expected: &CheckpointHeader) -> Result<(), CheckpointError>
+    {
+        if self.m_scene_hash != expected.m_scene_hash || self.m_seed != expected.m_seed
+            || self.m_sequence != expected.m_sequence
+        {
+            return Err(CheckpointError::SceneMismatch);
+        }
+        if self.m_img_dim != expected.m_img_dim || self.m_block_dim != expected.m_block_dim
+            || self.m_filter_hash != expected.m_filter_hash || self.m_aov_mask != expected.m_aov_mask
+        {
+            return Err(CheckpointError::BufferMismatch);
+        }
+        Ok(())
+    }
+
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        out.write_all(CHECKPOINT_MAGIC)?;
+        write_u64(out, self.m_scene_hash)?;
+        write_u32(out, self.m_seed)?;
+        write_u32(out, sequence_to_u32(self.m_sequence))?;
+        for value in [self.m_img_dim.0, self.m_img_dim.1, self.m_block_dim.0, self.m_block_dim.1].iter()
+        {
+            write_u64(out, *value as u64)?;
+        }
+        write_u64(out, self.m_filter_hash)?;
+        write_u32(out, self.m_aov_mask)?;
+        write_u32(out, self.m_sample_count)
+    }
+
+    pub fn read_from(input: &mut dyn Read) -> Result<CheckpointHeader, CheckpointError>
+    {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC
+        {
+            return Err(CheckpointError::InvalidFormat);
+        }
+
+        let scene_hash = read_u64(input)?;
+        let seed = read_u32(input)?;
+        let sequence = sequence_from_u32(read_u32(input)?).ok_or(CheckpointError::InvalidFormat)?;
+        let mut dims = [0usize; 4];
+        for value in dims.iter_mut()
+        {
+            *value = read_u64(input)? as usize;
+        }
+
+        Ok(CheckpointHeader
+        {
+            m_scene_hash: scene_hash,
+            m_seed: seed,
+            m_sequence: sequence,
+            m_img_dim: (dims[0], dims[1]),
+            m_block_dim: (dims[2], dims[3]),
+            m_filter_hash: read_u64(input)?,
+            m_aov_mask: read_u32(input)?,
+            m_sample_count: read_u32(input)?,
+        })
+    }
+}
+
+fn sequence_to_u32(sequence: SampleSequence) -> u32
+{
+    match sequence
+    {
+        SampleSequence::Random => 0,
+        SampleSequence::Halton => 1,
+        SampleSequence::Sobol => 2,
+        SampleSequence::OwenSobol => 3,
+        SampleSequence::BlueNoise => 4,
+    }
+}
+
+fn sequence_from_u32(value: u32) -> Option<SampleSequence>
+{
+    match value
+    {
+        0 => Some(SampleSequence::Random),
+        1 => Some(SampleSequence::Halton),
+        2 => Some(SampleSequence::Sobol),
+        3 => Some(SampleSequence::OwenSobol),
+        4 => Some(SampleSequence::BlueNoise),
+        _ => None,
+    }
+}
+
+// Checkpoints are little endian, whatever the platform
+
+pub fn write_u32(out: &mut dyn Write, value: u32) -> io::Result<()>
+{
+    out.write_all(&value.to_le_bytes())
+}
+
+pub fn write_u64(out: &mut dyn Write, value: u64) -> io::Result<()>
+{
+    out.write_all(&value.to_le_bytes())
+}
+
+pub fn write_f32(out: &mut dyn Write, value: f32) -> io::Result<()>
+{
+    out.write_all(&value.to_le_bytes())
+}
+
+pub fn read_u32(input: &mut dyn Read) -> io::Result<u32>
+{
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64(input: &mut dyn Read) -> io::Result<u64>
+{
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+pub fn read_f32(input: &mut dyn Read) -> io::Result<f32>
+{
+    let mut bytes = [0u8; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod CheckpointTest
+{
+    use super::*;
+    use cgmath::{Vector3, Zero};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use crate::brdf::lambertian::Lambertian;
+    use crate::light::pointlight::PointLight;
+    use crate::material::matte::Matte;
+    use crate::render::aov::Aov;
+    use crate::render::cam::pinhole::Pinhole;
+    use crate::render::filter::tent::TentFilter;
+    use crate::render::progressive::ProgressiveRendering;
+    use crate::render::renderbuffer::RenderBuffer;
+    use crate::render::testscene::{build_world, get_white_matte};
+    use crate::utils::colorconstant::COLOR_WHITE;
+    use crate::utils::multithread::MultiThread;
+
+    /// The edge of the sphere depends on where the samples land in the pixels
+    fn build_scene(radius: f32, seed: u32) -> World
+    {
+        build_world((10, 10), seed, vec![(Vector3::zero(), radius, get_white_matte())])
+    }
+
+    fn setup_scene(radius: f32, seed: u32) -> Arc<World>
+    {
+        Arc::new(build_scene(radius, seed))
+    }
+
+    fn setup_camera(eye: Vector3<f32>) -> Pinhole
+    {
+        let mut camera = Pinhole::new(eye, Vector3::zero(), Vector3::unit_y());
+        camera.m_core.m_exposure_time = 1.0;
+        camera
+    }
+
+    fn checkpoint_path(name: &str) -> String
+    {
+        std::env::temp_dir().join(format!("raytracer_{}_{}.ckpt", name, std::process::id()))
+            .to_string_lossy().into_owned()
+    }
+
+    fn render(world: &Arc<World>, buffer: &RenderBuffer, target_samples: u32, checkpoint: Option<&str>)
+    {
+        let camera = setup_camera(Vector3::new(0.0, 0.0, -100.0));
+        let mut settings = ProgressiveRendering::new(1, target_samples, Duration::from_secs(60));
+        if let Some(path) = checkpoint
+        {
+            settings.set_checkpoint(path, Duration::from_secs(60));
+        }
+        MultiThread::new(2).render_progressive(world.clone(), &camera, buffer, &settings, |_, _| {}).unwrap();
+    }
+
+    #[test]
+    fn scene_hash_is_stable()
+    {
+        let camera = setup_camera(Vector3::new(0.0, 0.0, -100.0));
+        let hash = |world: &World| get_scene_hash(world, &camera);
+        assert_eq!(hash(&build_scene(3.0, 0)), hash(&build_scene(3.0, 0)));
+        assert_ne!(hash(&build_scene(3.0, 0)), hash(&build_scene(3.5, 0)));
+        assert_ne!(hash(&build_scene(3.0, 0)), hash(&build_scene(3.0, 1)));
+
+        // An object another thread holds is waited for, not skipped
+        let world = build_scene(3.0, 0);
+        let object = world.m_objects[0].clone();
+        let guard = object.lock().unwrap();
+        let handle = std::thread::spawn(move || get_scene_hash(&world, &setup_camera(Vector3::new(0.0, 0.0, -100.0))));
+        std::thread::sleep(Duration::from_millis(20));
+        drop(guard);
+        assert_eq!(handle.join().unwrap(), hash(&build_scene(3.0, 0)));
+    }
+
+    #[test]
+    fn scene_hash_covers_materials_lights_and_camera()
+    {
+        let camera = setup_camera(Vector3::new(0.0, 0.0, -100.0));
+        let hash = |world: &World| get_scene_hash(world, &camera);
+        let scene = build_scene(3.0, 0);
+
+        // Only the material
+        let repainted = build_scene(3.0, 0);
+        repainted.m_objects[0].lock().unwrap()
+            .set_material(Arc::new(Matte::new(Arc::new(Lambertian::new(0.8, COLOR_WHITE)),
+                                              Arc::new(Lambertian::new(0.5, COLOR_WHITE)))));
+        assert_ne!(hash(&scene), hash(&repainted));
+
+        // Only a light
+        let mut lit = build_scene(3.0, 0);
+        lit.add_light(Arc::new(PointLight::new(1.0, COLOR_WHITE, Vector3::new(0.0, 10.0, 0.0))));
+        let mut relit = build_scene(3.0, 0);
+        relit.add_light(Arc::new(PointLight::new(1.0, COLOR_WHITE, Vector3::new(0.0, 12.0, 0.0))));
+        assert_ne!(hash(&lit), hash(&relit));
+
+        // Only the camera position
+        assert_ne!(hash(&scene), get_scene_hash(&scene, &setup_camera(Vector3::new(0.0, 1.0, -100.0))));
+    }
+
+    #[test]
+    fn resume_gives_uninterrupted_render()
+    {
+        let path = checkpoint_path("resume");
+        let world = setup_scene(3.0, 5);
+        let camera = setup_camera(Vector3::new(0.0, 0.0, -100.0));
+
+        let uninterrupted = RenderBuffer::new((10, 10), (4, 4));
+        render(&world, &uninterrupted, 6, None);
+
+        // Killed after 2 samples per pixel, then resumed in another buffer
+        let interrupted = RenderBuffer::new((10, 10), (4, 4));
+        render(&world, &interrupted, 2, Some(&path));
+        let resumed = RenderBuffer::new((10, 10), (4, 4));
+        resumed.load_checkpoint(&path, &world, &camera).unwrap();
+        assert_eq!(resumed.get_sample_count(), 2);
+        assert_eq!(resumed.resolve(), interrupted.resolve());
+        render(&world, &resumed, 6, None);
+
+        assert_eq!(resumed.resolve(), uninterrupted.resolve());
+        let meta = crate::render::renderdata::RenderMeta::new((8, 8), (10, 10), (2, 2));
+        assert_eq!(*resumed.lock_statistics(&meta), *uninterrupted.lock_statistics(&meta));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn resume_keeps_the_aovs()
+    {
+        let path = checkpoint_path("aovs");
+        let world = setup_scene(3.0, 5);
+        let camera = setup_camera(Vector3::new(0.0, 0.0, -100.0));
+        let aovs = [Aov::Depth, Aov::ObjectId, Aov::Direct];
+        let meta = crate::render::renderdata::RenderMeta::new((0, 4), (4, 8), (0, 1));
+
+        let mut uninterrupted = RenderBuffer::new((10, 10), (4, 4));
+        uninterrupted.set_aovs(&aovs);
+        render(&world, &uninterrupted, 4, None);
+
+        let mut interrupted = RenderBuffer::new((10, 10), (4, 4));
+        interrupted.set_aovs(&aovs);
+        render(&world, &interrupted, 2, Some(&path));
+        let result = RenderBuffer::new((10, 10), (4, 4)).load_checkpoint(&path, &world, &camera);
+        assert!(matches!(result, Err(CheckpointError::BufferMismatch)));
+
+        let mut resumed = RenderBuffer::new((10, 10), (4, 4));
+        resumed.set_aovs(&aovs);
+        resumed.load_checkpoint(&path, &world, &camera).unwrap();
+        render(&world, &resumed, 4, None);
+        for local_index in 0..meta.m_area
+        {
+            for aov in aovs.iter()
+            {
+                assert_eq!(resumed.lock_aovs(&meta).get_pixel(local_index, *aov),
+                           uninterrupted.lock_aovs(&meta).get_pixel(local_index, *aov));
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mismatches_are_rejected()
+    {
+        let path = checkpoint_path("mismatch");
+        let world = setup_scene(3.0, 5);
+        let camera = setup_camera(Vector3::new(0.0, 0.0, -100.0));
+        let buffer = RenderBuffer::new((10, 10), (4, 4));
+        buffer.save_checkpoint(&path, &world, &camera).unwrap();
+
+        let result = RenderBuffer::new((10, 10), (4, 4)).load_checkpoint(&path, &setup_scene(3.5, 5), &camera);
+        assert!(matches!(result, Err(CheckpointError::SceneMismatch)));
+        let result = RenderBuffer::new((10, 10), (4, 4)).load_checkpoint(&path, &setup_scene(3.0, 6), &camera);
+        assert!(matches!(result, Err(CheckpointError::SceneMismatch)));
+        let result = RenderBuffer::new((10, 10), (5, 5)).load_checkpoint(&path, &world, &camera);
+        assert!(matches!(result, Err(CheckpointError::BufferMismatch)));
+        // Another kind of filter of the same radius
+        let result = RenderBuffer::new_with_filter((10, 10), (4, 4), Arc::new(TentFilter::new(0.5)))
+            .load_checkpoint(&path, &world, &camera);
+        assert!(matches!(result, Err(CheckpointError::BufferMismatch)));
+        let result = RenderBuffer::new((10, 10), (4, 4))
+            .load_checkpoint(&path, &world, &setup_camera(Vector3::new(0.0, 0.0, -90.0)));
+        assert!(matches!(result, Err(CheckpointError::SceneMismatch)));
+
+        std::fs::write(&path, b"RTCKPT03 truncated").unwrap();
+        let result = RenderBuffer::new((10, 10), (4, 4)).load_checkpoint(&path, &world, &camera);
+        assert!(matches!(result, Err(CheckpointError::InvalidFormat)));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
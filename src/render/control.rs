@@ -0,0 +1,406 @@
+use std::fmt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Why a render stopped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderStatus
+{
+    NotStarted,
+    Running,
+    Completed,
+    Cancelled,
+    /// The time budget ran out
+    OutOfTime,
+    /// The estimated noise went below the target (progressive renders only)
+    NoiseTargetReached,
+}
+
+/// Snapshot of the progress of a render
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderProgress
+{
+    pub m_tiles_done: usize,
+    pub m_tile_count: usize,
+    /// Camera samples rendered so far, over the whole image
+    pub m_sample_count: u64,
+    /// Samples per pixel of the last complete progressive pass
+    pub m_samples_per_pixel: u32,
+    /// Average standard error of the pixels after the last complete progressive pass
+    pub m_noise_level: Option<f32>,
+    pub m_elapsed: Duration,
+}
+
+impl RenderProgress
+{
+    pub fn get_fraction_done(&self) -> f32
+    {
+        if self.m_tile_count == 0 { 0.0 } else { self.m_tiles_done as f32 / self.m_tile_count as f32 }
+    }
+
+    /// Time left if the remaining tiles go as fast as the ones done, None before the first one
+    pub fn get_eta(&self) -> Option<Duration>
+    {
+        let fraction_done = self.get_fraction_done();
+        if fraction_done <= 0.0 { None } else { Some(self.m_elapsed.mul_f32((1.0 - fraction_done) / fraction_done)) }
+    }
+}
+
+type ProgressCallback = Box<dyn Fn(&RenderProgress) + Send + Sync>;
+
+/// Handle to control a render from another thread: it can be cancelled, given a time budget and
+/// a noise target, and reports the progress as tiles complete. Renders check it between tiles.
+pub struct RenderControl
+{
+    m_cancelled: AtomicBool,
+    m_time_budget: Option<Duration>,
+    m_noise_target: Option<f32>,
+    m_on_progress: Option<ProgressCallback>,
+    m_start_time: Mutex<Option<Instant>>,
+    m_status: Mutex<RenderStatus>,
+    m_tiles_done: AtomicUsize,
+    m_tile_count: AtomicUsize,
+    m_sample_count: AtomicU64,
+    m_pass_state: Mutex<(u32, Option<f32>)>, // Samples per pixel and noise level of the last pass
+}
+
+impl RenderControl
+{
+    pub fn new() -> RenderControl
+    {
+        RenderControl
+        {
+            m_cancelled: AtomicBool::new(false),
+            m_time_budget: None,
+            m_noise_target: None,
+            m_on_progress: None,
+            m_start_time: Mutex::new(None),
+            m_status: Mutex::new(RenderStatus::NotStarted),
+            m_tiles_done: AtomicUsize::new(0),
+            m_tile_count: AtomicUsize::new(0),
+            m_sample_count: AtomicU64::new(0),
+            m_pass_state: Mutex::new((0, None)),
+        }
+    }
+
+    /// Stop the render once this much time has elapsed since its start
+    pub fn set_time_budget(&mut self, budget: Duration)
+    {
+        self.m_time_budget = Some(budget);
+    }
+
+    /// Stop a progressive render once the average standard error of the pixels goes below "noise_level"
+    pub fn set_noise_target(&mut self, noise_level: f32)
+    {
+        self.m_noise_target = Some(noise_level);
+    }
+
+    pub fn get_noise_target(&self) -> Option<f32>
+    {
+        self.m_noise_target
+    }
+
+    /// Called from the render threads every time a tile completes
+    pub fn set_progress_callback<F>(&mut self, on_progress: F)
+        where F: Fn(&RenderProgress) + Send + Sync + 'static
+    {
+        self.m_on_progress = Some(Box::new(on_progress));
+    }
+
+    /// Stop the render at the next tile. The tiles already rendered stay in the buffer.
+    pub fn cancel(&self)
+    {
+        self.m_cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn get_status(&self) -> RenderStatus
+    {
+        *self.m_status.lock().unwrap()
+    }
+
+    pub fn get_progress(&self) -> RenderProgress
+    {
+        let (samples_per_pixel, noise_level) = *self.m_pass_state.lock().unwrap();
+        RenderProgress
+        {
+            m_tiles_done: self.m_tiles_done.load(Ordering::Acquire),
+            m_tile_count: self.m_tile_count.load(Ordering::Acquire),
+            m_sample_count: self.m_sample_count.load(Ordering::Acquire),
+            m_samples_per_pixel: samples_per_pixel,
+            m_noise_level: noise_level,
+            m_elapsed: self.get_elapsed(),
+        }
+    }
+
+    fn get_elapsed(&self) -> Duration
+    {
+        self.m_start_time.lock().unwrap().map_or(Duration::from_secs(0), |start_time| start_time.elapsed())
+    }
+
+    /// Start the clock and the progress of a render of "tile_count" tiles.
+    /// A cancellation before the start still applies.
+    pub fn start(&self, tile_count: usize)
+    {
+        *self.m_start_time.lock().unwrap() = Some(Instant::now());
+        *self.m_status.lock().unwrap() = RenderStatus::Running;
+        *self.m_pass_state.lock().unwrap() = (0, None);
+        self.m_tiles_done.store(0, Ordering::Release);
+        self.m_tile_count.store(tile_count, Ordering::Release);
+        self.m_sample_count.store(0, Ordering::Release);
+    }
+
+    /// Whether the render must stop, because it was cancelled, ran out of time or reached its noise target
+    pub fn should_stop(&self) -> bool
+    {
+        let mut status = self.m_status.lock().unwrap();
+        if *status == RenderStatus::Running
+        {
+            if self.m_cancelled.load(Ordering::Acquire)
+            {
+                *status = RenderStatus::Cancelled;
+            }
+            else if self.m_time_budget.is_some_and(|budget| self.get_elapsed() >= budget)
+            {
+                *status = RenderStatus::OutOfTime;
+            }
+        }
+        *status != RenderStatus::Running
+    }
+
+    pub fn complete_tile(&self, sample_count: u64)
+    {
+        self.m_tiles_done.fetch_add(1, Ordering::AcqRel);
+        self.add_samples(sample_count);
+    }
+
+    /// Record the samples of a part of a tile, which isn't done until its other parts are
+    pub fn add_samples(&self, sample_count: u64)
+    {
+        self.m_sample_count.fetch_add(sample_count, Ordering::AcqRel);
+        if let Some(on_progress) = self.m_on_progress.as_ref()
+        {
+            on_progress(&self.get_progress());
+        }
+    }
+
+    /// Record a complete progressive pass, and whether it reached the noise target
+    pub fn complete_pass(&self, samples_per_pixel: u32, noise_level: f32)
+    {
+        *self.m_pass_state.lock().unwrap() = (samples_per_pixel, Some(noise_level));
+        if self.m_noise_target.is_some_and(|target| noise_level <= target)
+        {
+            let mut status = self.m_status.lock().unwrap();
+            if *status == RenderStatus::Running
+            {
+                *status = RenderStatus::NoiseTargetReached;
+            }
+        }
+    }
+
+    /// End of the render: if nothing stopped it, it completed
+    pub fn finish(&self)
+    {
+        let mut status = self.m_status.lock().unwrap();
+        if *status == RenderStatus::Running
+        {
+            *status = RenderStatus::Completed;
+        }
+    }
+}
+
+impl Default for RenderControl
+{
+    fn default() -> Self
+    {
+        RenderControl::new()
+    }
+}
+
+impl fmt::Debug for RenderControl
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        f.debug_struct("RenderControl")
+            .field("status", &self.get_status())
+            .field("time budget", &self.m_time_budget)
+            .field("noise target", &self.m_noise_target)
+            .field("progress", &self.get_progress())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod RenderControlTest
+{
+    use super::*;
+    use cgmath::{Vector3, Zero};
+    use std::sync::Arc;
+    use approx::assert_relative_eq;
+    use crate::render::filter::tent::TentFilter;
+    use crate::render::progressive::ProgressiveRendering;
+    use crate::render::renderbuffer::RenderBuffer;
+    use crate::render::renderdata::RenderRegion;
+    use crate::render::testscene::{setup_world, setup_camera, get_white_matte};
+    use crate::render::tileorder::TileOrder;
+    use crate::sampler::DEFAULT_SEED;
+    use crate::utils::multithread::MultiThread;
+    use crate::world::world::World;
+
+    fn setup_scene() -> Arc<World>
+    {
+        setup_world((16, 16), DEFAULT_SEED, vec![(Vector3::zero(), 6.0, get_white_matte())])
+    }
+
+    fn render(control: RenderControl) -> (RenderStatus, RenderProgress)
+    {
+        let control = Arc::new(control);
+        let mut multithread = MultiThread::new(2);
+        multithread.set_render_control(control.clone());
+        let status = multithread.render_to_buffer(setup_scene(), &setup_camera(), &RenderBuffer::new((16, 16), (4, 4)));
+        (status, control.get_progress())
+    }
+
+    #[test]
+    fn complete_render_reports_all_tiles()
+    {
+        let reports = Arc::new(AtomicUsize::new(0));
+        let mut control = RenderControl::new();
+        let counter = reports.clone();
+        control.set_progress_callback(move |_| { counter.fetch_add(1, Ordering::AcqRel); });
+
+        let (status, progress) = render(control);
+        assert_eq!(status, RenderStatus::Completed);
+        assert_eq!((progress.m_tiles_done, progress.m_tile_count), (16, 16));
+        assert_eq!(progress.m_sample_count, 16 * 16 * 4);
+        assert_eq!(progress.get_fraction_done(), 1.0);
+        assert_eq!(reports.load(Ordering::Acquire), 16);
+    }
+
+    #[test]
+    fn cancelled_render_stops_between_tiles()
+    {
+        let control = RenderControl::new();
+        control.cancel();
+        let (status, progress) = render(control);
+        assert_eq!(status, RenderStatus::Cancelled);
+        assert_eq!(progress.m_tiles_done, 0);
+
+        // Cancelled by the scheduler while rendering
+        let control = Arc::new(RenderControl::new());
+        let mut multithread = MultiThread::new(2);
+        multithread.set_render_control(control.clone());
+        let handle = control.clone();
+        let canceller = std::thread::spawn(move || {
+            while handle.get_progress().m_tiles_done < 3 { std::thread::yield_now(); }
+            handle.cancel();
+        });
+        let status = multithread.render_progressive(setup_scene(), &setup_camera(), &RenderBuffer::new((16, 16), (4, 4)),
+                                                    &ProgressiveRendering::new(1, 1000, Duration::from_secs(60)),
+                                                    |_, _| {}).unwrap();
+        canceller.join().unwrap();
+        assert_eq!(status, RenderStatus::Cancelled);
+        assert!(control.get_progress().m_tiles_done < control.get_progress().m_tile_count);
+    }
+
+    #[test]
+    fn time_budget()
+    {
+        let mut control = RenderControl::new();
+        control.set_time_budget(Duration::from_secs(0));
+        let (status, progress) = render(control);
+        assert_eq!(status, RenderStatus::OutOfTime);
+        assert_eq!(progress.m_tiles_done, 0);
+    }
+
+    #[test]
+    fn region_render_schedules_the_intersecting_tiles()
+    {
+        let control = Arc::new(RenderControl::new());
+        let mut multithread = MultiThread::new(2);
+        multithread.set_render_control(control.clone());
+        let mut buffer = RenderBuffer::new((16, 16), (4, 4));
+        buffer.set_region(Some(RenderRegion::new((3, 5), (9, 8)).unwrap())).unwrap();
+        let status = multithread.render_to_buffer(setup_scene(), &setup_camera(), &buffer);
+        let progress = control.get_progress();
+        assert_eq!(status, RenderStatus::Completed);
+        assert_eq!((progress.m_tiles_done, progress.m_tile_count), (3, 3));
+        assert_eq!(progress.m_sample_count, 6 * 3 * 4);
+
+        let full_buffer = RenderBuffer::new((16, 16), (4, 4));
+        MultiThread::new(2).render_to_buffer(setup_scene(), &setup_camera(), &full_buffer);
+        let (region, full) = (buffer.resolve(), full_buffer.resolve());
+        for x in 0..16
+        {
+            for y in 0..16
+            {
+                let index = x + y * 16;
+                if x >= 3 && x < 9 && y >= 5 && y < 8
+                {
+                    assert_eq!(region[index].resolve(), full[index].resolve());
+                }
+                else if x < 2 || x > 9 || y < 4 || y > 8
+                {
+                    // Beyond the reach of the filter
+                    assert_eq!(region[index].m_weight, 0.0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tile_orders_render_the_same_image()
+    {
+        let raster = RenderBuffer::new((16, 16), (4, 4));
+        MultiThread::new(2).render_to_buffer(setup_scene(), &setup_camera(), &raster);
+        for (tile_order, work_stealing) in [(TileOrder::Spiral, false), (TileOrder::Hilbert, true), (TileOrder::Raster, true)].iter()
+        {
+            let mut buffer = RenderBuffer::new((16, 16), (4, 4));
+            buffer.set_tile_order(*tile_order);
+            buffer.set_work_stealing(*work_stealing);
+            let status = MultiThread::new(3).render_to_buffer(setup_scene(), &setup_camera(), &buffer);
+            assert_eq!(status, RenderStatus::Completed);
+            let (expected, film) = (raster.resolve(), buffer.resolve());
+            assert!(expected.iter().zip(film.iter()).all(|(a, b)| a.resolve() == b.resolve()));
+        }
+    }
+
+    #[test]
+    fn split_blocks_render_the_same_image()
+    {
+        let filter = Arc::new(TentFilter::new(1.0));
+        let single_queue = RenderBuffer::new_with_filter((16, 16), (8, 8), filter.clone());
+        MultiThread::new(1).render_to_buffer(setup_scene(), &setup_camera(), &single_queue);
+
+        let mut buffer = RenderBuffer::new_with_filter((16, 16), (8, 8), filter);
+        buffer.set_work_stealing(true);
+        let control = Arc::new(RenderControl::new());
+        let mut multithread = MultiThread::new(4);
+        multithread.set_render_control(control.clone());
+        assert_eq!(multithread.render_to_buffer(setup_scene(), &setup_camera(), &buffer), RenderStatus::Completed);
+        // The sub-tiles of a block count for a single tile
+        let progress = control.get_progress();
+        assert_eq!((progress.m_tiles_done, progress.m_tile_count, progress.m_sample_count), (4, 4, 16 * 16 * 4));
+        assert_eq!(single_queue.resolve(), buffer.resolve());
+    }
+
+    #[test]
+    fn noise_target()
+    {
+        let mut control = RenderControl::new();
+        control.set_noise_target(0.02);
+        let control = Arc::new(control);
+        let mut multithread = MultiThread::new(2);
+        multithread.set_render_control(control.clone());
+
+        let buffer = RenderBuffer::new((16, 16), (4, 4));
+        let status = multithread.render_progressive(setup_scene(), &setup_camera(), &buffer,
+                                                    &ProgressiveRendering::new(2, 256, Duration::from_secs(60)),
+                                                    |_, _| {}).unwrap();
+        let progress = control.get_progress();
+        assert_eq!(status, RenderStatus::NoiseTargetReached);
+        assert!(progress.m_samples_per_pixel < 256);
+        assert_eq!(progress.m_samples_per_pixel, buffer.get_sample_count());
+        assert!(progress.m_noise_level.unwrap() <= 0.02);
+    }
+}
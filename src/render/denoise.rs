@@ -0,0 +1,365 @@
+use cgmath::{Vector3, InnerSpace};
+use std::fmt;
+
+use crate::output::OutputManager;
+use crate::render::aov::Aov;
+use crate::render::renderbuffer::RenderBuffer;
+use crate::utils::color::Colorf;
+
+/// Below this, a channel of the albedo is too dark to divide the radiance by
+const MIN_ALBEDO: f32 = 1e-3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DenoiserError
+{
+    /// The strength is within [0, 1]
+    InvalidStrength(f32),
+    /// A sigma is positive, or infinite to ignore its feature
+    InvalidSigma { name: &'static str, sigma: f32 },
+}
+
+impl std::error::Error for DenoiserError {}
+
+impl fmt::Display for DenoiserError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            DenoiserError::InvalidStrength(strength) =>
+                write!(f, "The strength of the denoiser must be within [0, 1], got {}", strength),
+            DenoiserError::InvalidSigma { name, sigma } =>
+                write!(f, "The {} sigma of the denoiser must be positive, got {}", name, sigma),
+        }
+    }
+}
+
+/// Joint cross-bilateral filter for renders with few samples per pixel. A pixel averages its
+/// neighbours within m_radius, weighted by their distance and by how much their color and
+/// features differ from its own. The features are the albedo, normal and depth AOVs of the
+/// RenderBuffer, those it doesn't store are ignored. The radiance is divided by the albedo
+/// before filtering and multiplied back after, so that textures stay sharp.
+#[derive(Clone, Copy, Debug)]
+pub struct Denoiser
+{
+    pub m_radius: usize,
+    /// 0 keeps the noisy image, 1 gives the filtered one
+    pub m_strength: f32,
+    pub m_sigma_spatial: f32,
+    /// The colors of a few samples per pixel are too noisy to tell edges apart, so they are ignored
+    /// by default, with an infinite sigma
+    pub m_sigma_color: f32,
+    pub m_sigma_albedo: f32,
+    pub m_sigma_normal: f32,
+    /// Relative to the depth of the pixel
+    pub m_sigma_depth: f32,
+}
+
+impl Denoiser
+{
+    pub fn new(radius: usize, strength: f32) -> Result<Denoiser, DenoiserError>
+    {
+        if !(0.0..=1.0).contains(&strength)
+        {
+            return Err(DenoiserError::InvalidStrength(strength));
+        }
+        Ok(Denoiser::with_radius(radius, strength))
+    }
+
+    fn with_radius(radius: usize, strength: f32) -> Denoiser
+    {
+        Denoiser
+        {
+            m_radius: radius,
+            m_strength: strength,
+            m_sigma_spatial: (radius as f32 * 0.5).max(0.5),
+            m_sigma_color: f32::INFINITY,
+            m_sigma_albedo: 0.1,
+            m_sigma_normal: 0.3,
+            m_sigma_depth: 0.1,
+        }
+    }
+
+    /// How much the color, albedo, normal and depth of two pixels can differ before they stop being
+    /// averaged. The sigmas must be positive; infinity ignores the feature. Invalid sigmas leave
+    /// the denoiser unchanged.
+    pub fn set_sigmas(&mut self, color: f32, albedo: f32, normal: f32, depth: f32) -> Result<(), DenoiserError>
+    {
+        for (name, sigma) in [("color", color), ("albedo", albedo), ("normal", normal), ("depth", depth)].iter()
+        {
+            if sigma.is_nan() || *sigma <= 0.0
+            {
+                return Err(DenoiserError::InvalidSigma { name, sigma: *sigma });
+            }
+        }
+        self.m_sigma_color = color;
+        self.m_sigma_albedo = albedo;
+        self.m_sigma_normal = normal;
+        self.m_sigma_depth = depth;
+        Ok(())
+    }
+
+    /// The denoised image of the buffer, row by row
+    pub fn denoise(&self, buffer: &RenderBuffer) -> Vec<Colorf>
+    {
+        let (width, height) = buffer.get_dim();
+        let noisy: Vec<Colorf> = buffer.resolve().iter().map(|pixel| pixel.resolve()).collect();
+        let features = Features::new(buffer);
+        let irradiance: Vec<Colorf> = noisy.iter().enumerate()
+            .map(|(index, color)| demodulate(*color, features.get_albedo(index)))
+            .collect();
+
+        let radius = self.m_radius as isize;
+        let spatial_weights: Vec<f32> = (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .map(|(dx, dy)| gaussian((dx * dx + dy * dy) as f32, self.m_sigma_spatial))
+            .collect();
+
+        let mut denoised = Vec::with_capacity(width * height);
+        for y in 0..height as isize
+        {
+            for x in 0..width as isize
+            {
+                let center = x as usize + y as usize * width;
+                let mut sum = Colorf::new(0.0, 0.0, 0.0);
+                let mut weight_sum = 0.0;
+
+                for (window_index, spatial_weight) in spatial_weights.iter().enumerate()
+                {
+                    let neighbour_x = x + window_index as isize % (2 * radius + 1) - radius;
+                    let neighbour_y = y + window_index as isize / (2 * radius + 1) - radius;
+                    if neighbour_x < 0 || neighbour_y < 0 || neighbour_x >= width as isize || neighbour_y >= height as isize
+                    {
+                        continue;
+                    }
+                    let neighbour = neighbour_x as usize + neighbour_y as usize * width;
+
+                    let weight = spatial_weight
+                        * gaussian(get_distance_sqr(irradiance[center], irradiance[neighbour]), self.m_sigma_color)
+                        * features.get_weight(self, center, neighbour);
+                    sum += irradiance[neighbour] * weight;
+                    weight_sum += weight;
+                }
+
+                // The weight of the center is 1, so the sum is never 0
+                let filtered = remodulate(sum / weight_sum, features.get_albedo(center));
+                denoised.push(noisy[center] * (1.0 - self.m_strength) + filtered * self.m_strength);
+            }
+        }
+        denoised
+    }
+
+    /// Write the denoised image of the buffer to the OutputManager, through the buffer's post-processing
+    pub fn write(&self, buffer: &RenderBuffer, out_manager: &mut dyn OutputManager)
+    {
+        buffer.write_image(self.denoise(buffer), out_manager);
+    }
+}
+
+impl Default for Denoiser
+{
+    fn default() -> Self
+    {
+        Denoiser::with_radius(3, 1.0)
+    }
+}
+
+/// The feature buffers of a render, those it has
+struct Features
+{
+    m_albedo: Option<Vec<f32>>,
+    m_normal: Option<Vec<Vector3<f32>>>,
+    m_depth: Option<Vec<f32>>,
+}
+
+impl Features
+{
+    fn new(buffer: &RenderBuffer) -> Features
+    {
+        let resolve = |aov| if buffer.get_aovs().contains(&aov) { Some(buffer.resolve_aov(aov)) } else { None };
+        Features
+        {
+            m_albedo: resolve(Aov::Albedo),
+            // The normals of the pixels on edges average to shorter ones
+            m_normal: resolve(Aov::Normal).map(|normals| normals.chunks(3)
+                .map(|normal| Vector3::new(normal[0], normal[1], normal[2]))
+                .map(|normal| if normal.magnitude2() > 0.0 { normal.normalize() } else { normal })
+                .collect()),
+            m_depth: resolve(Aov::Depth),
+        }
+    }
+
+    fn get_albedo(&self, index: usize) -> Option<Colorf>
+    {
+        self.m_albedo.as_ref().map(|albedo| Colorf::new(albedo[3 * index], albedo[3 * index + 1], albedo[3 * index + 2]))
+    }
+
+    /// How similar the features of two pixels are, 1 if they are the same
+    fn get_weight(&self, denoiser: &Denoiser, center: usize, neighbour: usize) -> f32
+    {
+        let mut weight = 1.0;
+        if let (Some(center_albedo), Some(neighbour_albedo)) = (self.get_albedo(center), self.get_albedo(neighbour))
+        {
+            weight *= gaussian(get_distance_sqr(center_albedo, neighbour_albedo), denoiser.m_sigma_albedo);
+        }
+        if let Some(normals) = self.m_normal.as_ref()
+        {
+            weight *= gaussian((normals[center] - normals[neighbour]).magnitude2(), denoiser.m_sigma_normal);
+        }
+        if let Some(depths) = self.m_depth.as_ref()
+        {
+            let scale = depths[center].max(depths[neighbour]);
+            if scale > 0.0
+            {
+                let difference = (depths[center] - depths[neighbour]) / scale;
+                weight *= gaussian(difference * difference, denoiser.m_sigma_depth);
+            }
+        }
+        weight
+    }
+}
+
+fn gaussian(distance_sqr: f32, sigma: f32) -> f32
+{
+    (-distance_sqr / (2.0 * sigma * sigma)).exp()
+}
+
+fn get_distance_sqr(a: Colorf, b: Colorf) -> f32
+{
+    let difference = a - b;
+    difference.m_r * difference.m_r + difference.m_g * difference.m_g + difference.m_b * difference.m_b
+}
+
+fn map_albedo(color: Colorf, albedo: Option<Colorf>, operation: impl Fn(f32, f32) -> f32) -> Colorf
+{
+    match albedo
+    {
+        Some(albedo) =>
+            {
+                let channel = |value, albedo: f32| if albedo > MIN_ALBEDO { operation(value, albedo) } else { value };
+                Colorf::new(channel(color.m_r, albedo.m_r), channel(color.m_g, albedo.m_g), channel(color.m_b, albedo.m_b))
+            }
+        None => color,
+    }
+}
+
+/// The light reaching a pixel, without the color of its surface
+fn demodulate(color: Colorf, albedo: Option<Colorf>) -> Colorf
+{
+    map_albedo(color, albedo, |value, albedo| value / albedo)
+}
+
+fn remodulate(irradiance: Colorf, albedo: Option<Colorf>) -> Colorf
+{
+    map_albedo(irradiance, albedo, |value, albedo| value * albedo)
+}
+
+#[cfg(test)]
+mod DenoiserTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use cgmath::Zero;
+    use std::sync::{Arc, Mutex};
+    use crate::brdf::lambertian::Lambertian;
+    use crate::geometry::Shadable;
+    use crate::geometry::sphere::Sphere;
+    use crate::light::ambientocc::AmbientOccluder;
+    use crate::material::matte::Matte;
+    use crate::render::cam::pinhole::Pinhole;
+    use crate::sampler::Sampler;
+    use crate::sampler::jittered::Jittered;
+    use crate::utils::colorconstant::{COLOR_BLACK, COLOR_WHITE};
+    use crate::utils::multithread::MultiThread;
+    use crate::world::viewplane::ViewPlane;
+    use crate::world::world::World;
+
+    /// Two spheres lit by an ambient occluder, whose shadow rays make the image noisy
+    fn render_reference_scene(sample_count: usize) -> RenderBuffer
+    {
+        let mut sampler = Jittered::new(sample_count, 1);
+        sampler.generate_sample_pattern();
+        let mut vp = ViewPlane::new(Arc::new(sampler));
+        vp.m_hres = 32;
+        vp.m_vres = 24;
+        vp.m_pixsize = 0.25;
+        vp.set_zoom(&1.0);
+
+        let mut world = World::new(Box::new(vp), "whitted");
+        world.m_backgroundcolor = Colorf::new(0.1, 0.1, 0.1);
+        let mut occluder = AmbientOccluder::new(COLOR_BLACK, 1.0);
+        occluder.set_color(COLOR_WHITE);
+        world.set_ambient(Arc::new(occluder));
+        let colors = [Colorf::new(0.9, 0.3, 0.2), Colorf::new(0.2, 0.5, 0.9)];
+        for (center, color) in [Vector3::new(-3.0, 0.0, 0.0), Vector3::new(3.5, 0.0, 1.0)].iter().zip(colors.iter())
+        {
+            let mut sphere = Sphere::new(4.0, *center);
+            sphere.set_material(Arc::new(Matte::new(Arc::new(Lambertian::new(1.0, *color)),
+                                                    Arc::new(Lambertian::new(1.0, *color)))));
+            world.add_object(Arc::new(Mutex::new(sphere)));
+        }
+
+        let mut camera = Pinhole::new(Vector3::new(0.0, 0.0, -100.0), Vector3::zero(), Vector3::unit_y());
+        camera.m_core.m_exposure_time = 1.0;
+        let mut buffer = RenderBuffer::new((32, 24), (8, 8));
+        buffer.set_aovs(&[Aov::Albedo, Aov::Normal, Aov::Depth]);
+        MultiThread::new(2).render_to_buffer(Arc::new(world), &camera, &buffer);
+        buffer
+    }
+
+    fn get_mean_squared_error(image: &[Colorf], reference: &[Colorf]) -> f32
+    {
+        image.iter().zip(reference.iter()).map(|(color, expected)| get_distance_sqr(*color, *expected)).sum::<f32>()
+            / image.len() as f32
+    }
+
+    #[test]
+    fn albedo_demodulation()
+    {
+        let albedo = Some(Colorf::new(0.5, 0.0, 2.0));
+        let color = Colorf::new(0.25, 0.3, 1.0);
+        assert_eq!(demodulate(color, albedo), Colorf::new(0.5, 0.3, 0.5));
+        assert_eq!(remodulate(demodulate(color, albedo), albedo), color);
+        assert_eq!(demodulate(color, None), color);
+    }
+
+    #[test]
+    fn strength_zero_keeps_the_image()
+    {
+        let buffer = render_reference_scene(1);
+        let noisy: Vec<Colorf> = buffer.resolve().iter().map(|pixel| pixel.resolve()).collect();
+        let denoised = Denoiser::new(2, 0.0).unwrap().denoise(&buffer);
+        for (color, expected) in denoised.iter().zip(noisy.iter())
+        {
+            assert_relative_eq!(color.m_g, expected.m_g, epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    fn invalid_settings()
+    {
+        assert_eq!(Denoiser::new(2, 1.5).unwrap_err(), DenoiserError::InvalidStrength(1.5));
+        let mut denoiser = Denoiser::default();
+        assert_eq!(denoiser.set_sigmas(f32::INFINITY, 0.1, 0.0, 0.1),
+                   Err(DenoiserError::InvalidSigma { name: "normal", sigma: 0.0 }));
+        assert_eq!(denoiser.m_sigma_normal, 0.3);
+        assert_eq!(denoiser.set_sigmas(f32::INFINITY, 0.1, 0.5, 0.1), Ok(()));
+        assert_eq!(denoiser.m_sigma_normal, 0.5);
+    }
+
+    #[test]
+    fn benchmark_on_reference_scene()
+    {
+        let reference: Vec<Colorf> = render_reference_scene(256).resolve().iter().map(|pixel| pixel.resolve()).collect();
+        let buffer = render_reference_scene(1);
+        let noisy: Vec<Colorf> = buffer.resolve().iter().map(|pixel| pixel.resolve()).collect();
+        let denoised = Denoiser::default().denoise(&buffer);
+
+        let noisy_error = get_mean_squared_error(&noisy, &reference);
+        let denoised_error = get_mean_squared_error(&denoised, &reference);
+        assert!(denoised_error < 0.5 * noisy_error);
+
+        // The features keep the spheres and the background apart
+        let background = denoised[0];
+        assert_relative_eq!(background.m_r, 0.1, epsilon = 1e-4);
+    }
+}
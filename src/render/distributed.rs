@@ -0,0 +1,684 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::render::aov::{get_aov_mask, get_aovs};
+use crate::render::checkpoint::{CheckpointError, CheckpointHeader, read_u32, read_u64, write_u32, write_u64};
+use crate::render::control::{RenderControl, RenderStatus};
+use crate::render::film::FilmTile;
+use crate::render::filter::Filter;
+use crate::render::filter::boxfilter::BoxFilter;
+use crate::render::renderbuffer::RenderBuffer;
+use crate::render::renderdata::{RenderMeta, RenderRegion};
+use crate::utils::multithread::render_samples;
+use crate::world::scenefile::{SceneError, SceneFile};
+
+// The coordinator opens the session with the magic, the scene file and the checkpoint header of
+// its buffer; the worker answers with a status, and its thread count if it accepts. The coordinator
+// then sends blocks, each answered with the samples of the block, until it ends the session.
+const SESSION_MAGIC: &[u8; 8] = b"RTDIST02";
+const STATUS_ACCEPTED: u32 = 0;
+const STATUS_INVALID_SCENE: u32 = 1;
+const STATUS_SCENE_MISMATCH: u32 = 2;
+const STATUS_BUFFER_MISMATCH: u32 = 3;
+const REQUEST_DONE: u32 = 0;
+const REQUEST_BLOCK: u32 = 1;
+
+/// Largest scene file a session carries, in bytes
+pub const MAX_SCENE_SIZE: usize = 64 << 20;
+
+#[derive(Debug)]
+pub enum DistributedError
+{
+    IoError(io::Error),
+    InvalidFormat,
+    /// The worker can't read the scene file
+    InvalidScene,
+    /// The worker loaded another scene, or with another seed or sequence
+    SceneMismatch,
+    /// The worker renders into a RenderBuffer of other dimensions, with another filter or other AOVs
+    BufferMismatch,
+    /// All the workers are gone with blocks left to render
+    WorkersLost,
+}
+
+impl std::error::Error for DistributedError {}
+
+impl fmt::Display for DistributedError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            DistributedError::IoError(error) => write!(f, "Can't reach the worker: {}", error),
+            DistributedError::InvalidFormat => write!(f, "Not a render session"),
+            DistributedError::InvalidScene => write!(f, "The worker can't read the scene"),
+            DistributedError::SceneMismatch => write!(f, "The worker renders another scene"),
+            DistributedError::BufferMismatch => write!(f, "The worker renders into a buffer of other dimensions"),
+            DistributedError::WorkersLost => write!(f, "No worker left to render the remaining blocks"),
+        }
+    }
+}
+
+impl From<io::Error> for DistributedError
+{
+    fn from(error: io::Error) -> Self
+    {
+        DistributedError::IoError(error)
+    }
+}
+
+impl From<CheckpointError> for DistributedError
+{
+    fn from(error: CheckpointError) -> Self
+    {
+        match error
+        {
+            CheckpointError::IoError(error) => DistributedError::IoError(error),
+            CheckpointError::InvalidFormat => DistributedError::InvalidFormat,
+            CheckpointError::SceneMismatch => DistributedError::SceneMismatch,
+            CheckpointError::BufferMismatch => DistributedError::BufferMismatch,
+        }
+    }
+}
+
+/// Blocks waiting for a worker, and how many are being rendered
+struct BlockQueue
+{
+    m_state: Mutex<(VecDeque<RenderMeta>, usize)>,
+}
+
+impl BlockQueue
+{
+    fn new(blocks: Vec<RenderMeta>) -> BlockQueue
+    {
+        BlockQueue{ m_state: Mutex::new((blocks.into_iter().collect(), 0)) }
+    }
+
+    /// The next block to render; Err once all the blocks are rendered
+    fn take(&self) -> Result<Option<RenderMeta>, ()>
+    {
+        let mut state = self.m_state.lock().unwrap();
+        match state.0.pop_front()
+        {
+            Some(block) =>
+                {
+                    state.1 += 1;
+                    Ok(Some(block))
+                }
+            None if state.1 == 0 => Err(()),
+            // Wait for the blocks being rendered, which come back if their worker is lost
+            None => Ok(None),
+        }
+    }
+
+    fn complete(&self)
+    {
+        self.m_state.lock().unwrap().1 -= 1;
+    }
+
+    fn give_back(&self, block: RenderMeta)
+    {
+        let mut state = self.m_state.lock().unwrap();
+        state.0.push_front(block);
+        state.1 -= 1;
+    }
+
+    fn is_empty(&self) -> bool
+    {
+        let state = self.m_state.lock().unwrap();
+        state.0.is_empty() && state.1 == 0
+    }
+}
+
+/// Renders a RenderBuffer with worker processes, reached over TCP on this host or others. The
+/// workers receive the scene file, then render as many blocks at a time as they have threads, and
+/// send their samples back. The blocks of a worker that disappears are rendered by the others,
+/// while the coordinator tries to reach it again.
+pub struct RenderCoordinator
+{
+    m_workers: Vec<String>,
+    m_scene_text: String,
+    m_scene: SceneFile, // Parsed from m_scene_text, like the workers do
+    pub m_max_retries: u32, // Attempts to reach a worker again before giving up on it
+    pub m_retry_delay: Duration,
+    pub m_timeout: Duration, // Longest wait for a block, after which its worker counts as lost
+    m_control: Arc<RenderControl>,
+}
+
+impl RenderCoordinator
+{
+    /// Coordinator of the workers at "workers", e.g. "render-node:7878", which render the scene
+    /// file "scene_text"; fails if it isn't a scene, or is larger than MAX_SCENE_SIZE
+    pub fn new(workers: &[&str], scene_text: &str) -> Result<RenderCoordinator, SceneError>
+    {
+        if scene_text.len() > MAX_SCENE_SIZE
+        {
+            return Err(SceneError::Invalid(format!("the scene file is larger than {} bytes", MAX_SCENE_SIZE)));
+        }
+        Ok(RenderCoordinator
+        {
+            m_workers: workers.iter().map(|address| address.to_string()).collect(),
+            m_scene_text: scene_text.to_string(),
+            m_scene: SceneFile::parse(scene_text)?,
+            m_max_retries: 3,
+            m_retry_delay: Duration::from_secs(1),
+            m_timeout: Duration::from_secs(600),
+            m_control: Arc::new(RenderControl::new()),
+        })
+    }
+
+    /// The scene the workers render, e.g. for the dimensions of the buffer
+    pub fn get_scene(&self) -> &SceneFile
+    {
+        &self.m_scene
+    }
+
+    /// Handle the renders report to, and which can stop them
+    pub fn set_render_control(&mut self, control: Arc<RenderControl>)
+    {
+        self.m_control = control;
+    }
+
+    pub fn get_render_control(&self) -> &Arc<RenderControl>
+    {
+        &self.m_control
+    }
+
+    /// Render the region of the buffer, which the workers render the scene into, unless the render
+    /// control stops it; fails if all the workers are lost with blocks left. The workers use a box
+    /// filter unless they are set up with the buffer's.
+    pub fn render_to_buffer(&self, buffer: &RenderBuffer) -> Result<RenderStatus, DistributedError>
+    {
+        let header = buffer.get_checkpoint_header(&self.m_scene.m_world, self.m_scene.m_camera.as_ref());
+        let queue = BlockQueue::new(buffer.get_scheduled_blocks());
+        let block_count = buffer.get_block_count();
+        let last_error = Mutex::new(None);
+        let control = self.m_control.as_ref();
+        control.start(block_count);
+
+        thread::scope(|scope|
+            {
+                for address in self.m_workers.iter()
+                {
+                    let (header, queue, last_error) = (&header, &queue, &last_error);
+                    scope.spawn(move ||
+                        {
+                            match self.connect(address, header)
+                            {
+                                Ok((stream, thread_count)) =>
+                                    {
+                                        // A session per block at most, whatever the worker claims
+                                        for _ in 1..(thread_count as usize).min(block_count)
+                                        {
+                                            scope.spawn(move || self.run_session(address, header, None, queue, buffer, last_error));
+                                        }
+                                        self.run_session(address, header, Some(stream), queue, buffer, last_error);
+                                    }
+                                Err(error) => *last_error.lock().unwrap() = Some(error),
+                            }
+                        });
+                }
+            });
+
+        if !queue.is_empty() && !control.should_stop()
+        {
+            return Err(last_error.into_inner().unwrap().unwrap_or(DistributedError::WorkersLost));
+        }
+        control.finish();
+        Ok(control.get_status())
+    }
+
+    /// Render blocks on a connection to a worker until there are none left, reconnecting if it's lost
+    fn run_session(&self,
+                   address: &str,
+                   header: &CheckpointHeader,
+                   stream: Option<TcpStream>,
+                   queue: &BlockQueue,
+                   buffer: &RenderBuffer,
+                   last_error: &Mutex<Option<DistributedError>>)
+    {
+        let mut stream = match stream.map_or_else(|| self.connect(address, header), |stream| Ok((stream, 0)))
+        {
+            Ok((stream, _)) => stream,
+            Err(error) =>
+                {
+                    *last_error.lock().unwrap() = Some(error);
+                    return;
+                }
+        };
+
+        while !self.m_control.should_stop()
+        {
+            let block = match queue.take()
+            {
+                Ok(Some(block)) => block,
+                Ok(None) =>
+                    {
+                        thread::sleep(Duration::from_millis(10));
+                        continue;
+                    }
+                Err(()) => break,
+            };
+
+            match render_remote(&mut stream, &block, buffer)
+            {
+                Ok(sample_count) =>
+                    {
+                        queue.complete();
+                        self.m_control.complete_tile(sample_count);
+                    }
+                Err(error) =>
+                    {
+                        queue.give_back(block);
+                        *last_error.lock().unwrap() = Some(error);
+                        match self.connect(address, header)
+                        {
+                            Ok((new_stream, _)) => stream = new_stream,
+                            Err(error) =>
+                                {
+                                    *last_error.lock().unwrap() = Some(error);
+                                    return;
+                                }
+                        }
+                    }
+            }
+        }
+        // The worker notices a lost coordinator by itself
+        let _ = write_u32(&mut stream, REQUEST_DONE);
+    }
+
+    /// Open a session with a worker, retrying if it can't be reached; return it with the worker's thread count
+    fn connect(&self, address: &str, header: &CheckpointHeader) -> Result<(TcpStream, u32), DistributedError>
+    {
+        let mut attempt = 0;
+        loop
+        {
+            match open_session(address, &self.m_scene_text, header, self.m_timeout)
+            {
+                Err(DistributedError::IoError(error)) =>
+                    {
+                        if attempt >= self.m_max_retries
+                        {
+                            return Err(DistributedError::IoError(error));
+                        }
+                        attempt += 1;
+                        thread::sleep(self.m_retry_delay);
+                    }
+                // A worker that refuses the scene won't change its mind
+                result => return result,
+            }
+        }
+    }
+}
+
+fn open_session(address: &str,
+                scene_text: &str,
+                header: &CheckpointHeader,
+                timeout: Duration) -> Result<(TcpStream, u32), DistributedError>
+{
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(timeout))?;
+
+    let mut message = Vec::new();
+    message.extend_from_slice(SESSION_MAGIC);
+    write_u32(&mut message, scene_text.len() as u32)?;
+    message.extend_from_slice(scene_text.as_bytes());
+    header.write_to(&mut message)?;
+    stream.write_all(&message)?;
+
+    match read_u32(&mut stream)?
+    {
+        STATUS_ACCEPTED =>
+            {
+                let thread_count = read_u32(&mut stream)?.max(1);
+                Ok((stream, thread_count))
+            }
+        STATUS_INVALID_SCENE => Err(DistributedError::InvalidScene),
+        STATUS_SCENE_MISMATCH => Err(DistributedError::SceneMismatch),
+        STATUS_BUFFER_MISMATCH => Err(DistributedError::BufferMismatch),
+        _ => Err(DistributedError::InvalidFormat),
+    }
+}
+
+/// Have the worker render a block, and replace the block of the buffer with its samples
+fn render_remote(stream: &mut TcpStream, block: &RenderMeta, buffer: &RenderBuffer) -> Result<u64, DistributedError>
+{
+    let mut request = Vec::new();
+    write_u32(&mut request, REQUEST_BLOCK)?;
+    let (start, end, indices) = (block.get_start_coords(), block.get_end_coords(), block.get_block_indices());
+    for value in [start.0, start.1, end.0, end.1, indices.0, indices.1].iter()
+    {
+        write_u64(&mut request, *value as u64)?;
+    }
+    stream.write_all(&request)?;
+
+    let sample_count = read_u64(stream)?;
+    let block_size = buffer.get_block_size(block);
+    if read_u64(stream)? != block_size as u64
+    {
+        return Err(DistributedError::InvalidFormat);
+    }
+    let mut samples = vec![0u8; block_size];
+    // Received whole, so that a lost worker leaves the buffer as it was
+    stream.read_exact(&mut samples)?;
+    buffer.read_block(block, &mut samples.as_slice())?;
+    Ok(sample_count)
+}
+
+/// What a worker renders: the scene file of a coordinator, and a buffer laid out like its
+/// buffer, which only tells the blocks apart
+struct WorkerScene
+{
+    m_text: String,
+    m_scene: SceneFile,
+    m_layout: RenderBuffer,
+}
+
+/// Renders the blocks of the scene files coordinators send
+pub struct RenderWorker
+{
+    m_thread_count: u32,
+    m_filter: Arc<dyn Filter>,
+    pub m_timeout: Duration, // Longest wait for a coordinator, after which its session ends
+    m_scene: Mutex<Option<Arc<WorkerScene>>>, // The last one, parsed once for all the sessions of a render
+}
+
+impl RenderWorker
+{
+    /// Worker that renders "thread_count" blocks at a time
+    pub fn new(thread_count: u32) -> RenderWorker
+    {
+        RenderWorker
+        {
+            m_thread_count: thread_count.max(1),
+            m_filter: Arc::new(BoxFilter::new(0.5)),
+            m_timeout: Duration::from_secs(600),
+            m_scene: Mutex::new(None),
+        }
+    }
+
+    pub fn get_thread_count(&self) -> u32
+    {
+        self.m_thread_count
+    }
+
+    /// The reconstruction filter of the coordinators' buffers, which the scene files don't
+    /// describe; a box filter like RenderBuffer::new's by default
+    pub fn set_filter(&mut self, filter: Arc<dyn Filter>)
+    {
+        self.m_filter = filter;
+    }
+
+    /// Serve the sessions coordinators open on "listener", each on its own thread, until it fails
+    pub fn serve(&self, listener: &TcpListener) -> io::Result<()>
+    {
+        thread::scope(|scope|
+            {
+                for stream in listener.incoming()
+                {
+                    let stream = stream?;
+                    scope.spawn(move || self.run_session(stream));
+                }
+                Ok(())
+            })
+    }
+
+    /// Serve a session, until the coordinator ends it or is lost
+    pub fn run_session(&self, mut stream: TcpStream) -> Result<(), DistributedError>
+    {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(self.m_timeout))?;
+        let mut magic = [0u8; 8];
+        stream.read_exact(&mut magic)?;
+        if &magic != SESSION_MAGIC
+        {
+            return Err(DistributedError::InvalidFormat);
+        }
+        let scene_size = read_u32(&mut stream)? as usize;
+        if scene_size > MAX_SCENE_SIZE
+        {
+            return Err(DistributedError::InvalidFormat);
+        }
+        let mut scene_text = vec![0u8; scene_size];
+        stream.read_exact(&mut scene_text)?;
+        let scene_text = String::from_utf8(scene_text).map_err(|_| DistributedError::InvalidFormat)?;
+        let header = CheckpointHeader::read_from(&mut stream)?;
+        if header.m_block_dim.0 == 0 || header.m_block_dim.1 == 0
+        {
+            return Err(DistributedError::InvalidFormat);
+        }
+
+        let scene = match self.get_scene(scene_text, &header)
+        {
+            Ok(scene) => scene,
+            Err(_) =>
+                {
+                    write_u32(&mut stream, STATUS_INVALID_SCENE)?;
+                    return Err(DistributedError::InvalidScene);
+                }
+        };
+        let (world, camera) = (&scene.m_scene.m_world, scene.m_scene.m_camera.as_ref());
+        if let Err(error) = header.check(&scene.m_layout.get_checkpoint_header(world, camera))
+        {
+            let status = match error
+            {
+                CheckpointError::SceneMismatch => STATUS_SCENE_MISMATCH,
+                _ => STATUS_BUFFER_MISMATCH,
+            };
+            write_u32(&mut stream, status)?;
+            return Err(error.into());
+        }
+        let mut answer = Vec::new();
+        write_u32(&mut answer, STATUS_ACCEPTED)?;
+        write_u32(&mut answer, self.m_thread_count)?;
+        stream.write_all(&answer)?;
+
+        loop
+        {
+            match read_u32(&mut stream)?
+            {
+                REQUEST_DONE => return Ok(()),
+                REQUEST_BLOCK =>
+                    {
+                        // Rendered into samples of its own, which no other session sees, e.g. one
+                        // still rendering the block the coordinator gave up on. The samples are
+                        // splatted in the order of a local render, for the same image.
+                        let block = read_block_request(&mut stream, &scene.m_layout)?;
+                        let mut samples = scene.m_layout.new_block_samples(&block);
+                        let mut tile = FilmTile::new_deferred(&block, scene.m_layout.get_filter().clone());
+                        let sample_count = render_samples(world, camera, &block, &mut samples.m_statistics,
+                                                          &mut tile, &mut samples.m_aovs);
+                        samples.m_tile.add_samples(&mut tile.take_deferred());
+
+                        let mut answer = Vec::new();
+                        write_u64(&mut answer, sample_count)?;
+                        write_u64(&mut answer, scene.m_layout.get_block_size(&block) as u64)?;
+                        samples.write_to(&mut answer)?;
+                        stream.write_all(&answer)?;
+                    }
+                _ => return Err(DistributedError::InvalidFormat),
+            }
+        }
+    }
+
+    /// The scene of "scene_text", with a buffer laid out as "header" describes
+    fn get_scene(&self, scene_text: String, header: &CheckpointHeader) -> Result<Arc<WorkerScene>, SceneError>
+    {
+        let mut last_scene = self.m_scene.lock().unwrap();
+        if let Some(scene) = last_scene.as_ref().filter(|scene| scene.m_text == scene_text
+            && scene.m_layout.get_block_dim() == header.m_block_dim
+            && get_aov_mask(scene.m_layout.get_aovs()) == header.m_aov_mask)
+        {
+            return Ok(scene.clone());
+        }
+
+        let parsed = SceneFile::parse(&scene_text)?;
+        let mut layout = RenderBuffer::new_with_filter(parsed.m_img_dim, header.m_block_dim, self.m_filter.clone());
+        layout.set_aovs(&get_aovs(header.m_aov_mask));
+        let scene = Arc::new(WorkerScene{ m_text: scene_text, m_scene: parsed, m_layout: layout });
+        *last_scene = Some(scene.clone());
+        Ok(scene)
+    }
+}
+
+/// The part of a block of "buffer" the coordinator asks for
+fn read_block_request(stream: &mut TcpStream, buffer: &RenderBuffer) -> Result<RenderMeta, DistributedError>
+{
+    let mut values = [0usize; 6];
+    for value in values.iter_mut()
+    {
+        *value = read_u64(stream)? as usize;
+    }
+    let region = RenderRegion::new((values[0], values[1]), (values[2], values[3]))
+        .map_err(|_| DistributedError::InvalidFormat)?;
+    buffer.get_block((values[4], values[5]))
+        .and_then(|block| block.clip(&region))
+        .ok_or(DistributedError::InvalidFormat)
+}
+
+#[cfg(test)]
+mod DistributedTest
+{
+    use super::*;
+    use crate::render::filter::tent::TentFilter;
+    use crate::utils::multithread::MultiThread;
+    use crate::world::scenefile::SPHERE_SCENE;
+
+    /// Address of "worker", serving in the background
+    fn start_worker(worker: RenderWorker) -> String
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || worker.serve(&listener));
+        address
+    }
+
+    /// Address of a worker that accepts a session, then disappears while rendering its first
+    /// block, after announcing "block_size" bytes of samples if some
+    fn start_lost_worker(block_size: Option<u64>) -> String
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move ||
+            {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut handshake = [0u8; 8];
+                stream.read_exact(&mut handshake).unwrap();
+                let mut scene_text = vec![0u8; read_u32(&mut stream).unwrap() as usize];
+                stream.read_exact(&mut scene_text).unwrap();
+                CheckpointHeader::read_from(&mut stream).unwrap();
+                write_u32(&mut stream, STATUS_ACCEPTED).unwrap();
+                write_u32(&mut stream, 1).unwrap();
+                let mut request = [0u8; 4 + 6 * 8];
+                stream.read_exact(&mut request).unwrap();
+                if let Some(block_size) = block_size
+                {
+                    write_u64(&mut stream, 16).unwrap();
+                    write_u64(&mut stream, block_size).unwrap();
+                }
+            });
+        address
+    }
+
+    fn new_buffer() -> RenderBuffer
+    {
+        RenderBuffer::new((16, 12), (4, 4))
+    }
+
+    fn render_locally() -> RenderBuffer
+    {
+        let scene = SceneFile::parse(SPHERE_SCENE).unwrap();
+        let buffer = new_buffer();
+        MultiThread::new(2).render_to_buffer(scene.m_world.clone(), scene.m_camera.as_ref(), &buffer);
+        buffer
+    }
+
+    fn assert_same_image(buffer: &RenderBuffer, expected: &RenderBuffer)
+    {
+        for (pixel, expected_pixel) in buffer.resolve().iter().zip(expected.resolve().iter())
+        {
+            assert_eq!(pixel.resolve(), expected_pixel.resolve());
+        }
+    }
+
+    #[test]
+    fn workers_render_the_frame()
+    {
+        // The second one claims more threads than the coordinator opens sessions for
+        let (first, second) = (start_worker(RenderWorker::new(2)), start_worker(RenderWorker::new(u32::MAX)));
+        let coordinator = RenderCoordinator::new(&[&first, &second], SPHERE_SCENE).unwrap();
+        assert_eq!(coordinator.get_scene().m_img_dim, (16, 12));
+        let buffer = new_buffer();
+        let status = coordinator.render_to_buffer(&buffer).unwrap();
+
+        assert_eq!(status, RenderStatus::Completed);
+        let progress = coordinator.get_render_control().get_progress();
+        assert_eq!((progress.m_tiles_done, progress.m_tile_count), (12, 12));
+        assert_eq!(progress.m_sample_count, 16 * 12 * 4);
+        assert_same_image(&buffer, &render_locally());
+    }
+
+    #[test]
+    fn blocks_of_a_lost_worker_are_rendered_again()
+    {
+        // The second one announces more samples than a block has, which mustn't be allocated
+        for block_size in [None, Some(u64::MAX)].iter()
+        {
+            let mut coordinator = RenderCoordinator::new(&[&start_lost_worker(*block_size), &start_worker(RenderWorker::new(1))],
+                                                         SPHERE_SCENE).unwrap();
+            coordinator.m_retry_delay = Duration::from_millis(10);
+            let buffer = new_buffer();
+            assert_eq!(coordinator.render_to_buffer(&buffer).unwrap(), RenderStatus::Completed);
+            assert_eq!(coordinator.get_render_control().get_progress().m_tiles_done, 12);
+            assert_same_image(&buffer, &render_locally());
+        }
+    }
+
+    #[test]
+    fn workers_check_the_sessions()
+    {
+        let mut worker = RenderWorker::new(1);
+        worker.set_filter(Arc::new(TentFilter::new(1.0)));
+        let coordinator = RenderCoordinator::new(&[&start_worker(worker)], SPHERE_SCENE).unwrap();
+        assert!(matches!(coordinator.render_to_buffer(&new_buffer()), Err(DistributedError::BufferMismatch)));
+        assert!(matches!(RenderCoordinator::new(&[], "{"), Err(SceneError::JsonError(_))));
+
+        // Sessions opened by hand: a scene too large to read, and one that doesn't parse
+        let scene = SceneFile::parse(SPHERE_SCENE).unwrap();
+        let header = new_buffer().get_checkpoint_header(&scene.m_world, scene.m_camera.as_ref());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let worker = RenderWorker::new(1);
+        for (scene_size, scene_text) in [(MAX_SCENE_SIZE as u32 + 1, ""), (1, "{")].iter()
+        {
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            let mut message = SESSION_MAGIC.to_vec();
+            write_u32(&mut message, *scene_size).unwrap();
+            message.extend_from_slice(scene_text.as_bytes());
+            header.write_to(&mut message).unwrap();
+            client.write_all(&message).unwrap();
+
+            let result = worker.run_session(listener.accept().unwrap().0);
+            if scene_text.is_empty()
+            {
+                assert!(matches!(result, Err(DistributedError::InvalidFormat)));
+            }
+            else
+            {
+                assert!(matches!(result, Err(DistributedError::InvalidScene)));
+                assert_eq!(read_u32(&mut client).unwrap(), STATUS_INVALID_SCENE);
+            }
+        }
+
+        // A coordinator that never sends anything
+        let mut worker = RenderWorker::new(1);
+        worker.m_timeout = Duration::from_millis(50);
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert!(matches!(worker.run_session(listener.accept().unwrap().0), Err(DistributedError::IoError(_))));
+    }
+}
@@ -0,0 +1,303 @@
+use cgmath::Vector2;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+use crate::render::checkpoint::{write_f32, read_f32};
+use crate::render::filter::Filter;
+use crate::render::renderdata::{RenderMeta, RenderSample};
+use crate::utils::color::Colorf;
+use crate::utils::colorconstant::COLOR_BLACK;
+
+/// Smallest total filter weight a pixel is resolved from. Filters with negative lobes, like
+/// Mitchell or Lanczos, can leave sparse pixels a tiny or negative weight, which would blow up
+/// the weighted sum instead of averaging it.
+pub const MIN_FILM_WEIGHT: f32 = 1e-3;
+
+/// Weighted sum of the samples splatted onto a pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilmPixel
+{
+    pub m_weighted_sum: Colorf,
+    pub m_weight: f32,
+}
+
+impl FilmPixel
+{
+    pub fn new() -> FilmPixel
+    {
+        FilmPixel{ m_weighted_sum: COLOR_BLACK, m_weight: 0.0 }
+    }
+
+    /// Whether enough filter weight reached the pixel to estimate its color
+    pub fn has_estimate(&self) -> bool
+    {
+        self.m_weight > MIN_FILM_WEIGHT
+    }
+
+    /// Filtered color of the pixel, black without an estimate. It stays linear and unbounded,
+    /// only the negative lobes of the filter are cut off.
+    pub fn resolve(&self) -> Colorf
+    {
+        if !self.has_estimate() { return COLOR_BLACK; }
+        let color = self.m_weighted_sum / self.m_weight;
+        Colorf::new(color.m_r.max(0.0), color.m_g.max(0.0), color.m_b.max(0.0))
+    }
+
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        for value in [self.m_weighted_sum.m_r, self.m_weighted_sum.m_g, self.m_weighted_sum.m_b, self.m_weight].iter()
+        {
+            write_f32(out, *value)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from(input: &mut dyn Read) -> io::Result<FilmPixel>
+    {
+        Ok(FilmPixel
+        {
+            m_weighted_sum: Colorf::new(read_f32(input)?, read_f32(input)?, read_f32(input)?),
+            m_weight: read_f32(input)?,
+        })
+    }
+}
+
+impl Default for FilmPixel
+{
+    fn default() -> Self
+    {
+        FilmPixel::new()
+    }
+}
+
+/// The part of the film a block of the RenderBuffer is rendered into. The samples of the
+/// block are splatted onto every pixel within the filter radius, so the tile overlaps the
+/// neighbouring blocks by the radius; overlapping tiles are summed when the film is resolved.
+#[derive(Debug)]
+pub struct FilmTile
+{
+    m_start_coords: (usize, usize),
+    m_end_coords: (usize, usize),
+    m_filter: Arc<dyn Filter>,
+    m_pixels: Vec<FilmPixel>,
+    m_deferred: Option<Vec<RenderSample>>, // The samples of a deferred tile, splatted later by add_samples
+}
+
+impl FilmTile
+{
+    pub fn new(rendermeta: &RenderMeta, img_dim: (usize, usize), filter: Arc<dyn Filter>) -> FilmTile
+    {
+        // Pixels whose center is within the radius of a sample of the block
+        let padding = (filter.get_radius() - 0.5).ceil().max(0.0) as usize;
+        let start_coords = (rendermeta.get_start_coords().0.saturating_sub(padding),
+                            rendermeta.get_start_coords().1.saturating_sub(padding));
+        let end_coords = ((rendermeta.get_end_coords().0 + padding).min(img_dim.0),
+                          (rendermeta.get_end_coords().1 + padding).min(img_dim.1));
+
+        FilmTile
+        {
+            m_start_coords: start_coords,
+            m_end_coords: end_coords,
+            m_filter: filter,
+            m_pixels: vec![FilmPixel::new(); (end_coords.0 - start_coords.0) * (end_coords.1 - start_coords.1)],
+            m_deferred: None,
+        }
+    }
+
+    /// Tile that keeps the samples added to it instead of splatting them, so that they can be
+    /// splatted onto the tile of the block in the fixed order of add_samples
+    pub fn new_deferred(rendermeta: &RenderMeta, filter: Arc<dyn Filter>) -> FilmTile
+    {
+        FilmTile
+        {
+            m_start_coords: *rendermeta.get_start_coords(),
+            m_end_coords: *rendermeta.get_start_coords(),
+            m_filter: filter,
+            m_pixels: Vec::new(),
+            m_deferred: Some(Vec::new()),
+        }
+    }
+
+    /// The samples kept by a deferred tile, none for another one
+    pub fn take_deferred(&mut self) -> Vec<RenderSample>
+    {
+        self.m_deferred.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn get_start_coords(&self) -> &(usize, usize)
+    {
+        &self.m_start_coords
+    }
+
+    pub fn get_end_coords(&self) -> &(usize, usize)
+    {
+        &self.m_end_coords
+    }
+
+    fn get_index(&self, x: usize, y: usize) -> usize
+    {
+        (x - self.m_start_coords.0) + (y - self.m_start_coords.1) * (self.m_end_coords.0 - self.m_start_coords.0)
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> &FilmPixel
+    {
+        &self.m_pixels[self.get_index(x, y)]
+    }
+
+    /// Add a sample to the pixels of the tile within the filter radius, weighted by the filter
+    pub fn add_sample(&mut self, sample: &RenderSample)
+    {
+        if let Some(deferred) = self.m_deferred.as_mut()
+        {
+            deferred.push(*sample);
+            return;
+        }
+        let radius = self.m_filter.get_radius();
+        // Pixel p covers [p, p + 1), its center is at p + 0.5
+        let pixel_range = |coord: f32, start: usize, end: usize| {
+            let first = (coord - 0.5 - radius).ceil().max(start as f32) as usize;
+            let last = ((coord - 0.5 + radius).floor() + 1.0).max(0.0).min(end as f32) as usize;
+            first..last.max(first)
+        };
+        let film_coords = sample.m_film_coords;
+
+        for y in pixel_range(film_coords.y, self.m_start_coords.1, self.m_end_coords.1)
+        {
+            for x in pixel_range(film_coords.x, self.m_start_coords.0, self.m_end_coords.0)
+            {
+                let offset = Vector2::new(film_coords.x - (x as f32 + 0.5), film_coords.y - (y as f32 + 0.5));
+                let weight = self.m_filter.evaluate(offset);
+                if weight == 0.0 { continue; }
+
+                let index = self.get_index(x, y);
+                let pixel = &mut self.m_pixels[index];
+                pixel.m_weighted_sum += sample.m_color * weight;
+                pixel.m_weight += weight;
+            }
+        }
+    }
+
+    /// Add samples sorted by their film coordinates and colors. Floating point sums depend on their
+    /// order, so the pixels come out the same whichever threads rendered the samples and in what
+    /// order; samples that compare equal are identical.
+    pub fn add_samples(&mut self, samples: &mut [RenderSample])
+    {
+        samples.sort_unstable_by(|a, b|
+            a.m_film_coords.y.total_cmp(&b.m_film_coords.y)
+                .then(a.m_film_coords.x.total_cmp(&b.m_film_coords.x))
+                .then(a.m_color.m_r.total_cmp(&b.m_color.m_r))
+                .then(a.m_color.m_g.total_cmp(&b.m_color.m_g))
+                .then(a.m_color.m_b.total_cmp(&b.m_color.m_b)));
+        samples.iter().for_each(|sample| self.add_sample(sample));
+    }
+
+    /// Add the pixels of the tile to the pixels of the whole film, stored row by row
+    pub fn merge_into(&self, film: &mut [FilmPixel], img_width: usize)
+    {
+        for y in self.m_start_coords.1..self.m_end_coords.1
+        {
+            for x in self.m_start_coords.0..self.m_end_coords.0
+            {
+                let pixel = self.get_pixel(x, y);
+                let target = &mut film[x + y * img_width];
+                target.m_weighted_sum += pixel.m_weighted_sum;
+                target.m_weight += pixel.m_weight;
+            }
+        }
+    }
+
+    pub fn clear(&mut self)
+    {
+        self.m_pixels.iter_mut().for_each(|pixel| *pixel = FilmPixel::new());
+    }
+
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        self.m_pixels.iter().try_for_each(|pixel| pixel.write_to(out))
+    }
+
+    /// Replace the pixels of the tile with those saved by write_to
+    pub fn read_from(&mut self, input: &mut dyn Read) -> io::Result<()>
+    {
+        for pixel in self.m_pixels.iter_mut()
+        {
+            *pixel = FilmPixel::read_from(input)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod FilmTileTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use crate::render::filter::boxfilter::BoxFilter;
+    use crate::render::filter::mitchell::MitchellFilter;
+    use crate::render::filter::tent::TentFilter;
+
+    fn sample(x: f32, y: f32, value: f32) -> RenderSample
+    {
+        RenderSample::new(Vector2::new(x, y), Colorf::new(value, value, value))
+    }
+
+    #[test]
+    fn box_filter_averages_pixel_samples()
+    {
+        let meta = RenderMeta::new((4, 4), (8, 8), (1, 1));
+        let mut tile = FilmTile::new(&meta, (16, 16), Arc::new(BoxFilter::new(0.5)));
+        assert_eq!(*tile.get_start_coords(), (4, 4));
+        assert_eq!(*tile.get_end_coords(), (8, 8));
+
+        tile.add_sample(&sample(5.0, 6.25, 0.2));
+        tile.add_sample(&sample(5.99, 6.75, 0.6));
+        assert_relative_eq!(tile.get_pixel(5, 6).resolve().m_r, 0.4);
+        assert_eq!(tile.get_pixel(4, 6).m_weight, 0.0);
+        assert_eq!(tile.get_pixel(6, 6).m_weight, 0.0);
+    }
+
+    #[test]
+    fn wide_filters_splat_on_neighbours()
+    {
+        let meta = RenderMeta::new((4, 4), (8, 8), (1, 1));
+        let mut tile = FilmTile::new(&meta, (16, 16), Arc::new(TentFilter::new(1.5)));
+        assert_eq!(*tile.get_start_coords(), (3, 3));
+        assert_eq!(*tile.get_end_coords(), (9, 9));
+
+        // A sample at the center of pixel (4, 4) reaches the pixels around it, outside of the block too
+        tile.add_sample(&sample(4.5, 4.5, 1.0));
+        assert_relative_eq!(tile.get_pixel(4, 4).m_weight, 2.25);
+        assert_relative_eq!(tile.get_pixel(3, 4).m_weight, 0.75);
+        assert_relative_eq!(tile.get_pixel(3, 3).m_weight, 0.25);
+        assert_eq!(tile.get_pixel(6, 4).m_weight, 0.0);
+
+        let mut film = vec![FilmPixel::new(); 16 * 16];
+        tile.merge_into(&mut film, 16);
+        tile.merge_into(&mut film, 16);
+        assert_relative_eq!(film[3 + 4 * 16].m_weight, 1.5);
+        assert_relative_eq!(film[3 + 4 * 16].resolve().m_g, 1.0);
+    }
+
+    #[test]
+    fn negative_weights_have_no_estimate()
+    {
+        let meta = RenderMeta::new((4, 4), (8, 8), (1, 1));
+        let mut tile = FilmTile::new(&meta, (16, 16), Arc::new(MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0)));
+
+        // Pixel (6, 4) is only reached by the negative lobe of the filter
+        tile.add_sample(&sample(4.75, 4.5, 1.0));
+        let lobe = tile.get_pixel(6, 4);
+        assert!(lobe.m_weight < 0.0);
+        assert!(!lobe.has_estimate());
+        assert_eq!(lobe.resolve(), COLOR_BLACK);
+
+        let center = tile.get_pixel(4, 4);
+        assert!(center.has_estimate());
+        assert_relative_eq!(center.resolve().m_r, 1.0, epsilon = 1e-5);
+
+        // Where the lobes almost cancel, the sum isn't divided by the tiny weight
+        let pixel = FilmPixel{ m_weighted_sum: Colorf::new(0.5, 0.5, 0.5), m_weight: 1e-6 };
+        assert_eq!(pixel.resolve(), COLOR_BLACK);
+    }
+}
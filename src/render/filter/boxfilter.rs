@@ -0,0 +1,56 @@
+use cgmath::Vector2;
+
+use crate::render::filter::{Filter, check_radius};
+use crate::utils::scenehash::SceneHasher;
+
+/// Equal weight within the radius. With a radius of 0.5, it averages the samples of each pixel.
+#[derive(Clone, Copy, Debug)]
+pub struct BoxFilter
+{
+    m_radius: f32,
+}
+
+impl BoxFilter
+{
+    pub fn new(radius: f32) -> BoxFilter
+    {
+        check_radius(radius);
+        BoxFilter{ m_radius: radius }
+    }
+}
+
+impl Filter for BoxFilter
+{
+    fn get_radius(&self) -> f32
+    {
+        self.m_radius
+    }
+
+    fn evaluate(&self, offset: Vector2<f32>) -> f32
+    {
+        // Half-open, so that a sample on a pixel border only counts for one pixel
+        let inside = |x: f32| x >= -self.m_radius && x < self.m_radius;
+        if inside(offset.x) && inside(offset.y) { 1.0 } else { 0.0 }
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("box");
+        hasher.write_f32(self.m_radius);
+    }
+}
+
+#[cfg(test)]
+mod BoxFilterTest
+{
+    use super::*;
+
+    #[test]
+    fn half_open_support()
+    {
+        let filter = BoxFilter::new(0.5);
+        assert_eq!(filter.evaluate(Vector2::new(0.0, 0.0)), 1.0);
+        assert_eq!(filter.evaluate(Vector2::new(-0.5, 0.2)), 1.0);
+        assert_eq!(filter.evaluate(Vector2::new(0.5, 0.2)), 0.0);
+    }
+}
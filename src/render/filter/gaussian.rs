@@ -0,0 +1,72 @@
+use cgmath::Vector2;
+
+use crate::render::filter::{Filter, check_radius};
+use crate::utils::scenehash::SceneHasher;
+
+/// Gaussian, shifted down so that it reaches 0 at the radius
+#[derive(Clone, Copy, Debug)]
+pub struct GaussianFilter
+{
+    m_radius: f32,
+    m_sigma: f32,
+    m_edge_value: f32,
+}
+
+impl GaussianFilter
+{
+    pub fn new(radius: f32, sigma: f32) -> GaussianFilter
+    {
+        check_radius(radius);
+        GaussianFilter
+        {
+            m_radius: radius,
+            m_sigma: sigma,
+            m_edge_value: gaussian(radius, sigma),
+        }
+    }
+
+    fn evaluate_1d(&self, x: f32) -> f32
+    {
+        if x.abs() >= self.m_radius { 0.0 } else { gaussian(x, self.m_sigma) - self.m_edge_value }
+    }
+}
+
+fn gaussian(x: f32, sigma: f32) -> f32
+{
+    (-x * x / (2.0 * sigma * sigma)).exp()
+}
+
+impl Filter for GaussianFilter
+{
+    fn get_radius(&self) -> f32
+    {
+        self.m_radius
+    }
+
+    fn evaluate(&self, offset: Vector2<f32>) -> f32
+    {
+        self.evaluate_1d(offset.x) * self.evaluate_1d(offset.y)
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("gaussian");
+        hasher.write_f32(self.m_radius);
+        hasher.write_f32(self.m_sigma);
+    }
+}
+
+#[cfg(test)]
+mod GaussianFilterTest
+{
+    use super::*;
+
+    #[test]
+    fn vanishes_at_radius()
+    {
+        let filter = GaussianFilter::new(1.5, 0.5);
+        assert_eq!(filter.evaluate(Vector2::new(1.5, 0.0)), 0.0);
+        assert!(filter.evaluate(Vector2::new(1.4, 0.0)) > 0.0);
+        assert!(filter.evaluate(Vector2::new(0.0, 0.0)) > filter.evaluate(Vector2::new(0.5, 0.0)));
+    }
+}
@@ -0,0 +1,69 @@
+use cgmath::Vector2;
+use std::f32::consts::PI;
+
+use crate::render::filter::{Filter, check_radius};
+use crate::utils::scenehash::SceneHasher;
+
+/// Sinc windowed by a wider sinc, "tau" being the number of lobes of the window
+#[derive(Clone, Copy, Debug)]
+pub struct LanczosFilter
+{
+    m_radius: f32,
+    m_tau: f32,
+}
+
+impl LanczosFilter
+{
+    pub fn new(radius: f32, tau: f32) -> LanczosFilter
+    {
+        check_radius(radius);
+        LanczosFilter{ m_radius: radius, m_tau: tau }
+    }
+
+    fn windowed_sinc(&self, x: f32) -> f32
+    {
+        if x.abs() > self.m_radius { 0.0 } else { sinc(x) * sinc(x / self.m_tau) }
+    }
+}
+
+fn sinc(x: f32) -> f32
+{
+    if x.abs() < 1e-5 { 1.0 } else { (PI * x).sin() / (PI * x) }
+}
+
+impl Filter for LanczosFilter
+{
+    fn get_radius(&self) -> f32
+    {
+        self.m_radius
+    }
+
+    fn evaluate(&self, offset: Vector2<f32>) -> f32
+    {
+        self.windowed_sinc(offset.x) * self.windowed_sinc(offset.y)
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("lanczos");
+        hasher.write_f32(self.m_radius);
+        hasher.write_f32(self.m_tau);
+    }
+}
+
+#[cfg(test)]
+mod LanczosFilterTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn zero_at_integers()
+    {
+        let filter = LanczosFilter::new(3.0, 3.0);
+        assert_relative_eq!(filter.evaluate(Vector2::new(0.0, 0.0)), 1.0);
+        assert_relative_eq!(filter.evaluate(Vector2::new(1.0, 0.0)), 0.0, epsilon = 1e-6);
+        assert_relative_eq!(filter.evaluate(Vector2::new(2.0, 0.5)), 0.0, epsilon = 1e-6);
+        assert_eq!(filter.evaluate(Vector2::new(3.5, 0.0)), 0.0);
+    }
+}
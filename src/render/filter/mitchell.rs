@@ -0,0 +1,81 @@
+use cgmath::Vector2;
+
+use crate::render::filter::{Filter, check_radius};
+use crate::utils::scenehash::SceneHasher;
+
+/// Mitchell-Netravali cubic filter. B = C = 1/3 is the usual compromise between blurring and ringing.
+#[derive(Clone, Copy, Debug)]
+pub struct MitchellFilter
+{
+    m_radius: f32,
+    m_b: f32,
+    m_c: f32,
+}
+
+impl MitchellFilter
+{
+    pub fn new(radius: f32, b: f32, c: f32) -> MitchellFilter
+    {
+        check_radius(radius);
+        MitchellFilter{ m_radius: radius, m_b: b, m_c: c }
+    }
+
+    /// The cubic, on [-2, 2]
+    fn mitchell_1d(&self, x: f32) -> f32
+    {
+        let (b, c) = (self.m_b, self.m_c);
+        let x = x.abs();
+        let value = if x > 2.0
+        {
+            0.0
+        }
+        else if x > 1.0
+        {
+            (-b - 6.0 * c) * x * x * x + (6.0 * b + 30.0 * c) * x * x + (-12.0 * b - 48.0 * c) * x + (8.0 * b + 24.0 * c)
+        }
+        else
+        {
+            (12.0 - 9.0 * b - 6.0 * c) * x * x * x + (-18.0 + 12.0 * b + 6.0 * c) * x * x + (6.0 - 2.0 * b)
+        };
+        value / 6.0
+    }
+}
+
+impl Filter for MitchellFilter
+{
+    fn get_radius(&self) -> f32
+    {
+        self.m_radius
+    }
+
+    fn evaluate(&self, offset: Vector2<f32>) -> f32
+    {
+        self.mitchell_1d(2.0 * offset.x / self.m_radius) * self.mitchell_1d(2.0 * offset.y / self.m_radius)
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("mitchell");
+        hasher.write_f32(self.m_radius);
+        hasher.write_f32(self.m_b);
+        hasher.write_f32(self.m_c);
+    }
+}
+
+#[cfg(test)]
+mod MitchellFilterTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn cubic_values()
+    {
+        let filter = MitchellFilter::new(2.0, 1.0 / 3.0, 1.0 / 3.0);
+        assert_relative_eq!(filter.mitchell_1d(0.0), 8.0 / 9.0, epsilon = 1e-6);
+        assert_relative_eq!(filter.mitchell_1d(1.0), 1.0 / 18.0, epsilon = 1e-6);
+        assert_relative_eq!(filter.mitchell_1d(2.0), 0.0, epsilon = 1e-6);
+        // Slightly negative lobe
+        assert!(filter.mitchell_1d(1.5) < 0.0);
+    }
+}
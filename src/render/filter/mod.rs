@@ -0,0 +1,29 @@
+pub mod boxfilter;
+pub mod tent;
+pub mod gaussian;
+pub mod mitchell;
+pub mod lanczos;
+
+use cgmath::Vector2;
+use std::fmt::Debug;
+
+use crate::utils::scenehash::SceneHasher;
+
+/// Pixel reconstruction filter: the weight of a sample in the pixels around it
+pub trait Filter: Debug + Send + Sync
+{
+    /// Distance from the pixel center, in pixels, past which the weight is 0
+    fn get_radius(&self) -> f32;
+    /// Weight of a sample at "offset" pixels from the pixel center
+    fn evaluate(&self, offset: Vector2<f32>) -> f32;
+    /// Kind and parameters of the filter; samples splatted by different filters can't be mixed
+    fn hash_scene(&self, hasher: &mut SceneHasher);
+}
+
+fn check_radius(radius: f32)
+{
+    if radius <= 0.0
+    {
+        panic!("The radius of a filter must be positive");
+    }
+}
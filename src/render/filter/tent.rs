@@ -0,0 +1,39 @@
+use cgmath::Vector2;
+
+use crate::render::filter::{Filter, check_radius};
+use crate::utils::scenehash::SceneHasher;
+
+/// Weight decreasing linearly from the pixel center to the radius
+#[derive(Clone, Copy, Debug)]
+pub struct TentFilter
+{
+    m_radius: f32,
+}
+
+impl TentFilter
+{
+    pub fn new(radius: f32) -> TentFilter
+    {
+        check_radius(radius);
+        TentFilter{ m_radius: radius }
+    }
+}
+
+impl Filter for TentFilter
+{
+    fn get_radius(&self) -> f32
+    {
+        self.m_radius
+    }
+
+    fn evaluate(&self, offset: Vector2<f32>) -> f32
+    {
+        (self.m_radius - offset.x.abs()).max(0.0) * (self.m_radius - offset.y.abs()).max(0.0)
+    }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("tent");
+        hasher.write_f32(self.m_radius);
+    }
+}
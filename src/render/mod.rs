@@ -1,3 +1,16 @@
+pub mod adaptive;
+pub mod aov;
+pub mod checkpoint;
+pub mod control;
+pub mod denoise;
+pub mod distributed;
+pub mod film;
+pub mod filter;
+pub mod progressive;
 pub mod renderbuffer;
 pub mod renderdata;
+pub mod server;
+#[cfg(test)]
+pub mod testscene;
+pub mod tileorder;
 pub mod cam;
\ No newline at end of file
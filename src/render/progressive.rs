@@ -0,0 +1,172 @@
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::output::OutputManager;
+use crate::output::imagewriter::ImageWriter;
+use crate::render::adaptive::PixelStatistics;
+use crate::render::aov::{AovTile, render_sample_with_aovs};
+use crate::render::cam::Camera;
+use crate::render::film::FilmTile;
+use crate::render::renderbuffer::RenderBuffer;
+use crate::render::renderdata::RenderMeta;
+use crate::sampler::pixelsampler::set_thread_sampler;
+use crate::world::world::World;
+
+/// Settings of a progressive render: every pass adds m_samples_per_pass samples to every pixel,
+/// until they have m_target_samples. The current estimate is reported at most every m_update_interval.
+#[derive(Clone, Debug)]
+pub struct ProgressiveRendering
+{
+    pub m_samples_per_pass: u32,
+    pub m_target_samples: u32,
+    pub m_update_interval: Duration,
+    pub m_checkpoint_path: Option<String>,
+    pub m_checkpoint_interval: Duration,
+}
+
+impl ProgressiveRendering
+{
+    pub fn new(samples_per_pass: u32, target_samples: u32, update_interval: Duration) -> ProgressiveRendering
+    {
+        if samples_per_pass == 0
+        {
+            panic!("A progressive pass needs at least one sample per pixel");
+        }
+
+        ProgressiveRendering
+        {
+            m_samples_per_pass: samples_per_pass,
+            m_target_samples: target_samples,
+            m_update_interval: update_interval,
+            m_checkpoint_path: None,
+            m_checkpoint_interval: Duration::from_secs(0),
+        }
+    }
+
+    /// Save a checkpoint to "path" after a pass, at most every "interval", and at the end of the render
+    pub fn set_checkpoint(&mut self, path: &str, interval: Duration)
+    {
+        self.m_checkpoint_path = Some(path.to_string());
+        self.m_checkpoint_interval = interval;
+    }
+
+    /// Sample indices of the pass that follows "sample_count" samples per pixel
+    pub fn get_pass_samples(&self, sample_count: u32) -> Range<u32>
+    {
+        sample_count..(sample_count + self.m_samples_per_pass).min(self.m_target_samples)
+    }
+}
+
+/// Add the samples "sample_indices" of every pixel of a block to its film tile, statistics and AOVs.
+/// Rendering the passes 0..n, n..2n, ... gives the same image as rendering all samples at once.
+/// Samples a pixel already has, e.g. when an interrupted pass is rendered again, are skipped.
+pub fn render_pass(camera: &dyn Camera,
+                   world_ptr: &Arc<World>,
+                   rendermeta: &RenderMeta,
+                   sample_indices: Range<u32>,
+                   statistics: &mut [PixelStatistics],
+                   tile: &mut FilmTile,
+                   aovs: &mut AovTile)
+{
+    set_thread_sampler(world_ptr.m_viewplaneptr.get_pixel_sampler());
+    let start_coords = rendermeta.get_start_coords();
+    let end_coords = rendermeta.get_end_coords();
+
+    for x in start_coords.0..end_coords.0
+    {
+        for y in start_coords.1..end_coords.1
+        {
+            let local_index = rendermeta.get_local_index(x, y);
+            let pixel = &mut statistics[local_index];
+            for sample_index in sample_indices.start.max(pixel.get_count())..sample_indices.end
+            {
+                let sample = render_sample_with_aovs(camera, world_ptr, x, y, sample_index, aovs, local_index);
+                pixel.add_sample(sample.m_color);
+                tile.add_sample(&sample);
+            }
+        }
+    }
+}
+
+/// Save the current estimate of a progressive render, e.g. from its update callback
+pub fn save_estimate(buffer: &RenderBuffer, img_path: &str)
+{
+    let img_dim = buffer.get_dim();
+    let mut imgwriter = ImageWriter::new(img_path, img_dim.0, img_dim.1);
+    buffer.write(&mut imgwriter);
+    imgwriter.output();
+}
+
+#[cfg(test)]
+mod ProgressiveRenderingTest
+{
+    use super::*;
+    use crate::render::testscene::{setup_world, setup_camera, get_two_spheres, get_white_matte};
+    use crate::sampler::DEFAULT_SEED;
+    use crate::utils::multithread::MultiThread;
+
+    #[test]
+    fn pass_samples()
+    {
+        let settings = ProgressiveRendering::new(4, 10, Duration::from_secs(1));
+        assert_eq!(settings.get_pass_samples(0), 0..4);
+        assert_eq!(settings.get_pass_samples(8), 8..10);
+        assert!(settings.get_pass_samples(10).is_empty());
+    }
+
+    fn setup_scene() -> Arc<World>
+    {
+        setup_world((12, 10), DEFAULT_SEED, get_two_spheres(get_white_matte(), get_white_matte()))
+    }
+
+    #[test]
+    fn passes_add_up_to_full_render()
+    {
+        let world = setup_scene();
+        let camera = setup_camera();
+        let mut multithread = MultiThread::new(2);
+
+        let full = RenderBuffer::new((12, 10), (8, 8));
+        multithread.render_to_buffer(world.clone(), &camera, &full);
+
+        let progressive = RenderBuffer::new((12, 10), (8, 8));
+        let mut updates = Vec::new();
+        let mut estimates = Vec::new();
+        multithread.render_progressive(world, &camera, &progressive,
+                                       &ProgressiveRendering::new(1, 4, Duration::from_secs(0)),
+                                       |buffer, sample_count| {
+                                           updates.push(sample_count);
+                                           estimates.push(buffer.resolve());
+                                       }).unwrap();
+
+        assert_eq!(updates, vec![1, 2, 3, 4]);
+        assert_ne!(estimates[0], estimates[3]);
+        assert_eq!(estimates[3], full.resolve());
+        let meta = RenderMeta::new((8, 8), (12, 10), (1, 1));
+        assert!(progressive.lock_statistics(&meta).iter().all(|pixel| pixel.get_count() == 4));
+    }
+
+    #[test]
+    fn interrupted_pass_is_resumed()
+    {
+        let world = setup_scene();
+        let camera = setup_camera();
+        let mut multithread = MultiThread::new(2);
+
+        let full = RenderBuffer::new((12, 10), (8, 8));
+        multithread.render_to_buffer(world.clone(), &camera, &full);
+
+        // The first pass was stopped after its first block
+        let resumed = RenderBuffer::new((12, 10), (8, 8));
+        let meta = resumed.get_block((0, 0)).unwrap();
+        render_pass(&camera, &world, &meta, 0..2, &mut resumed.lock_statistics(&meta),
+                    &mut resumed.lock_tile(&meta), &mut resumed.lock_aovs(&meta));
+        multithread.render_progressive(world, &camera, &resumed,
+                                       &ProgressiveRendering::new(2, 4, Duration::from_secs(0)),
+                                       |_, _| {}).unwrap();
+
+        assert!(resumed.lock_statistics(&meta).iter().all(|pixel| pixel.get_count() == 4));
+        assert_eq!(resumed.resolve(), full.resolve());
+    }
+}
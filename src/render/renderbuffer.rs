@@ -1,12 +1,28 @@
 use cgmath::{Vector3, InnerSpace, dot, ElementWise, Zero, Vector2};
-use std::sync::{Mutex, atomic};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard, atomic};
 use std::iter;
 
-use crate::utils::color::Colorf;
-use crate::utils::colorconstant::COLOR_BLACK;
-use crate::render::renderdata::RenderMeta;
+use crate::render::renderdata::{RegionError, RenderMeta, RenderRegion, RenderSample};
+use crate::render::film::{FilmTile, FilmPixel};
+use crate::render::filter::Filter;
+use crate::render::filter::boxfilter::BoxFilter;
 use std::option::Iter;
 use crate::output::OutputManager;
+use crate::output::exrwriter::ExrWriter;
+use crate::output::postprocess::PostProcessing;
+use crate::render::adaptive::{PixelStatistics, get_heatmap_color};
+use crate::render::aov::{Aov, AovTile, get_aov_mask};
+use crate::render::cam::Camera;
+use crate::render::checkpoint::{CheckpointError, CheckpointHeader};
+use crate::render::tileorder::{TileOrder, WorkStealingQueue};
+use crate::utils::color::Colorf;
+use crate::world::world::World;
+
+/// A rendered sub-tile of a block, and the film samples it keeps until the block is rendered
+type SubTileSamples = (RenderMeta, Vec<RenderSample>);
 
 /// The buffer that stores our render result while we are rendering
 pub struct RenderBuffer
@@ -14,30 +30,55 @@ pub struct RenderBuffer
     m_dim: (usize, usize),
     m_block_dim: (usize, usize), // The Render buffer is divided into blocks for multithreading
     m_num_blocks: (usize, usize),
-    m_sample_blocks: Vec<(RenderMeta, Mutex<Vec<Colorf>>)>,
+    m_sample_blocks: Vec<(RenderMeta, Mutex<FilmTile>)>,
+    m_filter: Arc<dyn Filter>,
+    m_statistics: Vec<Mutex<Vec<PixelStatistics>>>, // Running statistics of the pixels, for adaptive sampling
+    m_aovs: Vec<Aov>,
+    m_aov_tiles: Vec<Mutex<AovTile>>,
+    m_post_processing: PostProcessing, // Effects on the image before it's written
+    m_region: Option<RenderRegion>, // Only the pixels within it are rendered and written
+    m_tile_order: TileOrder,
+    m_work_stealing: Option<WorkStealingQueue>, // Threads share a single queue of blocks without it
+    m_sub_tile_samples: Mutex<HashMap<usize, Vec<SubTileSamples>>>, // Of the blocks whose sub-tiles aren't all rendered
     m_next: atomic::AtomicUsize,
+    m_sample_count: atomic::AtomicU32, // Samples per pixel accumulated by progressive passes
 }
 
 impl RenderBuffer
 {
+    /// RenderBuffer that averages the samples of each pixel
     pub fn new(img_dim: (usize, usize),
                block_dim: (usize, usize)) -> RenderBuffer
     {
-        if img_dim.0 % block_dim.0 != 0 || img_dim.1 % block_dim.1 != 0
+        RenderBuffer::new_with_filter(img_dim, block_dim, Arc::new(BoxFilter::new(0.5)))
+    }
+
+    /// RenderBuffer that reconstructs the pixels from the samples around them with "filter"
+    pub fn new_with_filter(img_dim: (usize, usize),
+                           block_dim: (usize, usize),
+                           filter: Arc<dyn Filter>) -> RenderBuffer
+    {
+        if block_dim.0 == 0 || block_dim.1 == 0
         {
-            panic!("Image dimensions are not multiples for block dimensions");
+            panic!("Block dimensions can't be 0");
         }
-        let num_blocks = (img_dim.0 / block_dim.0, img_dim.1 / block_dim.1);
+        // The blocks on the right and bottom edges are cut to the image
+        let num_blocks = (img_dim.0.div_ceil(block_dim.0), img_dim.1.div_ceil(block_dim.1));
         let mut samples = Vec::with_capacity(num_blocks.0 * num_blocks.1);
+        let mut statistics = Vec::with_capacity(num_blocks.0 * num_blocks.1);
+        let mut aov_tiles = Vec::with_capacity(num_blocks.0 * num_blocks.1);
 
-        for i in 0..num_blocks.0 - 1
+        // Row by row, so that block (i, j) is at i + j * num_blocks.0
+        for j in 0..num_blocks.1
         {
-            for j in 0..num_blocks.1 - 1
+            for i in 0..num_blocks.0
             {
-                samples.push((RenderMeta::new((i * block_dim.0, j * block_dim.1),
-                                              ((i+1) * block_dim.0, (j+1) * block_dim.1),
-                                              (i, j)),
-                              Mutex::new(vec![COLOR_BLACK; block_dim.0 * block_dim.1])));
+                let meta = RenderMeta::new((i * block_dim.0, j * block_dim.1),
+                                           (((i+1) * block_dim.0).min(img_dim.0), ((j+1) * block_dim.1).min(img_dim.1)),
+                                           (i, j));
+                samples.push((meta, Mutex::new(FilmTile::new(&meta, img_dim, filter.clone()))));
+                statistics.push(Mutex::new(vec![PixelStatistics::new(); meta.m_area]));
+                aov_tiles.push(Mutex::new(AovTile::new(&[], meta.m_area)));
             }
         }
 
@@ -47,50 +88,351 @@ impl RenderBuffer
             m_block_dim: block_dim,
             m_num_blocks: num_blocks,
             m_sample_blocks: samples,
+            m_filter: filter,
+            m_statistics: statistics,
+            m_aovs: Vec::new(),
+            m_aov_tiles: aov_tiles,
+            m_post_processing: PostProcessing::new(),
+            m_region: None,
+            m_tile_order: TileOrder::default(),
+            m_work_stealing: None,
+            m_sub_tile_samples: Mutex::new(HashMap::new()),
             m_next: atomic::AtomicUsize::new(0),
+            m_sample_count: atomic::AtomicU32::new(0),
         }
     }
 
-   /// Read an array of pixels into a block
-    pub fn read(&self, samples: Vec<Colorf>, rendermeta: &RenderMeta)
-   {
-       let block_indices = rendermeta.get_block_indices();
+    fn get_block_index(&self, rendermeta: &RenderMeta) -> usize
+    {
+        let block_indices = rendermeta.get_block_indices();
         assert!(block_indices.0 < self.m_num_blocks.0 && block_indices.1 < self.m_num_blocks.1);
-        let index = block_indices.0 + block_indices.1 * self.m_num_blocks.0;
+        block_indices.0 + block_indices.1 * self.m_num_blocks.0
+    }
+
+    /// The film tile a block is rendered into
+    pub fn lock_tile(&self, rendermeta: &RenderMeta) -> MutexGuard<'_, FilmTile>
+    {
+        self.m_sample_blocks[self.get_block_index(rendermeta)].1.lock().unwrap()
+    }
+
+    pub fn get_filter(&self) -> &Arc<dyn Filter>
+    {
+        &self.m_filter
+    }
+
+    /// Sum the overlapping film tiles into the pixels of the image, stored row by row
+    pub fn resolve(&self) -> Vec<FilmPixel>
+    {
+        self.add_unfinished_sub_tiles();
+        let mut film = vec![FilmPixel::new(); self.m_dim.0 * self.m_dim.1];
+        for (_, locked_tile) in self.m_sample_blocks.iter()
+        {
+            locked_tile.lock().unwrap().merge_into(&mut film, self.m_dim.0);
+        }
+        film
+    }
 
-        for (pixels, color) in self.m_sample_blocks[index].1.lock().unwrap()
-            .iter_mut().zip(samples.iter())
+    /// Like resolve, without the blocks being rendered, so that a preview doesn't wait for them
+    pub fn resolve_available(&self) -> Vec<FilmPixel>
+    {
+        let mut film = vec![FilmPixel::new(); self.m_dim.0 * self.m_dim.1];
+        for (_, locked_tile) in self.m_sample_blocks.iter()
         {
-            *pixels = *color;
+            if let Ok(tile) = locked_tile.try_lock()
+            {
+                tile.merge_into(&mut film, self.m_dim.0);
+            }
         }
+        film
     }
 
-    /// Write all blocks to the OutputManager
+    /// Write all blocks to the OutputManager, through the post-processing effects
     pub fn write(&self, out_manager: &mut dyn OutputManager)
     {
-        for (meta, locked_block) in self.m_sample_blocks.iter()
+        let image: Vec<Colorf> = self.resolve().iter().map(|pixel| pixel.resolve()).collect();
+        self.write_image(image, out_manager);
+    }
+
+    /// Apply the post-processing effects to an image of the buffer's dimensions, e.g. a denoised
+    /// one, and write it to the OutputManager. Only the pixels of the render region are written.
+    pub fn write_image(&self, mut image: Vec<Colorf>, out_manager: &mut dyn OutputManager)
+    {
+        self.m_post_processing.apply(&mut image, self.m_dim);
+        let img_dim = out_manager.get_img_dim();
+        let region = self.get_region();
+
+        for i in region.get_start_coords().0..region.get_end_coords().0.min(img_dim.0)
+        {
+            for j in region.get_start_coords().1..region.get_end_coords().1.min(img_dim.1)
+            {
+                out_manager.write_pixel(i, j, image[i + j * self.m_dim.0]);
+            }
+        }
+    }
+
+    /// Effects applied to the linear image whenever it's written, before the OutputManager tone maps it
+    pub fn set_post_processing(&mut self, post_processing: PostProcessing)
+    {
+        self.m_post_processing = post_processing;
+    }
+
+    pub fn get_post_processing(&self) -> &PostProcessing
+    {
+        &self.m_post_processing
+    }
+
+    /// Store "aovs" besides the image, from the next samples on. Their previous values are lost.
+    pub fn set_aovs(&mut self, aovs: &[Aov])
+    {
+        self.m_aovs = aovs.to_vec();
+        for ((meta, _), locked_aovs) in self.m_sample_blocks.iter().zip(self.m_aov_tiles.iter_mut())
+        {
+            *locked_aovs.get_mut().unwrap() = AovTile::new(aovs, meta.m_area);
+        }
+    }
+
+    pub fn get_aovs(&self) -> &[Aov]
+    {
+        &self.m_aovs
+    }
+
+    /// AOVs of the pixels of a block, in the order the cameras render them
+    pub fn lock_aovs(&self, rendermeta: &RenderMeta) -> MutexGuard<'_, AovTile>
+    {
+        self.m_aov_tiles[self.get_block_index(rendermeta)].lock().unwrap()
+    }
+
+    /// Write an AOV as an image: one channel as gray, UV as red and green, three channels as RGB.
+    /// The values are written as they are, e.g. negative normals, so they are meant for float images.
+    pub fn write_aov(&self, aov: Aov, out_manager: &mut dyn OutputManager)
+    {
+        if !self.m_aovs.contains(&aov)
+        {
+            panic!("The RenderBuffer doesn't store the {} AOV", aov.get_name());
+        }
+        let img_dim = out_manager.get_img_dim();
+        self.for_each_aov_pixel(aov, img_dim, |x, y, values|
+            {
+                let color = match values.len()
+                {
+                    1 => Colorf::new(values[0], values[0], values[0]),
+                    2 => Colorf::new(values[0], values[1], 0.0),
+                    _ => Colorf::new(values[0], values[1], values[2]),
+                };
+                out_manager.write_pixel(x, y, color);
+            });
+    }
+
+    /// The values of an AOV over the whole image, row by row, with its channels side by side.
+    /// They are 0 if the buffer doesn't store the AOV.
+    pub fn resolve_aov(&self, aov: Aov) -> Vec<f32>
+    {
+        let channel_count = aov.get_channel_count();
+        let mut values = vec![0.0; self.m_dim.0 * self.m_dim.1 * channel_count];
+        if self.m_aovs.contains(&aov)
+        {
+            self.for_each_aov_pixel(aov, self.m_dim, |x, y, pixel|
+                {
+                    let start = (x + y * self.m_dim.0) * channel_count;
+                    values[start..start + channel_count].copy_from_slice(pixel);
+                });
+        }
+        values
+    }
+
+    /// Add every AOV as a layer of the EXR image, e.g. channels "normal.X", "normal.Y" and "normal.Z"
+    pub fn write_aovs_to_exr(&self, writer: &mut ExrWriter)
+    {
+        let img_dim = writer.get_img_dim();
+        for aov in self.m_aovs.iter()
+        {
+            let channel_names: Vec<String> = aov.get_channel_names().iter()
+                .map(|channel| format!("{}.{}", aov.get_name(), channel))
+                .collect();
+            channel_names.iter().for_each(|name| writer.add_channel(name));
+            self.for_each_aov_pixel(*aov, img_dim, |x, y, values|
+                {
+                    for (name, value) in channel_names.iter().zip(values.iter())
+                    {
+                        writer.write_channel(name, x, y, *value);
+                    }
+                });
+        }
+    }
+
+    fn for_each_aov_pixel<F>(&self, aov: Aov, img_dim: (usize, usize), mut write: F)
+        where F: FnMut(usize, usize, &[f32])
+    {
+        for ((meta, _), locked_aovs) in self.m_sample_blocks.iter().zip(self.m_aov_tiles.iter())
+        {
+            let end_coord = (meta.get_end_coords().0.min(img_dim.0), meta.get_end_coords().1.min(img_dim.1));
+            let aovs = locked_aovs.lock().unwrap();
+
+            for i in meta.get_start_coords().0..end_coord.0
+            {
+                for j in meta.get_start_coords().1..end_coord.1
+                {
+                    write(i, j, &aovs.get_pixel(meta.get_local_index(i, j), aov));
+                }
+            }
+        }
+    }
+
+    /// Running statistics of the pixels of a block, in the order the cameras render them
+    pub fn lock_statistics(&self, rendermeta: &RenderMeta) -> MutexGuard<'_, Vec<PixelStatistics>>
+    {
+        self.m_statistics[self.get_block_index(rendermeta)].lock().unwrap()
+    }
+
+    /// Average standard error of the pixels of the render region, from their running statistics
+    pub fn get_noise_level(&self) -> f32
+    {
+        let pixel_count = self.get_region().get_area();
+        let sum: f32 = self.get_scheduled_blocks().iter()
+            .map(|meta|
+                {
+                    let statistics = self.lock_statistics(meta);
+                    let mut block_sum = 0.0;
+                    for i in meta.get_start_coords().0..meta.get_end_coords().0
+                    {
+                        for j in meta.get_start_coords().1..meta.get_end_coords().1
+                        {
+                            block_sum += statistics[meta.get_local_index(i, j)].get_standard_error();
+                        }
+                    }
+                    block_sum
+                })
+            .sum();
+        if pixel_count == 0 { 0.0 } else { sum / pixel_count as f32 }
+    }
+
+    /// Write the number of samples taken in every pixel, as a heatmap where max_samples is red
+    pub fn write_sample_counts(&self, out_manager: &mut dyn OutputManager, max_samples: u32)
+    {
+        for meta in self.get_scheduled_blocks().iter()
         {
-            let start_coord = meta.get_start_coords().max(&(0_usize, 0_usize));
             let img_dim = out_manager.get_img_dim();
-            let end_coord = meta.get_end_coords().min(&img_dim);
-            let block = locked_block.lock().unwrap();
+            let end_coord = (meta.get_end_coords().0.min(img_dim.0), meta.get_end_coords().1.min(img_dim.1));
+            let statistics = self.lock_statistics(meta);
 
-            for i in start_coord.0..end_coord.0
+            for i in meta.get_start_coords().0..end_coord.0
             {
-                for j in start_coord.1..end_coord.1
+                for j in meta.get_start_coords().1..end_coord.1
                 {
-                    out_manager.write_pixel(i, j, block[i + j * self.m_block_dim.0], 1.0);
+                    let count = statistics[meta.get_local_index(i, j)].get_count();
+                    out_manager.write_pixel(i, j, get_heatmap_color(count, max_samples));
                 }
             }
         }
     }
 
+    /// Hand out the blocks from the first one again, for another pass over the image
+    pub fn restart(&self)
+    {
+        self.add_unfinished_sub_tiles();
+        self.m_next.store(0, atomic::Ordering::Release);
+        if let Some(queue) = self.m_work_stealing.as_ref()
+        {
+            queue.restart();
+        }
+    }
+
+    /// Samples per pixel accumulated by the progressive passes so far
+    pub fn get_sample_count(&self) -> u32
+    {
+        self.m_sample_count.load(atomic::Ordering::Acquire)
+    }
+
+    pub fn set_sample_count(&self, sample_count: u32)
+    {
+        self.m_sample_count.store(sample_count, atomic::Ordering::Release);
+    }
+
+    /// What identifies the render of "world" through "camera" into this buffer, e.g. in its checkpoints
+    pub fn get_checkpoint_header(&self, world: &World, camera: &dyn Camera) -> CheckpointHeader
+    {
+        CheckpointHeader::new(world, camera, self.m_dim, self.m_block_dim, self.m_filter.as_ref(),
+                              get_aov_mask(&self.m_aovs), self.get_sample_count())
+    }
+
+    /// Save the accumulated samples of every pixel, so that the render of "world" can resume from them.
+    /// The checkpoint replaces "path" only once complete, so a crash while saving keeps the previous one.
+    pub fn save_checkpoint(&self, path: &str, world: &World, camera: &dyn Camera) -> Result<(), CheckpointError>
+    {
+        self.add_unfinished_sub_tiles();
+        let partial_path = format!("{}.partial", path);
+        let mut out = BufWriter::new(File::create(&partial_path)?);
+        self.get_checkpoint_header(world, camera).write_to(&mut out)?;
+        for (meta, _) in self.m_sample_blocks.iter()
+        {
+            self.write_block(meta, &mut out)?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(&partial_path, path)?;
+        Ok(())
+    }
+
+    /// Replace the content of the buffer with a checkpoint of the render of "world" through "camera".
+    /// A checkpoint of another scene, camera, seed or sample sequence is rejected.
+    pub fn load_checkpoint(&self, path: &str, world: &World, camera: &dyn Camera) -> Result<(), CheckpointError>
+    {
+        let mut input = BufReader::new(File::open(path)?);
+        let header = CheckpointHeader::read_from(&mut input)?;
+        header.check(&self.get_checkpoint_header(world, camera))?;
+
+        for (meta, _) in self.m_sample_blocks.iter()
+        {
+            self.read_block(meta, &mut input)?;
+        }
+        self.set_sample_count(header.m_sample_count);
+        Ok(())
+    }
+
+    /// Write the samples of a block: its film tile, pixel statistics and AOVs
+    pub fn write_block(&self, rendermeta: &RenderMeta, out: &mut dyn Write) -> io::Result<()>
+    {
+        self.lock_tile(rendermeta).write_to(out)?;
+        self.lock_statistics(rendermeta).iter().try_for_each(|pixel| pixel.write_to(out))?;
+        self.lock_aovs(rendermeta).write_to(out)
+    }
+
+    /// Replace the samples of a block with the ones written by write_block
+    pub fn read_block(&self, rendermeta: &RenderMeta, input: &mut dyn Read) -> io::Result<()>
+    {
+        self.lock_tile(rendermeta).read_from(input)?;
+        for pixel in self.lock_statistics(rendermeta).iter_mut()
+        {
+            *pixel = PixelStatistics::read_from(input)?;
+        }
+        self.lock_aovs(rendermeta).read_from(input)
+    }
+
+    /// Remove the samples of a block, before it is rendered again
+    pub fn clear_block(&self, rendermeta: &RenderMeta)
+    {
+        self.m_sub_tile_samples.lock().unwrap().remove(&self.get_block_index(rendermeta));
+        self.lock_tile(rendermeta).clear();
+        self.lock_statistics(rendermeta).iter_mut().for_each(|pixel| *pixel = PixelStatistics::new());
+        self.lock_aovs(rendermeta).clear();
+    }
+
     pub fn clear(&mut self)
     {
-        for (_, locked_block) in self.m_sample_blocks.iter()
+        self.m_sub_tile_samples.lock().unwrap().clear();
+        self.restart();
+        self.set_sample_count(0);
+        for (_, locked_tile) in self.m_sample_blocks.iter()
+        {
+            locked_tile.lock().unwrap().clear();
+        }
+        for locked_statistics in self.m_statistics.iter()
         {
-            let mut block = locked_block.lock().unwrap();
-            block.iter_mut().map(|pixel| *pixel = COLOR_BLACK).count();
+            locked_statistics.lock().unwrap().iter_mut().for_each(|pixel| *pixel = PixelStatistics::new());
+        }
+        for locked_aovs in self.m_aov_tiles.iter()
+        {
+            locked_aovs.lock().unwrap().clear();
         }
     }
 
@@ -104,31 +446,248 @@ impl RenderBuffer
         self.m_block_dim
     }
 
+    pub fn get_num_blocks(&self) -> (usize, usize)
+    {
+        self.m_num_blocks
+    }
+
+    /// Restrict the render to a region of the image, clipped to it; None renders the whole image.
+    /// A region outside the image is rejected and leaves the current one.
+    pub fn set_region(&mut self, region: Option<RenderRegion>) -> Result<(), RegionError>
+    {
+        self.m_region = match region
+        {
+            Some(region) => Some(region.clip_to_image(self.m_dim)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// The region of the image that is rendered, the whole image if none was set
+    pub fn get_region(&self) -> RenderRegion
+    {
+        self.m_region.unwrap_or_else(|| RenderRegion::from_image(self.m_dim))
+    }
+
+    pub fn set_tile_order(&mut self, tile_order: TileOrder)
+    {
+        self.m_tile_order = tile_order;
+    }
+
+    pub fn get_tile_order(&self) -> TileOrder
+    {
+        self.m_tile_order
+    }
+
+    /// Give every thread its own run of blocks, in the tile order, which the others steal from once
+    /// they are done with theirs, cutting the last blocks into sub-tiles
+    pub fn set_work_stealing(&mut self, work_stealing: bool)
+    {
+        self.m_work_stealing = if work_stealing { Some(WorkStealingQueue::new()) } else { None };
+    }
+
+    pub fn is_work_stealing(&self) -> bool
+    {
+        self.m_work_stealing.is_some()
+    }
+
+    /// The blocks that intersect the render region, clipped to it, in the tile order
+    pub fn get_scheduled_blocks(&self) -> Vec<RenderMeta>
+    {
+        let mut blocks: Vec<RenderMeta> = match self.m_region
+        {
+            Some(region) => self.m_sample_blocks.iter().filter_map(|block| block.0.clip(&region)).collect(),
+            None => self.m_sample_blocks.iter().map(|block| block.0).collect(),
+        };
+        self.m_tile_order.sort(&mut blocks, &self.get_region());
+        blocks
+    }
+
+    /// The whole block at "block_indices", None if the buffer has no such block
+    pub fn get_block(&self, block_indices: (usize, usize)) -> Option<RenderMeta>
+    {
+        if block_indices.0 >= self.m_num_blocks.0 || block_indices.1 >= self.m_num_blocks.1 { return None; }
+        Some(self.m_sample_blocks[block_indices.0 + block_indices.1 * self.m_num_blocks.0].0)
+    }
+
+    /// Record that a block or sub-tile handed out by an iterator is rendered; return whether its
+    /// whole block is, i.e. it isn't a sub-tile or it is the last of its block
+    pub fn finish(&self, rendermeta: &RenderMeta) -> bool
+    {
+        self.m_work_stealing.as_ref().is_none_or(|work_stealing| work_stealing.finish(rendermeta))
+    }
+
+    /// Run "render" on the pixel statistics, film tile and AOVs of the block of "rendermeta".
+    /// The samples are splatted onto the film tile of the block once it is rendered, in the order
+    /// of FilmTile::add_samples, so that the image is the same whether or not the block was split
+    /// into sub-tiles. A sub-tile gets statistics and AOVs of its own, copied back for its pixels,
+    /// so that the threads rendering the other sub-tiles don't wait for it, and its samples are
+    /// kept until the sub-tiles cover the block.
+    pub fn render_into<F>(&self, rendermeta: &RenderMeta, render: F)
+        where F: FnOnce(&mut [PixelStatistics], &mut FilmTile, &mut AovTile)
+    {
+        let mut tile = FilmTile::new_deferred(rendermeta, self.m_filter.clone());
+        let scheduled = match self.get_scheduled_block(rendermeta)
+        {
+            Some(scheduled) if !is_same_tile(&scheduled, rendermeta) => scheduled,
+            _ =>
+                {
+                    let mut statistics = self.lock_statistics(rendermeta);
+                    render(&mut statistics, &mut tile, &mut self.lock_aovs(rendermeta));
+                    self.lock_tile(rendermeta).add_samples(&mut tile.take_deferred());
+                    return;
+                }
+        };
+
+        let mut statistics = self.lock_statistics(rendermeta).clone();
+        let mut aovs = self.lock_aovs(rendermeta).clone();
+        render(&mut statistics, &mut tile, &mut aovs);
+        {
+            let mut block_statistics = self.lock_statistics(rendermeta);
+            let mut block_aovs = self.lock_aovs(rendermeta);
+            for x in rendermeta.get_start_coords().0..rendermeta.get_end_coords().0
+            {
+                for y in rendermeta.get_start_coords().1..rendermeta.get_end_coords().1
+                {
+                    let local_index = rendermeta.get_local_index(x, y);
+                    block_statistics[local_index] = statistics[local_index];
+                    block_aovs.copy_pixel(&aovs, local_index);
+                }
+            }
+        }
+
+        let block_index = self.get_block_index(rendermeta);
+        let mut locked_sub_tiles = self.m_sub_tile_samples.lock().unwrap();
+        let sub_tiles = locked_sub_tiles.entry(block_index).or_default();
+        sub_tiles.push((*rendermeta, tile.take_deferred()));
+        if sub_tiles.iter().map(|(sub_tile, _)| sub_tile.m_area).sum::<usize>() < scheduled.m_area { return; }
+
+        let sub_tiles = locked_sub_tiles.remove(&block_index).unwrap_or_default();
+        drop(locked_sub_tiles);
+        self.add_sub_tile_samples(rendermeta, sub_tiles);
+    }
+
+    /// Splat the samples of the sub-tiles of a block onto its film tile, in their order in the block
+    fn add_sub_tile_samples(&self, rendermeta: &RenderMeta, mut sub_tiles: Vec<SubTileSamples>)
+    {
+        sub_tiles.sort_by_key(|(sub_tile, _)| (sub_tile.get_start_coords().1, sub_tile.get_start_coords().0));
+        let mut samples: Vec<RenderSample> = sub_tiles.into_iter().flat_map(|(_, samples)| samples).collect();
+        self.lock_tile(rendermeta).add_samples(&mut samples);
+    }
+
+    /// Splat the samples of the sub-tiles of the blocks left unfinished, e.g. by a cancelled pass
+    fn add_unfinished_sub_tiles(&self)
+    {
+        let unfinished = std::mem::take(&mut *self.m_sub_tile_samples.lock().unwrap());
+        for (block_index, sub_tiles) in unfinished.into_iter()
+        {
+            let block = self.m_sample_blocks[block_index].0;
+            self.add_sub_tile_samples(&block, sub_tiles);
+        }
+    }
+
+    /// Empty samples for the block of "rendermeta", apart from the buffer's
+    pub fn new_block_samples(&self, rendermeta: &RenderMeta) -> BlockSamples
+    {
+        let block = self.m_sample_blocks[self.get_block_index(rendermeta)].0;
+        BlockSamples
+        {
+            m_statistics: vec![PixelStatistics::new(); block.m_area],
+            m_tile: FilmTile::new(&block, self.m_dim, self.m_filter.clone()),
+            m_aovs: AovTile::new(&self.m_aovs, block.m_area),
+        }
+    }
+
+    /// Number of bytes write_block writes for the block of "rendermeta"
+    pub fn get_block_size(&self, rendermeta: &RenderMeta) -> usize
+    {
+        let mut bytes = Vec::new();
+        self.new_block_samples(rendermeta).write_to(&mut bytes).expect("Writing to memory can't fail");
+        bytes.len()
+    }
+
+    /// The part of the block of "rendermeta" scheduled by the render region, None if it is outside it
+    fn get_scheduled_block(&self, rendermeta: &RenderMeta) -> Option<RenderMeta>
+    {
+        let block = self.m_sample_blocks[self.get_block_index(rendermeta)].0;
+        match self.m_region
+        {
+            Some(region) => block.clip(&region),
+            None => Some(block),
+        }
+    }
+
+    /// Whether "rendermeta" is only part of the block scheduled for it
+    fn is_sub_tile(&self, rendermeta: &RenderMeta) -> bool
+    {
+        self.get_scheduled_block(rendermeta).is_none_or(|scheduled| !is_same_tile(&scheduled, rendermeta))
+    }
+
+    /// Number of blocks handed out by a pass over the buffer
+    pub fn get_block_count(&self) -> usize
+    {
+        self.get_scheduled_blocks().len()
+    }
+
+    /// The blocks left in the current pass, for one thread
     pub fn iter(&self) -> RenderBufferIter
     {
+        let queue = self.get_scheduled_blocks();
+        let span = self.m_work_stealing.as_ref().map(|work_stealing| work_stealing.join(&queue));
         RenderBufferIter
         {
-            m_queue: self.m_sample_blocks.iter()
-                .map(|block| block.0).collect(),
+            m_queue: queue,
+            m_span: span,
             m_buffer: self
         }
     }
 
 }
 
+fn is_same_tile(a: &RenderMeta, b: &RenderMeta) -> bool
+{
+    a.get_start_coords() == b.get_start_coords() && a.get_end_coords() == b.get_end_coords()
+}
+
+/// The samples of a block rendered apart from the RenderBuffer, e.g. by a sub-tile or a worker process
+pub struct BlockSamples
+{
+    pub m_statistics: Vec<PixelStatistics>,
+    pub m_tile: FilmTile,
+    pub m_aovs: AovTile,
+}
+
+impl BlockSamples
+{
+    /// The samples in the format of RenderBuffer::write_block
+    pub fn write_to(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        self.m_tile.write_to(out)?;
+        self.m_statistics.iter().try_for_each(|pixel| pixel.write_to(out))?;
+        self.m_aovs.write_to(out)
+    }
+}
+
 pub struct RenderBufferIter<'a>
 {
     pub m_queue: Vec<RenderMeta>,
+    m_span: Option<usize>, // The thread's span of the queue, when work stealing
     m_buffer: &'a RenderBuffer,
 }
 
 impl RenderBufferIter<'_>
 {
-    fn next(&self) -> Option<RenderMeta>
+    fn next_block(&self) -> Option<RenderMeta>
     {
-        let index = self.m_buffer.m_next.fetch_add(1, atomic::Ordering::AcqRel);
-        if index >= self.m_queue.len() { None }
-        else { Some(self.m_queue[index]) }
+        match (self.m_span, self.m_buffer.m_work_stealing.as_ref())
+        {
+            (Some(span), Some(work_stealing)) => work_stealing.next(span),
+            _ =>
+                {
+                    let index = self.m_buffer.m_next.fetch_add(1, atomic::Ordering::AcqRel);
+                    self.m_queue.get(index).copied()
+                }
+        }
     }
 }
 
@@ -137,8 +696,254 @@ impl Iterator for RenderBufferIter<'_>
     type Item = RenderMeta;
     fn next(&mut self) -> Option<RenderMeta>
     {
-        let index = self.m_buffer.m_next.fetch_add(1, atomic::Ordering::AcqRel);
-        if index >= self.m_queue.len() { None }
-        else { Some(self.m_queue[index]) }
+        self.next_block()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod RenderBufferTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+    use cgmath::Vector2;
+    use crate::output::postprocess::VignetteEffect;
+    use crate::render::aov::AovSample;
+    use crate::render::filter::tent::TentFilter;
+    use crate::render::renderdata::RenderSample;
+    use crate::render::tileorder::split_tile;
+    use crate::utils::color::Colorf;
+    use crate::utils::colorconstant::COLOR_BLACK;
+
+    /// Remembers the pixels written to it
+    #[derive(Debug)]
+    struct PixelRecorder
+    {
+        m_dim: (usize, usize),
+        m_pixels: Vec<Option<Colorf>>,
+    }
+
+    impl PixelRecorder
+    {
+        fn new(dim: (usize, usize)) -> PixelRecorder
+        {
+            PixelRecorder{ m_dim: dim, m_pixels: vec![None; dim.0 * dim.1] }
+        }
+    }
+
+    impl OutputManager for PixelRecorder
+    {
+        fn get_img_dim(&self) -> (usize, usize)
+        {
+            self.m_dim
+        }
+
+        fn write_pixel(&mut self, x: usize, y: usize, colorf: Colorf)
+        {
+            assert!(self.m_pixels[x + y * self.m_dim.0].is_none(), "Pixel ({}, {}) written twice", x, y);
+            self.m_pixels[x + y * self.m_dim.0] = Some(colorf);
+        }
+
+        fn output(&mut self) {}
+    }
+
+    #[test]
+    fn partial_blocks_cover_the_image()
+    {
+        let buffer = RenderBuffer::new((801, 599), (100, 100));
+        assert_eq!(buffer.get_num_blocks(), (9, 6));
+
+        let blocks: Vec<RenderMeta> = buffer.iter().collect();
+        assert_eq!(blocks.len(), 54);
+        assert_eq!(blocks.iter().map(|meta| meta.m_area).sum::<usize>(), 801 * 599);
+        let corner = blocks.iter().find(|meta| *meta.get_block_indices() == (8, 5)).unwrap();
+        assert_eq!((*corner.get_start_coords(), *corner.get_end_coords()), ((800, 500), (801, 599)));
+
+        // Every pixel gets a sample at its center, colored after its position
+        let color = |x: usize, y: usize| Colorf::new(x as f32 / 801.0, y as f32 / 599.0, 0.5);
+        for meta in blocks.iter()
+        {
+            let mut tile = buffer.lock_tile(meta);
+            for x in meta.get_start_coords().0..meta.get_end_coords().0
+            {
+                for y in meta.get_start_coords().1..meta.get_end_coords().1
+                {
+                    tile.add_sample(&RenderSample::from_pixel(x, y, Vector2::new(0.5, 0.5), color(x, y)));
+                }
+            }
+            assert_eq!(buffer.lock_statistics(meta).len(), meta.m_area);
+        }
+
+        let mut recorder = PixelRecorder::new((801, 599));
+        buffer.write(&mut recorder);
+        for (index, pixel) in recorder.m_pixels.iter().enumerate()
+        {
+            assert_eq!(*pixel, Some(color(index % 801, index / 801)));
+        }
+
+        let mut heatmap = PixelRecorder::new((801, 599));
+        buffer.write_sample_counts(&mut heatmap, 16);
+        assert!(heatmap.m_pixels.iter().all(|pixel| pixel.is_some()));
+    }
+
+    #[test]
+    fn blocks_larger_than_the_image()
+    {
+        let buffer = RenderBuffer::new((7, 3), (8, 8));
+        let blocks: Vec<RenderMeta> = buffer.iter().collect();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(*blocks[0].get_end_coords(), (7, 3));
+
+        let mut recorder = PixelRecorder::new((7, 3));
+        buffer.write(&mut recorder);
+        assert!(recorder.m_pixels.iter().all(|pixel| *pixel == Some(COLOR_BLACK)));
+    }
+
+    #[test]
+    fn post_processing_before_writing()
+    {
+        let mut buffer = RenderBuffer::new((6, 4), (4, 4));
+        for meta in buffer.iter().collect::<Vec<RenderMeta>>().iter()
+        {
+            let mut tile = buffer.lock_tile(meta);
+            for x in meta.get_start_coords().0..meta.get_end_coords().0
+            {
+                for y in meta.get_start_coords().1..meta.get_end_coords().1
+                {
+                    tile.add_sample(&RenderSample::from_pixel(x, y, Vector2::new(0.5, 0.5), Colorf::new(2.0, 2.0, 2.0)));
+                }
+            }
+        }
+        let mut post_processing = PostProcessing::new();
+        post_processing.add_effect(Arc::new(VignetteEffect::new(1.0)));
+        buffer.set_post_processing(post_processing);
+
+        let mut recorder = PixelRecorder::new((6, 4));
+        buffer.write(&mut recorder);
+        // Still linear and above 1 in the middle
+        assert!(recorder.m_pixels[2 + 6].unwrap().m_g > 1.5);
+        assert!(recorder.m_pixels[0].unwrap().m_g < recorder.m_pixels[2 + 6].unwrap().m_g);
+        assert_eq!(recorder.m_pixels[0], recorder.m_pixels[5 + 3 * 6]);
+    }
+
+    #[test]
+    fn region_is_rendered_and_written_alone()
+    {
+        let mut buffer = RenderBuffer::new((10, 6), (4, 4));
+        buffer.set_region(Some(RenderRegion::from_window((0.3, 0.8), (0.5, 2.0), (10, 6)).unwrap())).unwrap();
+        assert_eq!(Ok(buffer.get_region()), RenderRegion::new((3, 3), (8, 6)));
+        let outside = RenderRegion::new((10, 0), (12, 6)).unwrap();
+        assert_eq!(buffer.set_region(Some(outside)), Err(RegionError::OutsideImage { region: outside, img_dim: (10, 6) }));
+        assert_eq!(Ok(buffer.get_region()), RenderRegion::new((3, 3), (8, 6)));
+
+        let blocks: Vec<RenderMeta> = buffer.iter().collect();
+        assert_eq!(buffer.get_block_count(), 4);
+        assert_eq!(blocks.iter().map(|meta| meta.m_area).sum::<usize>(), 15);
+        assert_eq!(blocks.iter().map(|meta| *meta.get_block_indices()).collect::<Vec<_>>(), vec![(0, 0), (1, 0), (0, 1), (1, 1)]);
+        for meta in blocks.iter()
+        {
+            let mut tile = buffer.lock_tile(meta);
+            for x in meta.get_start_coords().0..meta.get_end_coords().0
+            {
+                for y in meta.get_start_coords().1..meta.get_end_coords().1
+                {
+                    tile.add_sample(&RenderSample::from_pixel(x, y, Vector2::new(0.5, 0.5), Colorf::new(1.0, 0.5, 0.0)));
+                }
+            }
+        }
+
+        let mut recorder = PixelRecorder::new((10, 6));
+        buffer.write(&mut recorder);
+        for (index, pixel) in recorder.m_pixels.iter().enumerate()
+        {
+            let expected = if buffer.get_region().contains(index % 10, index / 10) { Some(Colorf::new(1.0, 0.5, 0.0)) } else { None };
+            assert_eq!(*pixel, expected);
+        }
+
+        buffer.set_region(None).unwrap();
+        assert_eq!(buffer.get_block_count(), 6);
+    }
+
+    #[test]
+    fn work_stealing_threads_share_the_blocks()
+    {
+        let mut buffer = RenderBuffer::new((40, 40), (4, 4));
+        buffer.set_tile_order(TileOrder::Hilbert);
+        buffer.set_work_stealing(true);
+        buffer.set_region(Some(RenderRegion::new((2, 2), (38, 38)).unwrap())).unwrap();
+
+        for _ in 0..2
+        {
+            buffer.restart();
+            let mut iters: Vec<RenderBufferIter> = (0..3).map(|_| buffer.iter()).collect();
+            let mut blocks = Vec::new();
+            // The threads take turns, until they are all done
+            while let Some(meta) = iters.iter_mut().filter_map(|iter| iter.next()).next()
+            {
+                blocks.push(*meta.get_block_indices());
+            }
+            assert!(iters.iter_mut().all(|iter| iter.next().is_none()));
+            blocks.sort();
+            blocks.dedup();
+            assert_eq!(blocks.len(), 100);
+        }
+    }
+
+    #[test]
+    fn sub_tiles_are_merged_into_their_block()
+    {
+        let mut direct = RenderBuffer::new_with_filter((16, 8), (8, 8), Arc::new(TentFilter::new(1.0)));
+        let mut split = RenderBuffer::new_with_filter((16, 8), (8, 8), Arc::new(TentFilter::new(1.0)));
+        direct.set_aovs(&[Aov::Depth, Aov::ObjectId]);
+        split.set_aovs(&[Aov::Depth, Aov::ObjectId]);
+        let render = |meta: &RenderMeta, statistics: &mut [PixelStatistics], tile: &mut FilmTile, aovs: &mut AovTile|
+            {
+                for x in meta.get_start_coords().0..meta.get_end_coords().0
+                {
+                    for y in meta.get_start_coords().1..meta.get_end_coords().1
+                    {
+                        let color = Colorf::new(x as f32, y as f32, 1.0);
+                        tile.add_sample(&RenderSample::from_pixel(x, y, Vector2::new(0.25, 0.75), color));
+                        let local_index = meta.get_local_index(x, y);
+                        statistics[local_index].add_sample(color);
+                        let mut sample = AovSample::new();
+                        sample.set(Aov::Depth, &[x as f32]);
+                        sample.set(Aov::ObjectId, &[1.0]);
+                        aovs.add_sample(local_index, &sample);
+                    }
+                }
+            };
+
+        let block = direct.get_block((1, 0)).unwrap();
+        direct.render_into(&block, |statistics, tile, aovs| render(&block, statistics, tile, aovs));
+        assert!(!split.is_sub_tile(&block));
+        for sub_tile in split_tile(&block).unwrap().iter()
+        {
+            assert!(split.is_sub_tile(sub_tile));
+            split.render_into(sub_tile, |statistics, tile, aovs| render(sub_tile, statistics, tile, aovs));
+        }
+
+        assert_eq!(direct.resolve(), split.resolve());
+        assert_eq!(*direct.lock_statistics(&block), *split.lock_statistics(&block));
+        for aov in [Aov::Depth, Aov::ObjectId].iter()
+        {
+            assert_eq!(direct.lock_aovs(&block).get_pixel(block.get_local_index(12, 3), *aov),
+                       split.lock_aovs(&block).get_pixel(block.get_local_index(12, 3), *aov));
+        }
+    }
+
+    #[test]
+    fn samples_are_splatted_across_blocks()
+    {
+        let buffer = RenderBuffer::new_with_filter((8, 4), (4, 4), Arc::new(TentFilter::new(1.0)));
+        let left = RenderMeta::new((0, 0), (4, 4), (0, 0));
+        let right = RenderMeta::new((4, 0), (8, 4), (1, 0));
+        buffer.lock_tile(&left).add_sample(&RenderSample::new(Vector2::new(3.9, 1.5), Colorf::new(1.0, 0.0, 0.0)));
+        buffer.lock_tile(&right).add_sample(&RenderSample::new(Vector2::new(4.5, 1.5), Colorf::new(0.0, 0.0, 1.0)));
+
+        let film = buffer.resolve();
+        // Pixel 4 gets 0.4 of the red sample and all of the blue one
+        assert_relative_eq!(film[4 + 8].m_weight, 1.4, epsilon = 1e-5);
+        assert_relative_eq!(film[4 + 8].resolve().m_r, 0.4 / 1.4, epsilon = 1e-5);
+        assert_relative_eq!(film[3 + 8].resolve().m_r, 1.0);
+        assert_eq!(film[5 + 8].m_weight, 0.0);
+    }
+}
@@ -1,23 +1,33 @@
 use cgmath::Vector2;
+use std::fmt;
 
 use crate::utils::color::Colorf;
 
+/// A sample rendered by a camera, and where it landed on the film, in pixels.
+/// Pixel (x, y) covers [x, x + 1) x [y, y + 1).
+#[derive(Clone, Copy, Debug)]
 pub struct RenderSample
 {
-    m_pix_coord: Vector2<u32>,
-    m_color: Colorf
+    pub m_film_coords: Vector2<f32>,
+    pub m_color: Colorf
 }
 
 impl RenderSample
 {
-    pub fn new(pix_x: u32, pix_y: u32, color: Colorf) -> RenderSample
+    pub fn new(film_coords: Vector2<f32>, color: Colorf) -> RenderSample
     {
         RenderSample
         {
-            m_pix_coord: Vector2::new(pix_x, pix_y),
+            m_film_coords: film_coords,
             m_color: color,
         }
     }
+
+    /// Sample at "offset" within pixel (x, y)
+    pub fn from_pixel(x: usize, y: usize, offset: Vector2<f32>, color: Colorf) -> RenderSample
+    {
+        RenderSample::new(Vector2::new(x as f32 + offset.x, y as f32 + offset.y), color)
+    }
 }
 
 /// MetaData for RenderBuffer's sample blocks
@@ -27,6 +37,8 @@ pub struct RenderMeta
     m_start_coords: (usize, usize),
     m_end_coords:   (usize, usize),
     m_block_index: (usize, usize),
+    m_storage_start: (usize, usize), // Layout of the pixels of the whole block, which a clipped block keeps
+    m_storage_height: usize,
     pub m_area: usize
 }
 
@@ -46,10 +58,29 @@ impl RenderMeta
             m_start_coords: start_coords,
             m_end_coords: end_coords,
             m_block_index: block_index,
+            m_storage_start: start_coords,
+            m_storage_height: end_coords.1 - start_coords.1,
             m_area: (end_coords.1 - start_coords.1) * (end_coords.0 - start_coords.0),
         }
     }
 
+    /// The part of the block within "region", None if they don't intersect. Its pixels keep
+    /// their local index in the block.
+    pub fn clip(&self, region: &RenderRegion) -> Option<RenderMeta>
+    {
+        let start = (self.m_start_coords.0.max(region.m_start_coords.0), self.m_start_coords.1.max(region.m_start_coords.1));
+        let end = (self.m_end_coords.0.min(region.m_end_coords.0), self.m_end_coords.1.min(region.m_end_coords.1));
+        if start.0 >= end.0 || start.1 >= end.1 { return None; }
+
+        Some(RenderMeta
+        {
+            m_start_coords: start,
+            m_end_coords: end,
+            m_area: (end.0 - start.0) * (end.1 - start.1),
+            ..*self
+        })
+    }
+
     pub fn get_start_coords(&self) -> &(usize, usize)
     {
         &self.m_start_coords
@@ -64,4 +95,152 @@ impl RenderMeta
     {
         &self.m_block_index
     }
-}
\ No newline at end of file
+
+    /// Index of pixel (x, y) in the block, whose pixels are stored column by column
+    pub fn get_local_index(&self, x: usize, y: usize) -> usize
+    {
+        (x - self.m_storage_start.0) * self.m_storage_height + (y - self.m_storage_start.1)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RegionError
+{
+    /// The region has no pixel between its start and end coordinates
+    Empty { start_coords: (usize, usize), end_coords: (usize, usize) },
+    /// The region starts outside the image it restricts
+    OutsideImage { region: RenderRegion, img_dim: (usize, usize) },
+}
+
+impl std::error::Error for RegionError {}
+
+impl fmt::Display for RegionError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            RegionError::Empty { start_coords, end_coords } =>
+                write!(f, "Empty render region from {:?} to {:?}", start_coords, end_coords),
+            RegionError::OutsideImage { region, img_dim } =>
+                write!(f, "The render region from {:?} to {:?} is outside the {} x {} image",
+                       region.m_start_coords, region.m_end_coords, img_dim.0, img_dim.1),
+        }
+    }
+}
+
+/// The rectangle of pixels [start, end) a render is restricted to, e.g. to re-render a single object
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderRegion
+{
+    m_start_coords: (usize, usize),
+    m_end_coords: (usize, usize),
+}
+
+impl RenderRegion
+{
+    pub fn new(start_coords: (usize, usize), end_coords: (usize, usize)) -> Result<RenderRegion, RegionError>
+    {
+        if end_coords.1 <= start_coords.1 || end_coords.0 <= start_coords.0
+        {
+            return Err(RegionError::Empty { start_coords, end_coords });
+        }
+        Ok(RenderRegion{ m_start_coords: start_coords, m_end_coords: end_coords })
+    }
+
+    /// Every pixel of an image, none for an empty one
+    pub fn from_image(img_dim: (usize, usize)) -> RenderRegion
+    {
+        RenderRegion{ m_start_coords: (0, 0), m_end_coords: img_dim }
+    }
+
+    /// Region of the pixels whose centers are within a window of the image, given as fractions
+    /// of its width and height, e.g. (0.25, 0.75) for the middle half. A window without any pixel
+    /// center gives an Empty error.
+    pub fn from_window(x_range: (f32, f32), y_range: (f32, f32), img_dim: (usize, usize)) -> Result<RenderRegion, RegionError>
+    {
+        let to_pixels = |range: (f32, f32), resolution: usize|
+            {
+                let to_pixel = |fraction: f32| (fraction.clamp(0.0, 1.0) * resolution as f32 - 0.5).ceil().max(0.0) as usize;
+                (to_pixel(range.0), to_pixel(range.1))
+            };
+        let (x_start, x_end) = to_pixels(x_range, img_dim.0);
+        let (y_start, y_end) = to_pixels(y_range, img_dim.1);
+        RenderRegion::new((x_start, y_start), (x_end, y_end))
+    }
+
+    pub fn get_start_coords(&self) -> &(usize, usize)
+    {
+        &self.m_start_coords
+    }
+
+    pub fn get_end_coords(&self) -> &(usize, usize)
+    {
+        &self.m_end_coords
+    }
+
+    pub fn get_area(&self) -> usize
+    {
+        (self.m_end_coords.0 - self.m_start_coords.0) * (self.m_end_coords.1 - self.m_start_coords.1)
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool
+    {
+        x >= self.m_start_coords.0 && x < self.m_end_coords.0 && y >= self.m_start_coords.1 && y < self.m_end_coords.1
+    }
+
+    /// The part of the region within an image, which it must intersect
+    pub fn clip_to_image(&self, img_dim: (usize, usize)) -> Result<RenderRegion, RegionError>
+    {
+        if self.m_start_coords.0 >= img_dim.0 || self.m_start_coords.1 >= img_dim.1
+        {
+            return Err(RegionError::OutsideImage { region: *self, img_dim });
+        }
+        RenderRegion::new(self.m_start_coords, (self.m_end_coords.0.min(img_dim.0), self.m_end_coords.1.min(img_dim.1)))
+    }
+}
+
+#[cfg(test)]
+mod RenderRegionTest
+{
+    use super::*;
+
+    #[test]
+    fn window_to_pixels()
+    {
+        let region = RenderRegion::from_window((0.25, 0.75), (0.0, 0.5), (8, 10)).unwrap();
+        assert_eq!((*region.get_start_coords(), *region.get_end_coords()), ((2, 0), (6, 5)));
+        assert_eq!(region.get_area(), 20);
+        assert!(region.contains(5, 4) && !region.contains(6, 4) && !region.contains(1, 0));
+
+        let whole = RenderRegion::from_window((-1.0, 1.0), (0.0, 1.0), (8, 10)).unwrap();
+        assert_eq!(whole, RenderRegion::from_image((8, 10)));
+    }
+
+    #[test]
+    fn regions_without_pixels()
+    {
+        // No pixel center between 2.64 and 2.8
+        assert_eq!(RenderRegion::from_window((0.33, 0.35), (0.0, 1.0), (8, 10)),
+                   Err(RegionError::Empty { start_coords: (3, 0), end_coords: (3, 10) }));
+        assert!(RenderRegion::from_window((0.5, 0.5), (0.0, 1.0), (8, 10)).is_err());
+        assert_eq!(RenderRegion::new((4, 2), (4, 6)), Err(RegionError::Empty { start_coords: (4, 2), end_coords: (4, 6) }));
+
+        let region = RenderRegion::new((6, 3), (20, 5)).unwrap();
+        assert_eq!(region.clip_to_image((8, 8)), RenderRegion::new((6, 3), (8, 5)));
+        assert_eq!(region.clip_to_image((6, 8)), Err(RegionError::OutsideImage { region, img_dim: (6, 8) }));
+    }
+
+    #[test]
+    fn clipped_blocks_keep_their_layout()
+    {
+        let meta = RenderMeta::new((4, 4), (8, 8), (1, 1));
+        let region = RenderRegion::new((6, 3), (20, 5)).unwrap();
+        let clipped = meta.clip(&region).unwrap();
+        assert_eq!((*clipped.get_start_coords(), *clipped.get_end_coords()), ((6, 4), (8, 5)));
+        assert_eq!(clipped.m_area, 2);
+        assert_eq!(*clipped.get_block_indices(), (1, 1));
+        assert_eq!(clipped.get_local_index(7, 4), meta.get_local_index(7, 4));
+
+        assert!(meta.clip(&RenderRegion::new((0, 0), (4, 16)).unwrap()).is_none());
+    }
+}
@@ -0,0 +1,615 @@
+use std::collections::VecDeque;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::output::imagewriter::ImageWriter;
+use crate::render::control::{RenderControl, RenderStatus};
+use crate::render::renderbuffer::RenderBuffer;
+use crate::utils::json::JsonValue;
+use crate::utils::multithread::MultiThread;
+use crate::world::scenefile::SceneFile;
+
+const MAX_BODY_SIZE: usize = 64 << 20;
+const MAX_HEADER_SIZE: usize = 64 << 10; // Of the request line and the headers together
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10); // Longest wait for the next bytes of a request
+
+/// Where a job of the render server is at
+#[derive(Clone, Debug, PartialEq)]
+pub enum JobState
+{
+    Queued,
+    Running,
+    Completed,
+    Cancelled,
+    Failed(String),
+}
+
+impl JobState
+{
+    pub fn get_name(&self) -> &'static str
+    {
+        match self
+        {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Completed => "completed",
+            JobState::Cancelled => "cancelled",
+            JobState::Failed(_) => "failed",
+        }
+    }
+
+    fn is_finished(&self) -> bool
+    {
+        !matches!(self, JobState::Queued | JobState::Running)
+    }
+}
+
+struct RenderJob
+{
+    m_id: usize,
+    m_state: Mutex<JobState>,
+    m_control: Arc<RenderControl>,
+    m_scene: Mutex<Option<SceneFile>>, // Taken by the render
+    m_result: Mutex<Option<Vec<u8>>>, // The image as a PNG file
+}
+
+impl RenderJob
+{
+    fn get_json(&self) -> JsonValue
+    {
+        let state = self.m_state.lock().unwrap().clone();
+        let progress = self.m_control.get_progress();
+        let mut members = vec![
+            ("id".to_string(), JsonValue::Number(self.m_id as f64)),
+            ("state".to_string(), JsonValue::String(state.get_name().to_string())),
+            ("progress".to_string(), JsonValue::Number(progress.get_fraction_done() as f64)),
+            ("tiles_done".to_string(), JsonValue::Number(progress.m_tiles_done as f64)),
+            ("tile_count".to_string(), JsonValue::Number(progress.m_tile_count as f64)),
+        ];
+        if let JobState::Failed(error) = state
+        {
+            members.push(("error".to_string(), JsonValue::String(error)));
+        }
+        JsonValue::Object(members)
+    }
+}
+
+struct HttpRequest
+{
+    m_method: String,
+    m_path: String, // Without the query
+    m_body: Vec<u8>,
+}
+
+struct HttpResponse
+{
+    m_status: u16,
+    m_content_type: &'static str,
+    m_body: Vec<u8>,
+}
+
+impl HttpResponse
+{
+    fn json(status: u16, value: &JsonValue) -> HttpResponse
+    {
+        HttpResponse{ m_status: status, m_content_type: "application/json", m_body: value.to_string().into_bytes() }
+    }
+
+    fn error(status: u16, message: &str) -> HttpResponse
+    {
+        HttpResponse::json(status, &JsonValue::Object(vec![("error".to_string(), JsonValue::String(message.to_string()))]))
+    }
+
+    fn write_to(&self, out: &mut dyn Write) -> io::Result<()>
+    {
+        let reason = match self.m_status
+        {
+            200 => "OK",
+            201 => "Created",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        };
+        write!(out, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+               self.m_status, reason, self.m_content_type, self.m_body.len())?;
+        out.write_all(&self.m_body)?;
+        out.flush()
+    }
+}
+
+/// Long-running server that renders the scene files it receives over a local HTTP API, one job
+/// at a time on its MultiThread pool:
+///
+/// - POST /jobs with a scene file as body queues a render, answers its id and state
+/// - GET /jobs lists the jobs, GET /jobs/{id} describes one with its progress
+/// - POST /jobs/{id}/cancel stops a queued or running job
+/// - GET /jobs/{id}/result downloads the image of a completed job, as PNG
+/// - DELETE /jobs/{id} forgets a finished job and its image
+/// - POST /shutdown cancels the jobs and stops the server
+///
+/// Every connection carries a single request, answered on a thread of its own. Past
+/// m_max_finished_jobs, the oldest finished jobs are forgotten as new ones arrive.
+pub struct RenderServer
+{
+    m_thread_count: u32,
+    pub m_max_finished_jobs: usize,
+    m_jobs: Mutex<Vec<Arc<RenderJob>>>, // In the order they arrived
+    m_next_id: AtomicUsize,
+    m_queue: Mutex<VecDeque<Arc<RenderJob>>>,
+    m_job_queued: Condvar,
+    m_shutdown: AtomicBool,
+}
+
+impl RenderServer
+{
+    pub fn new(thread_count: u32) -> RenderServer
+    {
+        RenderServer
+        {
+            m_thread_count: thread_count,
+            m_max_finished_jobs: 64,
+            m_jobs: Mutex::new(Vec::new()),
+            m_next_id: AtomicUsize::new(0),
+            m_queue: Mutex::new(VecDeque::new()),
+            m_job_queued: Condvar::new(),
+            m_shutdown: AtomicBool::new(false),
+        }
+    }
+
+    /// Answer the requests that arrive on "listener", e.g. bound to 127.0.0.1, and render the jobs,
+    /// until a shutdown request
+    pub fn serve(&self, listener: &TcpListener) -> io::Result<()>
+    {
+        thread::scope(|scope|
+            {
+                scope.spawn(|| self.run_jobs());
+                let result = self.answer_requests(listener);
+                // Also stops the renders if the listener failed
+                self.shutdown();
+                result
+            })
+    }
+
+    fn answer_requests(&self, listener: &TcpListener) -> io::Result<()>
+    {
+        // Where the thread answering a shutdown request wakes the listener up
+        let mut address = listener.local_addr()?;
+        if address.ip().is_unspecified()
+        {
+            address.set_ip(Ipv4Addr::LOCALHOST.into());
+        }
+        thread::scope(|scope|
+            {
+                for stream in listener.incoming()
+                {
+                    if self.m_shutdown.load(Ordering::Acquire) { break; }
+                    let stream = stream?;
+                    scope.spawn(move ||
+                        {
+                            // A client that goes away, or takes too long to send its request,
+                            // doesn't concern the other ones
+                            let _ = self.answer(stream);
+                            if self.m_shutdown.load(Ordering::Acquire)
+                            {
+                                let _ = TcpStream::connect(address);
+                            }
+                        });
+                }
+                Ok(())
+            })
+    }
+
+    fn shutdown(&self)
+    {
+        self.m_shutdown.store(true, Ordering::Release);
+        for job in self.m_jobs.lock().unwrap().iter()
+        {
+            self.cancel(job);
+        }
+        let _queue = self.m_queue.lock().unwrap();
+        self.m_job_queued.notify_all();
+    }
+
+    /// Render the queued jobs, in the order they arrived
+    fn run_jobs(&self)
+    {
+        let mut multithread = MultiThread::new(self.m_thread_count);
+        loop
+        {
+            let job = {
+                let mut queue = self.m_queue.lock().unwrap();
+                loop
+                {
+                    if self.m_shutdown.load(Ordering::Acquire) { return; }
+                    match queue.pop_front()
+                    {
+                        Some(job) => break job,
+                        None => queue = self.m_job_queued.wait(queue).unwrap(),
+                    }
+                }
+            };
+
+            {
+                let mut state = job.m_state.lock().unwrap();
+                if *state != JobState::Queued { continue; }
+                *state = JobState::Running;
+            }
+            let scene = job.m_scene.lock().unwrap().take().unwrap();
+            let buffer = RenderBuffer::new(scene.m_img_dim, multithread.get_tile_dim(scene.m_img_dim));
+            multithread.set_render_control(job.m_control.clone());
+            let status = multithread.render_to_buffer(scene.m_world.clone(), scene.m_camera.as_ref(), &buffer);
+
+            let state = match status
+            {
+                RenderStatus::Cancelled => JobState::Cancelled,
+                _ =>
+                    {
+                        let mut imgwriter = ImageWriter::new("", scene.m_img_dim.0, scene.m_img_dim.1);
+                        buffer.write(&mut imgwriter);
+                        match imgwriter.encode(image::ImageOutputFormat::Png)
+                        {
+                            Ok(png) =>
+                                {
+                                    *job.m_result.lock().unwrap() = Some(png);
+                                    JobState::Completed
+                                }
+                            Err(error) => JobState::Failed(error.to_string()),
+                        }
+                    }
+            };
+            *job.m_state.lock().unwrap() = state;
+        }
+    }
+
+    fn cancel(&self, job: &RenderJob)
+    {
+        job.m_control.cancel();
+        let mut state = job.m_state.lock().unwrap();
+        if *state == JobState::Queued
+        {
+            *state = JobState::Cancelled;
+        }
+    }
+
+    fn answer(&self, stream: TcpStream) -> io::Result<()>
+    {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let response = match read_request(&mut reader)?
+        {
+            Ok(request) => self.route(&request.m_method, &request.m_path, &request.m_body),
+            Err(response) => response,
+        };
+        response.write_to(&mut &stream)
+    }
+
+    fn route(&self, method: &str, path: &str, body: &[u8]) -> HttpResponse
+    {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice())
+        {
+            ("POST", ["jobs"]) => self.submit(body),
+            ("GET", ["jobs"]) =>
+                {
+                    let jobs = self.m_jobs.lock().unwrap().iter().map(|job| job.get_json()).collect();
+                    HttpResponse::json(200, &JsonValue::Array(jobs))
+                }
+            ("POST", ["shutdown"]) =>
+                {
+                    self.shutdown();
+                    HttpResponse::json(200, &JsonValue::Object(Vec::new()))
+                }
+            (_, ["jobs"]) | (_, ["shutdown"]) => HttpResponse::error(405, "Method not allowed"),
+            (_, ["jobs", id, rest @ ..]) =>
+                {
+                    let job = match id.parse::<usize>().ok().and_then(|id| self.get_job(id))
+                    {
+                        Some(job) => job,
+                        None => return HttpResponse::error(404, "No such job"),
+                    };
+                    match (method, rest)
+                    {
+                        ("GET", []) => HttpResponse::json(200, &job.get_json()),
+                        ("DELETE", []) =>
+                            {
+                                if !job.m_state.lock().unwrap().is_finished()
+                                {
+                                    return HttpResponse::error(409, "The job isn't finished");
+                                }
+                                self.m_jobs.lock().unwrap().retain(|other| other.m_id != job.m_id);
+                                HttpResponse::json(200, &job.get_json())
+                            }
+                        ("POST", ["cancel"]) =>
+                            {
+                                if job.m_state.lock().unwrap().is_finished()
+                                {
+                                    return HttpResponse::error(409, "The job is finished");
+                                }
+                                self.cancel(&job);
+                                HttpResponse::json(200, &job.get_json())
+                            }
+                        ("GET", ["result"]) => match job.m_result.lock().unwrap().as_ref()
+                        {
+                            Some(png) => HttpResponse{ m_status: 200, m_content_type: "image/png", m_body: png.clone() },
+                            None => HttpResponse::error(409, "The job isn't completed"),
+                        },
+                        (_, []) | (_, ["cancel"]) | (_, ["result"]) => HttpResponse::error(405, "Method not allowed"),
+                        _ => HttpResponse::error(404, "Not found"),
+                    }
+                }
+            _ => HttpResponse::error(404, "Not found"),
+        }
+    }
+
+    fn get_job(&self, id: usize) -> Option<Arc<RenderJob>>
+    {
+        self.m_jobs.lock().unwrap().iter().find(|job| job.m_id == id).cloned()
+    }
+
+    /// Queue the scene file "body", forgetting the oldest finished jobs past m_max_finished_jobs
+    fn submit(&self, body: &[u8]) -> HttpResponse
+    {
+        if self.m_shutdown.load(Ordering::Acquire)
+        {
+            return HttpResponse::error(409, "The server is shutting down");
+        }
+        let scene = match std::str::from_utf8(body).map_err(|error| error.to_string())
+            .and_then(|text| SceneFile::parse(text).map_err(|error| error.to_string()))
+        {
+            Ok(scene) => scene,
+            Err(error) => return HttpResponse::error(400, &error),
+        };
+
+        let mut jobs = self.m_jobs.lock().unwrap();
+        let finished_count = jobs.iter().filter(|job| job.m_state.lock().unwrap().is_finished()).count();
+        let mut evicted_count = finished_count.saturating_sub(self.m_max_finished_jobs);
+        jobs.retain(|job|
+            {
+                let evict = evicted_count > 0 && job.m_state.lock().unwrap().is_finished();
+                if evict { evicted_count -= 1; }
+                !evict
+            });
+
+        let job = Arc::new(RenderJob
+        {
+            m_id: self.m_next_id.fetch_add(1, Ordering::Relaxed),
+            m_state: Mutex::new(JobState::Queued),
+            m_control: Arc::new(RenderControl::new()),
+            m_scene: Mutex::new(Some(scene)),
+            m_result: Mutex::new(None),
+        });
+        jobs.push(job.clone());
+        self.m_queue.lock().unwrap().push_back(job.clone());
+        self.m_job_queued.notify_one();
+        HttpResponse::json(201, &job.get_json())
+    }
+}
+
+/// The request, or the response to a request that can't be answered
+fn read_request(reader: &mut dyn BufRead) -> io::Result<Result<HttpRequest, HttpResponse>>
+{
+    let mut header_bytes_left = MAX_HEADER_SIZE;
+    let request_line = match read_header_line(reader, &mut header_bytes_left)?
+    {
+        Some(line) => line,
+        None => return Ok(Err(HttpResponse::error(431, "The headers are too large"))),
+    };
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next())
+    {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(Err(HttpResponse::error(400, "Invalid request line"))),
+    };
+
+    let mut content_length = 0;
+    loop
+    {
+        let header = match read_header_line(reader, &mut header_bytes_left)?
+        {
+            Some(header) => header,
+            None => return Ok(Err(HttpResponse::error(431, "The headers are too large"))),
+        };
+        if header.trim().is_empty() { break; }
+        if let Some((name, value)) = header.split_once(':')
+        {
+            if name.trim().eq_ignore_ascii_case("content-length")
+            {
+                content_length = match value.trim().parse::<usize>()
+                {
+                    Ok(length) => length,
+                    Err(_) => return Ok(Err(HttpResponse::error(400, "Invalid Content-Length"))),
+                };
+            }
+        }
+    }
+    if content_length > MAX_BODY_SIZE
+    {
+        return Ok(Err(HttpResponse::error(413, "The body is too large")));
+    }
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body)?;
+    let path = path.split('?').next().unwrap_or("").to_string();
+    Ok(Ok(HttpRequest{ m_method: method, m_path: path, m_body: body }))
+}
+
+/// A line of the request line or headers, empty at the end of the stream; None if it doesn't
+/// end within the "bytes_left" the headers may still take
+fn read_header_line(reader: &mut dyn BufRead, bytes_left: &mut usize) -> io::Result<Option<String>>
+{
+    let mut line = String::new();
+    let length = Read::take(reader, *bytes_left as u64).read_line(&mut line)?;
+    *bytes_left -= length;
+    if *bytes_left == 0 && !line.ends_with('\n') { return Ok(None); }
+    Ok(Some(line))
+}
+
+#[cfg(test)]
+mod RenderServerTest
+{
+    use super::*;
+    use std::io::Read;
+    use std::time::{Duration, Instant};
+
+    const SCENE: &str = r#"{
+        "image": { "width": 16, "height": 12, "samples": 4 },
+        "lights": [ { "type": "point", "position": [-30, 20, -50] } ],
+        "objects": [ { "type": "sphere", "center": [0, 0, 0], "radius": 5 } ]
+    }"#;
+
+    fn start_server() -> (String, thread::JoinHandle<io::Result<()>>)
+    {
+        serve_in_background(RenderServer::new(2))
+    }
+
+    fn serve_in_background(server: RenderServer) -> (String, thread::JoinHandle<io::Result<()>>)
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        (address, thread::spawn(move || server.serve(&listener)))
+    }
+
+    /// Status and body of the answer to a request
+    fn request(address: &str, method: &str, path: &str, body: &str) -> (u16, Vec<u8>)
+    {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\n\r\n{}", method, path, address, body.len(), body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+
+        let header_end = response.windows(4).position(|window| window == b"\r\n\r\n").unwrap();
+        let status_line = String::from_utf8_lossy(&response[..header_end]).to_string();
+        let status = status_line.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, response[header_end + 4..].to_vec())
+    }
+
+    fn request_json(address: &str, method: &str, path: &str, body: &str) -> (u16, JsonValue)
+    {
+        let (status, body) = request(address, method, path, body);
+        (status, JsonValue::parse(std::str::from_utf8(&body).unwrap()).unwrap())
+    }
+
+    fn wait_for_state(address: &str, path: &str, state: &str) -> JsonValue
+    {
+        let start = Instant::now();
+        loop
+        {
+            let (_, job) = request_json(address, "GET", path, "");
+            if job.get("state").unwrap().as_str() == Some(state) { return job; }
+            assert!(start.elapsed() < Duration::from_secs(60), "The job is still {}", job.get("state").unwrap());
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn render_a_submitted_scene()
+    {
+        let (address, server) = start_server();
+        let (status, job) = request_json(&address, "POST", "/jobs", SCENE);
+        assert_eq!(status, 201);
+        assert_eq!(job.get("id").unwrap().as_f64(), Some(0.0));
+
+        let job = wait_for_state(&address, "/jobs/0", "completed");
+        assert_eq!(job.get("progress").unwrap().as_f64(), Some(1.0));
+        let (status, png) = request(&address, "GET", "/jobs/0/result", "");
+        assert_eq!(status, 200);
+        let image = image::load_from_memory(&png).unwrap().to_rgba();
+        assert_eq!(image.dimensions(), (16, 12));
+        assert!(image.get_pixel(8, 6)[0] > 0);
+
+        let (status, jobs) = request_json(&address, "GET", "/jobs", "");
+        assert_eq!(status, 200);
+        assert_eq!(jobs.as_array().unwrap().len(), 1);
+        assert_eq!(request(&address, "POST", "/jobs/0/cancel", "").0, 409);
+
+        assert_eq!(request(&address, "POST", "/shutdown", "").0, 200);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn a_silent_client_doesnt_hold_up_the_others()
+    {
+        let (address, server) = start_server();
+        let mut silent = TcpStream::connect(&address).unwrap();
+        write!(silent, "GET /jobs HTTP/1.1\r\n").unwrap();
+
+        let start = Instant::now();
+        assert_eq!(request(&address, "GET", "/jobs", "").0, 200);
+        assert!(start.elapsed() < REQUEST_TIMEOUT);
+        drop(silent);
+
+        // Headers that never end
+        let mut endless = TcpStream::connect(&address).unwrap();
+        let request_line = "GET /jobs HTTP/1.1\r\nX-Padding: ";
+        write!(endless, "{}{}", request_line, "a".repeat(MAX_HEADER_SIZE - request_line.len())).unwrap();
+        let mut response = String::new();
+        endless.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+
+        assert_eq!(request(&address, "POST", "/shutdown", "").0, 200);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn cancel_jobs_and_reject_bad_requests()
+    {
+        let (address, server) = start_server();
+        let (status, error) = request_json(&address, "POST", "/jobs", r#"{ "image": {} }"#);
+        assert_eq!(status, 400);
+        assert_eq!(error.get("error").unwrap().as_str(), Some("Invalid scene: no width"));
+        let huge_scene = SCENE.replace(r#""width": 16, "height": 12"#, r#""width": 65535, "height": 65535"#);
+        assert_eq!(request(&address, "POST", "/jobs", &huge_scene).0, 400);
+        assert_eq!(request(&address, "GET", "/jobs/3", "").0, 404);
+        assert_eq!(request(&address, "GET", "/scenes", "").0, 404);
+        assert_eq!(request(&address, "DELETE", "/jobs", "").0, 405);
+
+        let large_scene = SCENE.replace(r#""width": 16, "height": 12, "samples": 4"#, r#""width": 640, "height": 480, "samples": 64"#);
+        assert_eq!(request(&address, "POST", "/jobs", &large_scene).0, 201);
+        assert_eq!(request(&address, "POST", "/jobs", SCENE).0, 201);
+        assert_eq!(request(&address, "POST", "/jobs/1/cancel", "").0, 200);
+        assert_eq!(request(&address, "POST", "/jobs/0/cancel", "").0, 200);
+        wait_for_state(&address, "/jobs/0", "cancelled");
+        wait_for_state(&address, "/jobs/1", "cancelled");
+        assert_eq!(request(&address, "GET", "/jobs/0/result", "").0, 409);
+
+        assert_eq!(request(&address, "POST", "/shutdown", "").0, 200);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn finished_jobs_are_forgotten()
+    {
+        let mut server = RenderServer::new(2);
+        server.m_max_finished_jobs = 1;
+        let (address, server) = serve_in_background(server);
+        for id in 0..3
+        {
+            assert_eq!(request(&address, "POST", "/jobs", SCENE).0, 201);
+            wait_for_state(&address, &format!("/jobs/{}", id), "completed");
+        }
+        // Job 0 made way for job 2
+        assert_eq!(request(&address, "GET", "/jobs/0", "").0, 404);
+        assert_eq!(request(&address, "DELETE", "/jobs/1", "").0, 200);
+        assert_eq!(request(&address, "GET", "/jobs/1/result", "").0, 404);
+        assert_eq!(request(&address, "GET", "/jobs/2/result", "").0, 200);
+
+        let large_scene = SCENE.replace(r#""width": 16, "height": 12, "samples": 4"#, r#""width": 640, "height": 480, "samples": 64"#);
+        let (_, job) = request_json(&address, "POST", "/jobs", &large_scene);
+        assert_eq!(job.get("id").unwrap().as_f64(), Some(3.0));
+        assert_eq!(request(&address, "DELETE", "/jobs/3", "").0, 409);
+        let (_, jobs) = request_json(&address, "GET", "/jobs", "");
+        assert_eq!(jobs.as_array().unwrap().len(), 2);
+
+        assert_eq!(request(&address, "POST", "/shutdown", "").0, 200);
+        server.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+use cgmath::{Vector3, Zero};
+use std::sync::{Arc, Mutex};
+
+use crate::brdf::lambertian::Lambertian;
+use crate::geometry::Shadable;
+use crate::geometry::sphere::Sphere;
+use crate::light::ambientocc::AmbientOccluder;
+use crate::material::Material;
+use crate::material::matte::Matte;
+use crate::render::cam::pinhole::Pinhole;
+use crate::sampler::Sampler;
+use crate::sampler::jittered::Jittered;
+use crate::utils::colorconstant::{COLOR_BLACK, COLOR_WHITE};
+use crate::world::viewplane::ViewPlane;
+use crate::world::world::World;
+
+/// Center, radius and material of a sphere of a test scene
+pub type TestSphere = (Vector3<f32>, f32, Arc<dyn Material>);
+
+pub fn get_white_matte() -> Arc<dyn Material>
+{
+    Arc::new(Matte::new(Arc::new(Lambertian::new(0.8, COLOR_WHITE)),
+                        Arc::new(Lambertian::new(0.8, COLOR_WHITE))))
+}
+
+/// Two spheres almost touching each other, so that the ambient occlusion between them depends
+/// on the sampled directions
+pub fn get_two_spheres(left: Arc<dyn Material>, right: Arc<dyn Material>) -> Vec<TestSphere>
+{
+    vec![(Vector3::new(-5.0, 0.0, 0.0), 5.5, left), (Vector3::new(6.0, 0.0, 0.0), 5.5, right)]
+}
+
+/// Scene of the render tests: "dim" pixels of size 1 with 4 jittered samples each, and
+/// "spheres" lit by a white ambient occluder
+pub fn build_world(dim: (u16, u16), seed: u32, spheres: Vec<TestSphere>) -> World
+{
+    let mut sampler = Jittered::new(4, 3);
+    sampler.generate_sample_pattern();
+    let mut vp = ViewPlane::new(Arc::new(sampler));
+    vp.m_hres = dim.0;
+    vp.m_vres = dim.1;
+    vp.m_pixsize = 1.0;
+    vp.set_zoom(&1.0);
+    vp.m_seed = seed;
+
+    let mut world = World::new(Box::new(vp), "whitted");
+    let mut occluder = AmbientOccluder::new(COLOR_BLACK, 1.0);
+    occluder.set_color(COLOR_WHITE);
+    world.set_ambient(Arc::new(occluder));
+    for (center, radius, material) in spheres.into_iter()
+    {
+        let mut sphere = Sphere::new(radius, center);
+        sphere.set_material(material);
+        world.add_object(Arc::new(Mutex::new(sphere)));
+    }
+    world
+}
+
+pub fn setup_world(dim: (u16, u16), seed: u32, spheres: Vec<TestSphere>) -> Arc<World>
+{
+    Arc::new(build_world(dim, seed, spheres))
+}
+
+/// Pinhole looking at the origin from (0, 0, -100), whose samples aren't scaled by the exposure
+pub fn setup_camera() -> Pinhole
+{
+    let mut camera = Pinhole::new(Vector3::new(0.0, 0.0, -100.0), Vector3::zero(), Vector3::unit_y());
+    camera.m_core.m_exposure_time = 1.0;
+    camera
+}
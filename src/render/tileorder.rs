@@ -0,0 +1,351 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::f32::consts::PI;
+
+use crate::render::renderdata::{RenderMeta, RenderRegion};
+
+/// The order in which the blocks of a RenderBuffer are handed out to the threads
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TileOrder
+{
+    /// Row by row, from the top left block
+    #[default]
+    Raster,
+    /// Ring by ring from the block in the center of the region, which usually holds what matters
+    Spiral,
+    /// Along a Hilbert curve, so that consecutive blocks are neighbours and share their cached geometry
+    Hilbert,
+}
+
+impl TileOrder
+{
+    /// Sort the blocks of a region, given in raster order
+    pub fn sort(&self, blocks: &mut [RenderMeta], region: &RenderRegion)
+    {
+        match self
+        {
+            TileOrder::Raster => {}
+            TileOrder::Spiral =>
+                {
+                    let center = ((region.get_start_coords().0 + region.get_end_coords().0) as f32 * 0.5,
+                                  (region.get_start_coords().1 + region.get_end_coords().1) as f32 * 0.5);
+                    blocks.sort_by(|a, b| get_spiral_key(a, center).partial_cmp(&get_spiral_key(b, center)).unwrap());
+                }
+            TileOrder::Hilbert =>
+                {
+                    let num_blocks = blocks.iter()
+                        .fold((0, 0), |num, meta| (num.0.max(meta.get_block_indices().0 + 1), num.1.max(meta.get_block_indices().1 + 1)));
+                    let grid_size = num_blocks.0.max(num_blocks.1).next_power_of_two();
+                    blocks.sort_by_key(|meta| get_hilbert_index(*meta.get_block_indices(), grid_size));
+                }
+        }
+    }
+}
+
+/// Ring of the block around "center", in blocks, then its angle in the ring
+fn get_spiral_key(meta: &RenderMeta, center: (f32, f32)) -> (f32, f32)
+{
+    let size = ((meta.get_end_coords().0 - meta.get_start_coords().0) as f32,
+                (meta.get_end_coords().1 - meta.get_start_coords().1) as f32);
+    let offset = ((meta.get_start_coords().0 as f32 + size.0 * 0.5 - center.0),
+                  (meta.get_start_coords().1 as f32 + size.1 * 0.5 - center.1));
+    let ring = (offset.0.abs() / size.0).max(offset.1.abs() / size.1).round();
+    let angle = offset.1.atan2(offset.0);
+    (ring, if angle < 0.0 { angle + 2.0 * PI } else { angle })
+}
+
+/// Distance along the Hilbert curve filling a grid of grid_size x grid_size cells, a power of two
+pub fn get_hilbert_index(cell: (usize, usize), grid_size: usize) -> usize
+{
+    let (mut x, mut y) = cell;
+    let mut index = 0;
+    let mut s = grid_size / 2;
+    while s > 0
+    {
+        let rx = (x & s > 0) as usize;
+        let ry = (y & s > 0) as usize;
+        index += s * s * ((3 * rx) ^ ry);
+        // Rotate the quadrant, so that the curve inside it starts next to the previous one
+        if ry == 0
+        {
+            if rx == 1
+            {
+                x = s - 1 - (x & (s - 1));
+                y = s - 1 - (y & (s - 1));
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+/// Narrowest sub-tile the work stealing cuts a block into
+pub const MIN_SUB_TILE_DIM: usize = 4;
+
+/// The halves of a tile along the dimensions at least twice MIN_SUB_TILE_DIM long, None if it
+/// has none. The sub-tiles keep the local indices of their pixels in the block.
+pub fn split_tile(meta: &RenderMeta) -> Option<Vec<RenderMeta>>
+{
+    let (start, end) = (*meta.get_start_coords(), *meta.get_end_coords());
+    let get_cuts = |start: usize, end: usize|
+        {
+            if end - start >= 2 * MIN_SUB_TILE_DIM { vec![start, (start + end) / 2, end] } else { vec![start, end] }
+        };
+    let (x_cuts, y_cuts) = (get_cuts(start.0, end.0), get_cuts(start.1, end.1));
+    if x_cuts.len() == 2 && y_cuts.len() == 2 { return None; }
+
+    let mut sub_tiles = Vec::with_capacity(4);
+    for y in y_cuts.windows(2)
+    {
+        for x in x_cuts.windows(2)
+        {
+            sub_tiles.extend(RenderRegion::new((x[0], y[0]), (x[1], y[1])).ok().and_then(|region| meta.clip(&region)));
+        }
+    }
+    Some(sub_tiles)
+}
+
+/// Spans of the queue of blocks owned by the threads. A thread renders the blocks of its span in
+/// order, and once done steals the second half of the largest span left, so the threads start with
+/// large runs of neighbouring blocks and split the work finer as the render ends. When the largest
+/// span is down to a single tile, the thief splits it into sub-tiles and steals half of them, so
+/// the last blocks don't leave all but one thread waiting.
+pub struct WorkStealingQueue
+{
+    m_state: Mutex<Option<StealingState>>, // None until the first thread joins a pass
+}
+
+struct StealingState
+{
+    m_spans: Vec<VecDeque<RenderMeta>>,
+    m_parts_left: HashMap<(usize, usize), usize>, // Sub-tiles of the split blocks not done yet
+}
+
+impl WorkStealingQueue
+{
+    pub fn new() -> WorkStealingQueue
+    {
+        WorkStealingQueue{ m_state: Mutex::new(None) }
+    }
+
+    /// Start a new pass over the queue
+    pub fn restart(&self)
+    {
+        *self.m_state.lock().unwrap() = None;
+    }
+
+    /// Add a thread to the pass over a queue of "blocks"; return the index of its span
+    pub fn join(&self, blocks: &[RenderMeta]) -> usize
+    {
+        let mut locked_state = self.m_state.lock().unwrap();
+        match locked_state.as_mut()
+        {
+            Some(state) =>
+                {
+                    state.m_spans.push(VecDeque::new());
+                    state.m_spans.len() - 1
+                }
+            None =>
+                {
+                    *locked_state = Some(StealingState
+                    {
+                        m_spans: vec![blocks.iter().copied().collect()],
+                        m_parts_left: HashMap::new(),
+                    });
+                    0
+                }
+        }
+    }
+
+    /// Next block, or sub-tile of a block, of a thread; None once all blocks are handed out
+    pub fn next(&self, span_index: usize) -> Option<RenderMeta>
+    {
+        let mut locked_state = self.m_state.lock().unwrap();
+        let state = locked_state.as_mut()?;
+        if state.m_spans[span_index].is_empty()
+        {
+            let victim = state.m_spans.iter().enumerate()
+                .max_by_key(|(index, span)|
+                    (span.len(), span.iter().map(|meta| meta.m_area).sum::<usize>(), usize::MAX - index))?.0;
+            if state.m_spans[victim].is_empty() { return None; }
+            if state.m_spans[victim].len() == 1
+            {
+                let tile = state.m_spans[victim][0];
+                if let Some(sub_tiles) = split_tile(&tile)
+                {
+                    *state.m_parts_left.entry(*tile.get_block_indices()).or_insert(1) += sub_tiles.len() - 1;
+                    state.m_spans[victim] = sub_tiles.into_iter().collect();
+                }
+            }
+            // The victim keeps the first half, next to the block it is rendering
+            let middle = state.m_spans[victim].len() / 2;
+            let stolen = state.m_spans[victim].split_off(middle);
+            state.m_spans[span_index] = stolen;
+        }
+        state.m_spans[span_index].pop_front()
+    }
+
+    /// Record that a tile handed out by next is rendered; return whether its whole block is
+    pub fn finish(&self, meta: &RenderMeta) -> bool
+    {
+        let mut locked_state = self.m_state.lock().unwrap();
+        let parts_left = match locked_state.as_mut()
+            .and_then(|state| state.m_parts_left.get_mut(meta.get_block_indices()))
+        {
+            Some(parts_left) => parts_left,
+            None => return true,
+        };
+        *parts_left -= 1;
+        if *parts_left > 0 { return false; }
+        locked_state.as_mut().unwrap().m_parts_left.remove(meta.get_block_indices());
+        true
+    }
+}
+
+impl Default for WorkStealingQueue
+{
+    fn default() -> Self
+    {
+        WorkStealingQueue::new()
+    }
+}
+
+#[cfg(test)]
+mod TileOrderTest
+{
+    use super::*;
+
+    fn get_blocks(num_blocks: (usize, usize)) -> Vec<RenderMeta>
+    {
+        let mut blocks = Vec::new();
+        for j in 0..num_blocks.1
+        {
+            for i in 0..num_blocks.0
+            {
+                blocks.push(RenderMeta::new((i * 4, j * 4), ((i + 1) * 4, (j + 1) * 4), (i, j)));
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn hilbert_curve_visits_neighbours()
+    {
+        let mut blocks = get_blocks((8, 5));
+        TileOrder::Hilbert.sort(&mut blocks, &RenderRegion::from_image((32, 20)));
+        assert_eq!(*blocks[0].get_block_indices(), (0, 0));
+        assert_eq!(*blocks[1].get_block_indices(), (0, 1));
+        assert_eq!(*blocks[2].get_block_indices(), (1, 1));
+        let steps: Vec<usize> = blocks.windows(2)
+            .map(|pair| (pair[0].get_block_indices().0 as i32 - pair[1].get_block_indices().0 as i32).abs() as usize
+                + (pair[0].get_block_indices().1 as i32 - pair[1].get_block_indices().1 as i32).abs() as usize)
+            .collect();
+        // The blocks cut from the 8 x 8 curve are the only jumps
+        assert!(steps.iter().filter(|step| **step > 1).count() <= 3);
+
+        let mut indices: Vec<usize> = (0..64).map(|index| get_hilbert_index((index % 8, index / 8), 8)).collect();
+        indices.sort();
+        assert_eq!(indices, (0..64).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn spiral_starts_in_the_center()
+    {
+        let mut blocks = get_blocks((5, 5));
+        TileOrder::Spiral.sort(&mut blocks, &RenderRegion::from_image((20, 20)));
+        assert_eq!(*blocks[0].get_block_indices(), (2, 2));
+        assert!(blocks[1..9].iter().all(|meta|
+            {
+                let indices = meta.get_block_indices();
+                indices.0 >= 1 && indices.0 <= 3 && indices.1 >= 1 && indices.1 <= 3
+            }));
+        assert_eq!(blocks.len(), 25);
+
+        let mut raster = get_blocks((5, 5));
+        TileOrder::Raster.sort(&mut raster, &RenderRegion::from_image((20, 20)));
+        assert_eq!(*raster[6].get_block_indices(), (1, 1));
+    }
+
+    #[test]
+    fn work_stealing_hands_out_every_pixel_once()
+    {
+        let blocks: Vec<RenderMeta> = (0..10).map(|i| RenderMeta::new((i * 8, 0), ((i + 1) * 8, 8), (i, 0))).collect();
+        let queue = WorkStealingQueue::new();
+        let first = queue.join(&blocks);
+        let second = queue.join(&blocks);
+        assert_eq!(*queue.next(first).unwrap().get_block_indices(), (0, 0));
+        // The second thread steals the end of the queue
+        assert_eq!(*queue.next(second).unwrap().get_block_indices(), (5, 0));
+        let third = queue.join(&blocks);
+        assert_eq!(*queue.next(third).unwrap().get_block_indices(), (3, 0));
+
+        let mut pixels = vec![0; 80 * 8];
+        let mut parts_done = 0;
+        let mut blocks_done = 0;
+        let handed_out = [blocks[0], blocks[5], blocks[3]];
+        let mut record = |meta: RenderMeta|
+            {
+                for x in meta.get_start_coords().0..meta.get_end_coords().0
+                {
+                    for y in meta.get_start_coords().1..meta.get_end_coords().1
+                    {
+                        pixels[x + y * 80] += 1;
+                    }
+                }
+                parts_done += 1;
+                if queue.finish(&meta) { blocks_done += 1; }
+            };
+        handed_out.iter().for_each(|meta| record(*meta));
+        while let Some(meta) = queue.next(second).or_else(|| queue.next(first)).or_else(|| queue.next(third))
+        {
+            record(meta);
+        }
+        assert!(pixels.iter().all(|count| *count == 1));
+        assert_eq!(blocks_done, 10);
+        assert!(parts_done > 10, "No block was split");
+
+        queue.restart();
+        assert_eq!(queue.join(&blocks[..3]), 0);
+        assert_eq!(*queue.next(0).unwrap().get_block_indices(), (0, 0));
+    }
+
+    #[test]
+    fn splitting_the_last_blocks_balances_the_threads()
+    {
+        // Four threads and five 16 x 16 blocks: rendering whole blocks, a thread would render two
+        // of them, 512 pixels, while the others wait after 256
+        let blocks: Vec<RenderMeta> = (0..5).map(|i| RenderMeta::new((i * 16, 0), ((i + 1) * 16, 16), (i, 0))).collect();
+        let queue = WorkStealingQueue::new();
+        let spans: Vec<usize> = (0..4).map(|_| queue.join(&blocks)).collect();
+
+        // The threads take a pixel per unit of time, and ask for work in the order they get idle
+        let mut idle_since = vec![0; 4];
+        let mut finish_times = vec![None; 4];
+        while let Some(thread) = (0..4).filter(|thread| finish_times[*thread].is_none()).min_by_key(|thread| idle_since[*thread])
+        {
+            match queue.next(spans[thread])
+            {
+                Some(meta) => idle_since[thread] += meta.m_area,
+                None => finish_times[thread] = Some(idle_since[thread]),
+            }
+        }
+        let finish_times: Vec<usize> = finish_times.into_iter().flatten().collect();
+        let (first, last) = (*finish_times.iter().min().unwrap(), *finish_times.iter().max().unwrap());
+        assert!(last < 512, "The last thread finished after {}", last);
+        assert!(last - first <= 64, "The threads finished between {} and {}", first, last);
+    }
+
+    #[test]
+    fn sub_tiles_cover_the_tile()
+    {
+        let meta = RenderMeta::new((16, 8), (32, 14), (1, 1));
+        let sub_tiles = split_tile(&meta).unwrap();
+        // Too short to cut in height
+        assert_eq!(sub_tiles.len(), 2);
+        assert_eq!((*sub_tiles[0].get_start_coords(), *sub_tiles[0].get_end_coords()), ((16, 8), (24, 14)));
+        assert_eq!(sub_tiles[1].get_local_index(24, 8), meta.get_local_index(24, 8));
+        assert_eq!(sub_tiles.iter().map(|sub_tile| sub_tile.m_area).sum::<usize>(), meta.m_area);
+        assert!(split_tile(&RenderMeta::new((0, 0), (7, 7), (0, 0))).is_none());
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::OnceLock;
+
+use crate::sampler::{hash_u32, hash_combine};
+
+/// Width and height of the shared blue noise tile, which is repeated over the image
+pub const BLUE_NOISE_TILE_SIZE: usize = 64;
+
+const SIGMA: f32 = 1.5;
+const KERNEL_RADIUS: i32 = 6;
+const INITIAL_DENSITY: f32 = 0.1;
+
+static BLUE_NOISE_TILE: OnceLock<Vec<f32>> = OnceLock::new();
+
+/// Value of the shared blue noise tile at pixel (x, y); the tile wraps around
+pub fn get_blue_noise(x: u32, y: u32) -> f32
+{
+    let tile = BLUE_NOISE_TILE.get_or_init(|| generate_blue_noise_tile(BLUE_NOISE_TILE_SIZE, 0));
+    let size = BLUE_NOISE_TILE_SIZE as u32;
+    tile[((x % size) + (y % size) * size) as usize]
+}
+
+/// Gaussian energy of the set pixels around every pixel of a toroidal binary pattern
+struct EnergyField
+{
+    m_size: usize,
+    m_is_set: Vec<bool>,
+    m_energy: Vec<f32>,
+    m_kernel: Vec<f32>,
+}
+
+impl EnergyField
+{
+    fn new(size: usize) -> EnergyField
+    {
+        let width = (2 * KERNEL_RADIUS + 1) as usize;
+        let kernel = (0..width * width)
+            .map(|i| {
+                let dx = (i % width) as f32 - KERNEL_RADIUS as f32;
+                let dy = (i / width) as f32 - KERNEL_RADIUS as f32;
+                (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+
+        EnergyField
+        {
+            m_size: size,
+            m_is_set: vec![false; size * size],
+            m_energy: vec![0.0; size * size],
+            m_kernel: kernel,
+        }
+    }
+
+    fn toggle(&mut self, index: usize)
+    {
+        let set = !self.m_is_set[index];
+        self.m_is_set[index] = set;
+        let sign = if set { 1.0 } else { -1.0 };
+
+        let size = self.m_size as i32;
+        let width = 2 * KERNEL_RADIUS + 1;
+        let (x, y) = ((index % self.m_size) as i32, (index / self.m_size) as i32);
+        for dy in -KERNEL_RADIUS..=KERNEL_RADIUS
+        {
+            for dx in -KERNEL_RADIUS..=KERNEL_RADIUS
+            {
+                let target = ((x + dx).rem_euclid(size) + (y + dy).rem_euclid(size) * size) as usize;
+                let weight = self.m_kernel[((dx + KERNEL_RADIUS) + (dy + KERNEL_RADIUS) * width) as usize];
+                self.m_energy[target] += sign * weight;
+            }
+        }
+    }
+
+    /// The set pixel with the most set neighbours
+    fn tightest_cluster(&self) -> usize
+    {
+        self.find_extremum(true, |energy, best| energy > best)
+    }
+
+    /// The unset pixel with the fewest set neighbours
+    fn largest_void(&self) -> usize
+    {
+        self.find_extremum(false, |energy, best| energy < best)
+    }
+
+    fn find_extremum<F>(&self, is_set: bool, is_better: F) -> usize
+        where F: Fn(f32, f32) -> bool
+    {
+        let mut best_index = usize::MAX;
+        for (index, energy) in self.m_energy.iter().enumerate()
+        {
+            if self.m_is_set[index] == is_set
+                && (best_index == usize::MAX || is_better(*energy, self.m_energy[best_index]))
+            {
+                best_index = index;
+            }
+        }
+        best_index
+    }
+}
+
+/// Generate a size x size tile of blue noise with the void-and-cluster method (Ulichney).
+/// Every pixel gets a distinct value in (0, 1), and pixels of close values are far apart.
+pub fn generate_blue_noise_tile(size: usize, seed: u32) -> Vec<f32>
+{
+    let pixel_count = size * size;
+    let mut field = EnergyField::new(size);
+
+    // Random initial pattern...
+    let initial_count = ((pixel_count as f32 * INITIAL_DENSITY) as usize).max(1);
+    let mut counter = 0;
+    let mut placed = 0;
+    while placed < initial_count
+    {
+        let index = hash_u32(hash_combine(seed, counter)) as usize % pixel_count;
+        counter += 1;
+        if !field.m_is_set[index]
+        {
+            field.toggle(index);
+            placed += 1;
+        }
+    }
+
+    // ...made evenly distributed by moving the tightest cluster into the largest void
+    for _ in 0..pixel_count
+    {
+        let cluster = field.tightest_cluster();
+        field.toggle(cluster);
+        let void = field.largest_void();
+        field.toggle(void);
+        if void == cluster { break; }
+    }
+
+    let mut ranks = vec![0; pixel_count];
+
+    // Rank the initial pattern by removing its tightest clusters first
+    let mut removal = EnergyField::new(size);
+    for index in 0..pixel_count
+    {
+        if field.m_is_set[index] { removal.toggle(index); }
+    }
+    for rank in (0..initial_count).rev()
+    {
+        let cluster = removal.tightest_cluster();
+        removal.toggle(cluster);
+        ranks[cluster] = rank;
+    }
+
+    // Then fill the largest voids until the tile is full
+    for rank in initial_count..pixel_count
+    {
+        let void = field.largest_void();
+        field.toggle(void);
+        ranks[void] = rank;
+    }
+
+    ranks.iter().map(|rank| (*rank as f32 + 0.5) / pixel_count as f32).collect()
+}
+
+#[cfg(test)]
+mod BlueNoiseTest
+{
+    use super::*;
+
+    /// Standard deviation of the averages of the 4x4 blocks of a tile
+    fn block_average_deviation(tile: &[f32], size: usize) -> f32
+    {
+        let averages: Vec<f32> = (0..(size / 4) * (size / 4))
+            .map(|block| {
+                let (bx, by) = (block % (size / 4) * 4, block / (size / 4) * 4);
+                (0..16).map(|i| tile[bx + i % 4 + (by + i / 4) * size]).sum::<f32>() / 16.0
+            })
+            .collect();
+        let mean = averages.iter().sum::<f32>() / averages.len() as f32;
+        (averages.iter().map(|a| (a - mean) * (a - mean)).sum::<f32>() / averages.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn tile_is_a_permutation()
+    {
+        let size = 16;
+        let tile = generate_blue_noise_tile(size, 3);
+        let mut ranks: Vec<usize> = tile.iter().map(|value| (value * (size * size) as f32) as usize).collect();
+        ranks.sort();
+        assert_eq!(ranks, (0..size * size).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn tile_has_little_low_frequency_energy()
+    {
+        // Averaging white noise over 16 pixels divides its deviation by 4 only;
+        // blue noise lacks the low frequencies, so its local averages are much closer to 0.5
+        let size = 32;
+        let blue_noise = generate_blue_noise_tile(size, 1);
+        let white_noise: Vec<f32> = (0..size * size)
+            .map(|i| (hash_u32(i as u32) >> 8) as f32 / (1u32 << 24) as f32)
+            .collect();
+        assert!(block_average_deviation(&blue_noise, size) < 0.5 * block_average_deviation(&white_noise, size));
+    }
+
+    #[test]
+    fn shared_tile_wraps_around()
+    {
+        let size = BLUE_NOISE_TILE_SIZE as u32;
+        assert_eq!(get_blue_noise(3, 5), get_blue_noise(3 + size, 5 + 2 * size));
+        assert!(get_blue_noise(0, 0) > 0.0 && get_blue_noise(0, 0) < 1.0);
+    }
+}
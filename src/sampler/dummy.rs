@@ -25,6 +25,8 @@ impl Sampler for DummySampler
         1
     }
 
+    fn set_seed(&mut self, _seed: u32) {}
+
     fn set_map_to_disk(&mut self, flag: bool) {
         unimplemented!()
     }
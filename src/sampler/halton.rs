@@ -0,0 +1,207 @@
+use cgmath::{Vector2, Vector3};
+
+use crate::sampler::{Sampler, SamplerCore};
+
+pub const HALTON_MAX_DIMENSION: usize = 1024;
+
+/// The first primes, one per dimension of the Halton sequence
+static PRIMES: [u32; HALTON_MAX_DIMENSION] = compute_primes();
+
+const fn compute_primes() -> [u32; HALTON_MAX_DIMENSION]
+{
+    let mut primes = [0u32; HALTON_MAX_DIMENSION];
+    let mut count = 0;
+    let mut candidate = 2;
+    while count < HALTON_MAX_DIMENSION
+    {
+        let mut i = 0;
+        while i < count && primes[i] * primes[i] <= candidate && candidate % primes[i] != 0
+        {
+            i += 1;
+        }
+        if i == count || primes[i] * primes[i] > candidate
+        {
+            primes[count] = candidate;
+            count += 1;
+        }
+        candidate += 1;
+    }
+    primes
+}
+
+/// Mirror the digits of "index" in "base" around the radix point
+pub fn radical_inverse(base: u32, index: u64) -> f32
+{
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed_digits: u64 = 0;
+    let mut n = index;
+
+    while n > 0
+    {
+        let next = n / base as u64;
+        let digit = n - next * base as u64;
+        reversed_digits = reversed_digits * base as u64 + digit;
+        inv_base_n *= inv_base;
+        n = next;
+    }
+    ((reversed_digits as f64 * inv_base_n) as f32).min(1.0 - f32::EPSILON)
+}
+
+/// The "index"-th point of the Halton sequence, in "dimension", which must be below HALTON_MAX_DIMENSION
+pub fn halton_sample(index: u64, dimension: usize) -> f32
+{
+    radical_inverse(PRIMES[dimension], index)
+}
+
+/// Sampler based on the Halton sequence. The patterns are consecutive, non-overlapping
+/// runs of the sequence, so that together they stay well distributed.
+#[derive(Debug)]
+pub struct Halton
+{
+    m_core: SamplerCore,
+}
+
+impl Halton
+{
+    pub fn new(sample_per_pattern: usize, num_pattern: usize) -> Halton
+    {
+        Halton
+        {
+            m_core: SamplerCore::new(sample_per_pattern, num_pattern),
+        }
+    }
+}
+
+impl Sampler for Halton
+{
+    fn generate_sample_pattern(&mut self)
+    {
+        let sample_per_pattern = self.m_core.m_sample_per_pattern;
+
+        for (pattern_index, pattern) in self.m_core.m_samples_on_square.iter_mut().enumerate()
+        {
+            for (i, sample) in pattern.iter_mut().enumerate()
+            {
+                let index = (pattern_index * sample_per_pattern + i) as u64;
+                *sample = Vector2::new(halton_sample(index, 0), halton_sample(index, 1));
+            }
+        }
+
+        if self.m_core.m_map_to_disk { self.m_core.map_sample_to_disk(); }
+        if self.m_core.m_map_to_hemisphere { self.m_core.map_sample_to_hemisphere(); }
+    }
+
+    fn get_sample_per_pattern(&self) -> usize
+    {
+        self.m_core.m_sample_per_pattern
+    }
+
+    fn set_seed(&mut self, seed: u32)
+    {
+        self.m_core.set_seed(seed);
+    }
+
+    fn set_map_to_disk(&mut self, flag: bool) {
+        self.m_core.set_map_to_disk(flag);
+    }
+
+    fn set_map_to_hemisphere(&mut self, flag: bool, e: f32) {
+        self.m_core.set_map_to_hemisphere(flag, e);
+    }
+
+    fn get_unit_square_pattern(&self) -> &Vec<Vector2<f32>>
+    {
+        self.m_core.get_unit_square_pattern()
+    }
+
+    fn get_disk_pattern(&self) -> &Vec<Vector2<f32>>
+    {
+        match self.m_core.get_disk_pattern()
+        {
+            Ok(sample) => sample,
+            _ => panic!("The Halton Sampler isn't set to generate samples on disk")
+        }
+    }
+
+    fn get_disk_sample(&self) -> Vector2<f32>
+    {
+        self.m_core.get_disk_sample()
+    }
+
+    fn get_hemisphere_pattern(&self) -> &Vec<Vector3<f32>>
+    {
+        match self.m_core.get_hemisphere_pattern()
+        {
+            Ok(sample) => sample,
+            _ => panic!("The Halton Sampler isn't set to generate samples on hemisphere")
+        }
+    }
+
+    fn get_hemisphere_sample(&self) -> Vector3<f32>
+    {
+        self.m_core.get_hemisphere_sample()
+    }
+}
+
+#[cfg(test)]
+mod HaltonTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn radical_inverse_base_2_and_3()
+    {
+        assert_relative_eq!(radical_inverse(2, 1), 0.5);
+        assert_relative_eq!(radical_inverse(2, 6), 0.375);
+        assert_relative_eq!(radical_inverse(3, 1), 1.0 / 3.0);
+        assert_relative_eq!(radical_inverse(3, 5), 7.0 / 9.0);
+    }
+
+    #[test]
+    fn every_dimension_has_its_own_prime()
+    {
+        assert_eq!(PRIMES[..8], [2, 3, 5, 7, 11, 13, 17, 19]);
+        assert_eq!(PRIMES[HALTON_MAX_DIMENSION - 1], 8161);
+    }
+
+    #[test]
+    fn first_points_are_stratified()
+    {
+        // The first 2^k points of dimension 0 fall into distinct intervals of width 2^-k
+        let mut strata = vec![false; 64];
+        for index in 0..64
+        {
+            let stratum = (halton_sample(index, 0) * 64.0) as usize;
+            assert!(!strata[stratum]);
+            strata[stratum] = true;
+        }
+    }
+
+    #[test]
+    fn patterns_are_in_unit_square()
+    {
+        let mut sampler = Halton::new(16, 4);
+        sampler.generate_sample_pattern();
+        for pattern in sampler.m_core.m_samples_on_square.iter()
+        {
+            assert_eq!(pattern.len(), 16);
+            for sample in pattern.iter()
+            {
+                assert!(sample.x >= 0.0 && sample.x < 1.0 && sample.y >= 0.0 && sample.y < 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn hemisphere_samples_follow_the_exponent()
+    {
+        // A glossy lobe keeps the samples close to the pole
+        let mut sampler = Halton::new(64, 1);
+        sampler.set_map_to_hemisphere(true, 100.0);
+        sampler.generate_sample_pattern();
+        let mean_cos = sampler.m_core.m_samples_on_hemisphere[0].iter().map(|sample| sample.z).sum::<f32>() / 64.0;
+        assert_relative_eq!(mean_cos, 101.0 / 102.0, epsilon = 1e-2);
+    }
+}
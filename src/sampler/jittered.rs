@@ -2,7 +2,7 @@ use cgmath::{Vector2, Vector3};
 
 use crate::sampler::{Sampler, SamplerCore};
 use rand::prelude::StdRng;
-use rand::{Rng, thread_rng, SeedableRng};
+use rand::{Rng, SeedableRng};
 
 #[derive(Debug)]
 pub struct Jittered
@@ -29,7 +29,7 @@ impl Sampler for Jittered
     {
         let sqrt_sample_per_pattern = (self.m_core.m_sample_per_pattern as f32).sqrt() as usize;
         let inv_sqrt = 1.0 / sqrt_sample_per_pattern as f32;
-        let mut rng = thread_rng();
+        self.m_core.reset_rng();
 
         for pattern in 0..self.m_core.m_num_pattern
         {
@@ -37,14 +37,14 @@ impl Sampler for Jittered
             {
                 for j in 0..sqrt_sample_per_pattern
                 {
-                    self.m_core.m_samples_on_square[pattern][i * sqrt_sample_per_pattern + j] = Vector2::new(i as f32 * inv_sqrt + rng.gen_range(0.0, inv_sqrt),
-                                                                                                             j as f32 * inv_sqrt + rng.gen_range(0.0, inv_sqrt));
+                    self.m_core.m_samples_on_square[pattern][i * sqrt_sample_per_pattern + j] = Vector2::new(i as f32 * inv_sqrt + self.m_core.m_rng.gen_range(0.0, inv_sqrt),
+                                                                                                             j as f32 * inv_sqrt + self.m_core.m_rng.gen_range(0.0, inv_sqrt));
                 }
             }
         }
 
         if self.m_core.m_map_to_disk { self.m_core.map_sample_to_disk(); }
-        if self.m_core.m_map_to_hemisphere { self.m_core.map_sample_to_hemisphere(1.0); }
+        if self.m_core.m_map_to_hemisphere { self.m_core.map_sample_to_hemisphere(); }
     }
 
     fn get_sample_per_pattern(&self) -> usize
@@ -52,6 +52,11 @@ impl Sampler for Jittered
         self.m_core.m_sample_per_pattern
     }
 
+    fn set_seed(&mut self, seed: u32)
+    {
+        self.m_core.set_seed(seed);
+    }
+
     fn set_map_to_disk(&mut self, flag: bool) {
         self.m_core.set_map_to_disk(flag);
     }
@@ -104,7 +109,6 @@ mod JitteredTest
     use std::f32::consts::PI;
 
     const INV_PI: f32 = 1.0 / PI ;
-    const INV_GAMMA: f32 = 1.0 / 1.8;
 
     // This is not proper unit testing. We just write the result to
     // image to inspect whether the sampler is implemented correctly.
@@ -125,7 +129,7 @@ mod JitteredTest
             {
                 let x = sample.x * (width as f32);
                 let y = sample.y * (height as f32);
-                imgwriter.write_pixel(x as usize, y as usize, COLOR_WHITE, INV_GAMMA);
+                imgwriter.write_pixel(x as usize, y as usize, COLOR_WHITE);
             }
         }
 
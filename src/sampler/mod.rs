@@ -2,18 +2,26 @@ pub mod mutijittered;
 pub mod nrooks;
 pub mod jittered;
 pub mod dummy;
+pub mod halton;
+pub mod sobol;
+pub mod pixelsampler;
+pub mod bluenoise;
 
 use cgmath::{Vector2, Vector3, ElementWise, Zero};
 use std::{f32};
 use cgmath::num_traits::Inv;
-use rand::{seq::SliceRandom, Rng, thread_rng, SeedableRng};
+use rand::{seq::SliceRandom, Rng, SeedableRng};
 use std::error::Error;
 use std::fmt;
-use std::sync::RwLock;
 use rand::prelude::StdRng;
 
+use crate::sampler::pixelsampler::get_1d;
+
 type Point2<T> = Vector2<T>;
 
+/// Seed of the samplers and view planes that aren't given one
+pub const DEFAULT_SEED: u32 = 0;
+
 #[derive(Debug)]
 struct SamplerCore
 {
@@ -21,10 +29,12 @@ struct SamplerCore
     pub m_num_pattern: usize,
     m_map_to_disk: bool,
     m_map_to_hemisphere: bool,
+    m_hemisphere_exp: f32, // Cosine power of the hemisphere samples' density
     m_samples_on_square: Vec<Vec<Vector2<f32>>>,
     m_samples_on_disk: Vec<Vec<Vector2<f32>>>,
     m_samples_on_hemisphere: Vec<Vec<Vector3<f32>>>,
-    m_rng: RwLock<StdRng>,
+    m_seed: u32,
+    m_rng: StdRng,
     pub m_shuffled_indices: Vec<u32>,
 }
 
@@ -39,13 +49,30 @@ impl SamplerCore
             m_samples_on_square: vec![vec![Vector2::zero(); sample_per_pattern]; num_pattern],
             m_map_to_disk: false,
             m_map_to_hemisphere: false,
+            m_hemisphere_exp: 1.0,
             m_samples_on_disk: Vec::with_capacity(num_pattern),
             m_samples_on_hemisphere: Vec::with_capacity(num_pattern),
-            m_rng: RwLock::new(StdRng::from_rng(thread_rng()).unwrap()),
-            m_shuffled_indices: SamplerCore::setup_shuffled_indices(num_pattern, sample_per_pattern)
+            m_seed: DEFAULT_SEED,
+            m_rng: StdRng::seed_from_u64(DEFAULT_SEED as u64),
+            m_shuffled_indices: SamplerCore::setup_shuffled_indices(num_pattern, sample_per_pattern, DEFAULT_SEED)
         }
     }
 
+    /// The patterns are generated from the seed only; call generate_sample_pattern() again
+    /// after changing it
+    fn set_seed(&mut self, seed: u32)
+    {
+        self.m_seed = seed;
+        self.m_shuffled_indices = SamplerCore::setup_shuffled_indices(self.m_num_pattern, self.m_sample_per_pattern, seed);
+        self.reset_rng();
+    }
+
+    /// Restart the random numbers used to generate the patterns
+    fn reset_rng(&mut self)
+    {
+        self.m_rng = StdRng::seed_from_u64(self.m_seed as u64);
+    }
+
     // TODO: What the heck is this "flag" variable???
     fn set_map_to_disk(&mut self, flag: bool)
     {
@@ -61,26 +88,27 @@ impl SamplerCore
 
     fn set_map_to_hemisphere(&mut self, flag: bool, e: f32)
     {
-        if flag != self.m_map_to_hemisphere
+        if flag != self.m_map_to_hemisphere || e != self.m_hemisphere_exp
         {
             self.m_map_to_hemisphere = flag;
+            self.m_hemisphere_exp = e;
             if flag && !self.m_samples_on_square.is_empty()
             {
-                self.map_sample_to_hemisphere(e);
+                self.map_sample_to_hemisphere();
             }
         }
     }
 
     fn get_unit_square_pattern(&self) -> &Vec<Vector2<f32>>
     {
-        let pattern_index = self.get_pattern_index();
+        let pattern_index = self.draw_pattern_index();
         &self.m_samples_on_square[pattern_index]
     }
 
     fn get_disk_pattern(&self) -> Result<&Vec<Vector2<f32>>, &str>
     {
         if !self.m_map_to_disk { return Err("Didn't yet generate disk samples") }
-        let pattern_index = self.get_pattern_index();
+        let pattern_index = self.draw_pattern_index();
         Ok(&self.m_samples_on_disk[pattern_index])
     }
 
@@ -88,9 +116,8 @@ impl SamplerCore
     {
         if self.m_samples_on_disk.len() != 0
         {
-            let pattern_index = self.get_pattern_index();
-            let sample_index = self.m_rng.write().unwrap()
-                .gen::<usize>() % self.m_sample_per_pattern;
+            let pattern_index = self.draw_pattern_index();
+            let sample_index = self.draw_sample_index();
             self.m_samples_on_disk[pattern_index][sample_index]
         }
         else { panic!("Didn't yet generate hemisphere samples") }
@@ -99,7 +126,7 @@ impl SamplerCore
     fn get_hemisphere_pattern(&self) -> Result<&Vec<Vector3<f32>>, &str>
     {
         if !self.m_map_to_hemisphere { return Err("") }
-        let pattern_index = self.get_pattern_index();
+        let pattern_index = self.draw_pattern_index();
         Ok(&self.m_samples_on_hemisphere[pattern_index])
     }
 
@@ -107,9 +134,8 @@ impl SamplerCore
     {
         if self.m_samples_on_hemisphere.len() != 0
         {
-            let pattern_index = self.get_pattern_index();
-            let sample_index = self.m_rng.write().unwrap()
-                .gen::<usize>() % self.m_sample_per_pattern;
+            let pattern_index = self.draw_pattern_index();
+            let sample_index = self.draw_sample_index();
             self.m_samples_on_hemisphere[pattern_index][sample_index]
         }
         else { panic!("Didn't yet generate hemisphere samples") }
@@ -124,8 +150,7 @@ impl SamplerCore
         {
             for j in 0..self.m_sample_per_pattern
             {
-                let target_sample_index = self.m_rng.write().unwrap()
-                    .gen::<usize>() % self.m_sample_per_pattern;
+                let target_sample_index = self.m_rng.gen::<usize>() % self.m_sample_per_pattern;
                 let temp = self.m_samples_on_square[i][j].x;
                 self.m_samples_on_square[i][j].x = self.m_samples_on_square[i][target_sample_index].x;
                 self.m_samples_on_square[i][target_sample_index].x = temp;
@@ -148,7 +173,7 @@ impl SamplerCore
         {
             for j in 0..self.m_sample_per_pattern
             {
-                let target_sample_index = self.m_rng.write().unwrap().gen::<usize>() % self.m_sample_per_pattern;
+                let target_sample_index = self.m_rng.gen::<usize>() % self.m_sample_per_pattern;
                 let temp = self.m_samples_on_square[i][j].y;
                 self.m_samples_on_square[i][j].y = self.m_samples_on_square[i][target_sample_index].y;
                 self.m_samples_on_square[i][target_sample_index].y = temp;
@@ -161,9 +186,9 @@ impl SamplerCore
         }
     }
 
-    fn setup_shuffled_indices(num_pattern: usize, sample_per_pattern: usize) -> Vec<u32>
+    fn setup_shuffled_indices(num_pattern: usize, sample_per_pattern: usize, seed: u32) -> Vec<u32>
     {
-        let mut rng = rand::thread_rng();
+        let mut rng = StdRng::seed_from_u64(seed as u64);
         let mut shuffled_indices: Vec<u32> = Vec::with_capacity(sample_per_pattern * num_pattern);
         let mut orig_indices: Vec<_> = (0..sample_per_pattern as u32).collect();
 
@@ -177,93 +202,102 @@ impl SamplerCore
 
     fn map_sample_to_disk(&mut self)
     {
-        if self.m_samples_on_disk.len() != 0
-        { return; }
-        let mut radius = 0.0;
-        let mut phi = 0.0;
-        let mut samp = Vector2::new(0.0, 0.0);
+        self.m_samples_on_disk = self.m_samples_on_square.iter()
+            .map(|pattern| pattern.iter().map(|sample| map_square_to_disk(*sample)).collect())
+            .collect();
+    }
 
-        let n = self.m_num_pattern * self.m_sample_per_pattern;
-        let mut diskpattern = Vec::with_capacity(n);
-        let identityvec = Vector2::new(1.0, 1.0);
+    fn map_sample_to_hemisphere(&mut self)
+    {
+        let e = self.m_hemisphere_exp;
+        self.m_samples_on_hemisphere = self.m_samples_on_square.iter()
+            .map(|pattern| pattern.iter().map(|sample| map_square_to_hemisphere(*sample, e)).collect())
+            .collect();
+    }
 
-        for pattern in self.m_samples_on_square.iter()
-        {
-            for square_sample in pattern.iter()
-            {
-                samp = square_sample.mul_element_wise(2.0) - identityvec;
-                if samp.x > -samp.y
-                {
-                    if samp.x > samp.y
-                    {
-                        radius = samp.x;
-                        phi = samp.y / samp.x;
-                    }
-                    else
-                    {
-                        radius = samp.y;
-                        phi = 2.0 - samp.x / samp.y;
-                    }
-                }
-                else
-                {
-                    if samp.x < samp.y
-                    {
-                        radius = - samp.x;
-                        phi = 4.0 + samp.y / samp.x;
-                    }
-                    else {
-                        radius = - samp.y;
-                        if samp.y != 0.0
-                        {
-                            phi = 6.0 - samp.x / samp.y;
-                        }
-                        else { phi = 0.0; }
-                    }
-                }
-
-                phi *= f32::consts::PI / 4.0;
-                diskpattern.push(Vector2::new(radius * phi.cos(), radius * phi.sin()));
-            }
-        }
+    /// Patterns and samples are picked with the sampler of the current thread: every call draws
+    /// its next dimension, so two calls give different indices
+    fn draw_pattern_index(&self) -> usize
+    {
+        ((get_1d() * self.m_num_pattern as f32) as usize).min(self.m_num_pattern - 1)
+    }
 
-        self.m_samples_on_disk = (0..self.m_num_pattern)
-            .map(|pattern_index| diskpattern[pattern_index * self.m_sample_per_pattern..(pattern_index+1) * self.m_sample_per_pattern].to_vec())
-            .collect();
+    fn draw_sample_index(&self) -> usize
+    {
+        ((get_1d() * self.m_sample_per_pattern as f32) as usize).min(self.m_sample_per_pattern - 1)
     }
+}
 
-    fn map_sample_to_hemisphere(&mut self, e: f32)
+/// Concentric mapping of a sample on the unit square to the unit disk
+pub fn map_square_to_disk(square_sample: Vector2<f32>) -> Vector2<f32>
+{
+    let radius;
+    let mut phi;
+    let samp = square_sample.mul_element_wise(2.0) - Vector2::new(1.0, 1.0);
+
+    if samp.x > -samp.y
     {
-        let n = self.m_num_pattern * self.m_sample_per_pattern;
-        let mut hemisphere_pattern = Vec::with_capacity(n);
-        for pattern in self.m_samples_on_square.iter()
+        if samp.x > samp.y
         {
-            for s in pattern.iter()
-            {
-                let cos_phi = (2.0 * f32::consts::PI * s.x).cos();
-                let sin_phi = (2.0 * f32::consts::PI * s.x).sin();
-                let cos_theta = (1.0 - s.y).powf((e + 1.0).inv());
-                let sin_theta = (1.0 - cos_theta.powf(2.0)).sqrt();
-                let pu = sin_theta * cos_phi;
-                let pv = sin_theta * sin_phi;
-                let pw = cos_theta;
-                hemisphere_pattern.push(Vector3::new(pu, pv, pw));
-            }
+            radius = samp.x;
+            phi = samp.y / samp.x;
+        }
+        else
+        {
+            radius = samp.y;
+            phi = 2.0 - samp.x / samp.y;
         }
-        self.m_samples_on_hemisphere = (0..self.m_num_pattern)
-            .map(|pattern_index| hemisphere_pattern[pattern_index * self.m_sample_per_pattern..(pattern_index+1) * self.m_sample_per_pattern].to_vec())
-            .collect();
     }
-
-    fn get_pattern_index(&self) -> usize
+    else if samp.x < samp.y
+    {
+        radius = - samp.x;
+        phi = 4.0 + samp.y / samp.x;
+    }
+    else
     {
-        self.m_rng.write().unwrap().gen::<usize>() % self.m_num_pattern
+        radius = - samp.y;
+        phi = if samp.y != 0.0 { 6.0 - samp.x / samp.y } else { 0.0 };
     }
+
+    phi *= f32::consts::PI / 4.0;
+    Vector2::new(radius * phi.cos(), radius * phi.sin())
+}
+
+/// Map a sample on the unit square to the hemisphere around +z, with a cosine power density of exponent "e"
+pub fn map_square_to_hemisphere(s: Vector2<f32>, e: f32) -> Vector3<f32>
+{
+    let cos_phi = (2.0 * f32::consts::PI * s.x).cos();
+    let sin_phi = (2.0 * f32::consts::PI * s.x).sin();
+    let cos_theta = (1.0 - s.y).powf((e + 1.0).inv());
+    let sin_theta = (1.0 - cos_theta.powf(2.0)).sqrt();
+    Vector3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+}
+
+pub(crate) fn hash_u32(mut x: u32) -> u32
+{
+    x ^= x >> 16;
+    x = x.wrapping_mul(0x21f0_aaad);
+    x ^= x >> 15;
+    x = x.wrapping_mul(0x735a_2d97);
+    x ^ (x >> 15)
+}
+
+pub(crate) fn hash_combine(seed: u32, value: u32) -> u32
+{
+    seed ^ (value.wrapping_add(0x9e37_79b9).wrapping_add(seed << 6).wrapping_add(seed >> 2))
+}
+
+/// Fixed point value in [0, 1) to float
+pub(crate) fn to_unit_float(bits: u32) -> f32
+{
+    ((bits >> 8) as f32 / (1u32 << 24) as f32).min(1.0 - f32::EPSILON)
 }
 
 pub trait Sampler: Send + Sync
 {
     fn generate_sample_pattern(&mut self);
+    /// Seed of the patterns, which are the same for every generation with the same seed
+    fn set_seed(&mut self, seed: u32);
     fn get_sample_per_pattern(&self) -> usize;
     fn set_map_to_disk(&mut self, flag: bool);
     fn set_map_to_hemisphere(&mut self, flag: bool, e: f32);
@@ -293,20 +327,21 @@ mod ConvertSampleTest
     fn checkMap2Disk()
     {
         let mut core = SamplerCore::new(4, 2);
-        core.m_samples_on_square[0].push(Vector2::new(0.8, 0.7));
-        core.m_samples_on_square[0].push(Vector2::new(0.5, 0.6));
-        core.m_samples_on_square[0].push(Vector2::new(0.7, 0.3));
-        core.m_samples_on_square[0].push(Vector2::new(0.1, 0.2));
-        core.m_samples_on_square[1].push(Vector2::new(0.5, 0.0));
-        core.m_samples_on_square[1].push(Vector2::new(0.0, 0.0));
-        // place holder
-        core.m_samples_on_square[1].push(Vector2::new(0.0, 0.0));
-        core.m_samples_on_square[1].push(Vector2::new(0.0, 0.0));
+        core.m_samples_on_square[0] = vec![Vector2::new(0.8, 0.7),
+                                           Vector2::new(0.5, 0.6),
+                                           Vector2::new(0.7, 0.3),
+                                           Vector2::new(0.1, 0.2)];
+        core.m_samples_on_square[1] = vec![Vector2::new(0.5, 0.0),
+                                           Vector2::new(0.0, 0.0),
+                                           // place holder
+                                           Vector2::new(0.0, 0.0),
+                                           Vector2::new(0.0, 0.0)];
 
         core.map_sample_to_disk();
 
-        assert_relative_eq!(core.m_samples_on_disk.clone().unwrap()[0].y, 0.2999999999999, epsilon = f32::EPSILON);
-        assert_relative_eq!(core.m_samples_on_disk.clone().unwrap()[1].y, 0.0000000000, epsilon = f32::EPSILON);
+        assert_relative_eq!(core.m_samples_on_disk[0][0].y, 0.2999999999999, epsilon = 1e-6);
+        assert_relative_eq!(core.m_samples_on_disk[0][1].y, 0.2, epsilon = 1e-6);
+        assert_relative_eq!(core.m_samples_on_disk[1][0].y, -1.0, epsilon = 1e-6);
         /*
         assert_relative_eq!(res[2].x, -0.6607183312158572, epsilon = f32::EPSILON);
 
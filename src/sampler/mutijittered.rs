@@ -1,6 +1,6 @@
 use crate::sampler::{SamplerCore, Sampler};
 use cgmath::{Vector2, Vector3, ElementWise};
-use rand::{Rng, thread_rng};
+use rand::Rng;
 use rand::rngs::ThreadRng;
 use std::cell::RefCell;
 use std::ops::Deref;
@@ -32,7 +32,7 @@ impl Sampler for MultiJittered
     {
         let sqrt_samples_per_pattern = (self.m_core.m_sample_per_pattern as f32).sqrt() as usize;
         let inv_sqrt = 1.0 / sqrt_samples_per_pattern as f32;
-        let mut rng = thread_rng();
+        self.m_core.reset_rng();
         print!("sample patter, {}", self.m_core.m_samples_on_square.len());
 
         for pattern in 0..self.m_core.m_num_pattern
@@ -42,8 +42,8 @@ impl Sampler for MultiJittered
                 for i in 0..sqrt_samples_per_pattern
                 {
                     self.m_core.m_samples_on_square[pattern][i * sqrt_samples_per_pattern + j] =
-                        (Vector2::new((j as f32 + rng.gen_range(0.0, 1.0)) * inv_sqrt.powf(2.0),
-                                      (i as f32 + rng.gen_range(0.0, 1.0)) * inv_sqrt.powf(2.0)));
+                        (Vector2::new((j as f32 + self.m_core.m_rng.gen_range(0.0, 1.0)) * inv_sqrt.powf(2.0),
+                                      (i as f32 + self.m_core.m_rng.gen_range(0.0, 1.0)) * inv_sqrt.powf(2.0)));
                 }
             }
         }
@@ -64,7 +64,7 @@ impl Sampler for MultiJittered
         }
 
         if self.m_core.m_map_to_disk { self.m_core.map_sample_to_disk(); }
-        if self.m_core.m_map_to_hemisphere { self.m_core.map_sample_to_hemisphere(1.0); }
+        if self.m_core.m_map_to_hemisphere { self.m_core.map_sample_to_hemisphere(); }
     }
 
     fn get_sample_per_pattern(&self) -> usize
@@ -72,6 +72,11 @@ impl Sampler for MultiJittered
         self.m_core.m_sample_per_pattern
     }
 
+    fn set_seed(&mut self, seed: u32)
+    {
+        self.m_core.set_seed(seed);
+    }
+
     fn set_map_to_disk(&mut self, flag: bool) {
         self.m_core.set_map_to_disk(flag);
     }
@@ -129,7 +134,6 @@ mod MultiJitteredTest
     use std::f32::consts::PI;
 
     const INV_PI: f32 = 1.0 / PI;
-    const INV_GAMMA: f32 = 1.0 / 1.8;
     // This is not proper unit testing. We just write the result to
     // image to inspect whether the sampler is implemented correctly.
     #[test]
@@ -143,11 +147,11 @@ mod MultiJitteredTest
         let mut sampler = MultiJittered::new(32, 2);
         sampler.generate_sample_pattern();
 
-        for sample in sampler.m_core.m_samples_on_square.iter()
+        for sample in sampler.m_core.m_samples_on_square.iter().flatten()
         {
-            let x = sample.x * (width as f32);
-            let y = sample.y * (height as f32);
-            imgwriter.write_pixel(min(x, width -1), min(y, height -1), COLOR_WHITE, INV_GAMMA as f32);
+            let x = (sample.x * (width as f32)) as usize;
+            let y = (sample.y * (height as f32)) as usize;
+            imgwriter.write_pixel(min(x, width -1), min(y, height -1), COLOR_WHITE);
         }
         assert_eq!(sampler.get_sample_per_pattern(), 25);
         imgwriter.output();
@@ -166,19 +170,19 @@ mod MultiJitteredTest
         sampler.generate_sample_pattern();
 
         let radius = (min(width, height) / 2 - 2 ) as f32;
-        for sample in sampler.m_core.m_samples_on_disk.unwrap().iter()
+        for sample in sampler.m_core.m_samples_on_disk.iter().flatten()
         {
             println!("sample on disk");
-            let x = sample.x * radius + radius;
-            let y = sample.y * radius + radius;
-            imgwriter.write_pixel(min(x, width-1), min(y, height-1), COLOR_WHITE, INV_GAMMA as f32);
+            let x = (sample.x * radius + radius) as usize;
+            let y = (sample.y * radius + radius) as usize;
+            imgwriter.write_pixel(min(x, width-1), min(y, height-1), COLOR_WHITE);
         }
 
         for theta in 0..360
         {
             imgwriter.write_pixel((radius * (theta as f32 * INV_PI).cos() + radius) as usize,
                                   (radius * (theta as f32 * INV_PI).sin() + radius) as usize,
-                                    COLOR_GREEN, INV_GAMMA as f32);
+                                    COLOR_GREEN);
         }
         imgwriter.output();
     }
@@ -203,7 +207,7 @@ mod MultiJitteredTest
                 println!("sample on hemisphere");
                 let x = sample.x * radius + radius;
                 let y = sample.y * radius + radius;
-                imgwriter.write_pixel(x as usize, y as usize, COLOR_WHITE, INV_GAMMA as f32);
+                imgwriter.write_pixel(x as usize, y as usize, COLOR_WHITE);
             }
         }
 
@@ -211,8 +215,29 @@ mod MultiJitteredTest
         {
             imgwriter.write_pixel((radius * (theta as f32 * INV_PI).cos() + radius) as usize,
                                   (radius * (theta as f32 * INV_PI).sin() + radius) as usize,
-                                  COLOR_GREEN, INV_GAMMA as f32);
+                                  COLOR_GREEN);
         }
         imgwriter.output();
     }
+
+    #[test]
+    pub fn SeededPatterns()
+    {
+        let mut first = MultiJittered::new(16, 2);
+        first.set_seed(7);
+        first.generate_sample_pattern();
+        let mut second = MultiJittered::new(16, 2);
+        second.set_seed(7);
+        second.generate_sample_pattern();
+        assert_eq!(first.m_core.m_samples_on_square, second.m_core.m_samples_on_square);
+        assert_eq!(first.m_core.m_shuffled_indices, second.m_core.m_shuffled_indices);
+
+        // Generating again gives the same patterns, while another seed doesn't
+        let patterns = first.m_core.m_samples_on_square.clone();
+        first.generate_sample_pattern();
+        assert_eq!(first.m_core.m_samples_on_square, patterns);
+        second.set_seed(8);
+        second.generate_sample_pattern();
+        assert_ne!(second.m_core.m_samples_on_square, patterns);
+    }
 }
\ No newline at end of file
@@ -24,14 +24,14 @@ impl Sampler for NRooks
 {
     fn generate_sample_pattern(&mut self)
     {
-        let mut rng = rand::thread_rng();
+        self.m_core.reset_rng();
 
         for pattern in 0..self.m_core.m_num_pattern
         {
             for i in 0..self.m_core.m_sample_per_pattern
             {
-                self.m_core.m_samples_on_square[pattern][i] = (Vector2::new(i as f32 + rng.gen_range(0.0, 1.0),
-                                                                            i as f32 + rng.gen_range(0.0, 1.0))
+                self.m_core.m_samples_on_square[pattern][i] = (Vector2::new(i as f32 + self.m_core.m_rng.gen_range(0.0, 1.0),
+                                                                            i as f32 + self.m_core.m_rng.gen_range(0.0, 1.0))
                                                 .div_element_wise(self.m_core.m_sample_per_pattern as f32));
 
             }
@@ -46,6 +46,11 @@ impl Sampler for NRooks
         self.m_core.m_sample_per_pattern
     }
 
+    fn set_seed(&mut self, seed: u32)
+    {
+        self.m_core.set_seed(seed);
+    }
+
     fn set_map_to_disk(&mut self, flag: bool) {
         unimplemented!()
     }
@@ -0,0 +1,272 @@
+use cgmath::Vector2;
+use std::cell::RefCell;
+
+use crate::sampler::{hash_u32, hash_combine, to_unit_float};
+use crate::sampler::bluenoise::{get_blue_noise, BLUE_NOISE_TILE_SIZE};
+use crate::sampler::halton::{halton_sample, HALTON_MAX_DIMENSION};
+use crate::sampler::sobol::{sobol_sample, scrambled_sobol_sample, shuffled_sobol_sample, owen_sobol_sample,
+                            SOBOL_MAX_DIMENSION};
+
+/// The sequence a PixelSampler draws its values from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SampleSequence
+{
+    /// Independent uniform values
+    Random,
+    /// Halton sequence, shifted by a different offset in every pixel
+    Halton,
+    /// Sobol sequence, with random digit scrambling in every pixel
+    Sobol,
+    /// Owen-scrambled Sobol sequence, scrambled independently in every pixel
+    OwenSobol,
+    /// Sobol sequence shifted by a blue noise tile, so that the error between neighbouring
+    /// pixels is negatively correlated. Meant for previews with few samples per pixel.
+    BlueNoise,
+}
+
+/// Sampler state of a render thread. A sample is identified by its pixel and its index
+/// within the pixel, and each value drawn for it consumes the next dimension. The values only
+/// depend on those, so a render doesn't depend on which thread computed which pixel.
+#[derive(Clone, Copy, Debug)]
+pub struct PixelSampler
+{
+    pub m_sequence: SampleSequence,
+    pub m_seed: u32,
+    m_pixel: (u32, u32),
+    m_pixel_seed: u32,
+    m_sample_index: u32,
+    m_dimension: u32,
+}
+
+impl PixelSampler
+{
+    pub fn new(sequence: SampleSequence, seed: u32) -> PixelSampler
+    {
+        PixelSampler
+        {
+            m_sequence: sequence,
+            m_seed: seed,
+            m_pixel: (0, 0),
+            m_pixel_seed: hash_u32(seed),
+            m_sample_index: 0,
+            m_dimension: 0,
+        }
+    }
+
+    /// Restart at the first dimension of the "sample_index"-th sample of pixel (x, y)
+    pub fn start_pixel_sample(&mut self, x: u32, y: u32, sample_index: u32)
+    {
+        self.m_pixel = (x, y);
+        self.m_pixel_seed = hash_u32(hash_combine(hash_combine(hash_u32(self.m_seed), x), y));
+        self.m_sample_index = sample_index;
+        self.m_dimension = 0;
+    }
+
+    pub fn get_sample_index(&self) -> u32
+    {
+        self.m_sample_index
+    }
+
+    pub fn get_dimension(&self) -> u32
+    {
+        self.m_dimension
+    }
+
+    pub fn get_1d(&mut self) -> f32
+    {
+        let value = self.get_value(self.m_dimension);
+        self.m_dimension += 1;
+        value
+    }
+
+    pub fn get_2d(&mut self) -> Vector2<f32>
+    {
+        let x = self.get_1d();
+        let y = self.get_1d();
+        Vector2::new(x, y)
+    }
+
+    fn get_value(&self, dimension: u32) -> f32
+    {
+        let dimension_seed = hash_combine(self.m_pixel_seed, dimension);
+        match self.m_sequence
+        {
+            SampleSequence::Random => to_unit_float(hash_u32(hash_combine(dimension_seed, self.m_sample_index))),
+            SampleSequence::Halton if dimension as usize >= HALTON_MAX_DIMENSION =>
+                self.get_reused_sobol_value(dimension, self.m_pixel_seed),
+            SampleSequence::Halton =>
+                {
+                    let shift = to_unit_float(hash_u32(dimension_seed));
+                    let value = halton_sample(self.m_sample_index as u64, dimension as usize) + shift;
+                    (value - value.floor()).min(1.0 - f32::EPSILON)
+                }
+            SampleSequence::Sobol if dimension as usize >= SOBOL_MAX_DIMENSION =>
+                self.get_reused_sobol_value(dimension, self.m_pixel_seed),
+            SampleSequence::Sobol =>
+                scrambled_sobol_sample(self.m_sample_index, dimension as usize, hash_u32(dimension_seed)),
+            SampleSequence::OwenSobol => self.get_reused_sobol_value(dimension, self.m_pixel_seed),
+            SampleSequence::BlueNoise =>
+                {
+                    // The same sequence in every pixel, shifted by the blue noise tile.
+                    // Each dimension looks up the tile at another offset to stay decorrelated.
+                    let offset = hash_u32(hash_combine(hash_u32(self.m_seed), dimension));
+                    let size = BLUE_NOISE_TILE_SIZE as u32;
+                    let shift = get_blue_noise(self.m_pixel.0 + offset % size, self.m_pixel.1 + (offset >> 16) % size);
+                    // Past the last generator matrix, every run of dimensions shuffles the samples
+                    // another way, the same in every pixel
+                    let run = dimension / SOBOL_MAX_DIMENSION as u32;
+                    let value = if run == 0
+                    {
+                        sobol_sample(self.m_sample_index, dimension as usize)
+                    }
+                    else
+                    {
+                        let run_seed = hash_combine(hash_u32(self.m_seed), run);
+                        shuffled_sobol_sample(self.m_sample_index, dimension as usize % SOBOL_MAX_DIMENSION, run_seed)
+                    } + shift;
+                    (value - value.floor()).min(1.0 - f32::EPSILON)
+                }
+        }
+    }
+
+    /// Owen-scrambled Sobol value of any dimension. Past the last generator matrix, every run
+    /// of SOBOL_MAX_DIMENSION dimensions reuses the matrices with its own seed, which shuffles
+    /// the order of the samples as well: a dimension isn't a fixed function of the one a run before.
+    fn get_reused_sobol_value(&self, dimension: u32, seed: u32) -> f32
+    {
+        let run_seed = hash_combine(seed, dimension / SOBOL_MAX_DIMENSION as u32);
+        owen_sobol_sample(self.m_sample_index, dimension as usize % SOBOL_MAX_DIMENSION, run_seed)
+    }
+}
+
+thread_local!
+{
+    static THREAD_SAMPLER: RefCell<PixelSampler> = RefCell::new(PixelSampler::new(SampleSequence::Random, 0));
+}
+
+/// Replace the sampler of the current thread
+pub fn set_thread_sampler(sampler: PixelSampler)
+{
+    THREAD_SAMPLER.with(|thread_sampler| *thread_sampler.borrow_mut() = sampler);
+}
+
+pub fn get_thread_sampler() -> PixelSampler
+{
+    THREAD_SAMPLER.with(|thread_sampler| *thread_sampler.borrow())
+}
+
+pub fn start_pixel_sample(x: u32, y: u32, sample_index: u32)
+{
+    THREAD_SAMPLER.with(|thread_sampler| thread_sampler.borrow_mut().start_pixel_sample(x, y, sample_index));
+}
+
+/// Next dimension of the current sample of this thread
+pub fn get_1d() -> f32
+{
+    THREAD_SAMPLER.with(|thread_sampler| thread_sampler.borrow_mut().get_1d())
+}
+
+/// Next two dimensions of the current sample of this thread
+pub fn get_2d() -> Vector2<f32>
+{
+    THREAD_SAMPLER.with(|thread_sampler| thread_sampler.borrow_mut().get_2d())
+}
+
+#[cfg(test)]
+mod PixelSamplerTest
+{
+    use super::*;
+
+    const SEQUENCES: [SampleSequence; 5] = [SampleSequence::Random, SampleSequence::Halton,
+                                            SampleSequence::Sobol, SampleSequence::OwenSobol,
+                                            SampleSequence::BlueNoise];
+
+    #[test]
+    fn values_only_depend_on_pixel_sample_and_dimension()
+    {
+        for sequence in SEQUENCES.iter()
+        {
+            let mut sampler = PixelSampler::new(*sequence, 3);
+            sampler.start_pixel_sample(5, 7, 2);
+            let first: Vec<f32> = (0..8).map(|_| sampler.get_1d()).collect();
+            assert_eq!(sampler.get_dimension(), 8);
+
+            sampler.start_pixel_sample(1, 1, 0);
+            sampler.get_2d();
+            sampler.start_pixel_sample(5, 7, 2);
+            let second: Vec<f32> = (0..8).map(|_| sampler.get_1d()).collect();
+            assert_eq!(first, second);
+
+            sampler.start_pixel_sample(6, 7, 2);
+            let other_pixel: Vec<f32> = (0..8).map(|_| sampler.get_1d()).collect();
+            assert_ne!(first, other_pixel);
+            assert!(first.iter().all(|value| *value >= 0.0 && *value < 1.0));
+        }
+    }
+
+    #[test]
+    fn pixel_samples_are_stratified()
+    {
+        // The low discrepancy sequences cover the pixel evenly in every dimension
+        for sequence in SEQUENCES[1..].iter()
+        {
+            let mut sampler = PixelSampler::new(*sequence, 11);
+            for dimension in (0..4).chain(SOBOL_MAX_DIMENSION..SOBOL_MAX_DIMENSION + 4)
+            {
+                let mut strata = vec![false; 16];
+                for sample_index in 0..16
+                {
+                    sampler.start_pixel_sample(4, 2, sample_index);
+                    let value = (0..=dimension).map(|_| sampler.get_1d()).last().unwrap();
+                    let stratum = (value * 16.0) as usize;
+                    if *sequence != SampleSequence::Halton || dimension == 0
+                    {
+                        assert!(!strata[stratum], "{:?} isn't stratified in dimension {}", sequence, dimension);
+                    }
+                    strata[stratum] = true;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dimensions_past_the_tables_are_decorrelated()
+    {
+        // Neither a shift nor a flip of bits turns a dimension into one a table length later
+        for sequence in SEQUENCES[1..].iter()
+        {
+            let table_length = if *sequence == SampleSequence::Halton { HALTON_MAX_DIMENSION } else { SOBOL_MAX_DIMENSION };
+            let mut sampler = PixelSampler::new(*sequence, 5);
+            let mut shifts = Vec::new();
+            let mut flips = Vec::new();
+            for sample_index in 0..32
+            {
+                sampler.start_pixel_sample(3, 1, sample_index);
+                let values: Vec<f32> = (0..table_length + 3).map(|_| sampler.get_1d()).collect();
+                let (first, reused) = (values[2], values[table_length + 2]);
+                assert!(reused >= 0.0 && reused < 1.0);
+                shifts.push(((reused - first).rem_euclid(1.0) * 4096.0) as u32);
+                flips.push(((first * 4096.0) as u32) ^ ((reused * 4096.0) as u32));
+            }
+            shifts.dedup();
+            flips.sort();
+            flips.dedup();
+            assert!(shifts.len() > 1 && flips.len() > 1, "{:?} reuses its dimensions", sequence);
+        }
+    }
+
+    #[test]
+    fn thread_samplers_are_independent()
+    {
+        set_thread_sampler(PixelSampler::new(SampleSequence::Sobol, 1));
+        start_pixel_sample(0, 0, 3);
+        let value = get_1d();
+
+        let other_thread = std::thread::spawn(|| get_thread_sampler().m_sequence).join().unwrap();
+        assert_eq!(other_thread, SampleSequence::Random);
+        assert_eq!(get_thread_sampler().get_dimension(), 1);
+
+        start_pixel_sample(0, 0, 3);
+        assert_eq!(get_1d(), value);
+    }
+}
@@ -0,0 +1,397 @@
+use cgmath::{Vector2, Vector3};
+
+use crate::sampler::{Sampler, SamplerCore, hash_u32, hash_combine, to_unit_float};
+
+/// Primitive polynomials and initial direction numbers from Joe & Kuo, for the dimensions
+/// following the first one: (degree, coefficients, initial direction numbers)
+const SOBOL_POLYNOMIALS: [(u32, u32, [u32; 6]); 15] = [
+    (1, 0, [1, 0, 0, 0, 0, 0]),
+    (2, 1, [1, 3, 0, 0, 0, 0]),
+    (3, 1, [1, 3, 1, 0, 0, 0]),
+    (3, 2, [1, 1, 1, 0, 0, 0]),
+    (4, 1, [1, 1, 3, 3, 0, 0]),
+    (4, 4, [1, 3, 5, 13, 0, 0]),
+    (5, 2, [1, 1, 5, 5, 17, 0]),
+    (5, 4, [1, 1, 5, 5, 5, 0]),
+    (5, 7, [1, 1, 7, 11, 19, 0]),
+    (5, 11, [1, 1, 5, 1, 1, 0]),
+    (5, 13, [1, 1, 1, 3, 11, 0]),
+    (5, 14, [1, 3, 5, 5, 31, 0]),
+    (6, 1, [1, 3, 3, 9, 7, 49]),
+    (6, 13, [1, 1, 1, 15, 21, 21]),
+    (6, 16, [1, 3, 1, 13, 27, 49]),
+];
+
+pub const SOBOL_MAX_DIMENSION: usize = SOBOL_POLYNOMIALS.len() + 1;
+
+/// Generator matrices, one column per bit of the sample index
+static SOBOL_MATRICES: [[u32; 32]; SOBOL_MAX_DIMENSION] = compute_sobol_matrices();
+
+const fn compute_sobol_matrices() -> [[u32; 32]; SOBOL_MAX_DIMENSION]
+{
+    let mut matrices = [[0u32; 32]; SOBOL_MAX_DIMENSION];
+
+    // The first dimension is the van der Corput sequence in base 2
+    let mut i = 0;
+    while i < 32
+    {
+        matrices[0][i] = 1 << (31 - i);
+        i += 1;
+    }
+
+    let mut dimension = 1;
+    while dimension < SOBOL_MAX_DIMENSION
+    {
+        let (degree, coefficients, initial) = SOBOL_POLYNOMIALS[dimension - 1];
+        let degree = degree as usize;
+        let mut i = 0;
+        while i < 32
+        {
+            if i < degree
+            {
+                matrices[dimension][i] = initial[i] << (31 - i);
+            }
+            else
+            {
+                let mut v = matrices[dimension][i - degree];
+                v ^= v >> degree;
+                let mut k = 1;
+                while k < degree
+                {
+                    if (coefficients >> (degree - 1 - k)) & 1 == 1
+                    {
+                        v ^= matrices[dimension][i - k];
+                    }
+                    k += 1;
+                }
+                matrices[dimension][i] = v;
+            }
+            i += 1;
+        }
+        dimension += 1;
+    }
+    matrices
+}
+
+/// The "index"-th point of the Sobol sequence in "dimension", as 32 bits of fixed point.
+/// The dimension must be below SOBOL_MAX_DIMENSION.
+fn sobol_bits(index: u32, dimension: usize) -> u32
+{
+    let matrix = &SOBOL_MATRICES[dimension];
+    let mut result = 0;
+    let mut index = index;
+    let mut bit = 0;
+    while index != 0
+    {
+        if index & 1 == 1 { result ^= matrix[bit]; }
+        index >>= 1;
+        bit += 1;
+    }
+    result
+}
+
+pub fn sobol_sample(index: u32, dimension: usize) -> f32
+{
+    to_unit_float(sobol_bits(index, dimension))
+}
+
+/// Sobol sequence with random digit scrambling: the bits of every point are flipped by
+/// "scramble", which moves the whole net around while keeping its stratification
+pub fn scrambled_sobol_sample(index: u32, dimension: usize, scramble: u32) -> f32
+{
+    to_unit_float(sobol_bits(index, dimension) ^ scramble)
+}
+
+/// Hash based nested uniform scrambling (Laine & Karras, Burley): every bit is flipped
+/// depending only on the bits above it, which keeps the stratification of the sequence
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32
+{
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+/// Sobol sequence in another order: the first 2^k points are still the first 2^k points of
+/// the sequence, shuffled depending on "seed"
+pub fn shuffled_sobol_sample(index: u32, dimension: usize, seed: u32) -> f32
+{
+    sobol_sample(nested_uniform_scramble(index, hash_u32(seed)), dimension)
+}
+
+/// Owen-scrambled Sobol sequence. The order of the points is scrambled as well, so that
+/// sequences with different seeds are decorrelated even in the same dimension.
+pub fn owen_sobol_sample(index: u32, dimension: usize, seed: u32) -> f32
+{
+    let index = nested_uniform_scramble(index, hash_u32(seed));
+    let bits = sobol_bits(index, dimension);
+    to_unit_float(nested_uniform_scramble(bits, hash_u32(hash_combine(seed, dimension as u32))))
+}
+
+/// Sampler based on the Sobol sequence. The patterns are consecutive runs of the sequence;
+/// with power of two sample counts, every pattern is a (0, 2)-net.
+#[derive(Debug)]
+pub struct Sobol
+{
+    m_core: SamplerCore,
+}
+
+impl Sobol
+{
+    pub fn new(sample_per_pattern: usize, num_pattern: usize) -> Sobol
+    {
+        Sobol
+        {
+            m_core: SamplerCore::new(sample_per_pattern, num_pattern),
+        }
+    }
+}
+
+impl Sampler for Sobol
+{
+    fn generate_sample_pattern(&mut self)
+    {
+        let sample_per_pattern = self.m_core.m_sample_per_pattern;
+
+        for (pattern_index, pattern) in self.m_core.m_samples_on_square.iter_mut().enumerate()
+        {
+            for (i, sample) in pattern.iter_mut().enumerate()
+            {
+                let index = (pattern_index * sample_per_pattern + i) as u32;
+                *sample = Vector2::new(sobol_sample(index, 0), sobol_sample(index, 1));
+            }
+        }
+
+        if self.m_core.m_map_to_disk { self.m_core.map_sample_to_disk(); }
+        if self.m_core.m_map_to_hemisphere { self.m_core.map_sample_to_hemisphere(); }
+    }
+
+    fn get_sample_per_pattern(&self) -> usize
+    {
+        self.m_core.m_sample_per_pattern
+    }
+
+    fn set_seed(&mut self, seed: u32)
+    {
+        self.m_core.set_seed(seed);
+    }
+
+    fn set_map_to_disk(&mut self, flag: bool) {
+        self.m_core.set_map_to_disk(flag);
+    }
+
+    fn set_map_to_hemisphere(&mut self, flag: bool, e: f32) {
+        self.m_core.set_map_to_hemisphere(flag, e);
+    }
+
+    fn get_unit_square_pattern(&self) -> &Vec<Vector2<f32>>
+    {
+        self.m_core.get_unit_square_pattern()
+    }
+
+    fn get_disk_pattern(&self) -> &Vec<Vector2<f32>>
+    {
+        match self.m_core.get_disk_pattern()
+        {
+            Ok(sample) => sample,
+            _ => panic!("The Sobol Sampler isn't set to generate samples on disk")
+        }
+    }
+
+    fn get_disk_sample(&self) -> Vector2<f32>
+    {
+        self.m_core.get_disk_sample()
+    }
+
+    fn get_hemisphere_pattern(&self) -> &Vec<Vector3<f32>>
+    {
+        match self.m_core.get_hemisphere_pattern()
+        {
+            Ok(sample) => sample,
+            _ => panic!("The Sobol Sampler isn't set to generate samples on hemisphere")
+        }
+    }
+
+    fn get_hemisphere_sample(&self) -> Vector3<f32>
+    {
+        self.m_core.get_hemisphere_sample()
+    }
+}
+
+/// Sampler based on the Owen-scrambled Sobol sequence. Every pattern is scrambled with its
+/// own seed, so each one is an independent net instead of a run of the same sequence.
+#[derive(Debug)]
+pub struct OwenSobol
+{
+    m_core: SamplerCore,
+    m_seed: u32,
+}
+
+impl OwenSobol
+{
+    pub fn new(sample_per_pattern: usize, num_pattern: usize, seed: u32) -> OwenSobol
+    {
+        OwenSobol
+        {
+            m_core: SamplerCore::new(sample_per_pattern, num_pattern),
+            m_seed: seed,
+        }
+    }
+}
+
+impl Sampler for OwenSobol
+{
+    fn generate_sample_pattern(&mut self)
+    {
+        let seed = self.m_seed;
+
+        for (pattern_index, pattern) in self.m_core.m_samples_on_square.iter_mut().enumerate()
+        {
+            let pattern_seed = hash_combine(seed, pattern_index as u32);
+            for (i, sample) in pattern.iter_mut().enumerate()
+            {
+                *sample = Vector2::new(owen_sobol_sample(i as u32, 0, pattern_seed),
+                                       owen_sobol_sample(i as u32, 1, pattern_seed));
+            }
+        }
+
+        if self.m_core.m_map_to_disk { self.m_core.map_sample_to_disk(); }
+        if self.m_core.m_map_to_hemisphere { self.m_core.map_sample_to_hemisphere(); }
+    }
+
+    fn get_sample_per_pattern(&self) -> usize
+    {
+        self.m_core.m_sample_per_pattern
+    }
+
+    fn set_seed(&mut self, seed: u32)
+    {
+        self.m_seed = seed;
+        self.m_core.set_seed(seed);
+    }
+
+    fn set_map_to_disk(&mut self, flag: bool) {
+        self.m_core.set_map_to_disk(flag);
+    }
+
+    fn set_map_to_hemisphere(&mut self, flag: bool, e: f32) {
+        self.m_core.set_map_to_hemisphere(flag, e);
+    }
+
+    fn get_unit_square_pattern(&self) -> &Vec<Vector2<f32>>
+    {
+        self.m_core.get_unit_square_pattern()
+    }
+
+    fn get_disk_pattern(&self) -> &Vec<Vector2<f32>>
+    {
+        match self.m_core.get_disk_pattern()
+        {
+            Ok(sample) => sample,
+            _ => panic!("The OwenSobol Sampler isn't set to generate samples on disk")
+        }
+    }
+
+    fn get_disk_sample(&self) -> Vector2<f32>
+    {
+        self.m_core.get_disk_sample()
+    }
+
+    fn get_hemisphere_pattern(&self) -> &Vec<Vector3<f32>>
+    {
+        match self.m_core.get_hemisphere_pattern()
+        {
+            Ok(sample) => sample,
+            _ => panic!("The OwenSobol Sampler isn't set to generate samples on hemisphere")
+        }
+    }
+
+    fn get_hemisphere_sample(&self) -> Vector3<f32>
+    {
+        self.m_core.get_hemisphere_sample()
+    }
+}
+
+#[cfg(test)]
+mod SobolTest
+{
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// Whether the first "count" points of the two dimensions form a (0, 2)-net: every
+    /// elementary interval of area 1/count holds exactly one point
+    fn is_02_net<F>(count: usize, sample: F) -> bool
+        where F: Fn(u32) -> (f32, f32)
+    {
+        let log_count = count.trailing_zeros();
+        (0..=log_count).all(|log_x| {
+            let (nx, ny) = (1 << log_x, 1 << (log_count - log_x));
+            let mut strata = vec![0; count];
+            for index in 0..count as u32
+            {
+                let (x, y) = sample(index);
+                strata[(x * nx as f32) as usize + (y * ny as f32) as usize * nx] += 1;
+            }
+            strata.iter().all(|n| *n == 1)
+        })
+    }
+
+    #[test]
+    fn first_dimensions()
+    {
+        let expected = [(0.0, 0.0), (0.5, 0.5), (0.25, 0.75), (0.75, 0.25), (0.125, 0.625)];
+        for (index, (x, y)) in expected.iter().enumerate()
+        {
+            assert_relative_eq!(sobol_sample(index as u32, 0), *x);
+            assert_relative_eq!(sobol_sample(index as u32, 1), *y);
+        }
+    }
+
+    #[test]
+    fn every_dimension_is_stratified()
+    {
+        for dimension in 0..SOBOL_MAX_DIMENSION
+        {
+            let mut strata = vec![false; 256];
+            for index in 0..256
+            {
+                let stratum = (sobol_sample(index, dimension) * 256.0) as usize;
+                assert!(!strata[stratum], "dimension {} isn't stratified", dimension);
+                strata[stratum] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn first_two_dimensions_are_02_net()
+    {
+        assert!(is_02_net(256, |i| (sobol_sample(i, 0), sobol_sample(i, 1))));
+    }
+
+    #[test]
+    fn owen_scrambling_keeps_net_property()
+    {
+        for seed in 0..4
+        {
+            assert!(is_02_net(64, |i| (owen_sobol_sample(i, 0, seed), owen_sobol_sample(i, 1, seed))));
+        }
+        assert_ne!(owen_sobol_sample(3, 0, 0), owen_sobol_sample(3, 0, 1));
+    }
+
+    #[test]
+    fn patterns_are_in_unit_square()
+    {
+        let mut sampler = OwenSobol::new(16, 4, 7);
+        sampler.generate_sample_pattern();
+        for pattern in sampler.m_core.m_samples_on_square.iter()
+        {
+            for sample in pattern.iter()
+            {
+                assert!(sample.x >= 0.0 && sample.x < 1.0 && sample.y >= 0.0 && sample.y < 1.0);
+            }
+        }
+    }
+}
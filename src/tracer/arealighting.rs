@@ -2,9 +2,11 @@ use std::f32;
 use std::sync::Arc;
 
 use crate::ray::Ray;
+use crate::render::aov::{shade_and_record, record_miss};
 use crate::utils::color::{Colorf};
 use crate::world::world::World;
 use crate::tracer::{Tracer, HUGE_VAL_FOR_TIME};
+use crate::utils::scenehash::SceneHasher;
 
 pub struct AreaLighting
 {}
@@ -26,8 +28,14 @@ impl Tracer for AreaLighting
         {
             sr.m_ray = *ray;
             let mat_clone = sr.m_material.clone().unwrap();
-            return mat_clone.area_light_shade(&mut sr);
+            return shade_and_record(&mut sr, depth, |sr| mat_clone.area_light_shade(sr));
         }
+        record_miss(depth, worldptr.m_backgroundcolor);
         worldptr.m_backgroundcolor
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("area_lighting");
+    }
 }
\ No newline at end of file
@@ -8,6 +8,7 @@ use crate::ray::Ray;
 use crate::utils::color::Colorf;
 use crate::world::world::World;
 use crate::utils::colorconstant::COLOR_BLACK;
+use crate::utils::scenehash::SceneHasher;
 
 const HUGE_VAL_FOR_TIME: f32 = 1e9;
 
@@ -16,6 +17,7 @@ pub trait Tracer: Send + Sync
     fn trace_ray(&self, worldptr: Arc<World>, ray: &Ray, depth: u16) -> Colorf;
     fn trace_ray_with_time(&self, worldptr: Arc<World>, ray: &Ray, time: &mut f32, depth: u16) -> Colorf
     { COLOR_BLACK }
+    fn hash_scene(&self, hasher: &mut SceneHasher);
 }
 
 impl std::fmt::Debug for dyn Tracer
@@ -2,9 +2,11 @@ use std::f32;
 use std::sync::Arc;
 
 use crate::ray::Ray;
+use crate::render::aov::{shade_and_record, record_miss};
 use crate::utils::color::{Colorf};
 use crate::world::world::World;
 use crate::tracer::{Tracer};
+use crate::utils::scenehash::SceneHasher;
 
 pub struct RayCast
 {}
@@ -25,9 +27,10 @@ impl Tracer for RayCast
             sr.m_ray = *ray;
             if let material = sr.m_material.clone().unwrap()
             {
-                return material.shade(&mut sr);
+                return shade_and_record(&mut sr, depth, |sr| material.shade(sr));
             }
         }
+        record_miss(depth, worldptr.as_ref().m_backgroundcolor);
         worldptr.as_ref().m_backgroundcolor
     }
 
@@ -35,4 +38,9 @@ impl Tracer for RayCast
     {
         unimplemented!()
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("raycast");
+    }
 }
\ No newline at end of file
@@ -2,10 +2,12 @@ use std::f32;
 use std::sync::Arc;
 
 use crate::ray::Ray;
+use crate::render::aov::{shade_and_record, record_miss};
 use crate::utils::color::{Colorf};
 use crate::world::world::World;
 use crate::tracer::{Tracer, HUGE_VAL_FOR_TIME};
 use crate::utils::colorconstant::COLOR_BLACK;
+use crate::utils::scenehash::SceneHasher;
 
 pub struct Whitted
 {
@@ -34,9 +36,10 @@ impl Tracer for Whitted
                 sr.m_ray = *ray;
                 if let material = sr.m_material.clone().unwrap()
                 {
-                    return material.shade(&mut sr);
+                    return shade_and_record(&mut sr, depth, |sr| material.shade(sr));
                 }
             }
+            record_miss(depth, worldptr.as_ref().m_backgroundcolor);
             worldptr.as_ref().m_backgroundcolor
         }
     }
@@ -56,15 +59,21 @@ impl Tracer for Whitted
                 sr.m_ray = *ray;
                 *time = sr.m_time;
                 sr.m_material.clone()
-                    .map(|material|  material.shade(&mut sr)).unwrap()
+                    .map(|material| shade_and_record(&mut sr, depth, |sr| material.shade(sr))).unwrap()
             }
             else
             {
                 *time = HUGE_VAL_FOR_TIME;
+                record_miss(depth, worldptr.as_ref().m_backgroundcolor);
                 worldptr.as_ref().m_backgroundcolor
             }
         }
     }
+
+    fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_str("whitted");
+    }
 }
 /*
 #[cfg(test)]
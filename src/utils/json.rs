@@ -0,0 +1,410 @@
+use std::fmt;
+
+/// A JSON document, as read from scene files or exchanged with the render server
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue
+{
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// Members in the order they were written
+    Object(Vec<(String, JsonValue)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonError
+{
+    pub m_position: usize, // Byte offset of the error in the text
+    pub m_message: String,
+}
+
+impl std::error::Error for JsonError {}
+
+impl fmt::Display for JsonError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid JSON at byte {}: {}", self.m_position, self.m_message)
+    }
+}
+
+impl JsonValue
+{
+    pub fn parse(text: &str) -> Result<JsonValue, JsonError>
+    {
+        let mut parser = JsonParser{ m_text: text.as_bytes(), m_position: 0, m_depth: 0 };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.m_position < text.len()
+        {
+            return Err(parser.error("Text after the end of the document"));
+        }
+        Ok(value)
+    }
+
+    /// Member "key" of an object, None for other values
+    pub fn get(&self, key: &str) -> Option<&JsonValue>
+    {
+        match self
+        {
+            JsonValue::Object(members) => members.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64>
+    {
+        match self
+        {
+            JsonValue::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_f32(&self) -> Option<f32>
+    {
+        self.as_f64().map(|number| number as f32)
+    }
+
+    pub fn as_bool(&self) -> Option<bool>
+    {
+        match self
+        {
+            JsonValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str>
+    {
+        match self
+        {
+            JsonValue::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]>
+    {
+        match self
+        {
+            JsonValue::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&[(String, JsonValue)]>
+    {
+        match self
+        {
+            JsonValue::Object(members) => Some(members),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for JsonValue
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            JsonValue::Null => write!(f, "null"),
+            JsonValue::Bool(value) => write!(f, "{}", value),
+            // JSON has no infinities nor NaN
+            JsonValue::Number(number) if !number.is_finite() => write!(f, "null"),
+            JsonValue::Number(number) => write!(f, "{}", number),
+            JsonValue::String(string) => write_string(f, string),
+            JsonValue::Array(values) =>
+                {
+                    write!(f, "[")?;
+                    for (index, value) in values.iter().enumerate()
+                    {
+                        if index > 0 { write!(f, ",")?; }
+                        write!(f, "{}", value)?;
+                    }
+                    write!(f, "]")
+                }
+            JsonValue::Object(members) =>
+                {
+                    write!(f, "{{")?;
+                    for (index, (name, value)) in members.iter().enumerate()
+                    {
+                        if index > 0 { write!(f, ",")?; }
+                        write_string(f, name)?;
+                        write!(f, ":{}", value)?;
+                    }
+                    write!(f, "}}")
+                }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result
+{
+    write!(f, "\"")?;
+    for character in string.chars()
+    {
+        match character
+        {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Deepest nesting of arrays and objects a document can have, so that parsing doesn't overflow the stack
+pub const MAX_JSON_DEPTH: usize = 128;
+
+struct JsonParser<'a>
+{
+    m_text: &'a [u8],
+    m_position: usize,
+    m_depth: usize, // Arrays and objects the parser is in
+}
+
+impl JsonParser<'_>
+{
+    fn error(&self, message: &str) -> JsonError
+    {
+        JsonError{ m_position: self.m_position, m_message: message.to_string() }
+    }
+
+    fn skip_whitespace(&mut self)
+    {
+        while self.m_position < self.m_text.len() && self.m_text[self.m_position].is_ascii_whitespace()
+        {
+            self.m_position += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8>
+    {
+        self.m_text.get(self.m_position).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), JsonError>
+    {
+        self.skip_whitespace();
+        if self.peek() != Some(byte)
+        {
+            return Err(self.error(&format!("Expected '{}'", byte as char)));
+        }
+        self.m_position += 1;
+        Ok(())
+    }
+
+    fn parse_keyword(&mut self, keyword: &str, value: JsonValue) -> Result<JsonValue, JsonError>
+    {
+        if !self.m_text[self.m_position..].starts_with(keyword.as_bytes())
+        {
+            return Err(self.error("Unknown value"));
+        }
+        self.m_position += keyword.len();
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, JsonError>
+    {
+        self.skip_whitespace();
+        match self.peek()
+        {
+            None => Err(self.error("Unexpected end of the document")),
+            Some(b'n') => self.parse_keyword("null", JsonValue::Null),
+            Some(b't') => self.parse_keyword("true", JsonValue::Bool(true)),
+            Some(b'f') => self.parse_keyword("false", JsonValue::Bool(false)),
+            Some(b'"') => Ok(JsonValue::String(self.parse_string()?)),
+            Some(b'[') | Some(b'{') =>
+                {
+                    if self.m_depth == MAX_JSON_DEPTH
+                    {
+                        return Err(self.error("Arrays and objects nested too deeply"));
+                    }
+                    self.m_depth += 1;
+                    let value = if self.peek() == Some(b'[') { self.parse_array() } else { self.parse_object() };
+                    self.m_depth -= 1;
+                    value
+                }
+            Some(_) => self.parse_number(),
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, JsonError>
+    {
+        self.m_position += 1;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']')
+        {
+            self.m_position += 1;
+            return Ok(JsonValue::Array(values));
+        }
+        loop
+        {
+            values.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.peek()
+            {
+                Some(b',') => self.m_position += 1,
+                Some(b']') =>
+                    {
+                        self.m_position += 1;
+                        return Ok(JsonValue::Array(values));
+                    }
+                _ => return Err(self.error("Expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, JsonError>
+    {
+        self.m_position += 1;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}')
+        {
+            self.m_position += 1;
+            return Ok(JsonValue::Object(members));
+        }
+        loop
+        {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"')
+            {
+                return Err(self.error("Expected the name of a member"));
+            }
+            let name = self.parse_string()?;
+            self.expect(b':')?;
+            members.push((name, self.parse_value()?));
+            self.skip_whitespace();
+            match self.peek()
+            {
+                Some(b',') => self.m_position += 1,
+                Some(b'}') =>
+                    {
+                        self.m_position += 1;
+                        return Ok(JsonValue::Object(members));
+                    }
+                _ => return Err(self.error("Expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<JsonValue, JsonError>
+    {
+        let start = self.m_position;
+        while let Some(byte) = self.peek()
+        {
+            if !(byte.is_ascii_digit() || byte == b'-' || byte == b'+' || byte == b'.' || byte == b'e' || byte == b'E')
+            {
+                break;
+            }
+            self.m_position += 1;
+        }
+        std::str::from_utf8(&self.m_text[start..self.m_position]).ok()
+            .and_then(|number| number.parse::<f64>().ok())
+            .map(JsonValue::Number)
+            .ok_or(JsonError{ m_position: start, m_message: "Invalid number".to_string() })
+    }
+
+    fn parse_string(&mut self) -> Result<String, JsonError>
+    {
+        // Past the opening quote
+        self.m_position += 1;
+        let mut bytes = Vec::new();
+        loop
+        {
+            match self.peek()
+            {
+                None => return Err(self.error("Unterminated string")),
+                Some(b'"') =>
+                    {
+                        self.m_position += 1;
+                        return String::from_utf8(bytes).map_err(|_| self.error("Invalid UTF-8 in string"));
+                    }
+                Some(b'\\') =>
+                    {
+                        self.m_position += 1;
+                        let escaped = match self.peek()
+                        {
+                            Some(b'"') => '"',
+                            Some(b'\\') => '\\',
+                            Some(b'/') => '/',
+                            Some(b'b') => '\u{8}',
+                            Some(b'f') => '\u{c}',
+                            Some(b'n') => '\n',
+                            Some(b'r') => '\r',
+                            Some(b't') => '\t',
+                            Some(b'u') =>
+                                {
+                                    let code = self.m_text.get(self.m_position + 1..self.m_position + 5)
+                                        .and_then(|digits| std::str::from_utf8(digits).ok())
+                                        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                                        .ok_or_else(|| self.error("Invalid unicode escape"))?;
+                                    self.m_position += 4;
+                                    // Lone surrogates, e.g. halves of a pair, aren't supported
+                                    std::char::from_u32(code).unwrap_or('\u{fffd}')
+                                }
+                            _ => return Err(self.error("Invalid escape")),
+                        };
+                        self.m_position += 1;
+                        let mut encoded = [0u8; 4];
+                        bytes.extend_from_slice(escaped.encode_utf8(&mut encoded).as_bytes());
+                    }
+                Some(byte) =>
+                    {
+                        bytes.push(byte);
+                        self.m_position += 1;
+                    }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod JsonTest
+{
+    use super::*;
+
+    #[test]
+    fn parse_and_write_back()
+    {
+        let text = r#" { "name": "sphere \"A\"\n", "radius": -2.5e1, "center": [0, 1.5, 3],
+                         "visible": true, "material": null, "tags": {}, "empty": [] } "#;
+        let value = JsonValue::parse(text).unwrap();
+        assert_eq!(value.get("name").unwrap().as_str(), Some("sphere \"A\"\n"));
+        assert_eq!(value.get("radius").unwrap().as_f32(), Some(-25.0));
+        assert_eq!(value.get("center").unwrap().as_array().unwrap().len(), 3);
+        assert_eq!(value.get("visible").unwrap().as_bool(), Some(true));
+        assert_eq!(value.get("material"), Some(&JsonValue::Null));
+        assert!(value.get("missing").is_none());
+
+        let written = value.to_string();
+        assert_eq!(written, r#"{"name":"sphere \"A\"\n","radius":-25,"center":[0,1.5,3],"visible":true,"material":null,"tags":{},"empty":[]}"#);
+        assert_eq!(JsonValue::parse(&written).unwrap(), value);
+        assert_eq!(JsonValue::parse(r#""café""#).unwrap().as_str(), Some("café"));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem()
+    {
+        assert_eq!(JsonValue::parse("[1, 2").unwrap_err().m_position, 5);
+        assert_eq!(JsonValue::parse("{\"a\" 1}").unwrap_err().m_position, 5);
+        assert!(JsonValue::parse("[1] 2").is_err());
+        assert!(JsonValue::parse("tru").is_err());
+        assert!(JsonValue::parse("\"open").is_err());
+
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(JsonValue::parse(&nested(MAX_JSON_DEPTH)).is_ok());
+        let error = JsonValue::parse(&nested(MAX_JSON_DEPTH + 1)).unwrap_err();
+        assert_eq!(error.m_position, MAX_JSON_DEPTH);
+        assert!(JsonValue::parse(&"{\"a\":".repeat(100_000)).is_err());
+    }
+}
@@ -1,4 +1,6 @@
 pub mod colorconstant;
 pub mod color;
 pub mod computequeue;
-pub mod multithread;
\ No newline at end of file
+pub mod json;
+pub mod multithread;
+pub mod scenehash;
\ No newline at end of file
@@ -1,5 +1,6 @@
 use std::iter;
-use std::time::SystemTime;
+use std::ops::Range;
+use std::time::Instant;
 
 use scoped_threadpool::Pool;
 use crate::world::world::World;
@@ -7,15 +8,23 @@ use std::sync::Arc;
 use crate::output::OutputManager;
 use crate::render::cam::Camera;
 use crate::utils::computequeue::ComputeQueue;
-use cgmath::{Vector2, Vector3};
 use crate::render::renderbuffer::RenderBuffer;
 use crate::render::renderdata::RenderMeta;
+use crate::render::adaptive::{PixelStatistics, render_adaptive};
+use crate::render::aov::AovTile;
+use crate::render::control::{RenderControl, RenderStatus};
+use crate::render::film::FilmTile;
+use crate::render::progressive::{ProgressiveRendering, render_pass};
+use crate::render::checkpoint::CheckpointError;
 
-const BLOCK_DIM: Vector2<u32> = Vector2::new(8, 8);
+const MIN_TILE_DIM: usize = 8;
+const MAX_TILE_DIM: usize = 64;
+const TILES_PER_THREAD: usize = 8; // Enough tiles that the threads finish close to each other
 
 pub struct MultiThread
 {
-    m_pool: Pool
+    m_pool: Pool,
+    m_control: Arc<RenderControl>,
 }
 
 impl MultiThread
@@ -24,7 +33,8 @@ impl MultiThread
     {
         MultiThread
         {
-            m_pool: Pool::new(n_threads)
+            m_pool: Pool::new(n_threads),
+            m_control: Arc::new(RenderControl::new()),
         }
     }
 
@@ -33,24 +43,112 @@ impl MultiThread
         self.m_pool.thread_count()
     }
 
+    /// Dimensions of the square blocks of a RenderBuffer for an image rendered by the pool: the
+    /// largest power of two between 8 and 64 pixels that gives every thread several blocks
+    pub fn get_tile_dim(&self, img_dim: (usize, usize)) -> (usize, usize)
+    {
+        let min_tile_count = TILES_PER_THREAD * self.get_thread_count() as usize;
+        let mut tile_dim = MAX_TILE_DIM;
+        while tile_dim > MIN_TILE_DIM && img_dim.0.div_ceil(tile_dim) * img_dim.1.div_ceil(tile_dim) < min_tile_count
+        {
+            tile_dim /= 2;
+        }
+        (tile_dim, tile_dim)
+    }
+
+    /// Handle the renders report to, and which can stop them
+    pub fn set_render_control(&mut self, control: Arc<RenderControl>)
+    {
+        self.m_control = control;
+    }
+
+    pub fn get_render_control(&self) -> &Arc<RenderControl>
+    {
+        &self.m_control
+    }
+
     // We also need to know the dimensions of the view plane
+    /// Render the buffer's region, the whole image by default, unless the render control stops it; return why the render ended
     pub fn render_to_buffer<'a>(&mut self,
                                 world: Arc<World>,
                                 camera: &dyn Camera,
-                                buffer: &'a RenderBuffer)
+                                buffer: &'a RenderBuffer) -> RenderStatus
+    {
+        let control = self.m_control.clone();
+        control.start(buffer.get_block_count());
+        self.execute(buffer, || work(world.clone(), camera, buffer, &control));
+        control.finish();
+        control.get_status()
+    }
+
+    /// Render in passes that accumulate into the buffer, from the samples it already has, e.g.
+    /// after loading a checkpoint. After a pass, "on_update" receives the buffer and the number of
+    /// samples per pixel so far, if m_update_interval has elapsed since the last update; it always
+    /// receives the final image. The render control can stop the render between two tiles, or after
+    /// a pass that reached its noise target; the samples of an interrupted pass stay in the buffer,
+    /// but aren't part of the checkpoint, and rendering the buffer again resumes the pass without
+    /// sampling its finished blocks twice. Fails if a checkpoint can't be saved.
+    pub fn render_progressive<F>(&mut self,
+                                 world: Arc<World>,
+                                 camera: &dyn Camera,
+                                 buffer: &RenderBuffer,
+                                 settings: &ProgressiveRendering,
+                                 mut on_update: F) -> Result<RenderStatus, CheckpointError>
+        where F: FnMut(&RenderBuffer, u32)
+    {
+        let control = self.m_control.clone();
+        let block_count = buffer.get_block_count();
+        let remaining_samples = settings.m_target_samples.saturating_sub(buffer.get_sample_count());
+        control.start(block_count * remaining_samples.div_ceil(settings.m_samples_per_pass) as usize);
+        let mut last_update = Instant::now();
+        let mut last_checkpoint = Instant::now();
+
+        while buffer.get_sample_count() < settings.m_target_samples
+        {
+            let pass_samples = settings.get_pass_samples(buffer.get_sample_count());
+            let tiles_done = control.get_progress().m_tiles_done;
+            self.execute(buffer, || work_pass(world.clone(), camera, buffer, pass_samples.clone(), &control));
+
+            if control.get_progress().m_tiles_done - tiles_done < block_count
+            {
+                // Interrupted pass
+                on_update(buffer, buffer.get_sample_count());
+                break;
+            }
+            buffer.set_sample_count(pass_samples.end);
+            control.complete_pass(pass_samples.end, buffer.get_noise_level());
+            let finished = pass_samples.end == settings.m_target_samples || control.should_stop();
+
+            if finished || last_update.elapsed() >= settings.m_update_interval
+            {
+                on_update(buffer, pass_samples.end);
+                last_update = Instant::now();
+            }
+            if let Some(checkpoint_path) = settings.m_checkpoint_path.as_ref()
+            {
+                if finished || last_checkpoint.elapsed() >= settings.m_checkpoint_interval
+                {
+                    buffer.save_checkpoint(checkpoint_path, &world, camera)?;
+                    last_checkpoint = Instant::now();
+                }
+            }
+            if finished { break; }
+        }
+        control.finish();
+        Ok(control.get_status())
+    }
+
+    /// Run "job" on every thread of the pool, which share the blocks of the buffer
+    fn execute<F>(&mut self, buffer: &RenderBuffer, job: F)
+        where F: Fn() + Sync
     {
+        buffer.restart();
         let n_threads = self.get_thread_count();
         self.m_pool.scoped(|scoped|
             {
                 for _ in 0..n_threads
                 {
-                    let b = &buffer;
-                    let w = world.clone();
-                    scoped.execute( move ||
-                        {
-                            work(w, camera, b);
-                        }
-                    );
+                    scoped.execute(&job);
                 }
             });
     }
@@ -58,11 +156,86 @@ impl MultiThread
 
 fn work<'a>(world: Arc<World>,
             camera: &dyn Camera,
-            buffer: &'a RenderBuffer)
+            buffer: &'a RenderBuffer,
+            control: &RenderControl)
 {
     for rendermeta in buffer.iter()
     {
-        let samples = camera.render(world.clone(), &rendermeta);
-        buffer.read(samples, &rendermeta);
+        if control.should_stop() { break; }
+        let sample_count = render_block(&world, camera, buffer, &rendermeta);
+        if buffer.finish(&rendermeta) { control.complete_tile(sample_count); }
+        else { control.add_samples(sample_count); }
     }
-}
\ No newline at end of file
+}
+
+/// Render the pixels of a block, or of a sub-tile of it, into the buffer; return the number of
+/// samples taken
+pub fn render_block(world: &Arc<World>, camera: &dyn Camera, buffer: &RenderBuffer, rendermeta: &RenderMeta) -> u64
+{
+    let mut sample_count = 0;
+    buffer.render_into(rendermeta, |statistics, tile, aovs|
+        sample_count = render_samples(world, camera, rendermeta, statistics, tile, aovs));
+    sample_count
+}
+
+/// Render the pixels of a block, or of a part of it, into its pixel statistics, film tile and
+/// AOVs, adaptively if the view plane asks for it; return the number of samples taken
+pub fn render_samples(world: &Arc<World>,
+                      camera: &dyn Camera,
+                      rendermeta: &RenderMeta,
+                      statistics: &mut [PixelStatistics],
+                      tile: &mut FilmTile,
+                      aovs: &mut AovTile) -> u64
+{
+    match world.m_viewplaneptr.m_adaptive
+    {
+        Some(settings) =>
+            {
+                let previous_count = get_total_count(statistics);
+                render_adaptive(camera, world, rendermeta, &settings, statistics, tile, aovs);
+                get_total_count(statistics) - previous_count
+            }
+        None =>
+            {
+                camera.render_to_film(world, rendermeta, tile, aovs);
+                let sample_count = world.m_viewplaneptr.m_sampler.get_sample_per_pattern();
+                (rendermeta.m_area * sample_count) as u64
+            }
+    }
+}
+
+fn work_pass(world: Arc<World>,
+             camera: &dyn Camera,
+             buffer: &RenderBuffer,
+             sample_indices: Range<u32>,
+             control: &RenderControl)
+{
+    for rendermeta in buffer.iter()
+    {
+        if control.should_stop() { break; }
+        buffer.render_into(&rendermeta, |statistics, tile, aovs|
+            render_pass(camera, &world, &rendermeta, sample_indices.clone(), statistics, tile, aovs));
+        let sample_count = (rendermeta.m_area * sample_indices.len()) as u64;
+        if buffer.finish(&rendermeta) { control.complete_tile(sample_count); }
+        else { control.add_samples(sample_count); }
+    }
+}
+
+fn get_total_count(statistics: &[PixelStatistics]) -> u64
+{
+    statistics.iter().map(|pixel| pixel.get_count() as u64).sum()
+}
+
+#[cfg(test)]
+mod MultiThreadTest
+{
+    use super::*;
+
+    #[test]
+    fn tile_dim_leaves_work_for_every_thread()
+    {
+        assert_eq!(MultiThread::new(4).get_tile_dim((1920, 1080)), (64, 64));
+        assert_eq!(MultiThread::new(4).get_tile_dim((160, 120)), (16, 16));
+        assert_eq!(MultiThread::new(8).get_tile_dim((16, 16)), (8, 8));
+    }
+}
@@ -0,0 +1,107 @@
+use cgmath::Vector3;
+
+use crate::utils::color::Colorf;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// Hash of the parameters that decide what a scene renders to: the parts of the scene write
+/// them one by one from their hash_scene method. FNV-1a on the little endian bytes, so that it
+/// stays the same from one run, and one platform, to another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SceneHasher
+{
+    m_hash: u64,
+}
+
+impl SceneHasher
+{
+    pub fn new() -> SceneHasher
+    {
+        SceneHasher{ m_hash: FNV_OFFSET_BASIS }
+    }
+
+    pub fn get_hash(&self) -> u64
+    {
+        self.m_hash
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8])
+    {
+        self.m_hash = bytes.iter()
+            .fold(self.m_hash, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME));
+    }
+
+    pub fn write_u32(&mut self, value: u32)
+    {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64)
+    {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    pub fn write_bool(&mut self, value: bool)
+    {
+        self.write_bytes(&[value as u8]);
+    }
+
+    pub fn write_f32(&mut self, value: f32)
+    {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    /// Prefixed with its length, so that consecutive strings can't be confused
+    pub fn write_str(&mut self, value: &str)
+    {
+        self.write_u64(value.len() as u64);
+        self.write_bytes(value.as_bytes());
+    }
+
+    pub fn write_vector3(&mut self, value: Vector3<f32>)
+    {
+        self.write_f32(value.x);
+        self.write_f32(value.y);
+        self.write_f32(value.z);
+    }
+
+    pub fn write_color(&mut self, color: Colorf)
+    {
+        self.write_f32(color.m_r);
+        self.write_f32(color.m_g);
+        self.write_f32(color.m_b);
+    }
+}
+
+impl Default for SceneHasher
+{
+    fn default() -> Self
+    {
+        SceneHasher::new()
+    }
+}
+
+#[cfg(test)]
+mod SceneHasherTest
+{
+    use super::*;
+
+    #[test]
+    fn fnv1a_of_the_written_bytes()
+    {
+        let mut hasher = SceneHasher::new();
+        assert_eq!(hasher.get_hash(), FNV_OFFSET_BASIS);
+        hasher.write_bytes(b"a");
+        assert_eq!(hasher.get_hash(), 0xaf63_dc4c_8601_ec8c);
+
+        // Strings are delimited by their length
+        let mut ab_c = SceneHasher::new();
+        ab_c.write_str("ab");
+        ab_c.write_str("c");
+        let mut a_bc = SceneHasher::new();
+        a_bc.write_str("a");
+        a_bc.write_str("bc");
+        assert_ne!(ab_c.get_hash(), a_bc.get_hash());
+    }
+}
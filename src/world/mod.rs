@@ -1,3 +1,4 @@
 pub mod viewplane;
 pub mod world;
+pub mod scenefile;
 pub mod shaderec;
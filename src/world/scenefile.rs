@@ -0,0 +1,405 @@
+use cgmath::Vector3;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use crate::brdf::glossyspec::GlossySpecular;
+use crate::brdf::lambertian::Lambertian;
+use crate::geometry::{Concrete, Shadable};
+use crate::geometry::cuboid::Cuboid;
+use crate::geometry::sphere::Sphere;
+use crate::geometry::triangle::Triangle;
+use crate::light::ambient::Ambient;
+use crate::light::directional::Directional;
+use crate::light::pointlight::PointLight;
+use crate::material::Material;
+use crate::material::matte::Matte;
+use crate::material::phong::Phong;
+use crate::material::reflector::Reflective;
+use crate::render::cam::Camera;
+use crate::render::cam::pinhole::Pinhole;
+use crate::sampler::Sampler;
+use crate::sampler::mutijittered::MultiJittered;
+use crate::utils::color::Colorf;
+use crate::utils::colorconstant::COLOR_WHITE;
+use crate::utils::json::{JsonError, JsonValue};
+use crate::world::viewplane::ViewPlane;
+use crate::world::world::World;
+
+/// Most pixels, samples per pixel and ray bounces a scene file can ask for, so that one can't
+/// exhaust the memory or the stack of e.g. the render server
+pub const MAX_PIXEL_COUNT: usize = 8192 * 8192;
+pub const MAX_SAMPLES: usize = 4096;
+pub const MAX_DEPTH: u16 = 64;
+
+/// The tracers World::new knows by name
+const TRACERS: [&str; 2] = ["whitted", "raycast"];
+
+/// A scene read from a JSON scene file:
+///
+/// {
+///   "image": { "width": 320, "height": 240, "pixel_size": 1.0, "samples": 16, "seed": 0, "max_depth": 4 },
+///   "tracer": "whitted",
+///   "background": [0.0, 0.0, 0.0],
+///   "camera": { "eye": [0, 0, -100], "lookat": [0, 0, 0], "up": [0, 1, 0], "distance": 100, "zoom": 1, "exposure": 1 },
+///   "ambient": { "color": [1, 1, 1], "intensity": 0.1 },
+///   "lights": [ { "type": "point", "position": [-30, 20, -20], "color": [1, 1, 1], "intensity": 1 },
+///               { "type": "directional", "direction": [0, -1, 0], "color": [1, 1, 1], "intensity": 1 } ],
+///   "materials": { "white": { "type": "matte", "color": [1, 1, 1], "ka": 0.2, "kd": 0.8 },
+///                  "shiny": { "type": "phong", "color": [1, 0, 0], "ks": 0.5, "exponent": 20 },
+///                  "mirror": { "type": "reflective", "color": [1, 1, 1], "kr": 0.75, "reflect_color": [1, 1, 1] } },
+///   "objects": [ { "type": "sphere", "center": [0, 0, 0], "radius": 10, "material": "white" },
+///                { "type": "cuboid", "min": [-20, -12, -20], "max": [20, -10, 20], "material": "shiny" },
+///                { "type": "triangle", "vertices": [[0, 0, 0], [1, 0, 0], [0, 1, 0]] } ]
+/// }
+///
+/// Everything but the image dimensions has a default. The tracer is "whitted" or "raycast".
+pub struct SceneFile
+{
+    pub m_world: Arc<World>,
+    pub m_camera: Arc<dyn Camera>,
+    pub m_img_dim: (usize, usize),
+}
+
+#[derive(Debug)]
+pub enum SceneError
+{
+    IoError(io::Error),
+    JsonError(JsonError),
+    /// The document isn't a scene, e.g. a member has the wrong type
+    Invalid(String),
+}
+
+impl std::error::Error for SceneError {}
+
+impl fmt::Display for SceneError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self
+        {
+            SceneError::IoError(error) => write!(f, "Can't read the scene file: {}", error),
+            SceneError::JsonError(error) => write!(f, "{}", error),
+            SceneError::Invalid(message) => write!(f, "Invalid scene: {}", message),
+        }
+    }
+}
+
+impl From<io::Error> for SceneError
+{
+    fn from(error: io::Error) -> Self
+    {
+        SceneError::IoError(error)
+    }
+}
+
+impl From<JsonError> for SceneError
+{
+    fn from(error: JsonError) -> Self
+    {
+        SceneError::JsonError(error)
+    }
+}
+
+impl SceneFile
+{
+    pub fn load(path: &str) -> Result<SceneFile, SceneError>
+    {
+        SceneFile::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<SceneFile, SceneError>
+    {
+        let document = JsonValue::parse(text)?;
+        let image = document.get("image").ok_or_else(|| invalid("no image"))?;
+        let width = get_f32(image, "width", None)?;
+        let height = get_f32(image, "height", None)?;
+        if width < 1.0 || height < 1.0 || width > u16::MAX as f32 || height > u16::MAX as f32
+        {
+            return Err(invalid("image dimensions out of range"));
+        }
+        if width as usize * height as usize > MAX_PIXEL_COUNT
+        {
+            return Err(invalid(&format!("the image has more than {} pixels", MAX_PIXEL_COUNT)));
+        }
+        let samples = get_f32(image, "samples", Some(16.0))?.max(1.0);
+        if samples > MAX_SAMPLES as f32
+        {
+            return Err(invalid(&format!("more than {} samples per pixel", MAX_SAMPLES)));
+        }
+
+        let mut sampler = MultiJittered::new(samples as usize, 3);
+        sampler.generate_sample_pattern();
+        let mut vp = ViewPlane::new(Arc::new(sampler));
+        vp.m_hres = width as u16;
+        vp.m_vres = height as u16;
+        vp.m_pixsize = get_f32(image, "pixel_size", Some(1.0))?;
+        vp.m_seed = get_f32(image, "seed", Some(0.0))? as u32;
+        let max_depth = get_f32(image, "max_depth", Some(vp.m_maxdepth as f32))?;
+        if !(0.0..=MAX_DEPTH as f32).contains(&max_depth)
+        {
+            return Err(invalid(&format!("max_depth out of range [0, {}]", MAX_DEPTH)));
+        }
+        vp.m_maxdepth = max_depth as u16;
+        vp.set_zoom(&1.0);
+
+        let tracer = match document.get("tracer")
+        {
+            Some(tracer) => tracer.as_str().ok_or_else(|| invalid("tracer isn't a string"))?,
+            None => "whitted",
+        };
+        if !TRACERS.contains(&tracer)
+        {
+            return Err(invalid(&format!("unknown tracer {}", tracer)));
+        }
+        let mut world = World::new(Box::new(vp), tracer);
+        world.set_background_color(get_color(&document, "background", Some(Colorf::new(0.0, 0.0, 0.0)))?);
+
+        if let Some(ambient_value) = document.get("ambient")
+        {
+            let mut ambient = Ambient::new(get_color(ambient_value, "color", Some(COLOR_WHITE))?);
+            ambient.set_radiance_scaling_factor(get_f32(ambient_value, "intensity", Some(0.1))?);
+            world.set_ambient(Arc::new(ambient));
+        }
+
+        for light in get_array(&document, "lights")?.iter()
+        {
+            let color = get_color(light, "color", Some(COLOR_WHITE))?;
+            let intensity = get_f32(light, "intensity", Some(1.0))?;
+            match get_str(light, "type")?
+            {
+                "point" => world.add_light(Arc::new(PointLight::new(intensity, color, get_vector(light, "position", None)?))),
+                "directional" => world.add_light(Arc::new(Directional::new(intensity, color, get_vector(light, "direction", None)?))),
+                other => return Err(invalid(&format!("unknown light type {}", other))),
+            }
+        }
+
+        let mut materials = HashMap::new();
+        if let Some(members) = document.get("materials")
+        {
+            for (name, material) in members.as_object().ok_or_else(|| invalid("materials isn't an object"))?.iter()
+            {
+                materials.insert(name.as_str(), parse_material(material)?);
+            }
+        }
+        let default_material = parse_material(&JsonValue::Object(vec![("type".to_string(), JsonValue::String("matte".to_string()))]))?;
+
+        for object in get_array(&document, "objects")?.iter()
+        {
+            let material = match object.get("material")
+            {
+                Some(name) =>
+                    {
+                        let name = name.as_str().ok_or_else(|| invalid("material isn't a name"))?;
+                        materials.get(name).ok_or_else(|| invalid(&format!("unknown material {}", name)))?.clone()
+                    }
+                None => default_material.clone(),
+            };
+            let geometry: Arc<Mutex<dyn Concrete>> = match get_str(object, "type")?
+            {
+                "sphere" =>
+                    {
+                        let mut sphere = Sphere::new(get_f32(object, "radius", None)?, get_vector(object, "center", None)?);
+                        sphere.set_material(material);
+                        Arc::new(Mutex::new(sphere))
+                    }
+                "cuboid" =>
+                    {
+                        let mut cuboid = Cuboid::new(get_vector(object, "min", None)?, get_vector(object, "max", None)?);
+                        cuboid.set_material(material);
+                        Arc::new(Mutex::new(cuboid))
+                    }
+                "triangle" =>
+                    {
+                        let vertices = get_array(object, "vertices")?;
+                        if vertices.len() != 3
+                        {
+                            return Err(invalid("a triangle has 3 vertices"));
+                        }
+                        let mut triangle = Triangle::new(to_vector(&vertices[0])?, to_vector(&vertices[1])?, to_vector(&vertices[2])?);
+                        triangle.set_material(material);
+                        Arc::new(Mutex::new(triangle))
+                    }
+                other => return Err(invalid(&format!("unknown object type {}", other))),
+            };
+            world.add_object(geometry);
+        }
+
+        let camera_value = document.get("camera").cloned().unwrap_or(JsonValue::Object(Vec::new()));
+        let mut camera = Pinhole::new(get_vector(&camera_value, "eye", Some(Vector3::new(0.0, 0.0, -100.0)))?,
+                                      get_vector(&camera_value, "lookat", Some(Vector3::new(0.0, 0.0, 0.0)))?,
+                                      get_vector(&camera_value, "up", Some(Vector3::new(0.0, 1.0, 0.0)))?);
+        camera.m_distance_from_vp = get_f32(&camera_value, "distance", Some(100.0))?;
+        let zoom = get_f32(&camera_value, "zoom", Some(1.0))?;
+        if zoom == 0.0
+        {
+            return Err(invalid("zoom can't be 0"));
+        }
+        camera.set_zoom(zoom);
+        camera.m_core.m_exposure_time = get_f32(&camera_value, "exposure", Some(1.0))?;
+
+        world.build();
+        Ok(SceneFile
+        {
+            m_world: Arc::new(world),
+            m_camera: Arc::new(camera),
+            m_img_dim: (width as usize, height as usize),
+        })
+    }
+}
+
+fn parse_material(material: &JsonValue) -> Result<Arc<dyn Material>, SceneError>
+{
+    let color = get_color(material, "color", Some(COLOR_WHITE))?;
+    let ambient = Arc::new(Lambertian::new(get_f32(material, "ka", Some(0.2))?, color));
+    let diffuse = Arc::new(Lambertian::new(get_f32(material, "kd", Some(0.8))?, color));
+    let get_phong = ||
+        {
+            let mut specular = GlossySpecular::new(0.0, color);
+            specular.m_ks = get_f32(material, "ks", Some(0.3))?;
+            specular.m_exp = get_f32(material, "exponent", Some(10.0))?;
+            specular.m_colors = get_color(material, "specular_color", Some(COLOR_WHITE))?;
+            Ok::<Phong, SceneError>(Phong::new(ambient.clone(), diffuse.clone(), Arc::new(specular)))
+        };
+
+    Ok(match get_str(material, "type")?
+    {
+        "matte" => Arc::new(Matte::new(ambient.clone(), diffuse.clone())),
+        "phong" => Arc::new(get_phong()?),
+        "reflective" => Arc::new(Reflective::new(Arc::new(get_phong()?),
+                                                 get_f32(material, "kr", Some(0.75))?,
+                                                 get_color(material, "reflect_color", Some(COLOR_WHITE))?)),
+        other => return Err(invalid(&format!("unknown material type {}", other))),
+    })
+}
+
+fn invalid(message: &str) -> SceneError
+{
+    SceneError::Invalid(message.to_string())
+}
+
+/// Number member "key" of "value", or "default" if it's missing
+fn get_f32(value: &JsonValue, key: &str, default: Option<f32>) -> Result<f32, SceneError>
+{
+    match value.get(key)
+    {
+        Some(number) => number.as_f32().ok_or_else(|| invalid(&format!("{} isn't a number", key))),
+        None => default.ok_or_else(|| invalid(&format!("no {}", key))),
+    }
+}
+
+fn get_str<'a>(value: &'a JsonValue, key: &str) -> Result<&'a str, SceneError>
+{
+    value.get(key).and_then(|string| string.as_str()).ok_or_else(|| invalid(&format!("no {}", key)))
+}
+
+/// Array member "key" of "value", empty if it's missing
+fn get_array<'a>(value: &'a JsonValue, key: &str) -> Result<&'a [JsonValue], SceneError>
+{
+    match value.get(key)
+    {
+        Some(array) => array.as_array().ok_or_else(|| invalid(&format!("{} isn't an array", key))),
+        None => Ok(&[]),
+    }
+}
+
+fn to_triple(value: &JsonValue) -> Result<[f32; 3], SceneError>
+{
+    match value.as_array()
+    {
+        Some([x, y, z]) => match (x.as_f32(), y.as_f32(), z.as_f32())
+        {
+            (Some(x), Some(y), Some(z)) => Ok([x, y, z]),
+            _ => Err(invalid("expected 3 numbers")),
+        },
+        _ => Err(invalid("expected 3 numbers")),
+    }
+}
+
+fn to_vector(value: &JsonValue) -> Result<Vector3<f32>, SceneError>
+{
+    to_triple(value).map(|[x, y, z]| Vector3::new(x, y, z))
+}
+
+fn get_vector(value: &JsonValue, key: &str, default: Option<Vector3<f32>>) -> Result<Vector3<f32>, SceneError>
+{
+    match value.get(key)
+    {
+        Some(vector) => to_vector(vector),
+        None => default.ok_or_else(|| invalid(&format!("no {}", key))),
+    }
+}
+
+fn get_color(value: &JsonValue, key: &str, default: Option<Colorf>) -> Result<Colorf, SceneError>
+{
+    match value.get(key)
+    {
+        Some(color) => to_triple(color).map(|[r, g, b]| Colorf::new(r, g, b)),
+        None => default.ok_or_else(|| invalid(&format!("no {}", key))),
+    }
+}
+
+/// A red sphere above a triangle, lit by a point light: the scene of the tests that need a scene file
+#[cfg(test)]
+pub const SPHERE_SCENE: &str = r#"{
+    "image": { "width": 16, "height": 12, "samples": 4 },
+    "camera": { "eye": [0, 0, -100], "lookat": [0, 0, 0] },
+    "lights": [ { "type": "point", "position": [-30, 20, -50] } ],
+    "materials": { "red": { "type": "phong", "color": [1, 0, 0] } },
+    "objects": [ { "type": "sphere", "center": [0, 0, 0], "radius": 5, "material": "red" },
+                 { "type": "triangle", "vertices": [[-8, -6, 10], [8, -6, 10], [0, -6, -10]] } ]
+}"#;
+
+#[cfg(test)]
+mod SceneFileTest
+{
+    use super::*;
+    use crate::render::renderbuffer::RenderBuffer;
+    use crate::utils::multithread::MultiThread;
+
+    #[test]
+    fn parse_a_scene()
+    {
+        let scene = SceneFile::parse(SPHERE_SCENE).unwrap();
+        assert_eq!(scene.m_img_dim, (16, 12));
+        assert_eq!(scene.m_world.m_objects.len(), 2);
+        assert_eq!(scene.m_world.m_lights.len(), 1);
+        assert_eq!(scene.m_world.m_viewplaneptr.m_sampler.get_sample_per_pattern(), 4);
+
+        let buffer = RenderBuffer::new(scene.m_img_dim, (4, 4));
+        MultiThread::new(2).render_to_buffer(scene.m_world.clone(), scene.m_camera.as_ref(), &buffer);
+        let center = buffer.resolve()[8 + 6 * 16].resolve();
+        // Red, with the white highlight of the point light
+        assert!(center.m_r > center.m_g && center.m_g == center.m_b);
+    }
+
+    #[test]
+    fn invalid_scenes()
+    {
+        assert!(matches!(SceneFile::parse("{"), Err(SceneError::JsonError(_))));
+        assert!(matches!(SceneFile::parse("{}"), Err(SceneError::Invalid(_))));
+        let unknown_material = r#"{ "image": { "width": 4, "height": 4 },
+                                    "objects": [ { "type": "sphere", "center": [0, 0, 0], "radius": 1, "material": "gold" } ] }"#;
+        match SceneFile::parse(unknown_material)
+        {
+            Err(SceneError::Invalid(message)) => assert_eq!(message, "unknown material gold"),
+            _ => panic!("The material doesn't exist"),
+        }
+        assert!(SceneFile::parse(r#"{ "image": { "width": 4, "height": "4" } }"#).is_err());
+        assert!(SceneFile::parse(r#"{ "image": { "width": 4, "height": 4 }, "tracer": "raycast" }"#).is_ok());
+        match SceneFile::parse(r#"{ "image": { "width": 4, "height": 4 }, "tracer": "whited" }"#)
+        {
+            Err(SceneError::Invalid(message)) => assert_eq!(message, "unknown tracer whited"),
+            _ => panic!("The tracer doesn't exist"),
+        }
+
+        // At the limits
+        assert!(SceneFile::parse(r#"{ "image": { "width": 65535, "height": 1, "samples": 4096, "max_depth": 64 } }"#).is_ok());
+        for image in [r#"{ "width": 65535, "height": 65535 }"#, r#"{ "width": 4, "height": 4, "samples": 1e9 }"#,
+                      r#"{ "width": 4, "height": 4, "max_depth": 65535 }"#].iter()
+        {
+            assert!(matches!(SceneFile::parse(&format!(r#"{{ "image": {} }}"#, image)), Err(SceneError::Invalid(_))), "{}", image);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use cgmath::{Vector3, Zero};
+use cgmath::{Vector2, Vector3, Zero};
 use std::{f32, fmt};
 use std::option::Option;
 use std::sync::Arc;
@@ -16,6 +16,8 @@ pub struct ShadeRec
     pub m_normal: Vector3<f32>,
     pub m_hitpoint: Vector3<f32>,
     pub m_local_hitpoint: Vector3<f32>, // For attaching texture
+    pub m_uv: Vector2<f32>, // Surface parametrization of the hit, where the geometry has one
+    pub m_object_index: Option<usize>, // Index of the hit object in the world
     pub m_ray: Ray, // For specular lights
     pub m_light_dir: Vector3<f32>, // For directional lights
     pub m_worldptr: Arc<World>,
@@ -33,6 +35,8 @@ impl ShadeRec
             m_normal: Vector3::zero(),
             m_hitpoint: Vector3::zero(),
             m_local_hitpoint: Vector3::zero(),
+            m_uv: Vector2::zero(),
+            m_object_index: None,
             m_ray: Ray::new(Vector3::zero(), Vector3::zero()),
             m_light_dir: Vector3::zero(),
             m_worldptr: world.clone(),
@@ -61,6 +65,8 @@ impl fmt::Debug for ShadeRec
             .field("normal", &self.m_normal)
             .field("hitpoint", &self.m_hitpoint)
             .field("local hitpoint", &self.m_local_hitpoint)
+            .field("uv", &self.m_uv)
+            .field("object index", &self.m_object_index)
             .finish()
     }
 }
\ No newline at end of file
@@ -4,6 +4,10 @@ use cgmath::Vector2;
 
 use crate::sampler::Sampler;
 use crate::sampler::dummy::DummySampler;
+use crate::sampler::DEFAULT_SEED;
+use crate::sampler::pixelsampler::{SampleSequence, PixelSampler};
+use crate::render::adaptive::AdaptiveSampling;
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Clone, Debug)]
 pub struct ViewPlane
@@ -12,11 +16,12 @@ pub struct ViewPlane
     pub m_vres: u16,
     pub m_pixsize: f32,
     m_pixsize_adjusted: f32,
-    m_gamma: f32,
-    m_invgamma: f32,
 
     pub m_maxdepth: u16,
     pub m_sampler: Arc<dyn Sampler>,
+    pub m_sample_sequence: SampleSequence, // Drives the per-pixel samplers of the render threads
+    pub m_seed: u32, // Renders with the same seed are identical, whatever the thread count or tile order
+    pub m_adaptive: Option<AdaptiveSampling>, // Replaces the fixed sample count of m_sampler when set
 }
 
 impl ViewPlane
@@ -30,22 +35,41 @@ impl ViewPlane
             m_vres: 200,
             m_pixsize: 0.2,
             m_pixsize_adjusted: 0.2,
-            m_gamma: 1.0,
-            m_invgamma: 1.0,
             m_maxdepth: 5,
-            m_sampler: sampler
+            m_sampler: sampler,
+            m_sample_sequence: SampleSequence::Random,
+            m_seed: DEFAULT_SEED,
+            m_adaptive: None,
         }
     }
 
-    pub fn set_gamma(&mut self, newgamma: f32)
+    /// Sampler for the render threads; every camera installs it before rendering
+    pub fn get_pixel_sampler(&self) -> PixelSampler
     {
-        self.m_gamma = newgamma;
-        self.m_invgamma = 1.0 / newgamma;
+        PixelSampler::new(self.m_sample_sequence, self.m_seed)
     }
 
-    pub fn get_inv_gamma(&self) -> f32
+    /// Resolution and sampling; the seed and sequence are part of it
+    pub fn hash_scene(&self, hasher: &mut SceneHasher)
     {
-        self.m_invgamma
+        hasher.write_u32(self.m_hres as u32);
+        hasher.write_u32(self.m_vres as u32);
+        hasher.write_f32(self.m_pixsize_adjusted);
+        hasher.write_u32(self.m_maxdepth as u32);
+        hasher.write_u64(self.m_sampler.get_sample_per_pattern() as u64);
+        hasher.write_str(&format!("{:?}", self.m_sample_sequence));
+        hasher.write_u32(self.m_seed);
+        match self.m_adaptive
+        {
+            Some(adaptive) =>
+                {
+                    hasher.write_u32(adaptive.m_min_samples);
+                    hasher.write_u32(adaptive.m_max_samples);
+                    hasher.write_u32(adaptive.m_batch_size);
+                    hasher.write_f32(adaptive.m_threshold);
+                }
+            None => hasher.write_str("no adaptive sampling"),
+        }
     }
 
     pub fn set_zoom(&mut self, zoom: &f32)
@@ -65,6 +89,24 @@ impl ViewPlane
         }
     }
 
+    /// Map a sample within pixel (i, j) to normalised device coordinates in [-1, 1].
+    /// Both axes are divided by the smaller resolution when "keep_aspect" is set,
+    /// so that a unit circle stays round on non-square images.
+    pub fn get_normalized_coordinate(&self, i: u16, j: u16, sample: Vector2<f32>, keep_aspect: bool) -> Vector2<f32>
+    {
+        let (hscale, vscale) = match keep_aspect
+        {
+            true =>
+                {
+                    let min_res = self.m_hres.min(self.m_vres) as f32;
+                    (min_res, min_res)
+                }
+            false => (self.m_hres as f32, self.m_vres as f32),
+        };
+        Vector2::new((2.0 * (i as f32 + sample.x) - self.m_hres as f32) / hscale,
+                     (2.0 * (j as f32 + sample.y) - self.m_vres as f32) / vscale)
+    }
+
     pub fn is_coordinates_valid(&self, i: u16, j: u16) -> bool
     {
         let mut res = true;
@@ -107,4 +149,17 @@ mod ViewPlaneTest
         vp.m_pixsize = 0.5;
         let coordinate = vp.get_coordinate_from_index(30, 50);
     }
+
+    #[test]
+    fn testGetNormalizedCoordinate()
+    {
+        let sampler = Arc::new(MultiJittered::new(16, 3));
+        let mut vp = ViewPlane::new(sampler);
+        vp.m_hres = 400;
+        vp.m_vres = 200;
+        assert_eq!(vp.get_normalized_coordinate(0, 0, Vector2::new(0.0, 0.0), false), Vector2::new(-1.0, -1.0));
+        assert_eq!(vp.get_normalized_coordinate(399, 199, Vector2::new(1.0, 1.0), false), Vector2::new(1.0, 1.0));
+        assert_eq!(vp.get_normalized_coordinate(0, 100, Vector2::new(0.0, 0.0), true), Vector2::new(-2.0, 0.0));
+        assert_eq!(vp.get_normalized_coordinate(199, 99, Vector2::new(1.0, 1.0), true), Vector2::new(0.0, 0.0));
+    }
 }
\ No newline at end of file
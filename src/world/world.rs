@@ -1,5 +1,6 @@
-use std::sync::{Arc, Mutex};
-use cgmath::{Vector3, Zero};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use cgmath::{Vector2, Vector3, Zero};
 use std::{f32};
 
 use crate::utils::color::Colorf;
@@ -9,10 +10,12 @@ use crate::ray::Ray;
 use crate::geometry::{Geometry, Shadable, Concrete};
 use crate::light::ambient::Ambient;
 use crate::light::Light;
+use crate::material::Material;
 use crate::tracer::Tracer;
 use crate::tracer::whitted::Whitted;
 use crate::tracer::raycast::RayCast;
 use crate::utils::colorconstant::COLOR_WHITE;
+use crate::utils::scenehash::SceneHasher;
 
 #[derive(Debug)]
 pub struct World
@@ -23,6 +26,8 @@ pub struct World
     pub m_ambientlight: Arc<dyn Light>,
     pub m_lights: Vec<Arc<dyn Light>>,
     pub m_tracer: Arc<dyn Tracer>, // TODO: Holy crap, we don't even use member tracer in World
+    // Address of each material to the index of the first object using it, see get_material_index
+    m_material_indices: OnceLock<HashMap<usize, usize>>,
 }
 
 impl World
@@ -37,6 +42,7 @@ impl World
             m_ambientlight: Arc::new(Ambient::new(COLOR_WHITE)),
             m_lights: Vec::with_capacity(30),
             m_tracer: World::get_tracer(tracer),
+            m_material_indices: OnceLock::new(),
         }
     }
 
@@ -45,20 +51,24 @@ impl World
         self.m_backgroundcolor = newColor;
     }
 
+    /// Not following the book: it only assigns the material indices, which the first
+    /// get_material_index does otherwise
     pub fn build(&mut self)
     {
-        // Not following the book
+        self.get_material_indices();
     }
 
     /// Using Mutex as we might want to mutate the underlying object... Or do we?
     pub fn add_object(&mut self, object: Arc<Mutex<dyn Concrete>>)
     {
         self.m_objects.push(object);
+        self.m_material_indices.take();
     }
 
     pub fn remove_object(&mut self, index: usize)
     {
         self.m_objects.remove(index);
+        self.m_material_indices.take();
     }
 
     pub fn add_light(&mut self, light: Arc<dyn Light>)
@@ -83,23 +93,25 @@ impl World
         let mut normal = Vector3::zero();
         let mut hitpoint = Vector3::zero();
         let mut local_hitpoint = Vector3::zero();
+        let mut uv = Vector2::zero();
         let mut tglobal = 100000.0;
         let mut tminglobal = tmin;
 
-        for object in &worldptr.as_ref().m_objects
+        for (index, object) in worldptr.as_ref().m_objects.iter().enumerate()
         {
             if let x = object.lock().unwrap()
             {
                 if x.hit(ray, &mut tglobal, srref).unwrap_or(false) && tglobal < tminglobal
                 {
-                    println!("does hit!");
                     tminglobal = tglobal;
                     srref.m_material = Some(x.get_material());
                     srref.m_hit = true;
+                    srref.m_object_index = Some(index);
                     srref.m_hitpoint = ray.m_origin + tminglobal * ray.m_direction;
                     normal = srref.m_normal;
                     hitpoint = srref.m_hitpoint;
                     local_hitpoint = srref.m_local_hitpoint;
+                    uv = srref.m_uv;
                 }
             }
         }
@@ -109,10 +121,47 @@ impl World
             sr.m_time = tminglobal;
             sr.m_normal = normal;
             sr.m_hitpoint= hitpoint;
+            sr.m_uv = uv;
         }
         sr
     }
 
+    /// Index of the first object that uses "material", which identifies the material in the scene.
+    /// The indices are assigned once, locking every object, and kept until objects are added or
+    /// removed; giving an object another material in the meantime doesn't update them.
+    pub fn get_material_index(&self, material: &Arc<dyn Material>) -> Option<usize>
+    {
+        self.get_material_indices().get(&(Arc::as_ptr(material) as *const () as usize)).copied()
+    }
+
+    fn get_material_indices(&self) -> &HashMap<usize, usize>
+    {
+        self.m_material_indices.get_or_init(||
+            {
+                let mut indices = HashMap::new();
+                for (index, object) in self.m_objects.iter().enumerate()
+                {
+                    let material = object.lock().unwrap().get_material();
+                    indices.entry(Arc::as_ptr(&material) as *const () as usize).or_insert(index);
+                }
+                indices
+            })
+    }
+
+    /// Everything of the scene that decides its render, through the hash_scene methods of its
+    /// parts. It locks the objects one by one, so it waits for those another thread holds.
+    pub fn hash_scene(&self, hasher: &mut SceneHasher)
+    {
+        hasher.write_color(self.m_backgroundcolor);
+        self.m_viewplaneptr.hash_scene(hasher);
+        self.m_tracer.hash_scene(hasher);
+        self.m_ambientlight.hash_scene(hasher);
+        hasher.write_u64(self.m_lights.len() as u64);
+        self.m_lights.iter().for_each(|light| light.hash_scene(hasher));
+        hasher.write_u64(self.m_objects.len() as u64);
+        self.m_objects.iter().for_each(|object| object.lock().unwrap().hash_scene(hasher));
+    }
+
     pub fn get_dummy() -> World
     {
         World::new(Box::new(ViewPlane::get_dummy()), "whitted")